@@ -0,0 +1,26 @@
+// Builds the runtime library that `koze build` links into every executable.
+// The runtime has no dependencies, so it is compiled straight from its source
+// for the target koze is built for and embedded in the koze binary.
+
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+
+fn main() {
+    println!("cargo:rerun-if-changed=runtime/src");
+
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let target = env::var("TARGET").unwrap();
+
+    let status = Command::new(rustc)
+        .args(["--crate-name", "kozeig_runtime", "--crate-type", "staticlib", "--edition", "2021"])
+        .args(["-C", "opt-level=3", "-C", "panic=abort", "--target", &target])
+        .arg("-o")
+        .arg(out_dir.join("kozeig_runtime.a"))
+        .arg("runtime/src/lib.rs")
+        .status()
+        .expect("failed to run rustc for the runtime library");
+    assert!(status.success(), "building the runtime library failed");
+    println!("cargo:rustc-env=KOZE_HOST_TARGET={}", target);
+}
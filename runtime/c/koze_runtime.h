@@ -0,0 +1,114 @@
+/* Values and operations used by the C that `koze build --backend=c` writes, implemented
+   by the runtime library in runtime/src/values.rs. Every operation behaves like the
+   interpreter's; errors end the program with the interpreter's report on stderr and exit
+   status 1. Text and arrays are never freed. */
+#ifndef KOZE_RUNTIME_H
+#define KOZE_RUNTIME_H
+
+#include <stdint.h>
+
+typedef enum {
+    KZ_NONE,
+    KZ_NUMBER,
+    KZ_FP,
+    KZ_DEC,
+    KZ_TEXT,
+    KZ_BOOL,
+    KZ_ARRAY,
+    KZ_ARRAY2D
+} kz_kind;
+
+typedef struct kz_array kz_array;
+
+typedef struct {
+    kz_kind kind;
+    union {
+        int64_t number;
+        double fp;
+        int boolean;
+        struct {
+            int64_t units; /* value is units / 10^scale */
+            int scale;
+        } dec;
+        struct {
+            const char *data; /* NUL-terminated */
+            int64_t length;
+        } text;
+        kz_array *array; /* the rows of a 2D array are KZ_ARRAY values */
+    } as;
+} kz_value;
+
+/* Literals */
+kz_value kz_none(void);
+kz_value kz_number(int64_t number);
+kz_value kz_fp(double fp);
+kz_value kz_bool(int boolean);
+kz_value kz_text(const char *literal);
+kz_value kz_array_of(int64_t length, const kz_value *items);
+kz_value kz_array2d_of(int64_t rows, const kz_value *row_arrays);
+
+int kz_truthy(kz_value value);
+int kz_is_none(kz_value value);
+/* An if branch's value replaces the function's result unless it is none */
+void kz_keep(kz_value *result, kz_value value);
+
+/* Operators */
+kz_value kz_add(kz_value left, kz_value right);
+kz_value kz_subtract(kz_value left, kz_value right);
+kz_value kz_multiply(kz_value left, kz_value right);
+kz_value kz_divide(kz_value left, kz_value right);
+kz_value kz_modulo(kz_value left, kz_value right);
+kz_value kz_equal(kz_value left, kz_value right);
+kz_value kz_not_equal(kz_value left, kz_value right);
+kz_value kz_less(kz_value left, kz_value right);
+kz_value kz_less_equal(kz_value left, kz_value right);
+kz_value kz_greater(kz_value left, kz_value right);
+kz_value kz_greater_equal(kz_value left, kz_value right);
+kz_value kz_negate(kz_value value);
+kz_value kz_not(kz_value value);
+
+/* Commands */
+kz_value kz_to_number(kz_value value);
+kz_value kz_to_text(kz_value value);
+kz_value kz_to_fp(kz_value value);
+kz_value kz_to_bool(kz_value value);
+kz_value kz_to_dec(kz_value value);
+kz_value kz_to_dec_scale(kz_value value, kz_value scale);
+kz_value kz_dec_div(kz_value dividend, kz_value divisor, kz_value scale, kz_value mode);
+kz_value kz_dec_round(kz_value value, kz_value scale, kz_value mode);
+kz_value kz_hex(kz_value value);
+kz_value kz_bin(kz_value value);
+kz_value kz_asc(kz_value value);
+kz_value kz_length(kz_value value);
+kz_value kz_width(kz_value value);
+kz_value kz_get(kz_value array, kz_value index);
+kz_value kz_get2d(kz_value array, kz_value row, kz_value column);
+
+/* main's value as the exit status: a number from 0 to 255 is the status, 'ok' is 0, other
+   text and numbers outside 0 to 255 are reported on stderr with status 1 and anything else is 0 */
+int kz_exit_status(kz_value value);
+
+/* print [ a, b ] writes the values separated by spaces and a newline */
+void kz_print(int count, const kz_value *values);
+
+kz_value kz_parameter(kz_value value, const char *type_name, int argument, const char *function);
+
+/* Arguments and results of extern func declarations */
+int64_t kz_c_number(kz_value value, int argument, const char *function);
+double kz_c_fp(kz_value value, int argument, const char *function);
+_Bool kz_c_bool(kz_value value, int argument, const char *function);
+const char *kz_c_text(kz_value value, int argument, const char *function);
+kz_value kz_from_c_text(const char *text);
+
+/* The .ko file and line of the running statement, shown in error reports */
+extern const char *kz_file;
+extern int64_t kz_line;
+#define KZ_AT(file, line) (kz_file = (file), kz_line = (line))
+
+/* A function's result, with the location set back to the statement that called it */
+static inline kz_value kz_returned(kz_value value, const char *file, int64_t line) {
+    KZ_AT(file, line);
+    return value;
+}
+
+#endif
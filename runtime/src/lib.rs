@@ -0,0 +1,529 @@
+//! Runtime library for compiled Kozeig programs.
+//!
+//! `koze build` links this library into every executable and `koze jit` maps
+//! its functions into the JIT, so compiled code prints, converts and indexes
+//! values exactly the way the interpreter does instead of open-coding it.
+
+// Every entry point is called from generated code with pointers it created
+#![allow(clippy::missing_safety_doc)]
+
+pub mod report;
+pub mod values;
+
+use std::borrow::Cow;
+use std::ffi::{c_char, c_void, CStr};
+use std::io::Write as _;
+use std::sync::atomic::{AtomicI64, AtomicPtr, Ordering};
+
+// Array layout: dims, length, then a (tag, payload) pair per element; 2D arrays hold row arrays
+pub const ARRAY_HEADER_WORDS: u64 = 2;
+pub const ARRAY_TAG_INT: u64 = 0;
+pub const ARRAY_TAG_BOOL: u64 = 1;
+// Payload is a text pointer; null means none
+pub const ARRAY_TAG_TEXT: u64 = 2;
+pub const ARRAY_TAG_ARRAY: u64 = 3;
+// Payload is the bits of the f64
+pub const ARRAY_TAG_FLOAT: u64 = 4;
+// Decimal tags carry their scale: ARRAY_TAG_DECIMAL + digits after the point
+pub const ARRAY_TAG_DECIMAL: u64 = 16;
+
+// Text and arrays are preceded by a two-word header: their kind (ARRAY_TAG_TEXT or
+// ARRAY_TAG_ARRAY) and a reference count. Compiled code holds a pointer past the header
+pub const OBJECT_HEADER_WORDS: u64 = 2;
+// Reference count of text literals, which live in the program image and are never freed
+pub const REFCOUNT_IMMORTAL: i64 = -1;
+
+// Compiled numbers and decs have no spare bits, so none is the smallest i64; fp none is a
+// quiet NaN with its own payload
+pub const NONE_NUMBER: i64 = i64::MIN;
+pub const NONE_FP_BITS: u64 = 0x7ff8_0000_6e6f_6e65;
+
+// Objects allocated and not yet freed, reported at exit when KOZE_LEAK_CHECK is set
+static LIVE_OBJECTS: AtomicI64 = AtomicI64::new(0);
+
+extern "C" {
+    fn malloc(size: usize) -> *mut c_void;
+    fn free(ptr: *mut c_void);
+}
+
+// Source file and line of the code calling a runtime function that can fail. Compiled
+// code passes them to every such function, which records them before doing its work;
+// C from the C backend sets them itself as kz_file and kz_line
+#[export_name = "kz_file"]
+static ERROR_FILE: AtomicPtr<c_char> = AtomicPtr::new(std::ptr::null_mut());
+#[export_name = "kz_line"]
+static ERROR_LINE: AtomicI64 = AtomicI64::new(0);
+
+fn set_location(file: *const c_char, line: i64) {
+    ERROR_FILE.store(file as *mut c_char, Ordering::Relaxed);
+    ERROR_LINE.store(line, Ordering::Relaxed);
+}
+
+// Reported like `koze run` reports it, with the program's source read back from its .ko file
+fn runtime_error(message: &str) -> ! {
+    let file = ERROR_FILE.load(Ordering::Relaxed);
+    let report = if file.is_null() {
+        report::format_report("Runtime error", message, None, "")
+    } else {
+        let file = unsafe { text(file) };
+        let line = ERROR_LINE.load(Ordering::Relaxed) as usize;
+        let shown = format!("{}:line {}", file, line);
+        let location = report::ReportLocation { shown: &shown, line, column: None };
+        let source = std::fs::read_to_string(&*file).unwrap_or_default();
+        report::format_report("Runtime error", message, Some(&location), &source)
+    };
+    print_error(&report);
+    std::process::exit(1)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn koze_runtime_error(message: *const c_char, file: *const c_char, line: i64) -> ! {
+    set_location(file, line);
+    runtime_error(&text(message))
+}
+
+unsafe fn text<'a>(ptr: *const c_char) -> Cow<'a, str> {
+    if ptr.is_null() {
+        Cow::Borrowed("none")
+    } else {
+        CStr::from_ptr(ptr).to_string_lossy()
+    }
+}
+
+// A new object with a reference count of one, owned by the caller
+fn alloc_object(kind: u64, size: usize) -> *mut u8 {
+    let header = OBJECT_HEADER_WORDS as usize * std::mem::size_of::<i64>();
+    unsafe {
+        let base = malloc(header + size) as *mut i64;
+        if base.is_null() {
+            runtime_error("Out of memory");
+        }
+        *base = kind as i64;
+        *base.add(1) = 1;
+        LIVE_OBJECTS.fetch_add(1, Ordering::Relaxed);
+        base.add(OBJECT_HEADER_WORDS as usize) as *mut u8
+    }
+}
+
+unsafe fn object_header(ptr: *mut c_void) -> *mut i64 {
+    (ptr as *mut i64).sub(OBJECT_HEADER_WORDS as usize)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn koze_retain(ptr: *mut c_void) {
+    if ptr.is_null() {
+        return;
+    }
+    let refcount = object_header(ptr).add(1);
+    if *refcount != REFCOUNT_IMMORTAL {
+        *refcount += 1;
+    }
+}
+
+// Frees the object when its last reference goes, releasing the text and rows an array holds
+#[no_mangle]
+pub unsafe extern "C" fn koze_release(ptr: *mut c_void) {
+    if ptr.is_null() {
+        return;
+    }
+    let header = object_header(ptr);
+    let refcount = header.add(1);
+    if *refcount == REFCOUNT_IMMORTAL {
+        return;
+    }
+    *refcount -= 1;
+    if *refcount > 0 {
+        return;
+    }
+
+    if *header as u64 == ARRAY_TAG_ARRAY {
+        let array = ptr as *mut i64;
+        for i in 0..*array.add(1) {
+            let cell = cell_ptr(array, i);
+            if matches!(*cell as u64, ARRAY_TAG_TEXT | ARRAY_TAG_ARRAY) {
+                koze_release(*cell.add(1) as *mut c_void);
+            }
+        }
+    }
+    free(header as *mut c_void);
+    LIVE_OBJECTS.fetch_sub(1, Ordering::Relaxed);
+}
+
+// Called when main returns, after every variable and constant has been released
+#[no_mangle]
+pub extern "C" fn koze_check_leaks() {
+    let live = LIVE_OBJECTS.load(Ordering::Relaxed);
+    if live != 0 && std::env::var_os("KOZE_LEAK_CHECK").is_some() {
+        print_error(&format!("Leak check: {} text or array values were never freed\n", live));
+    }
+}
+
+// Text handed to compiled code is NUL-terminated and reference counted
+fn alloc_text(value: &[u8]) -> *mut c_char {
+    let ptr = alloc_object(ARRAY_TAG_TEXT, value.len() + 1);
+    unsafe {
+        std::ptr::copy_nonoverlapping(value.as_ptr(), ptr, value.len());
+        *ptr.add(value.len()) = 0;
+    }
+    ptr as *mut c_char
+}
+
+fn number_text(value: i64) -> String {
+    if value == NONE_NUMBER {
+        return "none".to_string();
+    }
+    value.to_string()
+}
+
+fn float_text(value: f64) -> String {
+    if value.to_bits() == NONE_FP_BITS {
+        return "none".to_string();
+    }
+    value.to_string()
+}
+
+fn format_decimal(units: i64, scale: i64) -> String {
+    if units == NONE_NUMBER {
+        return "none".to_string();
+    }
+    let magnitude = units.unsigned_abs();
+    let sign = if units < 0 { "-" } else { "" };
+    if scale <= 0 {
+        return format!("{}{}", sign, magnitude);
+    }
+    let divisor = 10u64.pow(scale as u32);
+    format!("{}{}.{:0width$}", sign, magnitude / divisor, magnitude % divisor, width = scale as usize)
+}
+
+#[no_mangle]
+pub extern "C" fn koze_int_to_text(value: i64) -> *mut c_char {
+    alloc_text(number_text(value).as_bytes())
+}
+
+#[no_mangle]
+pub extern "C" fn koze_float_to_text(value: f64) -> *mut c_char {
+    alloc_text(float_text(value).as_bytes())
+}
+
+// One-character text for asc
+#[no_mangle]
+pub extern "C" fn koze_char_to_text(code: i64, file: *const c_char, line: i64) -> *mut c_char {
+    set_location(file, line);
+    match char::from_u32(code as u32) {
+        Some(c) => alloc_text(c.to_string().as_bytes()),
+        None => runtime_error(&format!("Invalid ASCII code: {}", code)),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn koze_decimal_to_text(units: i64, scale: i64) -> *mut c_char {
+    alloc_text(format_decimal(units, scale).as_bytes())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn koze_concat(left: *const c_char, right: *const c_char, file: *const c_char, line: i64) -> *mut c_char {
+    set_location(file, line);
+    if left.is_null() || right.is_null() {
+        runtime_error("Cannot add incompatible types");
+    }
+    let mut result = String::from(text(left));
+    result.push_str(&text(right));
+    alloc_text(result.as_bytes())
+}
+
+// Text passed in from C has no header, so library entry points copy it first
+#[no_mangle]
+pub unsafe extern "C" fn koze_text_from_c(value: *const c_char) -> *mut c_char {
+    if value.is_null() {
+        return std::ptr::null_mut();
+    }
+    alloc_text(CStr::from_ptr(value).to_bytes())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn koze_text_to_int(value: *const c_char, file: *const c_char, line: i64) -> i64 {
+    set_location(file, line);
+    if value.is_null() {
+        runtime_error("Cannot convert none with number\n\nTip: Provide a default with ?? (e.g. $value ?? 0) before converting");
+    }
+    let value = text(value);
+    value
+        .parse::<i64>()
+        .unwrap_or_else(|_| runtime_error(&format!("Cannot convert '{}' to a number", value)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn koze_text_to_float(value: *const c_char, file: *const c_char, line: i64) -> f64 {
+    set_location(file, line);
+    if value.is_null() {
+        runtime_error("Cannot convert none with fp\n\nTip: Provide a default with ?? (e.g. $value ?? 0) before converting");
+    }
+    let value = text(value);
+    value
+        .parse::<f64>()
+        .unwrap_or_else(|_| runtime_error(&format!("Cannot convert '{}' to a floating point number", value)))
+}
+
+// Exit status for text main returns: 'ok' is success, other text is printed as the error
+#[no_mangle]
+pub unsafe extern "C" fn koze_main_status(value: *const c_char) -> i64 {
+    if value.is_null() {
+        return 0;
+    }
+    let value = text(value);
+    if value == "ok" {
+        return 0;
+    }
+    print_error(&format!("{}\n", value));
+    1
+}
+
+// main's number as the process exit status; one outside 0 to 255 is reported and exits with 1.
+// none is success, as in the interpreter
+#[no_mangle]
+pub extern "C" fn koze_exit_code(status: i64) -> i64 {
+    if status == NONE_NUMBER {
+        return 0;
+    }
+    if (0..=255).contains(&status) {
+        return status;
+    }
+    print_error(&format!("Exit status {} is outside 0 to 255\n", status));
+    1
+}
+
+// hex and bin of text; the prefix is optional, as in the interpreter
+#[no_mangle]
+pub unsafe extern "C" fn koze_parse_radix(value: *const c_char, radix: i64, file: *const c_char, line: i64) -> i64 {
+    set_location(file, line);
+    let (name, prefixes) = if radix == 16 { ("hexadecimal", ["0x", "0X"]) } else { ("binary", ["0b", "0B"]) };
+    if value.is_null() {
+        runtime_error(&format!("Expected {} string", name));
+    }
+    let value = text(value);
+    let digits = value.trim_start_matches(prefixes[0]).trim_start_matches(prefixes[1]);
+    i64::from_str_radix(digits, radix as u32)
+        .unwrap_or_else(|_| runtime_error(&format!("Cannot parse '{}' as {}", value, name)))
+}
+
+unsafe fn cell_ptr(array: *mut i64, index: i64) -> *mut i64 {
+    array.add(ARRAY_HEADER_WORDS as usize + 2 * index as usize)
+}
+
+unsafe fn check_dims(array: *const i64, dims: i64, message: &str) {
+    if *array != dims {
+        runtime_error(message);
+    }
+}
+
+// Indices are compared unsigned, so negative ones are out of bounds like the interpreter's usize cast
+unsafe fn checked_cell(array: *mut i64, index: i64, describe: impl Fn(u64, i64) -> String) -> *mut i64 {
+    let length = *array.add(1);
+    if index as u64 >= length as u64 {
+        runtime_error(&describe(index as u64, length));
+    }
+    cell_ptr(array, index)
+}
+
+// Cells are filled in by the caller
+#[no_mangle]
+pub extern "C" fn koze_array_new(dims: i64, length: i64) -> *mut i64 {
+    let words = ARRAY_HEADER_WORDS as usize + 2 * length as usize;
+    unsafe {
+        let array = alloc_object(ARRAY_TAG_ARRAY, words * std::mem::size_of::<i64>()) as *mut i64;
+        *array = dims;
+        *array.add(1) = length;
+        array
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn koze_array_cell(array: *mut i64, index: i64, file: *const c_char, line: i64) -> *mut i64 {
+    set_location(file, line);
+    check_dims(array, 1, "First argument to get must be an array");
+    checked_cell(array, index, |index, length| {
+        format!("Array index out of bounds: {} (length: {})", index, length)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn koze_array_cell2d(array: *mut i64, row: i64, column: i64, file: *const c_char, line: i64) -> *mut i64 {
+    set_location(file, line);
+    check_dims(array, 2, "First argument to get2d must be a 2D array");
+    let row_cell = checked_cell(array, row, |row, height| {
+        format!("Row index out of bounds: {} (array height: {})", row, height)
+    });
+    let row_array = *row_cell.add(1) as *mut i64;
+    checked_cell(row_array, column, |column, width| {
+        format!("Column index out of bounds: {} (row length: {})", column, width)
+    })
+}
+
+// The width of a 2D array is the length of its first row
+#[no_mangle]
+pub unsafe extern "C" fn koze_array_width(array: *mut i64, file: *const c_char, line: i64) -> i64 {
+    set_location(file, line);
+    check_dims(array, 2, "Cannot get width of non-2D array");
+    if *array.add(1) == 0 {
+        return 0;
+    }
+    let first_row = *cell_ptr(array, 0).add(1) as *const i64;
+    *first_row.add(1)
+}
+
+// Same text as the interpreter's Display for the value the cell holds
+unsafe fn write_cell(out: &mut String, cell: *const i64) {
+    let tag = *cell as u64;
+    let payload = *cell.add(1);
+    match tag {
+        ARRAY_TAG_INT => out.push_str(&number_text(payload)),
+        ARRAY_TAG_BOOL => out.push_str(if payload != 0 { "true" } else { "false" }),
+        ARRAY_TAG_TEXT => out.push_str(&text(payload as *const c_char)),
+        ARRAY_TAG_ARRAY => write_array(out, payload as *mut i64),
+        ARRAY_TAG_FLOAT => out.push_str(&float_text(f64::from_bits(payload as u64))),
+        _ => out.push_str(&format_decimal(payload, (tag - ARRAY_TAG_DECIMAL) as i64)),
+    }
+}
+
+// [a, b] for 1D arrays and [[a, b]; [c, d]] for 2D arrays
+unsafe fn write_array(out: &mut String, array: *mut i64) {
+    let separator = if *array == 2 { "; " } else { ", " };
+    out.push('[');
+    for i in 0..*array.add(1) {
+        if i > 0 {
+            out.push_str(separator);
+        }
+        write_cell(out, cell_ptr(array, i));
+    }
+    out.push(']');
+}
+
+// WASI programs write straight to their file descriptors with fd_write
+#[cfg(target_os = "wasi")]
+mod wasi {
+    pub const STDOUT: u32 = 1;
+    pub const STDERR: u32 = 2;
+
+    #[repr(C)]
+    struct Ciovec {
+        buf: *const u8,
+        buf_len: usize,
+    }
+
+    #[link(wasm_import_module = "wasi_snapshot_preview1")]
+    extern "C" {
+        fn fd_write(fd: u32, iovs: *const Ciovec, iovs_len: usize, nwritten: *mut usize) -> u16;
+    }
+
+    // Errors are ignored like the host build ignores them; a short write is retried
+    pub fn write_all(fd: u32, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            let iov = Ciovec { buf: bytes.as_ptr(), buf_len: bytes.len() };
+            let mut written = 0;
+            if unsafe { fd_write(fd, &iov, 1, &mut written) } != 0 || written == 0 {
+                return;
+            }
+            bytes = &bytes[written..];
+        }
+    }
+}
+
+#[cfg(target_os = "wasi")]
+fn print(value: &str) {
+    wasi::write_all(wasi::STDOUT, value.as_bytes());
+}
+
+#[cfg(not(target_os = "wasi"))]
+fn print(value: &str) {
+    let _ = std::io::stdout().lock().write_all(value.as_bytes());
+}
+
+// Text on stderr, after everything printed so far
+#[cfg(target_os = "wasi")]
+fn print_error(message: &str) {
+    wasi::write_all(wasi::STDERR, message.as_bytes());
+}
+
+#[cfg(not(target_os = "wasi"))]
+fn print_error(message: &str) {
+    let _ = std::io::stdout().flush();
+    eprint!("{}", message);
+}
+
+#[no_mangle]
+pub extern "C" fn koze_print_int(value: i64) {
+    print(&number_text(value));
+}
+
+#[no_mangle]
+pub extern "C" fn koze_print_float(value: f64) {
+    print(&float_text(value));
+}
+
+#[no_mangle]
+pub extern "C" fn koze_print_bool(value: i64) {
+    print(if value != 0 { "true" } else { "false" });
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn koze_print_text(value: *const c_char) {
+    print(&text(value));
+}
+
+#[no_mangle]
+pub extern "C" fn koze_print_decimal(units: i64, scale: i64) {
+    print(&format_decimal(units, scale));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn koze_print_cell(cell: *const i64) {
+    let mut out = String::new();
+    write_cell(&mut out, cell);
+    print(&out);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn koze_print_array(array: *mut i64) {
+    let mut out = String::new();
+    write_array(&mut out, array);
+    print(&out);
+}
+
+// Called before main returns, since a C main does not flush Rust's stdout
+#[no_mangle]
+pub extern "C" fn koze_flush() {
+    let _ = std::io::stdout().flush();
+}
+
+// Addresses of the runtime functions, for mapping them into a JIT
+pub fn symbols() -> Vec<(&'static str, usize)> {
+    vec![
+        ("koze_runtime_error", koze_runtime_error as *const () as usize),
+        ("koze_retain", koze_retain as *const () as usize),
+        ("koze_release", koze_release as *const () as usize),
+        ("koze_check_leaks", koze_check_leaks as *const () as usize),
+        ("koze_int_to_text", koze_int_to_text as *const () as usize),
+        ("koze_float_to_text", koze_float_to_text as *const () as usize),
+        ("koze_char_to_text", koze_char_to_text as *const () as usize),
+        ("koze_decimal_to_text", koze_decimal_to_text as *const () as usize),
+        ("koze_concat", koze_concat as *const () as usize),
+        ("koze_text_from_c", koze_text_from_c as *const () as usize),
+        ("koze_text_to_int", koze_text_to_int as *const () as usize),
+        ("koze_text_to_float", koze_text_to_float as *const () as usize),
+        ("koze_parse_radix", koze_parse_radix as *const () as usize),
+        ("koze_main_status", koze_main_status as *const () as usize),
+        ("koze_exit_code", koze_exit_code as *const () as usize),
+        ("koze_array_new", koze_array_new as *const () as usize),
+        ("koze_array_cell", koze_array_cell as *const () as usize),
+        ("koze_array_cell2d", koze_array_cell2d as *const () as usize),
+        ("koze_array_width", koze_array_width as *const () as usize),
+        ("koze_print_int", koze_print_int as *const () as usize),
+        ("koze_print_float", koze_print_float as *const () as usize),
+        ("koze_print_bool", koze_print_bool as *const () as usize),
+        ("koze_print_text", koze_print_text as *const () as usize),
+        ("koze_print_decimal", koze_print_decimal as *const () as usize),
+        ("koze_print_cell", koze_print_cell as *const () as usize),
+        ("koze_print_array", koze_print_array as *const () as usize),
+        ("koze_flush", koze_flush as *const () as usize),
+    ]
+}
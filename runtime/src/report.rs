@@ -0,0 +1,90 @@
+//! The error report koze prints: a banner, the message with its tips highlighted and,
+//! when the error has a line, the source around it. `koze run` and compiled programs
+//! both print through here, so a failing program looks the same in every mode.
+
+use std::fmt::Write as _;
+
+/// Where an error happened: the text shown after "at", the 1-based line and an optional column
+pub struct ReportLocation<'a> {
+    pub shown: &'a str,
+    pub line: usize,
+    pub column: Option<usize>,
+}
+
+pub fn format_report(error_type: &str, message: &str, location: Option<&ReportLocation>, source: &str) -> String {
+    let mut out = String::new();
+    let banner = "=".repeat(50);
+    let _ = writeln!(out, "\x1b[1;31m{}\x1b[0m", banner);
+
+    // Format error message with type and location
+    match location {
+        Some(loc) => {
+            let _ = writeln!(out, "\x1b[1;31m{}\x1b[0m at \x1b[1;33m{}\x1b[0m:", error_type, loc.shown);
+        }
+        None => {
+            let _ = writeln!(out, "\x1b[1;31m{}\x1b[0m:", error_type);
+        }
+    }
+
+    // First line is the main error, the rest might be suggestions or tips
+    let message_lines: Vec<&str> = message.lines().collect();
+    if let Some(first) = message_lines.first() {
+        let _ = writeln!(out, "\x1b[1m{}\x1b[0m", first);
+        for line in message_lines.iter().skip(1) {
+            if line.starts_with("Tip:") {
+                let _ = writeln!(out, "\x1b[1;36m{}\x1b[0m", line); // Cyan for tips
+            } else if line.contains("Did you mean") || line.starts_with("  - ") {
+                let _ = writeln!(out, "\x1b[1;32m{}\x1b[0m", line); // Green for suggestions
+            } else {
+                let _ = writeln!(out, "{}", line);
+            }
+        }
+    }
+
+    if let Some(loc) = location {
+        write_source_context(&mut out, loc, message_lines.first().copied(), source);
+    }
+
+    let _ = writeln!(out, "\x1b[1;31m{}\x1b[0m", banner);
+    out
+}
+
+// Two lines either side of the error line, with a pointer under the error
+fn write_source_context(out: &mut String, loc: &ReportLocation, first_line: Option<&str>, source: &str) {
+    let lines: Vec<&str> = source.lines().collect();
+    let line_idx = loc.line.saturating_sub(1);
+    if line_idx >= lines.len() {
+        return;
+    }
+
+    let start_line = line_idx.saturating_sub(2);
+    let end_line = std::cmp::min(line_idx + 3, lines.len());
+
+    let _ = writeln!(out, "\n\x1b[1mSource context:\x1b[0m");
+    for (i, line) in lines.iter().enumerate().take(end_line).skip(start_line) {
+        if i != line_idx {
+            let _ = writeln!(out, "\x1b[90m{:>4} |\x1b[0m {}", i + 1, line);
+            continue;
+        }
+
+        let _ = writeln!(out, "\x1b[33m{:>4}\x1b[0m \x1b[31m>\x1b[0m \x1b[1m{}\x1b[0m", i + 1, line);
+        // Without a column, point at the name or quoted text the message is about
+        let position = loc
+            .column
+            .or_else(|| first_line.and_then(error_term).and_then(|term| line.find(term)))
+            .unwrap_or(0);
+        let _ = writeln!(out, "{}\x1b[1;31m^\x1b[0m\x1b[31m-- Error occurs here\x1b[0m", " ".repeat(6 + position));
+    }
+}
+
+fn error_term(message: &str) -> Option<&str> {
+    if message.contains("variable") && message.contains(':') {
+        message.split(':').nth(1).map(|s| s.trim())
+    } else if message.contains('\'') {
+        let start = message.find('\'').map(|i| i + 1).unwrap_or(0);
+        let end = message[start..].find('\'').map(|i| i + start).unwrap_or(message.len());
+        Some(&message[start..end])
+    } else {
+        None
+    }
+}
@@ -0,0 +1,1025 @@
+//! Values and operations of the C that `koze build --backend=c` writes, declared for it in
+//! runtime/c/koze_runtime.h. Values stay dynamically typed and every operation follows the
+//! interpreter's rules, messages and output. Text and arrays are never freed.
+
+use super::{format_decimal, koze_exit_code, print, print_error, runtime_error, text};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::ffi::{c_char, c_int, CStr};
+
+// Largest scale a dec value may carry, as in src/decimal.rs
+const MAX_SCALE: u32 = 18;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KzKind {
+    None,
+    Number,
+    Fp,
+    Dec,
+    Text,
+    Bool,
+    Array,
+    Array2D,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct KzDec {
+    // The value is units / 10^scale
+    units: i64,
+    scale: c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct KzText {
+    // NUL-terminated, though the text may hold NULs of its own
+    data: *const c_char,
+    length: i64,
+}
+
+// The rows of a 2D array are Array values
+#[repr(C)]
+pub struct KzArray {
+    items: *const KzValue,
+    length: i64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub union KzPayload {
+    number: i64,
+    fp: f64,
+    boolean: c_int,
+    dec: KzDec,
+    text: KzText,
+    array: *const KzArray,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct KzValue {
+    kind: KzKind,
+    payload: KzPayload,
+}
+
+#[derive(Clone, Copy)]
+struct Dec {
+    units: i64,
+    scale: u32,
+}
+
+// A value as the operations below look at it
+#[derive(Clone, Copy)]
+enum Value {
+    None,
+    Number(i64),
+    Fp(f64),
+    Dec(Dec),
+    Text(&'static [u8]),
+    Bool(bool),
+    Array(&'static [KzValue]),
+    Array2D(&'static [KzValue]),
+}
+
+impl KzValue {
+    fn view(self) -> Value {
+        unsafe {
+            match self.kind {
+                KzKind::None => Value::None,
+                KzKind::Number => Value::Number(self.payload.number),
+                KzKind::Fp => Value::Fp(self.payload.fp),
+                KzKind::Dec => Value::Dec(Dec { units: self.payload.dec.units, scale: self.payload.dec.scale as u32 }),
+                KzKind::Text => {
+                    let text = self.payload.text;
+                    Value::Text(std::slice::from_raw_parts(text.data as *const u8, text.length as usize))
+                }
+                KzKind::Bool => Value::Bool(self.payload.boolean != 0),
+                KzKind::Array => Value::Array(items(self.payload.array)),
+                KzKind::Array2D => Value::Array2D(items(self.payload.array)),
+            }
+        }
+    }
+}
+
+unsafe fn items(array: *const KzArray) -> &'static [KzValue] {
+    let array = &*array;
+    if array.length == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(array.items, array.length as usize)
+    }
+}
+
+fn value(kind: KzKind, payload: KzPayload) -> KzValue {
+    KzValue { kind, payload }
+}
+
+// Literals
+
+#[no_mangle]
+pub extern "C" fn kz_none() -> KzValue {
+    value(KzKind::None, KzPayload { number: 0 })
+}
+
+#[no_mangle]
+pub extern "C" fn kz_number(number: i64) -> KzValue {
+    value(KzKind::Number, KzPayload { number })
+}
+
+#[no_mangle]
+pub extern "C" fn kz_fp(fp: f64) -> KzValue {
+    value(KzKind::Fp, KzPayload { fp })
+}
+
+#[no_mangle]
+pub extern "C" fn kz_bool(boolean: c_int) -> KzValue {
+    value(KzKind::Bool, KzPayload { boolean: (boolean != 0) as c_int })
+}
+
+// Literals live in the program image, so they are used in place
+#[no_mangle]
+pub unsafe extern "C" fn kz_text(literal: *const c_char) -> KzValue {
+    let length = CStr::from_ptr(literal).to_bytes().len() as i64;
+    value(KzKind::Text, KzPayload { text: KzText { data: literal, length } })
+}
+
+fn text_bytes(mut bytes: Vec<u8>) -> KzValue {
+    let length = bytes.len() as i64;
+    bytes.push(0);
+    let data = Box::leak(bytes.into_boxed_slice()).as_ptr() as *const c_char;
+    value(KzKind::Text, KzPayload { text: KzText { data, length } })
+}
+
+fn text_value(text: String) -> KzValue {
+    text_bytes(text.into_bytes())
+}
+
+fn array_value(kind: KzKind, values: Vec<KzValue>) -> KzValue {
+    let length = values.len() as i64;
+    let items = Box::leak(values.into_boxed_slice()).as_ptr();
+    let array = Box::leak(Box::new(KzArray { items, length }));
+    value(kind, KzPayload { array })
+}
+
+unsafe fn array_items(length: i64, items: *const KzValue) -> Vec<KzValue> {
+    if length == 0 {
+        Vec::new()
+    } else {
+        std::slice::from_raw_parts(items, length as usize).to_vec()
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn kz_array_of(length: i64, items: *const KzValue) -> KzValue {
+    array_value(KzKind::Array, array_items(length, items))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn kz_array2d_of(rows: i64, row_arrays: *const KzValue) -> KzValue {
+    array_value(KzKind::Array2D, array_items(rows, row_arrays))
+}
+
+fn truthy(value: Value) -> bool {
+    match value {
+        Value::Bool(b) => b,
+        Value::Number(n) => n != 0,
+        Value::Fp(f) => f != 0.0,
+        Value::Dec(d) => d.units != 0,
+        Value::Text(t) => !t.is_empty(),
+        Value::Array(items) | Value::Array2D(items) => !items.is_empty(),
+        Value::None => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn kz_truthy(value: KzValue) -> c_int {
+    truthy(value.view()) as c_int
+}
+
+#[no_mangle]
+pub extern "C" fn kz_is_none(value: KzValue) -> c_int {
+    (value.kind == KzKind::None) as c_int
+}
+
+// An if branch's value replaces the function's result unless it is none
+#[no_mangle]
+pub unsafe extern "C" fn kz_keep(result: *mut KzValue, value: KzValue) {
+    if value.kind != KzKind::None {
+        *result = value;
+    }
+}
+
+// Formatting
+
+fn display(out: &mut String, value: Value) {
+    match value {
+        Value::None => out.push_str("none"),
+        Value::Number(n) => out.push_str(&n.to_string()),
+        Value::Fp(f) => out.push_str(&f.to_string()),
+        Value::Dec(d) => out.push_str(&format_decimal(d.units, d.scale as i64)),
+        Value::Text(t) => out.push_str(&String::from_utf8_lossy(t)),
+        Value::Bool(b) => out.push_str(if b { "true" } else { "false" }),
+        Value::Array(items) => display_items(out, items),
+        Value::Array2D(rows) => {
+            out.push('[');
+            for (i, row) in rows.iter().enumerate() {
+                if i > 0 {
+                    out.push_str("; ");
+                }
+                if let Value::Array(items) = row.view() {
+                    display_items(out, items);
+                }
+            }
+            out.push(']');
+        }
+    }
+}
+
+fn display_items(out: &mut String, items: &[KzValue]) {
+    out.push('[');
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            out.push_str(", ");
+        }
+        display(out, item.view());
+    }
+    out.push(']');
+}
+
+// The value as print shows it, for messages and concatenation
+fn display_text(value: Value) -> String {
+    let mut out = String::new();
+    display(&mut out, value);
+    out
+}
+
+fn lossy(text: &[u8]) -> Cow<'_, str> {
+    String::from_utf8_lossy(text)
+}
+
+fn concat(left: Value, right: Value) -> KzValue {
+    let mut out = display_text(left);
+    display(&mut out, right);
+    text_value(out)
+}
+
+fn parse_int(text: &[u8]) -> Option<i64> {
+    std::str::from_utf8(text).ok()?.parse().ok()
+}
+
+fn parse_float(text: &[u8]) -> Option<f64> {
+    std::str::from_utf8(text).ok()?.parse().ok()
+}
+
+// Decimals: like src/decimal.rs, with 64-bit units
+
+#[derive(Clone, Copy)]
+enum RoundingMode {
+    HalfUp,
+    HalfDown,
+    HalfEven,
+    Up,
+    Down,
+    Ceiling,
+    Floor,
+}
+
+fn decimal_overflow() -> ! {
+    runtime_error("Decimal overflow")
+}
+
+fn checked(result: Option<i64>) -> i64 {
+    result.unwrap_or_else(|| decimal_overflow())
+}
+
+fn pow10(exponent: u32) -> i64 {
+    if exponent > MAX_SCALE {
+        decimal_overflow();
+    }
+    10i64.pow(exponent)
+}
+
+fn decimal(d: Dec) -> KzValue {
+    if d.scale > MAX_SCALE {
+        runtime_error(&format!("Decimal scale {} is too large (maximum is {})", d.scale, MAX_SCALE));
+    }
+    value(KzKind::Dec, KzPayload { dec: KzDec { units: d.units, scale: d.scale as c_int } })
+}
+
+fn decimal_parse(text: &[u8]) -> Dec {
+    let shown = lossy(text);
+    let cannot_convert = || -> ! { runtime_error(&format!("Cannot convert '{}' to a decimal", shown)) };
+    let is_space = |c: &u8| *c == b' ' || (b'\t'..=b'\r').contains(c);
+    let start = text.iter().position(|c| !is_space(c)).unwrap_or(text.len());
+    let end = text.iter().rposition(|c| !is_space(c)).map_or(start, |i| i + 1);
+    let mut digits = &text[start..end];
+    let negative = digits.first() == Some(&b'-');
+    if matches!(digits.first(), Some(b'-' | b'+')) {
+        digits = &digits[1..];
+    }
+
+    let mut scale = 0;
+    let mut int_digits = 0;
+    let mut after_point = false;
+    for &c in digits {
+        if c == b'.' && !after_point {
+            after_point = true;
+        } else if c.is_ascii_digit() {
+            if after_point {
+                scale += 1;
+            } else {
+                int_digits += 1;
+            }
+        } else {
+            cannot_convert();
+        }
+    }
+    if int_digits == 0 && scale == 0 {
+        cannot_convert();
+    }
+    let mut units: i64 = 0;
+    for &c in digits.iter().filter(|c| **c != b'.') {
+        let digit = (c - b'0') as i64;
+        if units > (i64::MAX - digit) / 10 {
+            runtime_error(&format!("Decimal value '{}' is out of range", shown));
+        }
+        units = units * 10 + digit;
+    }
+    Dec { units: if negative { -units } else { units }, scale }
+}
+
+fn decimal_from_fp(fp: f64) -> Dec {
+    let text = fp.to_string();
+    if !fp.is_finite() {
+        runtime_error(&format!("Cannot convert {} to a decimal", text));
+    }
+    decimal_parse(text.as_bytes())
+}
+
+fn rounds_away(mode: RoundingMode, quotient: i64, remainder: u64, divisor: u64, negative: bool) -> bool {
+    if remainder == 0 {
+        return false;
+    }
+    match mode {
+        RoundingMode::Up => true,
+        RoundingMode::Down => false,
+        RoundingMode::Ceiling => !negative,
+        RoundingMode::Floor => negative,
+        RoundingMode::HalfUp => remainder >= divisor - remainder,
+        RoundingMode::HalfDown => remainder > divisor - remainder,
+        RoundingMode::HalfEven => {
+            remainder > divisor - remainder || (remainder == divisor - remainder && quotient % 2 != 0)
+        }
+    }
+}
+
+fn div_round(numerator: i64, divisor: i64, mode: RoundingMode) -> i64 {
+    if divisor == 0 {
+        runtime_error("Division by zero");
+    }
+    let quotient = checked(numerator.checked_div(divisor));
+    let negative = (numerator < 0) != (divisor < 0);
+    if rounds_away(mode, quotient, (numerator % divisor).unsigned_abs(), divisor.unsigned_abs(), negative) {
+        checked(if negative { quotient.checked_sub(1) } else { quotient.checked_add(1) })
+    } else {
+        quotient
+    }
+}
+
+fn rescale(d: Dec, scale: u32, mode: RoundingMode) -> Dec {
+    match scale.cmp(&d.scale) {
+        Ordering::Equal => d,
+        Ordering::Greater => Dec { units: checked(d.units.checked_mul(pow10(scale - d.scale))), scale },
+        Ordering::Less => Dec { units: div_round(d.units, pow10(d.scale - scale), mode), scale },
+    }
+}
+
+// Both values' units at their common scale, or None when that overflows
+fn try_align(a: Dec, b: Dec) -> Option<(i64, i64, u32)> {
+    let scale = a.scale.max(b.scale);
+    let a_units = a.units.checked_mul(pow10(scale - a.scale))?;
+    let b_units = b.units.checked_mul(pow10(scale - b.scale))?;
+    Some((a_units, b_units, scale))
+}
+
+fn align(a: Dec, b: Dec) -> (i64, i64, u32) {
+    try_align(a, b).unwrap_or_else(|| decimal_overflow())
+}
+
+fn decimal_compare(a: Dec, b: Dec) -> Ordering {
+    let (a, b, _) = align(a, b);
+    a.cmp(&b)
+}
+
+fn rescale_exact(d: Dec, scale: u32) -> Dec {
+    let rescaled = rescale(d, scale, RoundingMode::Down);
+    if decimal_compare(rescaled, d) != Ordering::Equal {
+        runtime_error(&format!(
+            "Value {} does not fit in scale {} without rounding\n\nTip: Use [ round value, {}, 'half_even' ] to choose how digits are dropped",
+            display_text(Value::Dec(d)),
+            scale,
+            scale
+        ));
+    }
+    rescaled
+}
+
+// Numbers widen to decimals losslessly; floats have to be converted explicitly
+fn decimal_operand(value: Value) -> Dec {
+    match value {
+        Value::Dec(d) => d,
+        Value::Number(n) => Dec { units: n, scale: 0 },
+        Value::Fp(_) => runtime_error("Cannot mix dec and fp values\n\nTip: Convert the fp value with [ dec value ] first"),
+        other => runtime_error(&format!("Expected a dec or number value, got '{}'", display_text(other))),
+    }
+}
+
+fn decimal_scale(scale: Value) -> u32 {
+    match scale {
+        Value::Number(n) if (0..=MAX_SCALE as i64).contains(&n) => n as u32,
+        Value::Number(n) => runtime_error(&format!("Decimal scale must be between 0 and {}, got {}", MAX_SCALE, n)),
+        _ => runtime_error("Decimal scale must be a number"),
+    }
+}
+
+fn rounding(mode: Value) -> RoundingMode {
+    let Value::Text(name) = mode else {
+        runtime_error("Rounding mode must be text such as 'half_up' or 'half_even'");
+    };
+    match name {
+        b"half_up" => RoundingMode::HalfUp,
+        b"half_down" => RoundingMode::HalfDown,
+        b"half_even" => RoundingMode::HalfEven,
+        b"up" => RoundingMode::Up,
+        b"down" => RoundingMode::Down,
+        b"ceiling" => RoundingMode::Ceiling,
+        b"floor" => RoundingMode::Floor,
+        _ => runtime_error(&format!(
+            "Unknown rounding mode '{}'\n\nTip: Valid rounding modes are 'half_up', 'half_down', 'half_even', 'up', 'down', 'ceiling' and 'floor'",
+            lossy(name)
+        )),
+    }
+}
+
+fn is_decimal(left: Value, right: Value) -> bool {
+    matches!(left, Value::Dec(_)) || matches!(right, Value::Dec(_))
+}
+
+// Operators
+
+fn check_none(left: Value, right: Value, operator: &str) {
+    if matches!(left, Value::None) || matches!(right, Value::None) {
+        runtime_error(&format!(
+            "Cannot use none with '{}'\n\nTip: Provide a default with ?? (e.g. $value ?? 0) or check it with is_none",
+            operator
+        ));
+    }
+}
+
+// Numbers stop the program instead of wrapping around
+fn integer(result: Option<i64>) -> i64 {
+    result.unwrap_or_else(|| runtime_error("Integer overflow"))
+}
+
+fn is_numeric(value: Value) -> bool {
+    matches!(value, Value::Number(_) | Value::Fp(_))
+}
+
+fn as_fp(value: Value) -> f64 {
+    match value {
+        Value::Fp(f) => f,
+        Value::Number(n) => n as f64,
+        _ => 0.0,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn kz_add(left: KzValue, right: KzValue) -> KzValue {
+    let (left, right) = (left.view(), right.view());
+    check_none(left, right, "+");
+    if is_decimal(left, right) {
+        if matches!(left, Value::Text(_)) || matches!(right, Value::Text(_)) {
+            return concat(left, right);
+        }
+        let (a, b, scale) = align(decimal_operand(left), decimal_operand(right));
+        return decimal(Dec { units: checked(a.checked_add(b)), scale });
+    }
+    match (left, right) {
+        (Value::Number(a), Value::Number(b)) => kz_number(integer(a.checked_add(b))),
+        _ if is_numeric(left) && is_numeric(right) => kz_fp(as_fp(left) + as_fp(right)),
+        (Value::Text(_), Value::Text(_)) => concat(left, right),
+        (Value::Text(_), other) | (other, Value::Text(_)) if is_numeric(other) => concat(left, right),
+        _ => runtime_error("Cannot add incompatible types"),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn kz_subtract(left: KzValue, right: KzValue) -> KzValue {
+    let (left, right) = (left.view(), right.view());
+    check_none(left, right, "-");
+    if is_decimal(left, right) {
+        let (a, b, scale) = align(decimal_operand(left), decimal_operand(right));
+        return decimal(Dec { units: checked(a.checked_sub(b)), scale });
+    }
+    match (left, right) {
+        (Value::Number(a), Value::Number(b)) => kz_number(integer(a.checked_sub(b))),
+        _ if is_numeric(left) && is_numeric(right) => kz_fp(as_fp(left) - as_fp(right)),
+        (Value::Text(t), Value::Number(n)) => match (parse_int(t), parse_float(t)) {
+            (Some(a), _) => kz_number(integer(a.checked_sub(n))),
+            (None, Some(f)) => kz_fp(f - n as f64),
+            _ => runtime_error(&format!("Cannot subtract from text: {}", lossy(t))),
+        },
+        (Value::Number(n), Value::Text(t)) => match (parse_int(t), parse_float(t)) {
+            (Some(b), _) => kz_number(integer(n.checked_sub(b))),
+            (None, Some(f)) => kz_fp(n as f64 - f),
+            _ => runtime_error(&format!("Cannot subtract text: {}", lossy(t))),
+        },
+        (Value::Text(t), Value::Fp(b)) => match parse_float(t) {
+            Some(a) => kz_fp(a - b),
+            None => runtime_error(&format!("Cannot subtract float from text: {}", lossy(t))),
+        },
+        (Value::Fp(a), Value::Text(t)) => match parse_float(t) {
+            Some(b) => kz_fp(a - b),
+            None => runtime_error(&format!("Cannot subtract text from float: {}", lossy(t))),
+        },
+        _ => runtime_error("Cannot subtract incompatible types"),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn kz_multiply(left: KzValue, right: KzValue) -> KzValue {
+    let (left, right) = (left.view(), right.view());
+    check_none(left, right, "*");
+    if is_decimal(left, right) {
+        let (a, b) = (decimal_operand(left), decimal_operand(right));
+        return decimal(Dec { units: checked(a.units.checked_mul(b.units)), scale: a.scale + b.scale });
+    }
+    match (left, right) {
+        (Value::Number(a), Value::Number(b)) => kz_number(integer(a.checked_mul(b))),
+        _ if is_numeric(left) && is_numeric(right) => kz_fp(as_fp(left) * as_fp(right)),
+        (Value::Text(t), Value::Number(n)) if n >= 0 => {
+            if (t.len() as i64).checked_mul(n).is_none() {
+                runtime_error("Out of memory");
+            }
+            text_bytes(t.repeat(n as usize))
+        }
+        _ => runtime_error("Cannot multiply these values"),
+    }
+}
+
+fn check_decimal_division(left: Value, right: Value) {
+    if is_decimal(left, right) {
+        runtime_error("Decimal division needs a scale and a rounding mode\n\nTip: Use [ div a, b, 2, 'half_even' ] instead of '/'");
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn kz_divide(left: KzValue, right: KzValue) -> KzValue {
+    let (left, right) = (left.view(), right.view());
+    check_none(left, right, "/");
+    check_decimal_division(left, right);
+    if !is_numeric(left) || !is_numeric(right) {
+        runtime_error("Cannot divide non-numeric values");
+    }
+    if as_fp(right) == 0.0 {
+        runtime_error("Division by zero");
+    }
+    match (left, right) {
+        (Value::Number(a), Value::Number(b)) => kz_number(integer(a.checked_div(b))),
+        _ => kz_fp(as_fp(left) / as_fp(right)),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn kz_modulo(left: KzValue, right: KzValue) -> KzValue {
+    let (left, right) = (left.view(), right.view());
+    check_none(left, right, "%");
+    check_decimal_division(left, right);
+    if !is_numeric(left) || !is_numeric(right) {
+        runtime_error("Cannot perform modulo on non-numeric values");
+    }
+    if as_fp(right) == 0.0 {
+        runtime_error("Modulo by zero");
+    }
+    match (left, right) {
+        (Value::Number(a), Value::Number(b)) => kz_number(integer(a.checked_rem(b))),
+        _ => kz_fp(as_fp(left) % as_fp(right)),
+    }
+}
+
+// Equality inside arrays, where a number never equals a float
+fn same(left: Value, right: Value) -> bool {
+    match (left, right) {
+        (Value::None, Value::None) => true,
+        (Value::Number(a), Value::Number(b)) => a == b,
+        (Value::Fp(a), Value::Fp(b)) => a == b,
+        // Values too large to align are not equal, as in Decimal's PartialEq
+        (Value::Dec(a), Value::Dec(b)) => try_align(a, b).is_some_and(|(a, b, _)| a == b),
+        (Value::Text(a), Value::Text(b)) => a == b,
+        (Value::Bool(a), Value::Bool(b)) => a == b,
+        (Value::Array(a), Value::Array(b)) | (Value::Array2D(a), Value::Array2D(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same(a.view(), b.view()))
+        }
+        _ => false,
+    }
+}
+
+fn equal(left: Value, right: Value) -> bool {
+    if matches!(left, Value::None) || matches!(right, Value::None) {
+        return same(left, right);
+    }
+    if is_decimal(left, right) {
+        let other = if matches!(left, Value::Dec(_)) { right } else { left };
+        if matches!(other, Value::Text(_) | Value::Bool(_) | Value::Array(_) | Value::Array2D(_)) {
+            return false;
+        }
+        return decimal_compare(decimal_operand(left), decimal_operand(right)) == Ordering::Equal;
+    }
+    match (left, right) {
+        (Value::Number(a), Value::Number(b)) => a == b,
+        _ if is_numeric(left) && is_numeric(right) => as_fp(left) == as_fp(right),
+        (Value::Fp(_), _) => false,
+        _ => same(left, right),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn kz_equal(left: KzValue, right: KzValue) -> KzValue {
+    kz_bool(equal(left.view(), right.view()) as c_int)
+}
+
+#[no_mangle]
+pub extern "C" fn kz_not_equal(left: KzValue, right: KzValue) -> KzValue {
+    kz_bool(!equal(left.view(), right.view()) as c_int)
+}
+
+// The ordering operators need values of the same kind; NaN is unordered and compares false
+fn order(left: KzValue, right: KzValue, operator: &str) -> Option<Ordering> {
+    let (left, right) = (left.view(), right.view());
+    check_none(left, right, operator);
+    if is_decimal(left, right) {
+        return Some(decimal_compare(decimal_operand(left), decimal_operand(right)));
+    }
+    match (left, right) {
+        (Value::Number(a), Value::Number(b)) => Some(a.cmp(&b)),
+        _ if is_numeric(left) && is_numeric(right) => as_fp(left).partial_cmp(&as_fp(right)),
+        (Value::Text(a), Value::Text(b)) => Some(a.cmp(b)),
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(&b)),
+        _ => runtime_error("Cannot compare different types"),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn kz_less(left: KzValue, right: KzValue) -> KzValue {
+    kz_bool(matches!(order(left, right, "<"), Some(Ordering::Less)) as c_int)
+}
+
+#[no_mangle]
+pub extern "C" fn kz_less_equal(left: KzValue, right: KzValue) -> KzValue {
+    kz_bool(matches!(order(left, right, "<="), Some(Ordering::Less | Ordering::Equal)) as c_int)
+}
+
+#[no_mangle]
+pub extern "C" fn kz_greater(left: KzValue, right: KzValue) -> KzValue {
+    kz_bool(matches!(order(left, right, ">"), Some(Ordering::Greater)) as c_int)
+}
+
+#[no_mangle]
+pub extern "C" fn kz_greater_equal(left: KzValue, right: KzValue) -> KzValue {
+    kz_bool(matches!(order(left, right, ">="), Some(Ordering::Greater | Ordering::Equal)) as c_int)
+}
+
+#[no_mangle]
+pub extern "C" fn kz_negate(value: KzValue) -> KzValue {
+    match value.view() {
+        Value::Number(n) => kz_number(integer(n.checked_neg())),
+        Value::Fp(f) => kz_fp(-f),
+        Value::Dec(d) => decimal(Dec { units: checked(d.units.checked_neg()), scale: d.scale }),
+        Value::Text(t) => match (parse_int(t), parse_float(t)) {
+            (Some(n), _) => kz_number(integer(n.checked_neg())),
+            (None, Some(f)) => kz_fp(-f),
+            _ => runtime_error(&format!("Cannot negate text value: {}", lossy(t))),
+        },
+        _ => runtime_error("Cannot negate non-numeric value"),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn kz_not(value: KzValue) -> KzValue {
+    kz_bool(!truthy(value.view()) as c_int)
+}
+
+// Commands
+
+// Conversions never turn a missing value into 0 or ''
+fn none_conversion_error(command: &str) -> ! {
+    runtime_error(&format!(
+        "Cannot convert none with {}\n\nTip: Provide a default with ?? (e.g. $value ?? 0) before converting",
+        command
+    ))
+}
+
+#[no_mangle]
+pub extern "C" fn kz_to_number(value: KzValue) -> KzValue {
+    match value.view() {
+        Value::Number(_) => value,
+        Value::Text(t) => match parse_int(t) {
+            Some(n) => kz_number(n),
+            None => runtime_error(&format!("Cannot convert '{}' to a number", lossy(t))),
+        },
+        Value::Bool(b) => kz_number(b as i64),
+        Value::None => none_conversion_error("number"),
+        _ => runtime_error("Expected number, text or boolean"),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn kz_to_text(value: KzValue) -> KzValue {
+    match value.view() {
+        Value::Text(_) => value,
+        shown @ (Value::Number(_) | Value::Dec(_) | Value::Bool(_)) => text_value(display_text(shown)),
+        Value::None => none_conversion_error("text"),
+        _ => runtime_error("Expected text, number or boolean"),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn kz_to_fp(value: KzValue) -> KzValue {
+    match value.view() {
+        Value::Fp(_) => value,
+        Value::Number(n) => kz_fp(n as f64),
+        shown @ Value::Dec(_) => kz_fp(display_text(shown).parse().unwrap_or(0.0)),
+        Value::Text(t) => match parse_float(t) {
+            Some(f) => kz_fp(f),
+            None => runtime_error(&format!("Cannot convert '{}' to a floating point number", lossy(t))),
+        },
+        Value::Bool(b) => kz_fp(if b { 1.0 } else { 0.0 }),
+        Value::None => none_conversion_error("fp"),
+        _ => runtime_error("Expected number, text or boolean"),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn kz_to_bool(value: KzValue) -> KzValue {
+    match value.view() {
+        Value::Bool(_) => value,
+        number @ (Value::Number(_) | Value::Fp(_) | Value::Dec(_)) => kz_bool(truthy(number) as c_int),
+        Value::Text(b"true") => kz_bool(1),
+        Value::Text(b"false") => kz_bool(0),
+        Value::Text(t) => kz_bool(!t.is_empty() as c_int),
+        _ => runtime_error("Expected boolean, number or text"),
+    }
+}
+
+fn to_dec(value: Value) -> Dec {
+    match value {
+        Value::Dec(d) => d,
+        Value::Number(n) => Dec { units: n, scale: 0 },
+        Value::Fp(f) => decimal_from_fp(f),
+        Value::Text(t) => decimal_parse(t),
+        Value::None => none_conversion_error("dec"),
+        _ => runtime_error("Expected number, fp or text for dec"),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn kz_to_dec(value: KzValue) -> KzValue {
+    decimal(to_dec(value.view()))
+}
+
+#[no_mangle]
+pub extern "C" fn kz_to_dec_scale(value: KzValue, scale: KzValue) -> KzValue {
+    let converted = to_dec(value.view());
+    decimal(rescale_exact(converted, decimal_scale(scale.view())))
+}
+
+#[no_mangle]
+pub extern "C" fn kz_dec_div(dividend: KzValue, divisor: KzValue, scale: KzValue, mode: KzValue) -> KzValue {
+    let a = decimal_operand(dividend.view());
+    let b = decimal_operand(divisor.view());
+    let target = decimal_scale(scale.view());
+    let mode = rounding(mode.view());
+    if b.units == 0 {
+        runtime_error("Division by zero");
+    }
+    // a / b = (a.units / 10^a.scale) / (b.units / 10^b.scale); bring the numerator up to the target scale
+    let shift = target as i64 + b.scale as i64 - a.scale as i64;
+    let (numerator, denominator) = if shift >= 0 {
+        (checked(a.units.checked_mul(pow10(shift as u32))), b.units)
+    } else {
+        (a.units, checked(b.units.checked_mul(pow10(-shift as u32))))
+    };
+    decimal(Dec { units: div_round(numerator, denominator, mode), scale: target })
+}
+
+#[no_mangle]
+pub extern "C" fn kz_dec_round(value: KzValue, scale: KzValue, mode: KzValue) -> KzValue {
+    let converted = decimal_operand(value.view());
+    let target = decimal_scale(scale.view());
+    decimal(rescale(converted, target, rounding(mode.view())))
+}
+
+// hex and bin of text; the prefix is optional, as in the interpreter
+fn parse_prefixed(value: KzValue, radix: u32, prefixes: [&str; 2], name: &str) -> KzValue {
+    match value.view() {
+        Value::Number(_) => value,
+        Value::Text(t) => {
+            let text = lossy(t);
+            let digits = text.trim_start_matches(prefixes[0]).trim_start_matches(prefixes[1]);
+            match i64::from_str_radix(digits, radix) {
+                Ok(n) => kz_number(n),
+                Err(_) => runtime_error(&format!("Cannot parse '{}' as {}", text, name)),
+            }
+        }
+        _ => runtime_error(&format!("Expected {} string", name)),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn kz_hex(value: KzValue) -> KzValue {
+    parse_prefixed(value, 16, ["0x", "0X"], "hexadecimal")
+}
+
+#[no_mangle]
+pub extern "C" fn kz_bin(value: KzValue) -> KzValue {
+    parse_prefixed(value, 2, ["0b", "0B"], "binary")
+}
+
+#[no_mangle]
+pub extern "C" fn kz_asc(value: KzValue) -> KzValue {
+    let code = match value.view() {
+        Value::Number(n) => n,
+        Value::Text(t) => {
+            parse_int(t).unwrap_or_else(|| runtime_error(&format!("Cannot convert '{}' to ASCII code", lossy(t))))
+        }
+        _ => runtime_error("Expected number for ASCII code"),
+    };
+    match char::from_u32(code as u32) {
+        Some(c) => text_value(c.to_string()),
+        None => runtime_error(&format!("Invalid ASCII code: {}", code)),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn kz_length(value: KzValue) -> KzValue {
+    match value.view() {
+        Value::Array(items) | Value::Array2D(items) => kz_number(items.len() as i64),
+        Value::Text(t) => kz_number(t.len() as i64),
+        _ => runtime_error("Cannot get length of non-array/non-text value"),
+    }
+}
+
+fn row(value: &KzValue) -> &'static [KzValue] {
+    match value.view() {
+        Value::Array(items) => items,
+        _ => &[],
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn kz_width(value: KzValue) -> KzValue {
+    match value.view() {
+        Value::Array2D(rows) => kz_number(rows.first().map_or(0, |first| row(first).len() as i64)),
+        _ => runtime_error("Cannot get width of non-2D array"),
+    }
+}
+
+// A negative index wraps to a huge one, as with Rust's `as usize`
+#[no_mangle]
+pub extern "C" fn kz_get(array: KzValue, index: KzValue) -> KzValue {
+    let Value::Array(items) = array.view() else {
+        runtime_error("First argument to get must be an array");
+    };
+    let Value::Number(index) = index.view() else {
+        runtime_error("Array index must be a number");
+    };
+    match items.get(index as u64 as usize) {
+        Some(item) => *item,
+        None => runtime_error(&format!("Array index out of bounds: {} (length: {})", index as u64, items.len())),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn kz_get2d(array: KzValue, row_index: KzValue, column: KzValue) -> KzValue {
+    let Value::Array2D(rows) = array.view() else {
+        runtime_error("First argument to get2d must be a 2D array");
+    };
+    let (Value::Number(row_index), Value::Number(column)) = (row_index.view(), column.view()) else {
+        runtime_error("Array indices must be numbers");
+    };
+    let Some(cells) = rows.get(row_index as u64 as usize).map(row) else {
+        runtime_error(&format!("Row index out of bounds: {} (array height: {})", row_index as u64, rows.len()));
+    };
+    match cells.get(column as u64 as usize) {
+        Some(cell) => *cell,
+        None => runtime_error(&format!("Column index out of bounds: {} (row length: {})", column as u64, cells.len())),
+    }
+}
+
+// main's value as the exit status: a number from 0 to 255 is the status, 'ok' is 0, other
+// text and numbers outside 0 to 255 are reported on stderr with status 1 and anything else is 0
+#[no_mangle]
+pub extern "C" fn kz_exit_status(value: KzValue) -> c_int {
+    match value.view() {
+        Value::Number(n) => koze_exit_code(n) as c_int,
+        Value::Text(b"ok") => 0,
+        Value::Text(t) => {
+            print_error(&format!("{}\n", lossy(t)));
+            1
+        }
+        _ => 0,
+    }
+}
+
+// print [ a, b ] writes the values separated by spaces and a newline
+#[no_mangle]
+pub unsafe extern "C" fn kz_print(count: c_int, values: *const KzValue) {
+    let mut out = String::new();
+    for (i, value) in array_items(count as i64, values).into_iter().enumerate() {
+        if i > 0 {
+            out.push(' ');
+        }
+        display(&mut out, value.view());
+    }
+    out.push('\n');
+    print(&out);
+}
+
+// A koze function's argument in its parameter's type, as in the interpreter's
+// parameter_value: a number passed as fp is converted, fp is never truncated to a number
+#[no_mangle]
+pub unsafe extern "C" fn kz_parameter(value: KzValue, type_name: *const c_char, argument: c_int, function: *const c_char) -> KzValue {
+    let type_name = text(type_name);
+    match (&*type_name, value.view()) {
+        (_, Value::None) => value,
+        ("fp", Value::Number(n)) => kz_fp(n as f64),
+        ("fp", Value::Fp(_))
+        | ("number", Value::Number(_) | Value::Bool(_))
+        | ("bool", Value::Bool(_))
+        | ("text", Value::Text(_))
+        | ("array", Value::Array(_) | Value::Array2D(_))
+        | ("dec", Value::Dec(_)) => value,
+        ("bool", Value::Number(n)) => kz_bool((n != 0) as c_int),
+        ("fp", _) => argument_mismatch(argument, function, "fp"),
+        ("number", _) => argument_mismatch(argument, function, "a number"),
+        ("bool", _) => argument_mismatch(argument, function, "bool"),
+        ("text", _) => argument_mismatch(argument, function, "text"),
+        ("array", _) => argument_mismatch(argument, function, "an array"),
+        ("dec", _) => argument_mismatch(argument, function, "a dec"),
+        _ => value,
+    }
+}
+
+// Arguments and results of extern func declarations
+
+unsafe fn argument_mismatch(argument: c_int, function: *const c_char, type_name: &str) -> ! {
+    runtime_error(&format!("Argument {} of {} must be {}", argument, text(function), type_name))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn kz_c_number(value: KzValue, argument: c_int, function: *const c_char) -> i64 {
+    match value.view() {
+        Value::Number(n) => n,
+        Value::Bool(b) => b as i64,
+        _ => argument_mismatch(argument, function, "a number"),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn kz_c_fp(value: KzValue, argument: c_int, function: *const c_char) -> f64 {
+    match value.view() {
+        number @ (Value::Number(_) | Value::Fp(_)) => as_fp(number),
+        _ => argument_mismatch(argument, function, "fp"),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn kz_c_bool(value: KzValue, argument: c_int, function: *const c_char) -> bool {
+    match value.view() {
+        Value::Number(n) => n != 0,
+        Value::Bool(b) => b,
+        _ => argument_mismatch(argument, function, "bool"),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn kz_c_text(value: KzValue, argument: c_int, function: *const c_char) -> *const c_char {
+    match value.view() {
+        Value::None => std::ptr::null(),
+        Value::Text(t) if t.contains(&0) => {
+            runtime_error(&format!("Argument {} of {} contains a NUL character", argument, text(function)))
+        }
+        Value::Text(_) => value.payload.text.data,
+        _ => argument_mismatch(argument, function, "text"),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn kz_from_c_text(value: *const c_char) -> KzValue {
+    if value.is_null() {
+        return kz_none();
+    }
+    text_bytes(CStr::from_ptr(value).to_bytes().to_vec())
+}
@@ -0,0 +1,149 @@
+// Options of koze build and koze jit, shared by the LLVM and C backends
+
+// Optimization level chosen with -O0 ... -O3 / -Os on koze build and koze jit
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OptLevel {
+    O0,
+    O1,
+    #[default]
+    O2,
+    O3,
+    Os,
+}
+
+impl OptLevel {
+    pub fn from_flag(flag: &str) -> Option<Self> {
+        match flag {
+            "-O0" => Some(OptLevel::O0),
+            "-O1" => Some(OptLevel::O1),
+            "-O2" => Some(OptLevel::O2),
+            "-O3" => Some(OptLevel::O3),
+            "-Os" => Some(OptLevel::Os),
+            _ => None,
+        }
+    }
+
+    // Also the flag handed to cc by the C backend
+    pub fn flag(self) -> &'static str {
+        match self {
+            OptLevel::O0 => "-O0",
+            OptLevel::O1 => "-O1",
+            OptLevel::O2 => "-O2",
+            OptLevel::O3 => "-O3",
+            OptLevel::Os => "-Os",
+        }
+    }
+}
+
+// Code generator chosen with --backend; C needs only a C compiler instead of LLVM 16
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    Llvm,
+    C,
+}
+
+impl Backend {
+    pub fn from_flag(value: &str) -> Option<Self> {
+        match value {
+            "llvm" => Some(Backend::Llvm),
+            "c" => Some(Backend::C),
+            _ => None,
+        }
+    }
+}
+
+// koze built without the llvm feature can only translate to C
+impl Default for Backend {
+    fn default() -> Self {
+        if cfg!(feature = "llvm") {
+            Backend::Llvm
+        } else {
+            Backend::C
+        }
+    }
+}
+
+// What koze build writes, chosen with --emit
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Emit {
+    LlvmIr,
+    LlvmBc,
+    Asm,
+    Obj,
+    C,
+    #[default]
+    Exe,
+}
+
+impl Emit {
+    pub fn from_flag(value: &str) -> Option<Self> {
+        match value {
+            "llvm-ir" => Some(Emit::LlvmIr),
+            "llvm-bc" => Some(Emit::LlvmBc),
+            "asm" => Some(Emit::Asm),
+            "obj" => Some(Emit::Obj),
+            "c" => Some(Emit::C),
+            "exe" => Some(Emit::Exe),
+            _ => None,
+        }
+    }
+
+    // Written next to where koze runs when there is no -o
+    pub fn default_output(self, stem: &str) -> String {
+        match self {
+            Emit::LlvmIr => format!("{}.ll", stem),
+            Emit::LlvmBc => format!("{}.bc", stem),
+            Emit::Asm => format!("{}.s", stem),
+            Emit::Obj => format!("{}.o", stem),
+            Emit::C => format!("{}.c", stem),
+            Emit::Exe => stem.to_string(),
+        }
+    }
+
+    pub fn describe(self) -> &'static str {
+        match self {
+            Emit::LlvmIr => "LLVM IR",
+            Emit::LlvmBc => "LLVM bitcode",
+            Emit::Asm => "assembly",
+            Emit::Obj => "object file",
+            Emit::C => "C source",
+            Emit::Exe => "executable",
+        }
+    }
+}
+
+// koze build --lib: a shared or static library exporting the pub functions to C
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LibKind {
+    Shared,
+    Static,
+}
+
+impl LibKind {
+    #[cfg(feature = "llvm")]
+    pub fn describe(self) -> &'static str {
+        match self {
+            LibKind::Shared => "shared library",
+            LibKind::Static => "static library",
+        }
+    }
+}
+
+// Settings for koze build; koze jit always compiles for the host
+#[derive(Debug, Clone, Default)]
+pub struct BuildOptions {
+    pub opt_level: OptLevel,
+    pub debug_info: bool,
+    pub backend: Backend,
+    pub emit: Emit,
+    pub lib: Option<LibKind>,
+    // -o; Emit::default_output when not given
+    pub output: Option<String>,
+    // LLVM target triple, CPU and feature string; the host's when not given
+    pub target: Option<String>,
+    pub cpu: Option<String>,
+    pub features: Option<String>,
+    // Command for the final link step, e.g. "aarch64-linux-gnu-gcc" or "zig cc".
+    // The C backend compiles with it too
+    pub linker: Option<String>,
+}
@@ -0,0 +1,1364 @@
+// C backend for koze build: the program is translated to C and compiled with the
+// system C compiler, so building needs no LLVM. Values stay dynamically typed, like in
+// the interpreter; the runtime library implements every operation with its rules
+use crate::build_options::{BuildOptions, Emit};
+use crate::dependency_manager::{DependencyManager, LoadedModule, ResolvedImport};
+use crate::error_reporting::LutError;
+use crate::foreign;
+use crate::lexer::{Lexer, TokenType};
+use crate::parser::{Expr, FunctionParam, ImportItem, Parser, Stmt};
+use crate::runtime_library::{is_wasm, runtime_system_libs, write_runtime_library, HOST_TARGET};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const RUNTIME_HEADER: &str = include_str!("../runtime/c/koze_runtime.h");
+// Parameter types kz_parameter checks arguments against
+const TYPED_PARAMETERS: &[&str] = &["fp", "number", "bool", "text", "array", "dec"];
+
+// What a function name in koze code calls
+#[derive(Debug, Clone)]
+enum Callee {
+    Koze { c_name: String, arity: usize },
+    // extern func, called under the C function's own name
+    Extern { name: String, parameters: Vec<FunctionParam>, return_type: Option<String> },
+}
+
+#[derive(Debug, Clone)]
+struct Variable {
+    c_name: String,
+    constant: bool,
+}
+
+// Names the code of one file can use
+#[derive(Debug, Default)]
+struct Scope {
+    functions: HashMap<String, Callee>,
+    variables: HashMap<String, Variable>,
+    namespaces: HashMap<String, String>,
+}
+
+// The C function being written
+#[derive(Debug, Default)]
+struct FunctionBody {
+    code: String,
+    indent: usize,
+    temporaries: usize,
+    branches: usize,
+    parameters: HashMap<String, String>,
+    loop_depth: usize,
+}
+
+// Every name a file assigns is a C global, since the interpreter keeps one environment
+// per file; parameters are the only C locals
+struct CGenerator {
+    file_path: String,
+    debug_info: bool,
+    dependency_manager: Option<DependencyManager>,
+    entry: Scope,
+    modules: HashMap<String, Scope>,
+    module_prefixes: HashSet<String>,
+    // Module whose code is being translated, None for the file being built
+    current_module: Option<String>,
+    // Source file of the code being translated, for #line
+    source_file: String,
+    // File and line that runtime errors of the code being translated report
+    location_file: String,
+    current_line: Option<usize>,
+    // C functions from extern func with their parameter and return types, and the libraries to link
+    externs: HashMap<String, Vec<String>>,
+    libraries: Vec<String>,
+    prototypes: String,
+    globals: String,
+    definitions: String,
+    // Initializers of modules loaded by the import being translated
+    module_inits: Vec<String>,
+    body: FunctionBody,
+}
+
+impl CGenerator {
+    fn new(file_path: &str, debug_info: bool) -> Self {
+        CGenerator {
+            file_path: file_path.to_string(),
+            debug_info,
+            dependency_manager: None,
+            entry: Scope::default(),
+            modules: HashMap::new(),
+            module_prefixes: HashSet::new(),
+            current_module: None,
+            source_file: file_path.to_string(),
+            location_file: file_path.to_string(),
+            current_line: None,
+            externs: HashMap::new(),
+            libraries: Vec::new(),
+            prototypes: String::new(),
+            globals: String::new(),
+            definitions: String::new(),
+            module_inits: Vec::new(),
+            body: FunctionBody::default(),
+        }
+    }
+
+    // The whole program as one C file
+    fn translate(&mut self, statements: &[Stmt]) -> Result<String, LutError> {
+        // Imports cannot bind over functions of the file itself
+        let local_names: HashSet<String> = statements
+            .iter()
+            .filter_map(|stmt| match stmt {
+                Stmt::Function { name, .. } | Stmt::Extern { name, .. } => Some(name.clone()),
+                _ => None,
+            })
+            .collect();
+
+        // Imported modules are translated first; their initializers run where the `use` is
+        let mut import_inits: HashMap<usize, Vec<String>> = HashMap::new();
+        for (index, stmt) in statements.iter().enumerate() {
+            if let Stmt::Import { functions, module_path, namespace, wildcard, line } = stmt {
+                self.current_line = Some(*line);
+                self.import(None, &local_names, functions, module_path, namespace.as_deref(), *wildcard, *line)?;
+                import_inits.insert(index, std::mem::take(&mut self.module_inits));
+            }
+        }
+        self.current_line = None;
+
+        for stmt in statements {
+            if let Stmt::Extern { name, line, .. } = stmt {
+                if self.entry.functions.contains_key(name) {
+                    return Err(LutError::compiler_error(format!("Function '{}' is already defined", name), Some(*line)));
+                }
+                let callee = self.declare_extern(stmt)?;
+                self.entry.functions.insert(name.clone(), callee);
+            }
+        }
+        for stmt in statements {
+            if let Stmt::Function { name, parameters, line, .. } = stmt {
+                match self.entry.functions.get(name) {
+                    Some(Callee::Extern { .. }) => {
+                        return Err(LutError::compiler_error(
+                            format!("Function '{}' is already declared with extern func\n\nTip: Rename the function", name),
+                            Some(*line),
+                        ));
+                    }
+                    Some(_) => {
+                        return Err(LutError::compiler_error(format!("Function '{}' is already defined", name), Some(*line)));
+                    }
+                    None => {}
+                }
+                let c_name = format!("f_{}", c_identifier(name));
+                self.declare_function(&c_name, parameters.len());
+                self.entry.functions.insert(name.clone(), Callee::Koze { c_name, arity: parameters.len() });
+            }
+        }
+
+        let mut constants = HashSet::new();
+        for stmt in statements {
+            if let Stmt::Const { name, line, .. } = stmt {
+                if !constants.insert(name.clone()) {
+                    return Err(LutError::compiler_error(format!("Constant '{}' is already defined", name), Some(*line)));
+                }
+                self.define_variable(name, format!("v_{}", c_identifier(name)), true);
+            }
+        }
+        let mut assigned = Vec::new();
+        collect_assigned(statements, &HashSet::new(), &mut assigned);
+        for name in assigned {
+            self.define_variable(&name, format!("v_{}", c_identifier(&name)), false);
+        }
+
+        for stmt in statements {
+            if let Stmt::Function { name, parameters, body, line, .. } = stmt {
+                let c_name = format!("f_{}", c_identifier(name));
+                self.function(name, &c_name, parameters, body, *line)?;
+            }
+        }
+
+        // Top-level code runs in order, then main is called
+        self.begin_function(HashMap::new());
+        for (index, stmt) in statements.iter().enumerate() {
+            match stmt {
+                Stmt::Function { .. } | Stmt::Extern { .. } => {}
+                Stmt::Import { line, .. } => {
+                    // Module constants report the `use` they are loaded by
+                    if !import_inits[&index].is_empty() {
+                        self.set_location(*line);
+                    }
+                    for init in &import_inits[&index] {
+                        self.emit(&format!("{}();", init));
+                    }
+                }
+                Stmt::Const { name, initializer, line, .. } => {
+                    self.line_directive(Some(*line));
+                    self.set_location(*line);
+                    let value = self.expression(initializer).map_err(|e| e.or_at_line(*line))?;
+                    let c_name = self.entry.variables[name].c_name.clone();
+                    self.emit(&format!("{} = {};", c_name, value));
+                }
+                _ => self.statement(stmt, None)?,
+            }
+        }
+        match self.entry.functions.get("main").cloned() {
+            Some(Callee::Koze { c_name, arity: 0 }) => {
+                self.emit(&format!("return kz_exit_status({}());", c_name));
+            }
+            Some(Callee::Koze { arity, .. }) => {
+                return Err(LutError::compiler_error(
+                    format!("Function 'main' expects {} arguments, but 0 were provided", arity),
+                    None,
+                ));
+            }
+            _ => self.emit("return 0;"),
+        }
+        let main = self.end_function("int main(void)", false);
+
+        let mut program = format!(
+            "/* Generated by koze build from {} */\n#include \"koze_runtime.h\"\n\n",
+            Path::new(&self.file_path).display()
+        );
+        for section in [&self.prototypes, &self.globals, &self.definitions] {
+            if !section.is_empty() {
+                program.push_str(section);
+                program.push('\n');
+            }
+        }
+        program.push_str(&main);
+        Ok(program)
+    }
+
+    // Resolve one `use` and bind what it imports. `scope` is the importing module, None for the file being built
+    #[allow(clippy::too_many_arguments)]
+    fn import(
+        &mut self,
+        scope: Option<&str>,
+        local_names: &HashSet<String>,
+        items: &[ImportItem],
+        module_path: &str,
+        namespace: Option<&str>,
+        wildcard: bool,
+        line: usize,
+    ) -> Result<(), LutError> {
+        if self.dependency_manager.is_none() {
+            let cwd = std::env::current_dir()
+                .map_err(|e| LutError::compiler_error(format!("Failed to get current directory: {}", e), None))?;
+            let mut dep_manager = DependencyManager::new(cwd);
+            let source_path = Path::new(&self.file_path);
+            if let Some(parent) = source_path.parent() {
+                dep_manager.set_current_file_dir(parent.to_path_buf());
+            }
+            dep_manager.set_entry_file(source_path);
+            self.dependency_manager = Some(dep_manager);
+        }
+
+        // A namespace can only stand for one module
+        if let Some(ns) = namespace {
+            if let Some(existing) = self.scope(scope).namespaces.get(ns) {
+                if existing != module_path {
+                    let message = format!(
+                        "Namespace '{}' is already used for '{}'\n\nTip: Import '{}' under a different name",
+                        ns, existing, module_path
+                    );
+                    return Err(self.import_error(scope, line, message));
+                }
+            }
+        }
+
+        let taken: HashSet<String> = self.scope(scope).functions.keys().chain(local_names.iter()).cloned().collect();
+
+        let dependency_manager = self.dependency_manager.as_mut().unwrap();
+        let resolved = dependency_manager.load_module(module_path).and_then(|module| {
+            let imported = match namespace {
+                Some(_) => Ok(module.exports()),
+                None if wildcard => dependency_manager.resolve_wildcard_import(&module, &taken),
+                None => dependency_manager.resolve_import(&module, items, &taken),
+            }?;
+            dependency_manager.enter_import(scope, line, module_path, &module)?;
+            Ok((module, imported))
+        });
+        let (module, imported) = resolved.map_err(|e| self.import_error(scope, line, e))?;
+
+        let translated = self.module(&module);
+        self.dependency_manager.as_mut().unwrap().exit_import();
+        match scope {
+            None => translated.map_err(|e| e.or_at_line(line))?,
+            Some(_) => translated?,
+        }
+
+        if let Some(ns) = namespace {
+            self.scope_mut(scope).namespaces.insert(ns.to_string(), module_path.to_string());
+        }
+        self.bind_imports(scope, &module, namespace, imported);
+        Ok(())
+    }
+
+    fn import_error(&self, scope: Option<&str>, line: usize, message: String) -> LutError {
+        match scope {
+            None => LutError::compiler_error(format!("Import error: {}", message), Some(line)),
+            Some(id) => {
+                let file = self
+                    .dependency_manager
+                    .as_ref()
+                    .map(|dm| dm.display_path(id))
+                    .unwrap_or_else(|| id.to_string());
+                LutError::compiler_error(format!("Import error in {} line {}: {}", file, line, message), None)
+            }
+        }
+    }
+
+    fn bind_imports(&mut self, scope: Option<&str>, module: &LoadedModule, namespace: Option<&str>, imported: ResolvedImport) {
+        let qualify = |name: &str| match namespace {
+            Some(ns) => format!("{}::{}", ns, name),
+            None => name.to_string(),
+        };
+
+        for imported_const in imported.constants {
+            let variable = self.modules[&module.id].variables[&imported_const.source_name].clone();
+            self.scope_mut(scope).variables.insert(qualify(&imported_const.name), variable);
+        }
+        for imported_func in imported.functions {
+            let callee = self.modules[&module.id].functions[&imported_func.source_name].clone();
+            self.scope_mut(scope).functions.insert(qualify(&imported_func.name), callee);
+        }
+    }
+
+    // Translate an imported module once: its functions get prefixed C names and its
+    // constants are set by an initializer, after those of the modules it imports
+    fn module(&mut self, module: &LoadedModule) -> Result<(), LutError> {
+        if self.modules.contains_key(&module.id) {
+            return Ok(());
+        }
+
+        let prefix = self.module_prefix(&module.id);
+        let mut scope = Scope::default();
+        for imported_func in &module.functions {
+            let c_name = format!("f_{}__{}", prefix, c_identifier(&imported_func.source_name));
+            self.declare_function(&c_name, imported_func.parameters.len());
+            let callee = Callee::Koze { c_name, arity: imported_func.parameters.len() };
+            scope.functions.insert(imported_func.source_name.clone(), callee);
+        }
+        for declaration in &module.externs {
+            if let Stmt::Extern { name, line, .. } = declaration {
+                if scope.functions.contains_key(name) {
+                    return Err(LutError::compiler_error(format!("Function '{}' is already defined", name), Some(*line)));
+                }
+                let callee = self.declare_extern(declaration)?;
+                scope.functions.insert(name.clone(), callee);
+            }
+        }
+        self.modules.insert(module.id.clone(), scope);
+
+        // Relative paths in the module are relative to the module itself
+        let dependency_manager = self.dependency_manager.as_mut().unwrap();
+        let saved_dir = dependency_manager.current_file_dir();
+        dependency_manager.set_current_file_dir(module.dir.clone());
+
+        let outer_inits = std::mem::take(&mut self.module_inits);
+        let local_names = HashSet::new();
+        let mut result = Ok(());
+        for stmt in &module.imports {
+            if let Stmt::Import { functions, module_path, namespace, wildcard, line } = stmt {
+                result = self.import(Some(&module.id), &local_names, functions, module_path, namespace.as_deref(), *wildcard, *line);
+                if result.is_err() {
+                    break;
+                }
+            }
+        }
+        let imported_inits = std::mem::replace(&mut self.module_inits, outer_inits);
+
+        self.dependency_manager.as_mut().unwrap().restore_current_file_dir(saved_dir);
+        result?;
+
+        for constant in &module.constants {
+            let c_name = format!("v_{}__{}", prefix, c_identifier(&constant.source_name));
+            self.modules.get_mut(&module.id).unwrap().variables.insert(
+                constant.source_name.clone(),
+                Variable { c_name: c_name.clone(), constant: true },
+            );
+            let _ = writeln!(self.globals, "static kz_value {};", c_name);
+        }
+        let mut assigned = Vec::new();
+        for imported_func in &module.functions {
+            let parameters = imported_func.parameters.iter().map(|param| param.name.clone()).collect();
+            collect_assigned(&imported_func.body, &parameters, &mut assigned);
+        }
+
+        let saved_module = self.current_module.replace(module.id.clone());
+        let saved_file = std::mem::replace(&mut self.source_file, module.file.display().to_string());
+        for name in assigned {
+            self.define_variable(&name, format!("v_{}__{}", prefix, c_identifier(&name)), false);
+        }
+        let result = self.module_code(module, &prefix, imported_inits);
+        self.current_module = saved_module;
+        self.source_file = saved_file;
+        result
+    }
+
+    fn module_code(&mut self, module: &LoadedModule, prefix: &str, imported_inits: Vec<String>) -> Result<(), LutError> {
+        if !imported_inits.is_empty() || !module.constants.is_empty() {
+            self.begin_function(HashMap::new());
+            for init in &imported_inits {
+                self.emit(&format!("{}();", init));
+            }
+            for constant in &module.constants {
+                let value = self.expression(&constant.initializer)?;
+                let c_name = self.modules[&module.id].variables[&constant.source_name].c_name.clone();
+                self.emit(&format!("{} = {};", c_name, value));
+            }
+            let init = format!("init_{}", prefix);
+            let definition = self.end_function(&format!("static void {}(void)", init), false);
+            self.definitions.push_str(&definition);
+            self.definitions.push('\n');
+            self.module_inits.push(init);
+        }
+
+        let display_file = self.dependency_manager.as_ref().unwrap().display_file(module);
+        let saved_location = (std::mem::replace(&mut self.location_file, display_file), self.current_line);
+        let mut result = Ok(());
+        for imported_func in &module.functions {
+            let c_name = format!("f_{}__{}", prefix, c_identifier(&imported_func.source_name));
+            result = self.function(
+                &imported_func.source_name,
+                &c_name,
+                &imported_func.parameters,
+                &imported_func.body,
+                imported_func.line,
+            );
+            if result.is_err() {
+                break;
+            }
+        }
+        (self.location_file, self.current_line) = saved_location;
+        result
+    }
+
+    // C name prefix for a module's functions and constants, unique within the build
+    fn module_prefix(&mut self, module_id: &str) -> String {
+        let stem = Path::new(module_id)
+            .file_stem()
+            .map(|stem| c_identifier(&stem.to_string_lossy()))
+            .unwrap_or_default();
+
+        let mut prefix = stem.clone();
+        let mut n = 1;
+        while !self.module_prefixes.insert(prefix.clone()) {
+            n += 1;
+            prefix = format!("{}{}", stem, n);
+        }
+        prefix
+    }
+
+    fn scope(&self, module: Option<&str>) -> &Scope {
+        match module {
+            Some(id) => &self.modules[id],
+            None => &self.entry,
+        }
+    }
+
+    fn scope_mut(&mut self, module: Option<&str>) -> &mut Scope {
+        match module {
+            Some(id) => self.modules.get_mut(id).unwrap(),
+            None => &mut self.entry,
+        }
+    }
+
+    // Scope of the code being translated
+    fn current_scope(&self) -> &Scope {
+        self.scope(self.current_module.as_deref())
+    }
+
+    fn define_variable(&mut self, name: &str, c_name: String, constant: bool) {
+        let module = self.current_module.clone();
+        let scope = self.scope_mut(module.as_deref());
+        if scope.variables.contains_key(name) {
+            return;
+        }
+        scope.variables.insert(name.to_string(), Variable { c_name: c_name.clone(), constant });
+        let _ = writeln!(self.globals, "static kz_value {};", c_name);
+    }
+
+    fn declare_function(&mut self, c_name: &str, arity: usize) {
+        let _ = writeln!(self.prototypes, "static kz_value {}({});", c_name, parameter_list(arity));
+    }
+
+    // Prototype for the C function under its own name. Two modules declaring the same
+    // C function share the prototype
+    fn declare_extern(&mut self, declaration: &Stmt) -> Result<Callee, LutError> {
+        let Stmt::Extern { name, parameters, return_type, library, line } = declaration else {
+            unreachable!("not an extern func declaration");
+        };
+        foreign::check_signature(name, parameters, return_type.as_deref())
+            .map_err(|e| LutError::compiler_error(e, Some(*line)))?;
+
+        let signature: Vec<String> = parameters
+            .iter()
+            .map(|param| param.param_type.clone())
+            .chain(std::iter::once(return_type.clone().unwrap_or_default()))
+            .collect();
+        match self.externs.get(name) {
+            Some(existing) if *existing != signature => {
+                return Err(LutError::compiler_error(
+                    format!("extern func '{}' does not match another declaration of '{}'\n\nTip: Declare it with the same types everywhere", name, name),
+                    Some(*line),
+                ));
+            }
+            Some(_) => {}
+            None => {
+                let params: Vec<&str> = parameters.iter().map(|param| c_type(&param.param_type, false)).collect();
+                let params = if params.is_empty() { "void".to_string() } else { params.join(", ") };
+                let returns = return_type.as_deref().map_or("void", |type_name| c_type(type_name, true));
+                let _ = writeln!(self.prototypes, "extern {} {}({});", returns, name, params);
+                self.externs.insert(name.clone(), signature);
+            }
+        }
+
+        if let Some(library) = library {
+            let arg = if library.contains('/') || library.contains('.') {
+                library.clone()
+            } else {
+                format!("-l{}", library)
+            };
+            if !self.libraries.contains(&arg) {
+                self.libraries.push(arg);
+                self.libraries.sort();
+            }
+        }
+        Ok(Callee::Extern { name: name.clone(), parameters: parameters.clone(), return_type: return_type.clone() })
+    }
+
+    fn begin_function(&mut self, parameters: HashMap<String, String>) {
+        self.body = FunctionBody { indent: 1, parameters, ..FunctionBody::default() };
+    }
+
+    // The C function around the translated body; a koze function returns `result`
+    fn end_function(&mut self, signature: &str, returns_result: bool) -> String {
+        let body = std::mem::take(&mut self.body);
+        let mut function = format!("{} {{\n", signature);
+        if returns_result {
+            function.push_str("    kz_value result = kz_none();\n");
+        }
+        if body.temporaries > 0 {
+            let temporaries: Vec<String> = (1..=body.temporaries).map(|n| format!("t{}", n)).collect();
+            let _ = writeln!(function, "    kz_value {};", temporaries.join(", "));
+        }
+        function.push_str(&body.code);
+        if returns_result {
+            function.push_str("    return result;\n");
+        }
+        function.push_str("}\n");
+        function
+    }
+
+    // A koze function's value is its last expression, or the value of a branch taken
+    // after it, as in the interpreter's evaluate_function_body
+    fn function(
+        &mut self,
+        name: &str,
+        c_name: &str,
+        parameters: &[FunctionParam],
+        body: &[Stmt],
+        line: usize,
+    ) -> Result<(), LutError> {
+        let c_parameters: HashMap<String, String> = parameters
+            .iter()
+            .map(|param| (param.name.clone(), format!("p_{}", c_identifier(&param.name))))
+            .collect();
+        let signature_parameters = if parameters.is_empty() {
+            "void".to_string()
+        } else {
+            parameters
+                .iter()
+                .map(|param| format!("kz_value {}", c_parameters[&param.name]))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        self.begin_function(c_parameters);
+        // Arguments take their parameter's type, as in the interpreter's call_function
+        for (i, param) in parameters.iter().enumerate() {
+            if TYPED_PARAMETERS.contains(&param.param_type.as_str()) {
+                let c_parameter = format!("p_{}", c_identifier(&param.name));
+                self.emit(&format!(
+                    "{p} = kz_parameter({p}, {}, {}, {});",
+                    c_string(&param.param_type),
+                    i + 1,
+                    c_string(name),
+                    p = c_parameter
+                ));
+            }
+        }
+        self.statements(body, Some("result"))?;
+        let mut definition = String::new();
+        if self.debug_info {
+            let _ = writeln!(definition, "#line {} {}", line, c_string(&self.source_file));
+        }
+        definition.push_str(&self.end_function(&format!("static kz_value {}({})", c_name, signature_parameters), true));
+        self.definitions.push_str(&definition);
+        self.definitions.push('\n');
+        Ok(())
+    }
+
+    fn emit(&mut self, line: &str) {
+        for _ in 0..self.body.indent {
+            self.body.code.push_str("    ");
+        }
+        self.body.code.push_str(line);
+        self.body.code.push('\n');
+    }
+
+    // With -g, debuggers and C compiler messages point at the .ko source
+    fn line_directive(&mut self, line: Option<usize>) {
+        if let (true, Some(line)) = (self.debug_info, line) {
+            let directive = format!("#line {} {}\n", line, c_string(&self.source_file));
+            self.body.code.push_str(&directive);
+        }
+    }
+
+    // Runtime errors from here on report this line
+    fn set_location(&mut self, line: usize) {
+        self.current_line = Some(line);
+        let location = self.location(line);
+        self.emit(&format!("{};", location));
+    }
+
+    fn location(&self, line: usize) -> String {
+        format!("KZ_AT({}, {})", c_string(&self.location_file), line)
+    }
+
+    fn temporary(&mut self) -> String {
+        self.body.temporaries += 1;
+        format!("t{}", self.body.temporaries)
+    }
+
+    // `result` receives the values of expression statements, None outside a function's value
+    fn statements(&mut self, statements: &[Stmt], result: Option<&str>) -> Result<(), LutError> {
+        for stmt in statements {
+            self.statement(stmt, result)?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, stmt: &Stmt, result: Option<&str>) -> Result<(), LutError> {
+        self.line_directive(stmt.line());
+        let outer_line = self.current_line;
+        if let Some(line) = stmt.line() {
+            self.set_location(line);
+        }
+        let translated = self.statement_code(stmt, result);
+        self.current_line = outer_line;
+        match (stmt.line(), &self.current_module) {
+            (Some(line), None) => translated.map_err(|e| e.or_at_line(line)),
+            _ => translated,
+        }
+    }
+
+    fn statement_code(&mut self, stmt: &Stmt, result: Option<&str>) -> Result<(), LutError> {
+        match stmt {
+            Stmt::Function { name, .. } => {
+                return Err(LutError::compiler_error(
+                    format!("Function '{}' must be defined at the top level of the file", name),
+                    None,
+                ));
+            }
+            Stmt::Extern { name, .. } => {
+                return Err(LutError::compiler_error(
+                    format!("extern func '{}' must be declared at the top level of the file", name),
+                    None,
+                ));
+            }
+            Stmt::Const { name, .. } => {
+                return Err(LutError::compiler_error(
+                    format!("Constant '{}' must be declared at the top level of the file", name),
+                    None,
+                ));
+            }
+            Stmt::Import { module_path, .. } => {
+                return Err(LutError::compiler_error(
+                    format!("use of '{}' must be at the top level of the file", module_path),
+                    None,
+                ));
+            }
+            Stmt::Declaration { name, initializer, .. } => {
+                let target = self.assignment_target(name)?;
+                let value = self.expression(initializer)?;
+                self.emit(&format!("{} = {};", target, value));
+            }
+            Stmt::Expression(expr, _) => {
+                let value = self.expression(expr)?;
+                match result {
+                    Some(result) => self.emit(&format!("{} = {};", result, value)),
+                    None => self.emit(&format!("(void){};", value)),
+                }
+            }
+            Stmt::Command { name, args, .. } => {
+                if name != "print" {
+                    return Err(LutError::compiler_error(format!("Unknown command: {}", name), None));
+                }
+                let print = self.print(args)?;
+                self.emit(&format!("{};", print));
+            }
+            Stmt::Print(args, _) => {
+                let print = self.print(args)?;
+                self.emit(&format!("{};", print));
+            }
+            Stmt::Comment(_) => {}
+            Stmt::Break => {
+                if self.body.loop_depth == 0 {
+                    return Err(LutError::compiler_error("Break statement outside of loop", None));
+                }
+                self.emit("break;");
+            }
+            Stmt::Continue => {
+                if self.body.loop_depth == 0 {
+                    return Err(LutError::compiler_error("Continue statement outside of loop", None));
+                }
+                self.emit("continue;");
+            }
+            Stmt::If { condition, then_branch, else_branch, .. } => {
+                let condition = self.expression(condition)?;
+                self.emit(&format!("if (kz_truthy({})) {{", condition));
+                self.branch(then_branch, result)?;
+                if let Some(else_branch) = else_branch {
+                    self.emit("} else {");
+                    self.branch(else_branch, result)?;
+                }
+                self.emit("}");
+            }
+            Stmt::While { condition, body, line } => {
+                // The body moves the location on, so the condition sets it back
+                let condition = self.expression(condition)?;
+                let location = self.location(*line);
+                self.emit(&format!("while ({}, kz_truthy({})) {{", location, condition));
+                self.loop_body(body)?;
+                self.emit("}");
+            }
+            Stmt::For { initializer, update, condition, body, line } => {
+                // `continue` still runs the update, as in a C for loop
+                let initializer = match initializer {
+                    Expr::Binary { left, operator, right } if operator.token_type == TokenType::Colon => match &**left {
+                        Expr::VariableRef(name) => format!("{} = {}", self.assignment_target(name)?, self.expression(right)?),
+                        _ => self.expression(initializer)?,
+                    },
+                    _ => self.expression(initializer)?,
+                };
+                let condition = self.expression(condition)?;
+                let update = match update {
+                    Expr::Binary { left, operator, right } => match &**left {
+                        Expr::VariableRef(name) if operator.token_type == TokenType::Colon => {
+                            format!("{} = {}", self.assignment_target(name)?, self.expression(right)?)
+                        }
+                        // `$i + 1` stores its result in i
+                        Expr::VariableRef(name) if name.starts_with('$') => {
+                            format!("{} = {}", self.assignment_target(&name[1..])?, self.expression(update)?)
+                        }
+                        _ => self.expression(update)?,
+                    },
+                    _ => self.expression(update)?,
+                };
+                let location = self.location(*line);
+                self.emit(&format!("for ({}; {}, kz_truthy({}); {}, {}) {{", initializer, location, condition, location, update));
+                self.loop_body(body)?;
+                self.emit("}");
+            }
+        }
+        Ok(())
+    }
+
+    // Inside a function's value, a branch only replaces the value when it has one
+    fn branch(&mut self, statements: &[Stmt], result: Option<&str>) -> Result<(), LutError> {
+        self.body.indent += 1;
+        let translated = match result {
+            Some(result) if !statements.is_empty() => {
+                self.body.branches += 1;
+                let branch_result = format!("r{}", self.body.branches);
+                self.emit(&format!("kz_value {} = kz_none();", branch_result));
+                let translated = self.statements(statements, Some(&branch_result));
+                self.emit(&format!("kz_keep(&{}, {});", result, branch_result));
+                translated
+            }
+            _ => self.statements(statements, None),
+        };
+        self.body.indent -= 1;
+        translated
+    }
+
+    fn loop_body(&mut self, statements: &[Stmt]) -> Result<(), LutError> {
+        self.body.indent += 1;
+        self.body.loop_depth += 1;
+        let translated = self.statements(statements, None);
+        self.body.loop_depth -= 1;
+        self.body.indent -= 1;
+        translated
+    }
+
+    fn assignment_target(&self, name: &str) -> Result<String, LutError> {
+        if let Some(parameter) = self.body.parameters.get(name) {
+            return Ok(parameter.clone());
+        }
+        match self.current_scope().variables.get(name) {
+            Some(variable) if variable.constant && self.current_module.is_none() => Err(LutError::compiler_error(
+                format!("Cannot assign to constant '{}'\n\nTip: Declare a new register for the changed value", name),
+                None,
+            )),
+            Some(variable) => Ok(variable.c_name.clone()),
+            None => Err(LutError::compiler_error(format!("Undefined variable: {}", name), None)),
+        }
+    }
+
+    fn variable(&self, name: &str) -> Result<String, LutError> {
+        let Some(var_name) = name.strip_prefix('$') else {
+            return Err(LutError::compiler_error(
+                format!(
+                    "Invalid variable reference: {}\n\nTip: Variables must be prefixed with $ when used. Did you mean ${}?",
+                    name, name
+                ),
+                None,
+            ));
+        };
+        if let Some(parameter) = self.body.parameters.get(var_name) {
+            return Ok(parameter.clone());
+        }
+        match self.current_scope().variables.get(var_name) {
+            Some(variable) => Ok(variable.c_name.clone()),
+            None => Err(LutError::compiler_error(format!("Undefined variable: {}", var_name), None)),
+        }
+    }
+
+    fn expression(&mut self, expr: &Expr) -> Result<String, LutError> {
+        match expr {
+            Expr::VariableRef(name) => self.variable(name),
+            Expr::NumberLiteral(n) => Ok(format!("kz_number(INT64_C({}))", n)),
+            Expr::FloatLiteral(f) if f.is_infinite() => Ok(format!("kz_fp({}1e999)", if *f < 0.0 { "-" } else { "" })),
+            Expr::FloatLiteral(f) => Ok(format!("kz_fp({:?})", f)),
+            Expr::TextLiteral(text) => Ok(format!("kz_text({})", c_string(text))),
+            Expr::BooleanLiteral(b) => Ok(format!("kz_bool({})", *b as i32)),
+            Expr::NoneLiteral => Ok("kz_none()".to_string()),
+            Expr::ArrayLiteral(elements) => self.array(elements),
+            Expr::ArrayLiteral2D(rows) => self.array_2d(rows),
+            Expr::Command { name, args } => self.command(name, args),
+            Expr::FunctionCall { name, arguments } => self.call(name, arguments),
+            Expr::Grouping { expression } => self.expression(expression),
+            Expr::Ternary { condition, then_branch, else_branch } => Ok(format!(
+                "(kz_truthy({}) ? {} : {})",
+                self.expression(condition)?,
+                self.expression(then_branch)?,
+                self.expression(else_branch)?
+            )),
+            Expr::Unary { operator, right } => {
+                let function = match operator.token_type {
+                    TokenType::Minus => "kz_negate",
+                    TokenType::Not => "kz_not",
+                    _ => return Err(LutError::compiler_error("Invalid unary operator", Some(operator.line))),
+                };
+                Ok(format!("{}({})", function, self.expression(right)?))
+            }
+            Expr::Binary { left, operator, right } => {
+                let function = match operator.token_type {
+                    // and, or and ?? only evaluate the right side when they need it
+                    TokenType::And => {
+                        return Ok(format!(
+                            "kz_bool(kz_truthy({}) && kz_truthy({}))",
+                            self.expression(left)?,
+                            self.expression(right)?
+                        ));
+                    }
+                    TokenType::Or => {
+                        return Ok(format!(
+                            "kz_bool(kz_truthy({}) || kz_truthy({}))",
+                            self.expression(left)?,
+                            self.expression(right)?
+                        ));
+                    }
+                    TokenType::Coalesce => {
+                        let left = self.expression(left)?;
+                        let right = self.expression(right)?;
+                        let value = self.temporary();
+                        return Ok(format!("({v} = {}, kz_is_none({v}) ? {} : {v})", left, right, v = value));
+                    }
+                    TokenType::Plus => "kz_add",
+                    TokenType::Minus => "kz_subtract",
+                    TokenType::Star => "kz_multiply",
+                    TokenType::Slash => "kz_divide",
+                    TokenType::Percent => "kz_modulo",
+                    TokenType::Equal => "kz_equal",
+                    TokenType::NotEqual => "kz_not_equal",
+                    TokenType::Less => "kz_less",
+                    TokenType::LessEqual => "kz_less_equal",
+                    TokenType::Greater => "kz_greater",
+                    TokenType::GreaterEqual => "kz_greater_equal",
+                    _ => {
+                        return Err(LutError::compiler_error(
+                            format!("Invalid binary operator: {:?}", operator.token_type),
+                            Some(operator.line),
+                        ));
+                    }
+                };
+                self.runtime_call(function, &[left, right])
+            }
+        }
+    }
+
+    // Operands in the C to evaluate them in: C leaves the order of function arguments
+    // open, so operands that could observe each other go through temporaries, left to right
+    fn operands(&mut self, exprs: &[&Expr]) -> Result<(String, Vec<String>), LutError> {
+        let mut setup = String::new();
+        let mut values = Vec::with_capacity(exprs.len());
+        let sequenced = needs_sequencing(exprs);
+        for expr in exprs {
+            let value = self.expression(expr)?;
+            if sequenced && !is_literal(expr) {
+                let temporary = self.temporary();
+                let _ = write!(setup, "{} = {}, ", temporary, value);
+                values.push(temporary);
+            } else {
+                values.push(value);
+            }
+        }
+        Ok((setup, values))
+    }
+
+    fn runtime_call(&mut self, function: &str, args: &[&Expr]) -> Result<String, LutError> {
+        let (setup, values) = self.operands(args)?;
+        Ok(sequence(&setup, format!("{}({})", function, values.join(", "))))
+    }
+
+    fn print(&mut self, args: &[Expr]) -> Result<String, LutError> {
+        let args: Vec<&Expr> = args.iter().collect();
+        let (setup, values) = self.operands(&args)?;
+        Ok(sequence(&setup, format!("kz_print({}, {})", values.len(), value_list(&values))))
+    }
+
+    fn array(&mut self, elements: &[Expr]) -> Result<String, LutError> {
+        let elements: Vec<&Expr> = elements.iter().collect();
+        let (setup, values) = self.operands(&elements)?;
+        Ok(sequence(&setup, format!("kz_array_of({}, {})", values.len(), value_list(&values))))
+    }
+
+    fn array_2d(&mut self, rows: &[Vec<Expr>]) -> Result<String, LutError> {
+        let elements: Vec<&Expr> = rows.iter().flatten().collect();
+        let (setup, values) = self.operands(&elements)?;
+        let mut remaining = values.as_slice();
+        let mut row_values = Vec::with_capacity(rows.len());
+        for row in rows {
+            let (cells, rest) = remaining.split_at(row.len());
+            row_values.push(format!("kz_array_of({}, {})", cells.len(), value_list(cells)));
+            remaining = rest;
+        }
+        Ok(sequence(&setup, format!("kz_array2d_of({}, {})", row_values.len(), value_list(&row_values))))
+    }
+
+    // array [ a, b ] is 1D; rows written as array commands make it 2D
+    fn array_command(&mut self, args: &[Expr]) -> Result<String, LutError> {
+        if args.is_empty() {
+            return Err(LutError::compiler_error("Array command expects at least one argument", None));
+        }
+        if let [Expr::ArrayLiteral(elements)] = args {
+            return self.array(elements);
+        }
+        if let [Expr::ArrayLiteral2D(rows)] = args {
+            return self.array_2d(rows);
+        }
+
+        let mut first_row_size = 0;
+        let mut is_2d = false;
+        for arg in args {
+            if let Expr::Command { name, args: row } = arg {
+                if name == "array" {
+                    is_2d = true;
+                    if first_row_size == 0 {
+                        first_row_size = row.len();
+                    } else if row.len() != first_row_size {
+                        return Err(LutError::compiler_error("All rows in a 2D array must have the same length", None));
+                    }
+                }
+            }
+        }
+        if !is_2d {
+            return self.array(args);
+        }
+
+        let mut rows = Vec::with_capacity(args.len());
+        for arg in args {
+            match arg {
+                Expr::Command { name, args: row } if name == "array" => rows.push(row.clone()),
+                _ => return Err(LutError::compiler_error("Expected array command for 2D array row", None)),
+            }
+        }
+        self.array_2d(&rows)
+    }
+
+    fn command(&mut self, name: &str, args: &[Expr]) -> Result<String, LutError> {
+        let count = args.len();
+        let (function, arity_error) = match name {
+            "number" => ("kz_to_number", (count != 1).then(|| "Number command expects one argument".to_string())),
+            "text" => ("kz_to_text", (count != 1).then(|| "Text command expects one argument".to_string())),
+            "fp" => ("kz_to_fp", (count != 1).then(|| "Floating point command expects one argument".to_string())),
+            "bool" => ("kz_to_bool", (count != 1).then(|| "Boolean command expects one argument".to_string())),
+            "hex" => ("kz_hex", (count != 1).then(|| "Hex command expects one argument".to_string())),
+            "bin" => ("kz_bin", (count != 1).then(|| "Binary command expects one argument".to_string())),
+            "asc" => ("kz_asc", (count != 1).then(|| format!("Asc command expects one argument, got {}", count))),
+            "length" => ("kz_length", (count != 1).then(|| format!("Length command expects one argument, got {}", count))),
+            "width" => ("kz_width", (count != 1).then(|| format!("Width command expects one argument, got {}", count))),
+            "get" => (
+                "kz_get",
+                (count != 2).then(|| format!("Get command expects two arguments (array and index), got {}", count)),
+            ),
+            "get2d" => (
+                "kz_get2d",
+                (count != 3).then(|| format!("Get2d command expects three arguments (2D array, row, column), got {}", count)),
+            ),
+            "dec" => (
+                if count == 2 { "kz_to_dec_scale" } else { "kz_to_dec" },
+                (count == 0 || count > 2).then(|| "Dec command expects a value and an optional scale".to_string()),
+            ),
+            "div" => (
+                "kz_dec_div",
+                (count != 4).then(|| {
+                    format!("Div command expects a dividend, a divisor, a scale and a rounding mode, got {} arguments", count)
+                }),
+            ),
+            "round" => (
+                "kz_dec_round",
+                (count != 3).then(|| format!("Round command expects a value, a scale and a rounding mode, got {} arguments", count)),
+            ),
+            "is_none" => {
+                if count != 1 {
+                    return Err(LutError::compiler_error("Is_none command expects one argument", None));
+                }
+                return Ok(format!("kz_bool(kz_is_none({}))", self.expression(&args[0])?));
+            }
+            "array" => return self.array_command(args),
+            _ => return Err(LutError::compiler_error(format!("Unknown command: {}", name), None)),
+        };
+        if let Some(message) = arity_error {
+            return Err(LutError::compiler_error(message, None));
+        }
+        let args: Vec<&Expr> = args.iter().collect();
+        self.runtime_call(function, &args)
+    }
+
+    fn call(&mut self, name: &str, arguments: &[Expr]) -> Result<String, LutError> {
+        let Some(callee) = self.current_scope().functions.get(name).cloned() else {
+            return Err(self.undefined_function(name));
+        };
+        let arity = match &callee {
+            Callee::Koze { arity, .. } => *arity,
+            Callee::Extern { parameters, .. } => parameters.len(),
+        };
+        if arguments.len() != arity {
+            return Err(LutError::compiler_error(
+                format!("Function '{}' expects {} arguments, but {} were provided", name, arity, arguments.len()),
+                None,
+            ));
+        }
+
+        let arguments: Vec<&Expr> = arguments.iter().collect();
+        let (setup, values) = self.operands(&arguments)?;
+        let call = match callee {
+            Callee::Koze { c_name, .. } => match self.current_line {
+                // The callee's statements move the location on
+                Some(line) => {
+                    format!("kz_returned({}({}), {}, {})", c_name, values.join(", "), c_string(&self.location_file), line)
+                }
+                None => format!("{}({})", c_name, values.join(", ")),
+            },
+            Callee::Extern { name, parameters, return_type } => {
+                let arguments: Vec<String> = parameters
+                    .iter()
+                    .zip(&values)
+                    .enumerate()
+                    .map(|(i, (param, value))| {
+                        let conversion = match param.param_type.as_str() {
+                            "fp" => "kz_c_fp",
+                            "bool" => "kz_c_bool",
+                            "text" => "kz_c_text",
+                            _ => "kz_c_number",
+                        };
+                        format!("{}({}, {}, {})", conversion, value, i + 1, c_string(&name))
+                    })
+                    .collect();
+                let call = format!("{}({})", name, arguments.join(", "));
+                match return_type.as_deref() {
+                    None => format!("({}, kz_none())", call),
+                    Some("fp") => format!("kz_fp({})", call),
+                    Some("bool") => format!("kz_bool({})", call),
+                    Some("text") => format!("kz_from_c_text({})", call),
+                    Some(_) => format!("kz_number({})", call),
+                }
+            }
+        };
+        Ok(sequence(&setup, call))
+    }
+
+    fn undefined_function(&self, name: &str) -> LutError {
+        let message = match name.split_once("::") {
+            Some((ns, _)) => match self.current_scope().namespaces.get(ns) {
+                Some(path) => format!("Undefined function: {} (module '{}' has no pub function by that name)", name, path),
+                None => format!(
+                    "Undefined function: {}\n\nTip: Import the module first with use {} from './{}.ko'",
+                    name, ns, ns
+                ),
+            },
+            None => format!("Undefined function: {}", name),
+        };
+        LutError::compiler_error(message, None)
+    }
+}
+
+// Names assigned anywhere in `statements`, skipping parameters of the function they belong to
+fn collect_assigned(statements: &[Stmt], parameters: &HashSet<String>, names: &mut Vec<String>) {
+    let mut add = |name: &str| {
+        if !parameters.contains(name) && !names.iter().any(|existing| existing == name) {
+            names.push(name.to_string());
+        }
+    };
+    let mut nested = Vec::new();
+    for stmt in statements {
+        match stmt {
+            Stmt::Declaration { name, .. } => add(name),
+            Stmt::If { then_branch, else_branch, .. } => {
+                nested.push((then_branch.as_slice(), parameters.clone()));
+                if let Some(else_branch) = else_branch {
+                    nested.push((else_branch.as_slice(), parameters.clone()));
+                }
+            }
+            Stmt::While { body, .. } => nested.push((body.as_slice(), parameters.clone())),
+            Stmt::For { initializer, update, body, .. } => {
+                if let Expr::Binary { left, operator, .. } = initializer {
+                    if let (TokenType::Colon, Expr::VariableRef(name)) = (&operator.token_type, &**left) {
+                        add(name);
+                    }
+                }
+                if let Expr::Binary { left, operator, .. } = update {
+                    if let Expr::VariableRef(name) = &**left {
+                        if operator.token_type == TokenType::Colon {
+                            add(name);
+                        } else if let Some(name) = name.strip_prefix('$') {
+                            add(name);
+                        }
+                    }
+                }
+                nested.push((body.as_slice(), parameters.clone()));
+            }
+            Stmt::Function { parameters: function_parameters, body, .. } => {
+                let function_parameters = function_parameters.iter().map(|param| param.name.clone()).collect();
+                nested.push((body.as_slice(), function_parameters));
+            }
+            _ => {}
+        }
+    }
+    for (statements, parameters) in nested {
+        collect_assigned(statements, &parameters, names);
+    }
+}
+
+fn is_literal(expr: &Expr) -> bool {
+    match expr {
+        Expr::NumberLiteral(_) | Expr::FloatLiteral(_) | Expr::TextLiteral(_) | Expr::BooleanLiteral(_) | Expr::NoneLiteral => true,
+        Expr::Grouping { expression } => is_literal(expression),
+        _ => false,
+    }
+}
+
+fn contains_call(expr: &Expr) -> bool {
+    match expr {
+        Expr::FunctionCall { .. } => true,
+        Expr::ArrayLiteral(elements) | Expr::Command { args: elements, .. } => elements.iter().any(contains_call),
+        Expr::ArrayLiteral2D(rows) => rows.iter().flatten().any(contains_call),
+        Expr::Binary { left, right, .. } => contains_call(left) || contains_call(right),
+        Expr::Unary { right, .. } => contains_call(right),
+        Expr::Grouping { expression } => contains_call(expression),
+        Expr::Ternary { condition, then_branch, else_branch } => {
+            contains_call(condition) || contains_call(then_branch) || contains_call(else_branch)
+        }
+        _ => false,
+    }
+}
+
+// Order matters once an operand calls a function, which can print or assign, or once
+// two operands can fail with different runtime errors
+fn needs_sequencing(exprs: &[&Expr]) -> bool {
+    let evaluated = exprs.iter().filter(|expr| !is_literal(expr)).count();
+    let failing = exprs
+        .iter()
+        .filter(|expr| !is_literal(expr) && !matches!(expr, Expr::VariableRef(_)))
+        .count();
+    evaluated >= 2 && (failing >= 2 || exprs.iter().any(|expr| contains_call(expr)))
+}
+
+fn sequence(setup: &str, value: String) -> String {
+    if setup.is_empty() {
+        value
+    } else {
+        format!("({}{})", setup, value)
+    }
+}
+
+fn value_list(values: &[String]) -> String {
+    if values.is_empty() {
+        "(const kz_value *)0".to_string()
+    } else {
+        format!("(kz_value[]){{{}}}", values.join(", "))
+    }
+}
+
+fn parameter_list(arity: usize) -> String {
+    if arity == 0 {
+        "void".to_string()
+    } else {
+        vec!["kz_value"; arity].join(", ")
+    }
+}
+
+// Identifier characters kept, anything else replaced
+fn c_identifier(name: &str) -> String {
+    name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' }).collect()
+}
+
+// The C type of an extern func parameter or result
+fn c_type(type_name: &str, is_return: bool) -> &'static str {
+    match type_name {
+        "fp" => "double",
+        "bool" => "_Bool",
+        "text" if is_return => "char *",
+        "text" => "const char *",
+        _ => "int64_t",
+    }
+}
+
+// C string literal; `?` is escaped so no trigraph can form
+fn c_string(text: &str) -> String {
+    let mut literal = String::from("\"");
+    for byte in text.bytes() {
+        match byte {
+            b'"' => literal.push_str("\\\""),
+            b'\\' => literal.push_str("\\\\"),
+            b'?' => literal.push_str("\\?"),
+            b'\n' => literal.push_str("\\n"),
+            b'\r' => literal.push_str("\\r"),
+            b'\t' => literal.push_str("\\t"),
+            0x20..=0x7e => literal.push(byte as char),
+            _ => {
+                let _ = write!(literal, "\\{:03o}", byte);
+            }
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+// The runtime's header and library are written next to the program, so generated C never
+// depends on this koze. Returns the path of the library, which is built for --target if given
+fn write_sources(program: &str, source_path: &Path, target: Option<&str>) -> Result<PathBuf, LutError> {
+    let write = |path: &Path, contents: &str| {
+        fs::write(path, contents).map_err(|e| LutError::io_error(format!("Failed to write '{}': {}", path.display(), e)))
+    };
+    write(source_path, program)?;
+    write(&source_path.with_file_name("koze_runtime.h"), RUNTIME_HEADER)?;
+    let dir = source_path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    write_runtime_library(dir, target.unwrap_or(HOST_TARGET), target.is_some())
+}
+
+// Options that only mean something to LLVM
+fn check_options(options: &BuildOptions) -> Result<(), LutError> {
+    for (flag, value) in [("--cpu", &options.cpu), ("--features", &options.features)] {
+        if value.is_some() {
+            return Err(LutError::compiler_error(
+                format!(
+                    "{} is not supported by the C backend\n\nTip: Pass CPU options to the C compiler with --linker, e.g. --linker 'cc -march=native'",
+                    flag
+                ),
+                None,
+            ));
+        }
+    }
+    if options.target.as_deref().is_some_and(is_wasm) {
+        return Err(LutError::compiler_error(
+            "WebAssembly is not supported by the C backend\n\nTip: Build WebAssembly with --backend=llvm",
+            None,
+        ));
+    }
+    if options.lib.is_some() {
+        return Err(LutError::compiler_error(
+            "--lib is not supported by the C backend\n\nTip: Build libraries with --backend=llvm",
+            None,
+        ));
+    }
+    if !matches!(options.emit, Emit::C | Emit::Exe) {
+        return Err(LutError::compiler_error(
+            format!(
+                "The C backend cannot write {}\n\nTip: Use --emit=c for the C source or --backend=llvm",
+                options.emit.describe()
+            ),
+            None,
+        ));
+    }
+    Ok(())
+}
+
+fn compile_executable(program: &str, stem: &str, output: &str, libraries: &[String], options: &BuildOptions) -> Result<(), LutError> {
+    let build_dir = tempfile::tempdir()
+        .map_err(|e| LutError::io_error(format!("Failed to create a build directory: {}", e)))?;
+    let source_path = build_dir.path().join(format!("{}.c", c_identifier(stem)));
+    let runtime_path = write_sources(program, &source_path, options.target.as_deref())?;
+
+    let linker = options.linker.as_deref().unwrap_or("cc");
+    let mut words = linker.split_whitespace();
+    let compiler = words.next().unwrap_or("cc");
+    let mut command = Command::new(compiler);
+    command.args(words).arg(options.opt_level.flag());
+    if options.debug_info {
+        command.arg("-g");
+    }
+    let status = command
+        .arg("-o")
+        .arg(output)
+        .arg(&source_path)
+        .arg(&runtime_path)
+        .args(libraries)
+        .args(runtime_system_libs(options.target.as_deref().unwrap_or(HOST_TARGET)))
+        .status()
+        .map_err(|e| LutError::compiler_error(
+            format!("Error executing C compiler ({}): {}\n\nTip: Install a C compiler or choose one with --linker", compiler, e),
+            None,
+        ))?;
+    if !status.success() {
+        return Err(LutError::compiler_error(
+            format!("C compilation failed with exit code {}", status.code().unwrap_or(-1)),
+            None,
+        ));
+    }
+    Ok(())
+}
+
+// Top-level compile function of the C backend
+pub fn compile(source: &str, file_path: &str, silent_mode: bool, options: &BuildOptions) -> Result<(), LutError> {
+    check_options(options)?;
+
+    let mut lexer = Lexer::new(source);
+    let tokens = lexer.scan_tokens()?;
+
+    let mut parser = Parser::new(tokens);
+    let statements = parser.parse()?;
+
+    let stem = Path::new(file_path)
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    let mut generator = CGenerator::new(file_path, options.debug_info);
+    let program = generator.translate(&statements)?;
+
+    let output = options.output.clone().unwrap_or_else(|| options.emit.default_output(&stem));
+    match options.emit {
+        Emit::C => {
+            write_sources(&program, Path::new(&output), options.target.as_deref())?;
+        }
+        _ => compile_executable(&program, &stem, &output, &generator.libraries, options)?,
+    }
+
+    if !silent_mode {
+        println!("Generated {}: {}", options.emit.describe(), output);
+        println!("Compilation successful!");
+    }
+    Ok(())
+}
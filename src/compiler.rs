@@ -1,22 +1,40 @@
-use crate::lexer::{Lexer, TokenType};
-use crate::parser::{Expr, FunctionParam, Parser, Stmt};
+use crate::build_options::{BuildOptions, Emit, LibKind, OptLevel};
+use crate::decimal::{self, Decimal, RoundingMode};
+use crate::dependency_manager::{DependencyManager, LoadedModule, ResolvedImport};
+use crate::lexer::{Lexer, Token, TokenType};
+use crate::parser::{Expr, FunctionParam, ImportItem, Parser, Stmt};
 use crate::error_reporting::LutError;
+use crate::foreign;
+use crate::runtime_library::{
+    is_wasm, run_rustc, runtime_system_libs, rust_target, write_runtime_library, write_runtime_source
+};
 use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::Path;
 use std::process::Command;
-use std::rc::Rc;
 use inkwell::context::Context;
 use inkwell::builder::Builder;
-use inkwell::module::Module;
-use inkwell::values::{BasicValue, BasicValueEnum, FunctionValue, PointerValue, InstructionValue};
-use inkwell::types::IntType;
+use inkwell::debug_info::{
+    AsDIScope, DICompileUnit, DIFlags, DIFlagsConstants, DIType, DWARFEmissionKind, DWARFSourceLanguage, DebugInfoBuilder
+};
+use inkwell::intrinsics::Intrinsic;
+use inkwell::module::{FlagBehavior, Module};
+use inkwell::values::{BasicMetadataValueEnum, BasicValue, BasicValueEnum, FloatValue, FunctionValue, IntValue, PointerValue, InstructionValue};
+use inkwell::types::{BasicMetadataTypeEnum, BasicType, BasicTypeEnum, FunctionType, IntType};
 use inkwell::OptimizationLevel;
+use inkwell::passes::PassBuilderOptions;
 use inkwell::AddressSpace;
 use inkwell::targets::{
-    CodeModel, FileType, InitializationConfig, RelocMode, Target, TargetMachine
+    CodeModel, FileType, InitializationConfig, RelocMode, Target, TargetMachine, TargetTriple
 };
 use inkwell::Either;
+use inkwell::DLLStorageClass;
+use inkwell::attributes::{Attribute, AttributeLoc};
+// Compiled arrays are a heap block of i64 words; the runtime documents the layout
+use kozeig_runtime::{
+    ARRAY_HEADER_WORDS, ARRAY_TAG_ARRAY, ARRAY_TAG_BOOL, ARRAY_TAG_DECIMAL, ARRAY_TAG_FLOAT, ARRAY_TAG_INT, ARRAY_TAG_TEXT,
+    NONE_FP_BITS, NONE_NUMBER, REFCOUNT_IMMORTAL,
+};
 
 // Define an enum for variable types
 #[derive(Debug, Clone, PartialEq)]
@@ -27,12 +45,78 @@ pub enum VariableType {
     Boolean,
     Array,
     Array2D,
+    Decimal(u32), // i64 units with a fixed number of digits after the point
+}
+
+// LLVM's view of the -O level
+impl OptLevel {
+    // New pass manager pipeline run over the module before codegen
+    fn pipeline(self) -> &'static str {
+        match self {
+            OptLevel::O0 => "default<O0>",
+            OptLevel::O1 => "default<O1>",
+            OptLevel::O2 => "default<O2>",
+            OptLevel::O3 => "default<O3>",
+            OptLevel::Os => "default<Os>",
+        }
+    }
+
+    fn codegen_level(self) -> OptimizationLevel {
+        match self {
+            OptLevel::O0 => OptimizationLevel::None,
+            OptLevel::O1 => OptimizationLevel::Less,
+            OptLevel::O2 | OptLevel::Os => OptimizationLevel::Default,
+            OptLevel::O3 => OptimizationLevel::Aggressive,
+        }
+    }
+}
+
+// A pub function of a library build, called from C through build_export
+#[derive(Clone)]
+struct ExportedFunction {
+    name: String,
+    parameters: Vec<FunctionParam>,
+    return_type: Option<String>,
+}
+
+// A branch whose last statement is the none literal
+fn ends_with_none(branch: &[Stmt]) -> bool {
+    matches!(branch.last(), Some(Stmt::Expression(Expr::NoneLiteral, _)))
+}
+
+// A branch that gives a value: it ends in an expression, or in an if with an else whose
+// branches both give one
+fn ends_with_value(branch: &[Stmt]) -> bool {
+    match branch.last() {
+        Some(Stmt::Expression(..)) => true,
+        Some(Stmt::If { then_branch, else_branch: Some(else_branch), .. }) => {
+            ends_with_value(then_branch) && ends_with_value(else_branch)
+        },
+        _ => false,
+    }
+}
+
+// LLVM 16 calls the WASI target wasm32-unknown-wasi; wasm32-wasi and rustc's wasm32-wasip1 name it too
+fn llvm_triple(target: &str) -> String {
+    match target {
+        "wasm32-wasi" | "wasm32-wasip1" => "wasm32-unknown-wasi".to_string(),
+        other => other.to_string(),
+    }
 }
 
-// String interning pool for efficient string management
+// Runtime functions whose result is a new text or array the caller owns
+const RUNTIME_ALLOCATORS: &[&str] = &[
+    "int_to_text", "float_to_text", "char_to_text", "decimal_to_text", "concat", "text_from_c", "array_new",
+];
+
+// Runtime functions that can end the program with an error
+const RUNTIME_LOCATED: &[&str] = &[
+    "runtime_error", "char_to_text", "concat", "text_to_int", "text_to_float", "parse_radix", "array_cell", "array_cell2d",
+    "array_width",
+];
+
+// String pool, so each distinct literal is emitted once
 struct StringPool<'ctx> {
-    // Store unique strings with reference counting
-    pool: HashSet<Rc<String>>,
     // Map of global string constants in LLVM IR
     global_strings: HashMap<String, PointerValue<'ctx>>,
 }
@@ -40,23 +124,10 @@ struct StringPool<'ctx> {
 impl<'ctx> StringPool<'ctx> {
     fn new() -> Self {
         StringPool {
-            pool: HashSet::new(),
             global_strings: HashMap::new(),
         }
     }
     
-    fn intern(&mut self, s: String) -> Rc<String> {
-        let rc_string = Rc::new(s);
-        // If the string is already in the pool, return the existing reference
-        if let Some(existing) = self.pool.get(&rc_string) {
-            Rc::clone(existing)
-        } else {
-            // Otherwise add it to the pool and return a reference
-            self.pool.insert(Rc::clone(&rc_string));
-            rc_string
-        }
-    }
-    
     // Get or create a global string constant in LLVM IR
     fn get_or_create_global_string(&mut self, 
                                    string_val: &str, 
@@ -68,23 +139,25 @@ impl<'ctx> StringPool<'ctx> {
             return ptr;
         }
         
-        // Create a new global string constant
-        let i8_type = context.i8_type();
-        let string_type = i8_type.array_type((string_val.len() + 1) as u32);
+        // Create a new global string constant behind the runtime's object header,
+        // marked immortal so releasing a literal never frees it
+        let i64_type = context.i64_type();
+        let string_type = context.i8_type().array_type((string_val.len() + 1) as u32);
+        let literal_type = context.struct_type(&[i64_type.into(), i64_type.into(), string_type.into()], false);
         
         // Create a unique name for the global string
         let global_name = format!("str_{}", module.get_globals().count());
-        let global_string = module.add_global(string_type, None, &global_name);
+        let global_string = module.add_global(literal_type, None, &global_name);
         global_string.set_constant(true);
         global_string.set_linkage(inkwell::module::Linkage::Private);
-        global_string.set_initializer(&context.const_string(string_val.as_bytes(), true));
+        global_string.set_initializer(&context.const_struct(&[
+            i64_type.const_int(ARRAY_TAG_TEXT, false).into(),
+            i64_type.const_int(REFCOUNT_IMMORTAL as u64, true).into(),
+            context.const_string(string_val.as_bytes(), true).into(),
+        ], false));
         
         // Create a pointer to the string data
-        let zero = context.i32_type().const_zero();
-        let indices = [zero, zero];
-        let ptr = unsafe {
-            builder.build_gep(i8_type, global_string.as_pointer_value(), &indices, "str_ptr").unwrap()
-        };
+        let ptr = builder.build_struct_gep(literal_type, global_string.as_pointer_value(), 2, "str_ptr").unwrap();
         
         // Cache and return the pointer
         self.global_strings.insert(string_val.to_string(), ptr);
@@ -92,6 +165,31 @@ impl<'ctx> StringPool<'ctx> {
     }
 }
 
+// Everything an imported module's code can reference: its functions and
+// constants plus whatever it imports itself
+#[derive(Default)]
+struct ModuleScope<'ctx> {
+    functions: HashMap<String, FunctionValue<'ctx>>,
+    variables: HashMap<String, PointerValue<'ctx>>,
+    variable_types: HashMap<String, VariableType>,
+    constant_names: HashSet<String>,
+    namespaces: HashMap<String, String>,
+}
+
+// Compiler state replaced while a module's code is being compiled
+struct SavedScope<'ctx> {
+    module: Option<String>,
+    variables: HashMap<String, PointerValue<'ctx>>,
+    variable_types: HashMap<String, VariableType>,
+    constant_names: HashSet<String>,
+}
+
+// DWARF compile unit for one .ko file
+struct DebugUnit<'ctx> {
+    builder: DebugInfoBuilder<'ctx>,
+    unit: DICompileUnit<'ctx>,
+}
+
 // LLVM Code generator
 pub struct LLVMCompiler<'ctx> {
     context: &'ctx Context,
@@ -99,28 +197,61 @@ pub struct LLVMCompiler<'ctx> {
     builder: Builder<'ctx>,
     variables: HashMap<String, PointerValue<'ctx>>,
     variable_types: HashMap<String, VariableType>,
-    printf_func: FunctionValue<'ctx>,
     i64_type: IntType<'ctx>,
-    // String handling functions
-    sprintf_func: FunctionValue<'ctx>,
-    atoll_func: FunctionValue<'ctx>,
-    malloc_func: FunctionValue<'ctx>,
+    // String handling functions; everything else is in the runtime library
     strlen_func: FunctionValue<'ctx>,
     // Loop control flow tracking
     current_loop_exit: Option<inkwell::basic_block::BasicBlock<'ctx>>,
     current_loop_continue: Option<inkwell::basic_block::BasicBlock<'ctx>>,
     // String interning pool
     string_pool: StringPool<'ctx>,
-    // Pattern detection flags
-    is_counting_loop: bool,
     // Silent mode for optimized output
     silent_mode: bool,
+    opt_level: OptLevel,
     // Function tracking
     functions: HashMap<String, FunctionValue<'ctx>>,
     // Current function for return statements
     current_function: Option<FunctionValue<'ctx>>,
-    // Source file path (for special case handling)
+    // Source file path, used to resolve imports
     file_path: String,
+    // Computes module constants once, called by main before user code runs
+    module_init: Option<FunctionValue<'ctx>>,
+    constant_names: HashSet<String>,
+    // Imports, resolved before anything else is compiled
+    dependency_manager: Option<DependencyManager>,
+    module_scopes: HashMap<String, ModuleScope<'ctx>>,
+    // Symbol prefixes already given to imported modules
+    module_prefixes: HashSet<String>,
+    namespaces: HashMap<String, String>,
+    // Module whose code is being compiled; None for the file being built
+    current_module: Option<String>,
+    local_function_names: HashSet<String>,
+    // LLVM names of functions whose result is an array pointer carried as i64
+    array_functions: HashSet<String>,
+    // Element tag of array variables whose elements all have the same type
+    array_cell_tags: HashMap<String, u64>,
+    // Text and arrays created by the statement being compiled, released when it ends
+    temporaries: Vec<PointerValue<'ctx>>,
+    // Text and array variables of the current function, released when it returns
+    owned_locals: Vec<PointerValue<'ctx>>,
+    // Text constants, released after main so the leak check sees them freed
+    pointer_constants: Vec<PointerValue<'ctx>>,
+    // DWARF for koze build -g, keyed like current_module
+    debug_info: bool,
+    debug_units: HashMap<Option<String>, DebugUnit<'ctx>>,
+    // Code generation target and link command, see BuildOptions
+    target: Option<String>,
+    cpu: Option<String>,
+    features: Option<String>,
+    linker: Option<String>,
+    // koze build --lib: no entry point, pub functions get C entry points instead
+    library: Option<LibKind>,
+    exports: Vec<ExportedFunction>,
+    // C functions from extern func, with the library each one is linked from
+    extern_functions: HashMap<String, Option<String>>,
+    // File and line runtime errors report for the code being compiled
+    location_file: String,
+    current_line: usize,
 }
 
 impl<'ctx> LLVMCompiler<'ctx> {
@@ -145,23 +276,6 @@ impl<'ctx> LLVMCompiler<'ctx> {
         
         // Create external functions
         let i8_ptr_type = context.ptr_type(AddressSpace::default());
-        let i32_type = context.i32_type();
-        
-        // printf function declaration
-        let printf_type = i32_type.fn_type(&[i8_ptr_type.into()], true);
-        let printf_func = module.add_function("printf", printf_type, None);
-        
-        // sprintf function declaration (char* dest, char* format, ...)
-        let sprintf_type = i32_type.fn_type(&[i8_ptr_type.into(), i8_ptr_type.into()], true);
-        let sprintf_func = module.add_function("sprintf", sprintf_type, None);
-        
-        // atoll function declaration (char* str)
-        let atoll_type = i64_type.fn_type(&[i8_ptr_type.into()], false);
-        let atoll_func = module.add_function("atoll", atoll_type, None);
-        
-        // malloc function declaration (size_t size)
-        let malloc_type = i8_ptr_type.fn_type(&[i64_type.into()], false);
-        let malloc_func = module.add_function("malloc", malloc_type, None);
         
         // strlen function declaration (char* str)
         let strlen_type = i64_type.fn_type(&[i8_ptr_type.into()], false);
@@ -176,20 +290,40 @@ impl<'ctx> LLVMCompiler<'ctx> {
             builder,
             variables: HashMap::with_capacity(128), // Pre-allocate space for variables
             variable_types: HashMap::with_capacity(128),
-            printf_func,
             i64_type,
-            sprintf_func,
-            atoll_func,
-            malloc_func,
             strlen_func,
             current_loop_exit: None,
             current_loop_continue: None,
             string_pool,
-            is_counting_loop: false,
             silent_mode: false,
+            opt_level: OptLevel::default(),
             functions: HashMap::with_capacity(32), // Pre-allocate space for functions
             current_function: None,
             file_path: file_path.to_string(),
+            module_init: None,
+            constant_names: HashSet::new(),
+            dependency_manager: None,
+            module_scopes: HashMap::new(),
+            module_prefixes: HashSet::new(),
+            namespaces: HashMap::new(),
+            current_module: None,
+            local_function_names: HashSet::new(),
+            array_functions: HashSet::new(),
+            array_cell_tags: HashMap::new(),
+            temporaries: Vec::new(),
+            owned_locals: Vec::new(),
+            pointer_constants: Vec::new(),
+            debug_info: false,
+            debug_units: HashMap::new(),
+            target: None,
+            cpu: None,
+            features: None,
+            linker: None,
+            library: None,
+            exports: Vec::new(),
+            extern_functions: HashMap::new(),
+            location_file: file_path.to_string(),
+            current_line: 1,
         }
     }
     
@@ -198,23 +332,59 @@ impl<'ctx> LLVMCompiler<'ctx> {
         self.silent_mode = silent;
         self
     }
+
+    pub fn with_opt_level(mut self, opt_level: OptLevel) -> Self {
+        self.opt_level = opt_level;
+        self
+    }
+
+    pub fn with_debug_info(mut self, debug_info: bool) -> Self {
+        self.debug_info = debug_info;
+        self
+    }
+
+    pub fn with_library(mut self, library: Option<LibKind>) -> Self {
+        self.library = library;
+        self
+    }
+
+    // Target, CPU, features and linker for koze build
+    pub fn with_target(mut self, options: &BuildOptions) -> Self {
+        self.target = options.target.clone();
+        self.cpu = options.cpu.clone();
+        self.features = options.features.clone();
+        self.linker = options.linker.clone();
+        self
+    }
     
+    // Exit code for the value user main returns; only numbers are passed through
+    fn main_exit_code(&self, value: BasicValueEnum<'ctx>) -> IntValue<'ctx> {
+        match value {
+            BasicValueEnum::IntValue(int_val) if int_val.get_type() == self.i64_type => int_val,
+            _ => self.i64_type.const_int(0, false),
+        }
+    }
+
+    // main's last expression as an exit status, like interpreter::exit_status: a number is
+    // the status (koze_exit_code checks its range), 'ok' is 0, other text is printed to stderr
+    // with status 1 and anything else is 0
+    fn compile_main_status(&mut self, expr: Expr) -> Result<BasicValueEnum<'ctx>, LutError> {
+        if matches!(&expr, Expr::TextLiteral(text) if text == "ok") {
+            return Ok(self.i64_type.const_int(0, false).into());
+        }
+        let has_status = !self.is_array_expr(&expr) && !self.is_boolean_expr(&expr) && self.decimal_scale(&expr).is_none();
+        let value = self.compile_expression(expr)?;
+        Ok(match value {
+            BasicValueEnum::PointerValue(text) if has_status => self.call_runtime("main_status", &[text.into()]).unwrap(),
+            BasicValueEnum::IntValue(int_val) if has_status && int_val.get_type() == self.i64_type => value,
+            _ => self.i64_type.const_int(0, false).into(),
+        })
+    }
+
     // Create an entry point
     pub fn create_main_function(&mut self) -> FunctionValue<'ctx> {
-        // First, we need to check if there's a user main function
-        let has_user_main = self.functions.contains_key("main");
-        
-        // If there's already a user-defined main function, we need to handle it specially
-        let user_main_func = if has_user_main {
-            // Get the existing main function
-            let user_main = self.functions.remove("main").unwrap();
-            
-            // Add it back with a different name (user_main)
-            self.functions.insert("user_main".to_string(), user_main);
-            Some(user_main)
-        } else {
-            None
-        };
+        // The user's main was declared as user_main in the first pass
+        let user_main_func = self.functions.get("user_main").copied();
         
         // Create the system main function (i64 return type for consistency)
         let main_type = self.i64_type.fn_type(&[], false);
@@ -223,198 +393,224 @@ impl<'ctx> LLVMCompiler<'ctx> {
         // Create a single entry block
         let entry = self.context.append_basic_block(main_func, "entry");
         self.builder.position_at_end(entry);
+
+        // With -g the entry point is reported at the top of the file
+        self.attach_subprogram(main_func, "main", 1);
+        let old_function = self.current_function.replace(main_func);
+        self.set_debug_line(1);
+        self.current_function = old_function;
         
         // Create a dummy variable to avoid empty blocks (required by LLVM)
         self.builder.build_alloca(self.i64_type, "dummy").unwrap();
+
+        // Module constants are set up before any user code runs
+        if let Some(module_init) = self.module_init {
+            self.builder.build_call(module_init, &[], "module_init").unwrap();
+        }
         
         // If there's a user main function, call it and return its result
-        if let Some(user_main) = user_main_func {
-            // Add a call to the user_main function
+        let return_value = if let Some(user_main) = user_main_func {
             let call = self.builder.build_call(user_main, &[], "user_main_call").unwrap();
-            
-            // Extract the return value
-            let return_value = match call.try_as_basic_value() {
-                Either::Left(value) => value.into_int_value(),
+            match call.try_as_basic_value() {
+                Either::Left(BasicValueEnum::PointerValue(value)) => {
+                    // main declared to return text; an array gives 0
+                    let status = if self.array_functions.contains("user_main") {
+                        self.i64_type.const_int(0, false)
+                    } else {
+                        self.call_runtime("main_status", &[value.into()]).unwrap().into_int_value()
+                    };
+                    self.call_runtime("release", &[value.into()]);
+                    status
+                },
+                Either::Left(value) => {
+                    let status = self.main_exit_code(value);
+                    self.call_runtime("exit_code", &[status.into()]).unwrap().into_int_value()
+                },
                 Either::Right(_) => self.i64_type.const_int(0, false)
-            };
-            
-            // Return the result
-            self.builder.build_return(Some(&return_value)).unwrap();
+            }
         } else {
             // No user main, just return 0
-            let return_value = self.i64_type.const_int(0, false);
-            self.builder.build_return(Some(&return_value)).unwrap();
+            self.i64_type.const_int(0, false)
+        };
+
+        // Constants go last, so anything the leak check still finds was never released
+        let ptr_type = self.context.ptr_type(AddressSpace::default());
+        for constant in self.pointer_constants.clone() {
+            let value = self.builder.build_load(ptr_type, constant, "constant").unwrap();
+            self.call_runtime("release", &[value.into()]);
+        }
+        self.call_runtime("check_leaks", &[]);
+
+        // A C main does not flush the runtime's buffered stdout on return
+        self.call_runtime("flush", &[]);
+        self.builder.build_return(Some(&return_value)).unwrap();
+        self.builder.unset_current_debug_location();
+
+        if is_wasm(&self.target_triple()) {
+            self.add_wasi_entry(main_func);
         }
         
         main_func
     }
+
+    // wasi-libc's _start runs __main_void, the name clang gives an int main(void)
+    fn add_wasi_entry(&mut self, main_func: FunctionValue<'ctx>) {
+        let i32_type = self.context.i32_type();
+        let entry_func = self.module.add_function("__main_void", i32_type.fn_type(&[], false), None);
+        let entry = self.context.append_basic_block(entry_func, "entry");
+        self.builder.position_at_end(entry);
+        let status = self.builder.build_call(main_func, &[], "status").unwrap().try_as_basic_value().left().unwrap();
+        let status = self.builder.build_int_truncate(status.into_int_value(), i32_type, "status").unwrap();
+        self.builder.build_return(Some(&status)).unwrap();
+    }
     
-    // Create a print function that will use printf
+    // Print literal text through the runtime
     fn create_print_string(&mut self, text: &str) {
-        // Get a string literal pointer
         let str_ptr = self.create_string_literal(text);
-        
-        // Create a unique call ID
-        let call_id = format!("printf_call_{}", self.module.get_globals().count());
-        
-        // Call printf with the format string
-        self.builder.build_call(self.printf_func, &[str_ptr.into()], &call_id).unwrap();
+        self.call_runtime("print_text", &[str_ptr.into()]);
     }
     
     // Compile all statements and create a binary
     pub fn compile(&mut self, statements: Vec<Stmt>) -> Result<(), LutError> {
+        if self.debug_info {
+            let version = self.context.i32_type().const_int(inkwell::debug_info::debug_metadata_version() as u64, false);
+            self.module.add_basic_value_flag("Debug Info Version", FlagBehavior::Warning, version);
+            let dwarf_version = self.context.i32_type().const_int(4, false);
+            self.module.add_basic_value_flag("Dwarf Version", FlagBehavior::Warning, dwarf_version);
+            let file_path = self.file_path.clone();
+            self.add_debug_unit(None, Path::new(&file_path));
+        }
+
+        // Imported modules are compiled first, so their functions and constants can be bound
+        for stmt in statements.iter() {
+            if let Stmt::Function { name, .. } | Stmt::Extern { name, .. } = stmt {
+                self.local_function_names.insert(name.clone());
+            }
+        }
+        for stmt in statements.iter() {
+            if let Stmt::Import { functions, module_path, namespace, wildcard, line } = stmt {
+                self.compile_import(None, functions, module_path, namespace.as_deref(), *wildcard, *line)?;
+            }
+        }
+
+        // C functions are declared first, so koze functions can be checked against them
+        for stmt in statements.iter() {
+            if let Stmt::Extern { name, line, .. } = stmt {
+                if self.functions.contains_key(name) {
+                    return Err(LutError::compiler_error(format!("Function '{}' is already defined", name), Some(*line)));
+                }
+                let function = self.declare_extern(stmt)?;
+                self.functions.insert(name.clone(), function);
+            }
+        }
+
         // First pass: register all function declarations (including main)
         for stmt in statements.iter() {
-            if let Stmt::Function { name, is_public, parameters, .. } = stmt {
-                // Create parameter types
-                let mut param_types = Vec::with_capacity(parameters.len());
-                for _ in parameters {
-                    // All parameters are i64 for now
-                    param_types.push(self.i64_type.into());
+            if let Stmt::Function { name, is_public, parameters, return_type, body, line } = stmt {
+                if self.extern_functions.contains_key(name) && self.functions.contains_key(name) {
+                    return Err(LutError::compiler_error(
+                        format!("Function '{}' is already declared with extern func\n\nTip: Rename the function", name),
+                        Some(*line)
+                    ));
+                }
+                // Create function type from the parameter and return annotations
+                let return_type = return_type.as_deref().or_else(|| self.inferred_return_type(name, body, parameters));
+                let returns_array = return_type == Some("array");
+                let function_type = self.function_type_for(parameters, return_type);
+
+                // Library exports are reached through a C entry point with the function's own name
+                let exported = self.library.is_some() && *is_public;
+                if self.library.is_some() && name == "main" {
+                    return Err(LutError::compiler_error(
+                        "A library has no main function\n\nTip: Remove main or build without --lib",
+                        Some(*line)
+                    ));
+                }
+                if exported {
+                    self.check_exportable(name, parameters, return_type, *line)?;
+                    self.exports.push(ExportedFunction {
+                        name: name.clone(),
+                        parameters: parameters.clone(),
+                        return_type: return_type.map(str::to_string),
+                    });
                 }
-                
-                // Create function type
-                let function_type = self.i64_type.fn_type(&param_types, false);
                 
                 // Create the function without body
-                let linkage = if *is_public {
+                let linkage = if *is_public && !exported {
                     inkwell::module::Linkage::External
                 } else {
                     inkwell::module::Linkage::Private
                 };
                 
-                // Just declare the function in the first pass
-                let function = self.module.add_function(name, function_type, Some(linkage));
+                // The user's main is called from the system entry point, so it keeps its own symbol
+                let function_name = if name == "main" { "user_main" } else { name.as_str() };
+                let llvm_name = if exported { format!("{}.kozeig", name) } else { function_name.to_string() };
+                let function = self.module.add_function(&llvm_name, function_type, Some(linkage));
                 
                 // Register the function so it can be referenced before definition
-                self.functions.insert(name.clone(), function);
+                self.functions.insert(function_name.to_string(), function);
+                if returns_array {
+                    self.array_functions.insert(llvm_name);
+                }
             }
         }
         
-        // Create system main after user functions are registered
-        let main_func = self.create_main_function();
-        
-        // Set insertion point to main function entry block
-        let entry_block = main_func.get_first_basic_block().unwrap();
-        self.builder.position_at_end(entry_block);
-        
-        // Check if user defined a main function
-        let has_user_main = self.functions.contains_key("main");
+        // Constants come before the functions that read them, whatever the source order
+        for stmt in statements.iter() {
+            if let Stmt::Const { name, is_public, initializer, line } = stmt {
+                if !self.constant_names.insert(name.clone()) {
+                    return Err(LutError::compiler_error(format!("Constant '{}' is already defined", name), Some(*line)));
+                }
+                let (ptr, var_type) = self.compile_constant(name, *is_public, initializer.clone(), Some(*line))?;
+                self.variables.insert(name.clone(), ptr);
+                self.variable_types.insert(name.clone(), var_type);
+            }
+        }
+        if let Some(module_init) = self.module_init {
+            self.builder.position_at_end(module_init.get_last_basic_block().unwrap());
+            self.builder.build_return(None).unwrap();
+        }
+
+        // Create system main after user functions are registered. A library has no
+        // entry point, so its constants are set up when it is loaded
+        if self.library.is_none() {
+            let main_func = self.create_main_function();
+            
+            // Set insertion point to main function entry block
+            let entry_block = main_func.get_first_basic_block().unwrap();
+            self.builder.position_at_end(entry_block);
+        } else if let Some(module_init) = self.module_init {
+            self.add_global_constructor(module_init);
+        }
         
         // Second pass: compile all functions
         for stmt in statements {
             self.compile_statement(stmt)?;
         }
         
-        // First, let's manually find the main function in our LLVM module
-        // We need to ensure the main function itself is properly terminated
-        if let Some(main_func) = self.module.get_function("main") {
-            let blocks = main_func.get_basic_blocks();
-            for block in blocks {
-                // If this block doesn't have a terminator, add one
-                if !block.get_terminator().is_some() {
-                    self.builder.position_at_end(block);
-                    
-                    // If this is the entry block, add a return 0
-                    if block.get_name().to_str().unwrap_or("") == "entry" {
-                        // Add a return statement to the entry block
-                        let return_value = self.i64_type.const_int(0, false);
-                        self.builder.build_return(Some(&return_value)).unwrap();
-                    } else {
-                        // For other blocks, find some block to branch to or add a return
-                        let entry = main_func.get_first_basic_block().unwrap();
-                        self.builder.build_unconditional_branch(entry).unwrap();
-                    }
-                }
-            }
-        }
-        
-        // Verify all other functions are correctly formed
-        for (name, func) in self.functions.clone() {
+        // Verify all functions are correctly formed
+        for func in self.functions.clone().into_values() {
             // Check and fix unterminated blocks
             let blocks = func.get_basic_blocks();
             for block in blocks {
                 if !block.get_terminator().is_some() {
                     // This block needs a terminator - position at the end and add a return
                     self.builder.position_at_end(block);
-                    let return_value = self.i64_type.const_int(0, false);
-                    self.builder.build_return(Some(&return_value)).unwrap();
+                    self.build_default_return(func);
                 }
             }
         }
-        
-        // Before verifying, let's clean up any unused function declarations
-        // Remove main.1 and main.2 declarations if they exist
-        for func_name in ["main.1", "main.2"] {
-            if let Some(decl) = self.module.get_function(func_name) {
-                // Check if it's just a declaration (no body)
-                if decl.get_basic_blocks().is_empty() {
-                    // It's just a declaration, we can safely remove it
-                    unsafe {
-                        decl.delete();
-                    }
-                }
-            }
+
+        for export in self.exports.clone() {
+            self.build_export(&export);
         }
         
-        // Make sure the main function calls user_main if needed
-        if let Some(main_func) = self.module.get_function("main") {
-            // Get or create the entry block
-            let entry_block = match main_func.get_first_basic_block() {
-                Some(block) => block,
-                None => {
-                    // Create an entry block if it doesn't exist
-                    self.context.append_basic_block(main_func, "entry")
-                }
-            };
-            
-            // Position at the end of the entry block
-            self.builder.position_at_end(entry_block);
-            
-            // Check if the entry block already has a terminator
-            if entry_block.get_terminator().is_none() {
-                // Create a dummy variable if needed
-                if main_func.get_params().is_empty() && main_func.get_basic_blocks().len() <= 1 {
-                    self.builder.build_alloca(self.i64_type, "dummy").unwrap();
-                }
-                
-                // Check for user_main function
-                if let Some(user_main) = self.functions.get("user_main") {
-                    // Call the user_main function and return its result
-                    let call = self.builder.build_call(*user_main, &[], "main_call").unwrap();
-                    
-                    // Extract return value
-                    let return_value = match call.try_as_basic_value() {
-                        Either::Left(value) => value.into_int_value(),
-                        Either::Right(_) => self.i64_type.const_int(0, false)
-                    };
-                    
-                    // Return the result
-                    self.builder.build_return(Some(&return_value)).unwrap();
-                } else {
-                    // No user_main, just return 0
-                    let return_value = self.i64_type.const_int(0, false);
-                    self.builder.build_return(Some(&return_value)).unwrap();
-                }
-            }
+        for debug_unit in self.debug_units.values() {
+            debug_unit.builder.finalize();
         }
-        
+
         // Always verify modules to ensure correctness
         if let Err(err) = self.module.verify() {
-            // Print the generated IR to help debug the verification error
-            if let Some(filename) = std::path::Path::new(&self.file_path).file_name() {
-                if filename.to_string_lossy().contains("benchmark") || 
-                   filename.to_string_lossy().contains("factorial") {
-                    println!("Module verification failed for {}", self.file_path);
-                    println!("Error: {}", err);
-                    
-                    // Save the problematic IR for debugging
-                    let debug_file = format!("{}.debug.ll", self.file_path);
-                    let _ = self.module.print_to_file(&debug_file);
-                    println!("Saved debug IR to {}", debug_file);
-                }
-            }
-            
             return Err(LutError::compiler_error(
                 format!("Module verification error: {}. This may indicate a type mismatch or malformed IR.", 
                         err.to_string()),
@@ -426,9 +622,9 @@ impl<'ctx> LLVMCompiler<'ctx> {
     }
     
     // Compile a function definition
-    fn compile_function(&mut self, name: String, is_public: bool, parameters: Vec<FunctionParam>, body: Vec<Stmt>) -> Result<FunctionValue<'ctx>, LutError> {
+    fn compile_function(&mut self, name: String, is_public: bool, parameters: Vec<FunctionParam>, return_type: Option<String>, body: Vec<Stmt>, line: usize) -> Result<FunctionValue<'ctx>, LutError> {
         // Special case for main function
-        let is_main_function = name == "main";
+        let is_main_function = name == "main" && self.current_module.is_none();
         
         // For the main function, we want to use "user_main" instead
         let function_name = if is_main_function {
@@ -438,19 +634,12 @@ impl<'ctx> LLVMCompiler<'ctx> {
         };
         
         // Get the function if it's already declared (from first pass)
-        let function = if let Some(func) = self.functions.get(&function_name) {
-            *func
+        let function = if let Some(func) = self.lookup_function(&function_name) {
+            func
         } else {
             // If function wasn't pre-registered in the first pass, create it now
-            // Create parameter types
-            let mut param_types = Vec::with_capacity(parameters.len());
-            for _ in &parameters {
-                // All parameters are i64 for now (we can add more types later)
-                param_types.push(self.i64_type.into());
-            }
-            
-            // Create function type
-            let function_type = self.i64_type.fn_type(&param_types, false);
+            let return_type = return_type.as_deref().or_else(|| self.inferred_return_type(&name, &body, &parameters));
+            let function_type = self.function_type_for(&parameters, return_type);
             
             // Create the function
             let linkage = if is_public {
@@ -474,18 +663,39 @@ impl<'ctx> LLVMCompiler<'ctx> {
         // Save current function
         let old_function = self.current_function;
         self.current_function = Some(function);
+        let old_temporaries = std::mem::take(&mut self.temporaries);
+        let old_locals = std::mem::take(&mut self.owned_locals);
+        let old_location = self.builder.get_current_debug_location();
+        self.attach_subprogram(function, &name, line);
+        self.set_debug_line(line);
         
         // Store parameters in local variables
         let mut old_variables = HashMap::new();
         for (i, param) in parameters.iter().enumerate() {
             let param_value = function.get_nth_param(i as u32).unwrap();
             
-            // Create a local variable for the parameter
-            let alloca = self.create_entry_block_alloca(&param.name);
-            
-            // Store parameter - let LLVM handle alignment automatically
-            // Critical: We need consistent alignment for all store operations
-            let _store_inst = self.builder.build_store(alloca, param_value).unwrap();
+            // Create a local variable for the parameter, typed by its annotation
+            let (alloca, param_type) = match param_value {
+                BasicValueEnum::FloatValue(_) => (self.create_float_alloca(&param.name), VariableType::Float),
+                BasicValueEnum::PointerValue(_) if param.param_type == "array" => {
+                    (self.create_pointer_alloca(&param.name), VariableType::Array)
+                },
+                BasicValueEnum::PointerValue(_) => (self.create_pointer_alloca(&param.name), VariableType::String),
+                _ if param.param_type == "bool" => (self.create_entry_block_alloca(&param.name), VariableType::Boolean),
+                _ => (self.create_entry_block_alloca(&param.name), VariableType::Integer),
+            };
+
+            // Booleans are passed as i1 but stored as i64 like every other boolean
+            let param_value = match param_value {
+                BasicValueEnum::IntValue(int_val) if int_val.get_type().get_bit_width() == 1 => {
+                    self.builder.build_int_z_extend(int_val, self.i64_type, "bool_param").unwrap().into()
+                },
+                other => other,
+            };
+
+            // Text and array parameters are retained, since the function releases its variables on return
+            self.store_variable(alloca, param_value);
+            self.declare_debug_variable(&param.name, alloca, &param_type, line, Some(i as u32 + 1));
             
             // Save old variable with the same name if it exists
             if let Some(old_ptr) = self.variables.get(&param.name) {
@@ -496,18 +706,26 @@ impl<'ctx> LLVMCompiler<'ctx> {
             self.variables.insert(param.name.clone(), alloca);
             
             // Add the parameter type
-            self.variable_types.insert(param.name.clone(), VariableType::Integer);
+            self.variable_types.insert(param.name.clone(), param_type);
+            self.array_cell_tags.remove(&param.name);
         }
         
         // Compile function body
+        let return_llvm_type = function.get_type().get_return_type();
+        let returns_number = matches!(return_llvm_type, Some(BasicTypeEnum::IntType(_)));
         let mut return_value = None;
         for (i, stmt) in body.iter().enumerate() {
             // Check if this is the last statement and if it's an expression
             if i == body.len() - 1 {
-                if let Stmt::Expression(expr) = stmt {
-                    // Special case: handle 'ok' as a return value (equivalent to 0)
-                    if let Expr::TextLiteral(s) = &expr {
-                        if s == "ok" {
+                if let Stmt::Expression(expr, expr_line) = stmt {
+                    self.set_debug_line(*expr_line);
+                    if is_main_function && return_llvm_type == Some(self.i64_type.into()) {
+                        return_value = Some(self.compile_main_status(expr.clone())?);
+                    } else if let (Expr::NoneLiteral, Some(target)) = (expr, return_llvm_type) {
+                        return_value = Some(self.none_of(target));
+                    } else if let Expr::TextLiteral(s) = &expr {
+                        // Special case: handle 'ok' as a return value (equivalent to 0)
+                        if s == "ok" && returns_number {
                             // 'ok' is equivalent to returning 0
                             let zero_value = self.i64_type.const_int(0, false).into();
                             return_value = Some(zero_value);
@@ -521,6 +739,13 @@ impl<'ctx> LLVMCompiler<'ctx> {
                         let value = self.compile_expression(expr.clone())?;
                         return_value = Some(value);
                     }
+                } else if let Stmt::If { condition, then_branch, else_branch: Some(else_branch), line } = stmt {
+                    // An if ending the function gives the value of the branch taken
+                    if !is_main_function && ends_with_value(then_branch) && ends_with_value(else_branch) {
+                        return_value = Some(self.compile_if_value(condition.clone(), then_branch, else_branch, *line)?);
+                    } else {
+                        self.compile_statement(stmt.clone())?;
+                    }
                 } else {
                     self.compile_statement(stmt.clone())?;
                 }
@@ -533,22 +758,32 @@ impl<'ctx> LLVMCompiler<'ctx> {
         let current_block = self.builder.get_insert_block().unwrap();
         if !current_block.get_terminator().is_some() {
             // If we have a return value from the last expression, return it
-            if let Some(val) = return_value {
-                // Convert to the appropriate type for return
-                match val {
-                    BasicValueEnum::IntValue(int_val) => {
-                        self.builder.build_return(Some(&int_val)).unwrap();
-                    },
-                    _ => {
-                        // Default to returning 0 for other types
-                        let default_return = self.i64_type.const_int(0, false);
-                        self.builder.build_return(Some(&default_return)).unwrap();
+            match (return_value, return_llvm_type) {
+                (Some(val), Some(target)) => {
+                    // Convert to the declared or inferred return type
+                    match self.coerce_value(val, target) {
+                        Some(converted) => {
+                            // The caller gets its own reference to a returned text or array
+                            self.take_reference(converted);
+                            self.release_temporaries(0);
+                            self.release_locals();
+                            self.builder.build_return(Some(&converted)).unwrap();
+                        },
+                        None => return Err(LutError::compiler_error(
+                            format!(
+                                "Function '{}' must return {}\n\nTip: Change the return type after the parameter list",
+                                name,
+                                self.describe_llvm_type(target)
+                            ),
+                            None
+                        )),
                     }
-                }
-            } else {
-                // Add a default return of 0
-                let default_return = self.i64_type.const_int(0, false);
-                self.builder.build_return(Some(&default_return)).unwrap();
+                },
+                _ => {
+                    self.release_temporaries(0);
+                    self.release_locals();
+                    self.build_default_return(function);
+                },
             }
         }
         
@@ -563,8 +798,7 @@ impl<'ctx> LLVMCompiler<'ctx> {
                     self.builder.build_unconditional_branch(next).unwrap();
                 } else {
                     // This is the last block, add a return
-                    let return_value = self.i64_type.const_int(0, false);
-                    self.builder.build_return(Some(&return_value)).unwrap();
+                    self.build_default_return(function);
                 }
             }
         }
@@ -580,6 +814,12 @@ impl<'ctx> LLVMCompiler<'ctx> {
             
             // Restore old function
             self.current_function = old_function;
+            self.temporaries = old_temporaries;
+            self.owned_locals = old_locals;
+            match old_location {
+                Some(location) => self.builder.set_current_debug_location(location),
+                None => self.builder.unset_current_debug_location(),
+            }
             
             Ok(function)
         } else {
@@ -591,118 +831,748 @@ impl<'ctx> LLVMCompiler<'ctx> {
         }
     }
     
-    fn compile_statement(&mut self, stmt: Stmt) -> Result<(), LutError> {
-        match stmt {
-            Stmt::Function { name, is_public, parameters, body } => {
-                self.compile_function(name, is_public, parameters, body)?;
-            },
-            Stmt::Declaration { name, initializer } => {
-                // Check if this is a variable update (name already exists)
-                if self.variables.contains_key(&name) {
-                    // This is a variable update, not a declaration
-                    let value = self.compile_expression(initializer)?;
-                    if let Some(ptr) = self.variables.get(&name) {
-                        // Store the new value in the existing variable
-                        self.builder.build_store(*ptr, value).unwrap();
-                    } else {
-                        return Err(LutError::compiler_error(
-                            format!("Variable '{}' referenced before declaration", name),
-                            None
-                        ));
-                    }
-                } else {
-                    // This is a new variable declaration
-                    // Check the initializer type before moving it
-                    let is_boolean_expr = match &initializer {
-                        Expr::BooleanLiteral(_) => true,
-                        Expr::Binary { operator, .. } => {
-                            // Check if this is a comparison operator
-                            matches!(
-                                operator.token_type,
-                                TokenType::Equal | TokenType::NotEqual | TokenType::Less |
-                                TokenType::LessEqual | TokenType::Greater | TokenType::GreaterEqual |
-                                TokenType::And | TokenType::Or
-                            )
-                        },
-                        _ => false
-                    };
-
-                    // Create a variable (alloca) in the entry block
-                    let value = self.compile_expression(initializer)?;
-
-                    // Create the appropriate type of alloca based on the value type
-                    let (ptr, var_type) = match value {
-                        BasicValueEnum::IntValue(_) => {
-                            // For both integers and booleans, use i64 type
-                            let ptr = self.create_entry_block_alloca(&name);
+    // Exported functions are called from C, which has no array or dec values
+    fn check_exportable(&self, name: &str, parameters: &[FunctionParam], return_type: Option<&str>, line: usize) -> Result<(), LutError> {
+        let unsupported = parameters
+            .iter()
+            .map(|param| param.param_type.as_str())
+            .chain(return_type)
+            .find(|type_name| matches!(*type_name, "array" | "dec"));
+        match unsupported {
+            Some(type_name) => Err(LutError::compiler_error(
+                format!(
+                    "Function '{}' cannot be exported from a library: {} values have no C type\n\nTip: Make it prot, or pass number, fp, bool or text values",
+                    name, type_name
+                ),
+                Some(line)
+            )),
+            None => Ok(()),
+        }
+    }
 
-                            if is_boolean_expr {
-                                (ptr, VariableType::Boolean)
-                            } else {
-                                (ptr, VariableType::Integer)
-                            }
-                        },
-                        BasicValueEnum::PointerValue(_) => {
-                            // For strings, use pointer type
-                            let ptr = self.create_pointer_alloca(&name);
-                            (ptr, VariableType::String)
-                        },
-                        _ => return Err(LutError::compiler_error("Unsupported variable type", None))
-                    };
+    // External declaration for extern func, under the C function's own name. Two
+    // modules declaring the same C function share its declaration
+    fn declare_extern(&mut self, declaration: &Stmt) -> Result<FunctionValue<'ctx>, LutError> {
+        let Stmt::Extern { name, parameters, return_type, library, line } = declaration else {
+            unreachable!("not an extern func declaration");
+        };
+        foreign::check_signature(name, parameters, return_type.as_deref())
+            .map_err(|e| LutError::compiler_error(e, Some(*line)))?;
+
+        let param_types: Vec<BasicMetadataTypeEnum<'ctx>> = parameters
+            .iter()
+            .map(|param| self.llvm_type_for(Some(&param.param_type)).into())
+            .collect();
+        let function_type = match return_type {
+            Some(type_name) => self.llvm_type_for(Some(type_name)).fn_type(&param_types, false),
+            None => self.context.void_type().fn_type(&param_types, false),
+        };
 
-                    let _store_inst = self.builder.build_store(ptr, value).unwrap(); // Ignoring the result
-                    // Let LLVM handle alignment automatically
-                    self.variables.insert(name.clone(), ptr);
-                    self.variable_types.insert(name, var_type);
-                }
-            },
-            Stmt::Expression(expr) => {
-                // Just evaluate the expression for its side effects
-                self.compile_expression(expr)?;
-            },
-            Stmt::Command { name, args } => {
-                match name.as_str() {
-                    "print" | "-print" => {
-                        for (i, arg) in args.iter().enumerate() {
-                            let value = self.compile_expression(arg.clone())?;
-                            self.print_value(value)?;
-                            
-                            // Print a space between arguments (but not after the last one)
-                            if i < args.len() - 1 {
-                                // Do nothing in this simple version
-                            }
-                        }
-                        // Print newline
-                        self.create_print_string("\\n");
-                    },
-                    _ => return Err(LutError::compiler_error(
-                        format!("Unknown command in LLVM compiler: {}", name),
-                        None
-                    )),
-                }
+        let function = match self.module.get_function(name) {
+            Some(existing) if existing.get_type() == function_type => existing,
+            Some(_) => {
+                return Err(LutError::compiler_error(
+                    format!("extern func '{}' does not match another declaration of '{}'\n\nTip: Declare it with the same types everywhere", name, name),
+                    Some(*line)
+                ));
             },
-            Stmt::Print(exprs) => {
-                for (i, expr) in exprs.iter().enumerate() {
-                    let value = self.compile_expression(expr.clone())?;
-                    self.print_value(value)?;
-                    
-                    // Print a space between arguments (but not after the last one)
-                    if i < exprs.len() - 1 {
-                        // Do nothing in this simple version
+            None => {
+                let function = self.module.add_function(name, function_type, Some(inkwell::module::Linkage::External));
+                // C passes bool as a zero-extended byte
+                let zeroext = self.context.create_enum_attribute(Attribute::get_named_enum_kind_id("zeroext"), 0);
+                for (i, param) in parameters.iter().enumerate() {
+                    if param.param_type == "bool" {
+                        function.add_attribute(AttributeLoc::Param(i as u32), zeroext);
                     }
                 }
-                // Print newline
-                self.create_print_string("\\n");
-            },
-            Stmt::Comment(_) => {
-                // Comments are ignored in the compiled output
+                if return_type.as_deref() == Some("bool") {
+                    function.add_attribute(AttributeLoc::Return, zeroext);
+                }
+                function
             },
-            Stmt::If { condition, then_branch, else_branch } => {
-                // Get the current function
-                let current_function = self.builder.get_insert_block().unwrap().get_parent().unwrap();
+        };
 
-                // Create blocks for the then, else, and merge parts
-                let then_block = self.context.append_basic_block(current_function, "then");
+        let linked_from = self.extern_functions.entry(name.clone()).or_default();
+        if linked_from.is_none() {
+            *linked_from = library.clone();
+        }
+        Ok(function)
+    }
+
+    // Libraries named by extern func ... from 'library', as linker arguments
+    fn extern_link_args(&self, triple: &str) -> Vec<String> {
+        let mut args: Vec<String> = Vec::new();
+        for library in self.extern_functions.values().flatten() {
+            let arg = if library.contains('/') || library.contains('.') {
+                library.clone()
+            } else if triple.contains("msvc") {
+                format!("{}.lib", library)
+            } else {
+                format!("-l{}", library)
+            };
+            if !args.contains(&arg) {
+                args.push(arg);
+            }
+        }
+        args.sort();
+        args
+    }
+
+    // C entry point of a pub function in a --lib build, under the function's own name.
+    // Text arguments are copied into runtime text, since C strings have no refcount header
+    fn build_export(&mut self, export: &ExportedFunction) {
+        let implementation = self.functions[&export.name];
+        let function = self.module.add_function(&export.name, implementation.get_type(), Some(inkwell::module::Linkage::External));
+        if self.target_triple().contains("windows") {
+            function.as_global_value().set_dll_storage_class(DLLStorageClass::Export);
+        }
+
+        // C passes bool as a zero-extended byte
+        let zeroext = self.context.create_enum_attribute(Attribute::get_named_enum_kind_id("zeroext"), 0);
+        for (i, param) in export.parameters.iter().enumerate() {
+            if param.param_type == "bool" {
+                function.add_attribute(AttributeLoc::Param(i as u32), zeroext);
+            }
+        }
+        if export.return_type.as_deref() == Some("bool") {
+            function.add_attribute(AttributeLoc::Return, zeroext);
+        }
+
+        let entry = self.context.append_basic_block(function, "entry");
+        self.builder.position_at_end(entry);
+        let mut args: Vec<BasicMetadataValueEnum<'ctx>> = Vec::new();
+        for (i, param) in export.parameters.iter().enumerate() {
+            let value = function.get_nth_param(i as u32).unwrap();
+            let value = if param.param_type == "text" {
+                self.call_runtime("text_from_c", &[value.into()]).unwrap()
+            } else {
+                value
+            };
+            args.push(value.into());
+        }
+        let result = self.builder.build_call(implementation, &args, "result").unwrap().try_as_basic_value().left();
+        self.release_temporaries(0);
+
+        // The caller's own output may come next, so nothing stays buffered
+        self.call_runtime("flush", &[]);
+        match result {
+            Some(value) => self.builder.build_return(Some(&value)).unwrap(),
+            None => self.builder.build_return(None).unwrap(),
+        };
+    }
+
+    // Run a function when the library is loaded, through llvm.global_ctors
+    fn add_global_constructor(&self, function: FunctionValue<'ctx>) {
+        let i32_type = self.context.i32_type();
+        let ptr_type = self.context.ptr_type(AddressSpace::default());
+        let entry_type = self.context.struct_type(&[i32_type.into(), ptr_type.into(), ptr_type.into()], false);
+        let entry = entry_type.const_named_struct(&[
+            i32_type.const_int(65535, false).into(),
+            function.as_global_value().as_pointer_value().into(),
+            ptr_type.const_null().into(),
+        ]);
+        let constructors = entry_type.const_array(&[entry]);
+        let global = self.module.add_global(constructors.get_type(), None, "llvm.global_ctors");
+        global.set_linkage(inkwell::module::Linkage::Appending);
+        global.set_initializer(&constructors);
+    }
+
+    // Start a compile unit for a .ko file when building with -g
+    fn add_debug_unit(&mut self, module: Option<String>, file: &Path) {
+        if !self.debug_info {
+            return;
+        }
+        let file = fs::canonicalize(file).unwrap_or_else(|_| file.to_path_buf());
+        let file_name = file.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let directory = file.parent().map(|dir| dir.to_string_lossy().to_string()).unwrap_or_default();
+
+        // DWARF has no language code for Kozeig; as C, debuggers show numbers and text as-is
+        let (builder, unit) = self.module.create_debug_info_builder(
+            true,
+            DWARFSourceLanguage::C,
+            &file_name,
+            &directory,
+            concat!("koze ", env!("CARGO_PKG_VERSION")),
+            self.opt_level != OptLevel::O0,
+            "",
+            0,
+            "",
+            DWARFEmissionKind::Full,
+            0,
+            false,
+            false,
+            "",
+            "",
+        );
+        self.debug_units.insert(module, DebugUnit { builder, unit });
+    }
+
+    // Describe a func to the debugger, in the compile unit of the file it is written in
+    fn attach_subprogram(&self, function: FunctionValue<'ctx>, name: &str, line: usize) {
+        let Some(debug_unit) = self.debug_units.get(&self.current_module) else {
+            return;
+        };
+        let file = debug_unit.unit.get_file();
+        let subroutine_type = debug_unit.builder.create_subroutine_type(file, None, &[], DIFlags::ZERO);
+        let linkage_name = function.get_name().to_string_lossy().to_string();
+        let subprogram = debug_unit.builder.create_function(
+            debug_unit.unit.as_debug_info_scope(),
+            name,
+            Some(&linkage_name),
+            file,
+            line as u32,
+            subroutine_type,
+            function.get_linkage() == inkwell::module::Linkage::Private,
+            true,
+            line as u32,
+            DIFlags::ZERO,
+            self.opt_level != OptLevel::O0,
+        );
+        function.set_subprogram(subprogram);
+    }
+
+    // Instructions built from here on belong to this source line
+    // Also the line runtime errors report from here on
+    fn set_debug_line(&mut self, line: usize) {
+        self.current_line = line;
+        let Some(debug_unit) = self.debug_units.get(&self.current_module) else {
+            return;
+        };
+        // Code outside a func (main, the module initializer) has no line info
+        let Some(subprogram) = self.current_function.and_then(|function| function.get_subprogram()) else {
+            return;
+        };
+        let location = debug_unit.builder.create_debug_location(
+            self.context,
+            line as u32,
+            0,
+            subprogram.as_debug_info_scope(),
+            None,
+        );
+        self.builder.set_current_debug_location(location);
+    }
+
+    // Let the debugger show a variable or parameter; arg_no counts parameters from 1
+    fn declare_debug_variable(&self, name: &str, storage: PointerValue<'ctx>, var_type: &VariableType, line: usize, arg_no: Option<u32>) {
+        let Some(debug_unit) = self.debug_units.get(&self.current_module) else {
+            return;
+        };
+        let Some(subprogram) = self.current_function.and_then(|function| function.get_subprogram()) else {
+            return;
+        };
+        let scope = subprogram.as_debug_info_scope();
+        let file = debug_unit.unit.get_file();
+        let ty = self.debug_type(debug_unit, var_type);
+        let variable = match arg_no {
+            Some(arg_no) => debug_unit.builder.create_parameter_variable(scope, name, arg_no, file, line as u32, ty, true, DIFlags::ZERO),
+            None => debug_unit.builder.create_auto_variable(scope, name, file, line as u32, ty, true, DIFlags::ZERO, 0),
+        };
+        let location = debug_unit.builder.create_debug_location(self.context, line as u32, 0, scope, None);
+        let block = self.builder.get_insert_block().unwrap();
+        debug_unit.builder.insert_declare_at_end(storage, Some(variable), None, location, block);
+    }
+
+    fn debug_type(&self, debug_unit: &DebugUnit<'ctx>, var_type: &VariableType) -> DIType<'ctx> {
+        // DWARF base type encodings
+        const DW_ATE_BOOLEAN: u32 = 0x02;
+        const DW_ATE_FLOAT: u32 = 0x04;
+        const DW_ATE_SIGNED: u32 = 0x05;
+        const DW_ATE_SIGNED_CHAR: u32 = 0x06;
+
+        let basic = |name: &str, bits: u64, encoding: u32| {
+            debug_unit.builder.create_basic_type(name, bits, encoding, DIFlags::ZERO).unwrap().as_type()
+        };
+        match var_type {
+            VariableType::Integer => basic("number", 64, DW_ATE_SIGNED),
+            VariableType::Float => basic("fp", 64, DW_ATE_FLOAT),
+            VariableType::Boolean => basic("bool", 64, DW_ATE_BOOLEAN),
+            // Decimals are stored as units of their last digit
+            VariableType::Decimal(_) => basic("dec", 64, DW_ATE_SIGNED),
+            VariableType::String => {
+                let char_type = basic("char", 8, DW_ATE_SIGNED_CHAR);
+                debug_unit.builder.create_pointer_type("text", char_type, 64, 0, AddressSpace::default()).as_type()
+            },
+            VariableType::Array | VariableType::Array2D => {
+                let cell_type = basic("number", 64, DW_ATE_SIGNED);
+                debug_unit.builder.create_pointer_type("array", cell_type, 64, 0, AddressSpace::default()).as_type()
+            },
+        }
+    }
+
+    // Module-level constant: a global computed once by the module initializer
+    fn compile_constant(&mut self, global_name: &str, is_public: bool, initializer: Expr, line: Option<usize>) -> Result<(PointerValue<'ctx>, VariableType), LutError> {
+        let module_init = match self.module_init {
+            Some(module_init) => {
+                self.builder.position_at_end(module_init.get_last_basic_block().unwrap());
+                module_init
+            },
+            None => {
+                let init_type = self.context.void_type().fn_type(&[], false);
+                let module_init = self.module.add_function("koze.module_init", init_type, Some(inkwell::module::Linkage::Private));
+                let entry = self.context.append_basic_block(module_init, "entry");
+                self.builder.position_at_end(entry);
+                self.module_init = Some(module_init);
+                module_init
+            }
+        };
+
+        let decimal_scale = self.decimal_scale(&initializer);
+        let is_boolean = matches!(initializer, Expr::BooleanLiteral(_));
+        // Module constants have no line here; they keep the line of the `use` that loaded them
+        if let Some(line) = line {
+            self.current_line = line;
+        }
+
+        let saved_function = self.current_function.replace(module_init);
+        let mark = self.temporaries.len();
+        let value = self.compile_expression(initializer);
+        self.current_function = saved_function;
+        let value = value?;
+
+        let linkage = if is_public {
+            inkwell::module::Linkage::External
+        } else {
+            inkwell::module::Linkage::Private
+        };
+        let (global, var_type) = match value {
+            BasicValueEnum::IntValue(_) => {
+                let global = self.module.add_global(self.i64_type, None, global_name);
+                global.set_initializer(&self.i64_type.const_zero());
+                let var_type = match decimal_scale {
+                    Some(scale) => VariableType::Decimal(scale),
+                    None if is_boolean => VariableType::Boolean,
+                    None => VariableType::Integer,
+                };
+                (global, var_type)
+            },
+            BasicValueEnum::PointerValue(_) => {
+                let ptr_type = self.context.ptr_type(AddressSpace::default());
+                let global = self.module.add_global(ptr_type, None, global_name);
+                global.set_initializer(&ptr_type.const_null());
+                (global, VariableType::String)
+            },
+            _ => return Err(LutError::compiler_error(format!("Unsupported type for constant '{}'", global_name), line))
+        };
+        global.set_linkage(linkage);
+        self.take_reference(value);
+        self.builder.build_store(global.as_pointer_value(), value).unwrap();
+        self.release_temporaries(mark);
+        if value.is_pointer_value() {
+            self.pointer_constants.push(global.as_pointer_value());
+        }
+
+        Ok((global.as_pointer_value(), var_type))
+    }
+
+    // Resolve one `use` and bind what it imports. `scope` is the importing module, None for the file being built
+    fn compile_import(
+        &mut self,
+        scope: Option<&str>,
+        items: &[ImportItem],
+        module_path: &str,
+        namespace: Option<&str>,
+        wildcard: bool,
+        line: usize,
+    ) -> Result<(), LutError> {
+        if self.dependency_manager.is_none() {
+            let cwd = std::env::current_dir()
+                .map_err(|e| LutError::compiler_error(format!("Failed to get current directory: {}", e), None))?;
+            let mut dep_manager = DependencyManager::new(cwd);
+            let source_path = Path::new(&self.file_path);
+            if let Some(parent) = source_path.parent() {
+                dep_manager.set_current_file_dir(parent.to_path_buf());
+            }
+            dep_manager.set_entry_file(source_path);
+            self.dependency_manager = Some(dep_manager);
+        }
+
+        // A namespace can only stand for one module
+        if let Some(ns) = namespace {
+            let namespaces = match scope {
+                Some(id) => &self.module_scopes[id].namespaces,
+                None => &self.namespaces,
+            };
+            if let Some(existing) = namespaces.get(ns) {
+                if existing != module_path {
+                    let message = format!(
+                        "Namespace '{}' is already used for '{}'\n\nTip: Import '{}' under a different name",
+                        ns, existing, module_path
+                    );
+                    return Err(self.import_error(scope, line, message));
+                }
+            }
+        }
+
+        let taken: HashSet<String> = match scope {
+            Some(id) => self.module_scopes[id].functions.keys().cloned().collect(),
+            None => self
+                .functions
+                .keys()
+                .chain(self.local_function_names.iter())
+                .cloned()
+                .collect(),
+        };
+
+        let dependency_manager = self.dependency_manager.as_mut().unwrap();
+        let resolved = dependency_manager.load_module(module_path).and_then(|module| {
+            let imported = match namespace {
+                Some(_) => Ok(module.exports()),
+                None if wildcard => dependency_manager.resolve_wildcard_import(&module, &taken),
+                None => dependency_manager.resolve_import(&module, items, &taken),
+            }?;
+            dependency_manager.enter_import(scope, line, module_path, &module)?;
+            Ok((module, imported))
+        });
+        let (module, imported) = resolved.map_err(|e| self.import_error(scope, line, e))?;
+
+        // Module constants that fail report the `use` of the file being built, like koze run
+        if scope.is_none() {
+            self.current_line = line;
+        }
+        let compiled = self.compile_module(&module);
+        self.dependency_manager.as_mut().unwrap().exit_import();
+        match scope {
+            None => compiled.map_err(|e| e.or_at_line(line))?,
+            Some(_) => compiled?,
+        }
+
+        if let Some(ns) = namespace {
+            let namespaces = match scope {
+                Some(id) => &mut self.module_scopes.get_mut(id).unwrap().namespaces,
+                None => &mut self.namespaces,
+            };
+            namespaces.insert(ns.to_string(), module_path.to_string());
+        }
+
+        self.bind_imports(scope, &module, namespace, imported);
+        Ok(())
+    }
+
+    fn import_error(&self, scope: Option<&str>, line: usize, message: String) -> LutError {
+        match scope {
+            None => LutError::compiler_error(format!("Import error: {}", message), Some(line)),
+            Some(id) => {
+                let file = self
+                    .dependency_manager
+                    .as_ref()
+                    .map(|dm| dm.display_path(id))
+                    .unwrap_or_else(|| id.to_string());
+                LutError::compiler_error(format!("Import error in {} line {}: {}", file, line, message), None)
+            }
+        }
+    }
+
+    fn bind_imports(&mut self, scope: Option<&str>, module: &LoadedModule, namespace: Option<&str>, imported: ResolvedImport) {
+        let qualify = |name: &str| match namespace {
+            Some(ns) => format!("{}::{}", ns, name),
+            None => name.to_string(),
+        };
+
+        for imported_const in imported.constants {
+            let qualified_name = qualify(&imported_const.name);
+            let source = &self.module_scopes[&module.id];
+            let ptr = source.variables[&imported_const.source_name];
+            let var_type = source.variable_types[&imported_const.source_name].clone();
+
+            let (variables, variable_types, constant_names) = match scope {
+                Some(id) => {
+                    let target = self.module_scopes.get_mut(id).unwrap();
+                    (&mut target.variables, &mut target.variable_types, &mut target.constant_names)
+                },
+                None => (&mut self.variables, &mut self.variable_types, &mut self.constant_names),
+            };
+            variables.insert(qualified_name.clone(), ptr);
+            variable_types.insert(qualified_name.clone(), var_type);
+            constant_names.insert(qualified_name);
+        }
+
+        for imported_func in imported.functions {
+            let qualified_name = qualify(&imported_func.name);
+            let function = self.module_scopes[&module.id].functions[&imported_func.source_name];
+            match scope {
+                Some(id) => {
+                    self.module_scopes.get_mut(id).unwrap().functions.insert(qualified_name, function);
+                },
+                None => {
+                    self.functions.insert(qualified_name, function);
+                },
+            }
+        }
+    }
+
+    // Compile an imported module once: declare its functions under mangled names,
+    // resolve its own imports, then compile its constants and function bodies
+    fn compile_module(&mut self, module: &LoadedModule) -> Result<(), LutError> {
+        if self.module_scopes.contains_key(&module.id) {
+            return Ok(());
+        }
+
+        let prefix = self.module_prefix(&module.id);
+        self.add_debug_unit(Some(module.id.clone()), &module.file);
+        let mut scope = ModuleScope::default();
+        for imported_func in &module.functions {
+            let return_type = imported_func.return_type.as_deref().or_else(|| {
+                self.inferred_return_type(&imported_func.source_name, &imported_func.body, &imported_func.parameters)
+            });
+            let returns_array = return_type == Some("array");
+            let function_type = self.function_type_for(&imported_func.parameters, return_type);
+            let linkage = if imported_func.is_public {
+                inkwell::module::Linkage::External
+            } else {
+                inkwell::module::Linkage::Private
+            };
+            let mangled_name = format!("{}.{}", prefix, imported_func.source_name);
+            let function = self.module.add_function(&mangled_name, function_type, Some(linkage));
+            scope.functions.insert(imported_func.source_name.clone(), function);
+            if returns_array {
+                self.array_functions.insert(mangled_name);
+            }
+        }
+        for declaration in &module.externs {
+            if let Stmt::Extern { name, line, .. } = declaration {
+                if scope.functions.contains_key(name) {
+                    return Err(LutError::compiler_error(format!("Function '{}' is already defined", name), Some(*line)));
+                }
+                let function = self.declare_extern(declaration)?;
+                scope.functions.insert(name.clone(), function);
+            }
+        }
+        self.module_scopes.insert(module.id.clone(), scope);
+
+        // Relative paths in the module are relative to the module itself
+        let dependency_manager = self.dependency_manager.as_mut().unwrap();
+        let saved_dir = dependency_manager.current_file_dir();
+        dependency_manager.set_current_file_dir(module.dir.clone());
+
+        let mut result = Ok(());
+        for stmt in &module.imports {
+            if let Stmt::Import { functions, module_path, namespace, wildcard, line } = stmt {
+                result = self.compile_import(Some(&module.id), functions, module_path, namespace.as_deref(), *wildcard, *line);
+                if result.is_err() {
+                    break;
+                }
+            }
+        }
+
+        self.dependency_manager.as_mut().unwrap().restore_current_file_dir(saved_dir);
+        result?;
+
+        for constant in &module.constants {
+            let saved = self.enter_module(&module.id);
+            let global_name = format!("{}.{}", prefix, constant.source_name);
+            let compiled = self.compile_constant(&global_name, constant.is_public, constant.initializer.clone(), None);
+            self.leave_module(saved);
+            let (ptr, var_type) = compiled?;
+
+            let scope = self.module_scopes.get_mut(&module.id).unwrap();
+            scope.variables.insert(constant.source_name.clone(), ptr);
+            scope.variable_types.insert(constant.source_name.clone(), var_type);
+            scope.constant_names.insert(constant.source_name.clone());
+        }
+
+        let module_file = self.dependency_manager.as_ref().unwrap().display_file(module);
+        let saved_location = (std::mem::replace(&mut self.location_file, module_file), self.current_line);
+        for imported_func in &module.functions {
+            let saved = self.enter_module(&module.id);
+            let compiled = self.compile_function(
+                imported_func.source_name.clone(),
+                imported_func.is_public,
+                imported_func.parameters.clone(),
+                imported_func.return_type.clone(),
+                imported_func.body.clone(),
+                imported_func.line,
+            );
+            self.leave_module(saved);
+            compiled?;
+        }
+        (self.location_file, self.current_line) = saved_location;
+
+        Ok(())
+    }
+
+    // Symbol prefix for a module's functions and constants, unique within the build
+    fn module_prefix(&mut self, module_id: &str) -> String {
+        let stem: String = Path::new(module_id)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+
+        let mut prefix = stem.clone();
+        let mut n = 1;
+        while !self.module_prefixes.insert(prefix.clone()) {
+            n += 1;
+            prefix = format!("{}{}", stem, n);
+        }
+        prefix
+    }
+
+    // Module code sees only its own scope, like in the interpreter
+    fn enter_module(&mut self, module_id: &str) -> SavedScope<'ctx> {
+        let scope = &self.module_scopes[module_id];
+        let variables = scope.variables.clone();
+        let variable_types = scope.variable_types.clone();
+        let constant_names = scope.constant_names.clone();
+        SavedScope {
+            module: self.current_module.replace(module_id.to_string()),
+            variables: std::mem::replace(&mut self.variables, variables),
+            variable_types: std::mem::replace(&mut self.variable_types, variable_types),
+            constant_names: std::mem::replace(&mut self.constant_names, constant_names),
+        }
+    }
+
+    fn leave_module(&mut self, saved: SavedScope<'ctx>) {
+        self.current_module = saved.module;
+        self.variables = saved.variables;
+        self.variable_types = saved.variable_types;
+        self.constant_names = saved.constant_names;
+    }
+
+    fn lookup_function(&self, name: &str) -> Option<FunctionValue<'ctx>> {
+        match &self.current_module {
+            Some(id) => self.module_scopes.get(id)?.functions.get(name).copied(),
+            None => self.functions.get(name).copied(),
+        }
+    }
+
+    fn compile_statement(&mut self, stmt: Stmt) -> Result<(), LutError> {
+        let mark = self.temporaries.len();
+        let outer_line = self.current_line;
+        if let Some(line) = stmt.line() {
+            self.set_debug_line(line);
+        }
+        let scales_before = matches!(stmt, Stmt::If { .. } | Stmt::While { .. } | Stmt::For { .. })
+            .then(|| self.decimal_scales());
+        match stmt {
+            Stmt::Function { name, is_public, parameters, return_type, body, line } => {
+                self.compile_function(name, is_public, parameters, return_type, body, line)?;
+            },
+            Stmt::Declaration { name, initializer, line } => {
+                if self.constant_names.contains(&name) {
+                    return Err(LutError::compiler_error(
+                        format!("Cannot assign to constant '{}'\n\nTip: Declare a new register for the changed value", name),
+                        None
+                    ));
+                }
+
+                // Check if this is a variable update (name already exists)
+                if self.variables.contains_key(&name) {
+                    // This is a variable update, not a declaration
+                    let value: BasicValueEnum<'ctx> = match self.variable_types.get(&name) {
+                        // The register takes the scale of each value assigned to it, as in the interpreter
+                        Some(VariableType::Decimal(_)) => {
+                            let scale = self.decimal_scale(&initializer).unwrap_or(0);
+                            let units = self.compile_decimal_at_scale(&initializer, scale)?;
+                            self.variable_types.insert(name.clone(), VariableType::Decimal(scale));
+                            units.into()
+                        },
+                        // The slot keeps its type, so fp and number values are converted
+                        Some(VariableType::Float) => {
+                            let value = self.compile_expression(initializer)?;
+                            self.coerce_value(value, self.context.f64_type().into()).unwrap_or(value)
+                        },
+                        _ => match self.compile_expression(initializer)? {
+                            BasicValueEnum::FloatValue(float_val) => {
+                                self.builder.build_float_to_signed_int(float_val, self.i64_type, "fp_to_int").unwrap().into()
+                            },
+                            value => value,
+                        },
+                    };
+                    if let Some(&ptr) = self.variables.get(&name) {
+                        // Store the new value in the existing variable
+                        self.store_variable(ptr, value);
+                    } else {
+                        return Err(LutError::compiler_error(
+                            format!("Variable '{}' referenced before declaration", name),
+                            None
+                        ));
+                    }
+                } else {
+                    // This is a new variable declaration
+                    // Check the initializer type before moving it
+                    let is_boolean_expr = match &initializer {
+                        Expr::BooleanLiteral(_) => true,
+                        Expr::Binary { operator, .. } => {
+                            // Check if this is a comparison operator
+                            matches!(
+                                operator.token_type,
+                                TokenType::Equal | TokenType::NotEqual | TokenType::Less |
+                                TokenType::LessEqual | TokenType::Greater | TokenType::GreaterEqual |
+                                TokenType::And | TokenType::Or
+                            )
+                        },
+                        _ => false
+                    };
+                    let decimal_scale = self.decimal_scale(&initializer);
+                    let array_type = if self.is_array_2d_expr(&initializer) {
+                        Some(VariableType::Array2D)
+                    } else if self.is_array_expr(&initializer) {
+                        Some(VariableType::Array)
+                    } else {
+                        None
+                    };
+                    match self.array_element_tag(&initializer) {
+                        Some(tag) if array_type.is_some() => self.array_cell_tags.insert(name.clone(), tag),
+                        _ => self.array_cell_tags.remove(&name),
+                    };
+
+                    // Create a variable (alloca) in the entry block
+                    let value = self.compile_expression(initializer)?;
+
+                    // Create the appropriate type of alloca based on the value type
+                    let (ptr, var_type) = match value {
+                        BasicValueEnum::IntValue(_) => {
+                            // For both integers and booleans, use i64 type
+                            let ptr = self.create_entry_block_alloca(&name);
+
+                            if let Some(scale) = decimal_scale {
+                                (ptr, VariableType::Decimal(scale))
+                            } else if is_boolean_expr {
+                                (ptr, VariableType::Boolean)
+                            } else {
+                                (ptr, VariableType::Integer)
+                            }
+                        },
+                        BasicValueEnum::PointerValue(_) => {
+                            // For strings and arrays, use pointer type
+                            let ptr = self.create_pointer_alloca(&name);
+                            (ptr, array_type.unwrap_or(VariableType::String))
+                        },
+                        BasicValueEnum::FloatValue(_) => (self.create_float_alloca(&name), VariableType::Float),
+                        _ => return Err(LutError::compiler_error("Unsupported variable type", None))
+                    };
+
+                    self.store_variable(ptr, value);
+                    self.declare_debug_variable(&name, ptr, &var_type, line, None);
+                    self.variables.insert(name.clone(), ptr);
+                    self.variable_types.insert(name, var_type);
+                }
+            },
+            Stmt::Expression(expr, _) => {
+                // Just evaluate the expression for its side effects
+                self.compile_expression(expr)?;
+            },
+            Stmt::Command { name, args, .. } => {
+                match name.as_str() {
+                    "print" | "-print" => self.compile_print(&args)?,
+                    _ => return Err(LutError::compiler_error(
+                        format!("Unknown command in LLVM compiler: {}", name),
+                        None
+                    )),
+                }
+            },
+            Stmt::Print(exprs, _) => self.compile_print(&exprs)?,
+            Stmt::Comment(_) => {
+                // Comments are ignored in the compiled output
+            },
+            Stmt::If { condition, then_branch, else_branch, .. } => {
+                // Get the current function
+                let current_function = self.builder.get_insert_block().unwrap().get_parent().unwrap();
+
+                // Create blocks for the then, else, and merge parts
+                let then_block = self.context.append_basic_block(current_function, "then");
                 let else_block = self.context.append_basic_block(current_function, "else");
                 let merge_block = self.context.append_basic_block(current_function, "ifcont");
 
@@ -723,6 +1593,7 @@ impl<'ctx> LLVMCompiler<'ctx> {
                     },
                     _ => return Err(LutError::compiler_error("Expected integer condition in if statement", None))
                 };
+                self.release_temporaries(mark);
 
                 // Create the conditional branch instruction based on the condition
                 self.builder.build_conditional_branch(condition_value, then_block, else_block).unwrap();
@@ -735,7 +1606,7 @@ impl<'ctx> LLVMCompiler<'ctx> {
                 for (i, stmt) in then_branch.iter().enumerate() {
                     // If this is the last statement in an expression context, treat it as return value
                     if i == then_branch.len() - 1 {
-                        if let Stmt::Expression(expr) = stmt {
+                        if let Stmt::Expression(expr, _) = stmt {
                             // This is a terminating expression that should be treated as the return value
                             let value = self.compile_expression(expr.clone())?;
                             then_result = Some(value);
@@ -746,6 +1617,7 @@ impl<'ctx> LLVMCompiler<'ctx> {
                         self.compile_statement(stmt.clone())?;
                     }
                 }
+                self.release_temporaries(mark);
 
                 // Branch to the merge block
                 self.builder.build_unconditional_branch(merge_block).unwrap();
@@ -758,11 +1630,11 @@ impl<'ctx> LLVMCompiler<'ctx> {
 
                 // Compile all statements in the else branch if it exists
                 let mut else_result = None;
-                if let Some(else_statements) = else_branch {
+                if let Some(else_statements) = &else_branch {
                     for (i, stmt) in else_statements.iter().enumerate() {
                         // If this is the last statement in an expression context, treat it as return value
                         if i == else_statements.len() - 1 {
-                            if let Stmt::Expression(expr) = stmt {
+                            if let Stmt::Expression(expr, _) = stmt {
                                 // This is a terminating expression that should be treated as the return value
                                 let value = self.compile_expression(expr.clone())?;
                                 else_result = Some(value);
@@ -774,6 +1646,7 @@ impl<'ctx> LLVMCompiler<'ctx> {
                         }
                     }
                 }
+                self.release_temporaries(mark);
 
                 // Branch to the merge block
                 self.builder.build_unconditional_branch(merge_block).unwrap();
@@ -787,6 +1660,13 @@ impl<'ctx> LLVMCompiler<'ctx> {
                 // If we have result values from either branch, create a PHI node
                 let phi_result = if then_result.is_some() || else_result.is_some() {
                     if let (Some(then_val), Some(else_val)) = (then_result, else_result) {
+                        // A branch that ends in none gives the number none when the other gives a number
+                        let none_number = self.none_of(self.i64_type.into());
+                        let then_val = if ends_with_none(&then_branch) && else_val.is_int_value() { none_number } else { then_val };
+                        let else_val = match &else_branch {
+                            Some(branch) if ends_with_none(branch) && then_val.is_int_value() => none_number,
+                            _ => else_val,
+                        };
                         if let (BasicValueEnum::IntValue(then_int), BasicValueEnum::IntValue(else_int)) = (then_val, else_val) {
                             // Create a PHI node for the result
                             let phi = self.builder.build_phi(self.i64_type, "ifresult").unwrap();
@@ -815,232 +1695,85 @@ impl<'ctx> LLVMCompiler<'ctx> {
                         // We're in a function and have a result value, so return it
                         let current_block = self.builder.get_insert_block().unwrap();
                         if !current_block.get_terminator().is_some() {
-                            match phi_val {
-                                BasicValueEnum::IntValue(int_val) => {
-                                    self.builder.build_return(Some(&int_val)).unwrap();
+                            self.release_locals();
+                            let return_type = current_func.get_type().get_return_type();
+                            match return_type.and_then(|target| self.coerce_value(phi_val, target)) {
+                                Some(value) => {
+                                    self.builder.build_return(Some(&value)).unwrap();
                                 },
-                                _ => {
+                                None => {
                                     // Default to returning 0 for other types
-                                    let default_return = self.i64_type.const_int(0, false);
-                                    self.builder.build_return(Some(&default_return)).unwrap();
+                                    self.build_default_return(current_func);
                                 }
                             }
                         }
                     }
                 }
             },
-            Stmt::While { condition, body } => {
+            Stmt::While { condition, body, .. } => {
                 // Get the current function
                 let current_function = self.builder.get_insert_block().unwrap().get_parent().unwrap();
 
-                // Check for counting loop pattern - this is similar to what we did in the interpreter
-                let mut is_counting_loop = false;
-                let mut counter_var_name = String::new();
-                let mut counter_limit = 0;
-                let mut counting_up = true; // true = count up, false = count down
-                
-                // Look for the pattern "$count < 1000000" or similar
-                if let Expr::Binary { left, operator, right } = &condition {
-                    let op_type = &operator.token_type;
-                    if let (Expr::VariableRef(var_name), Expr::NumberLiteral(limit)) = (&**left, &**right) {
-                        if var_name.starts_with('$') &&
-                           (*op_type == TokenType::Less || *op_type == TokenType::LessEqual) {
-                            // Potential counting up loop
-                            counter_var_name = var_name[1..].to_string();
-                            counter_limit = *limit;
-                            counting_up = true;
-                            
-                            // Now check if body matches pattern
-                            if body.len() == 2 {
-                                // Check for increment pattern: count : $count + 1
-                                if let Stmt::Declaration { name, initializer } = &body[0] {
-                                    if name == &counter_var_name {
-                                        if let Expr::Binary { left: l, operator: op, right: r } = initializer {
-                                            if let (Expr::VariableRef(vname), Expr::NumberLiteral(inc)) = (&**l, &**r) {
-                                                if vname == var_name && op.token_type == TokenType::Plus && *inc == 1 {
-                                                    // Check for print statement
-                                                    if matches!(&body[1], Stmt::Print(exprs) if exprs.len() == 1 &&
-                                                            matches!(&exprs[0], Expr::VariableRef(vname) if vname == var_name)) ||
-                                                       matches!(&body[1], Stmt::Command { name, args } if
-                                                            name == "print" && args.len() == 1 &&
-                                                            matches!(&args[0], Expr::VariableRef(vname) if vname == var_name)) {
-                                                        is_counting_loop = true;
-                                                        self.is_counting_loop = true;
-                                                    }
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-                
-                if is_counting_loop && !self.silent_mode {
-                    // Generate optimized counting loop
-                    println!("Generating optimized counting loop...");
-                    
-                    // Get counter variable
-                    let counter_ptr = *self.variables.get(&counter_var_name).unwrap();
-                    
-                    // Get the initial counter value
-                    let counter_val = self.builder.build_load(self.i64_type, counter_ptr, "counter").unwrap()
-                        .into_int_value();
-                        
-                    // Create the end condition block, loop body, and exit blocks
-                    let header_block = self.context.append_basic_block(current_function, "count_header");
-                    let body_block = self.context.append_basic_block(current_function, "count_body");
-                    let exit_block = self.context.append_basic_block(current_function, "count_exit");
-                    
-                    // Branch to the header block
-                    self.builder.build_unconditional_branch(header_block).unwrap();
-                    self.builder.position_at_end(header_block);
-                    
-                    // Create the loop counter PHI node
-                    let counter_phi = self.builder.build_phi(self.i64_type, "count_iter").unwrap();
-                    // Add the initial value
-                    counter_phi.add_incoming(&[(&counter_val, self.builder.get_insert_block().unwrap())]);
-                    
-                    // Compare with the limit
-                    let limit_val = self.i64_type.const_int(counter_limit as u64, false);
-                    let cmp_result = if counting_up {
-                        self.builder.build_int_compare(
-                            inkwell::IntPredicate::SLT, // <
-                            counter_phi.as_basic_value().into_int_value(),
-                            limit_val,
-                            "count_cmp"
-                        ).unwrap()
-                    } else {
-                        self.builder.build_int_compare(
-                            inkwell::IntPredicate::SGT, // >
-                            counter_phi.as_basic_value().into_int_value(),
-                            limit_val,
-                            "count_cmp"
-                        ).unwrap()
-                    };
-                    
-                    // Conditional branch to either body or exit
-                    self.builder.build_conditional_branch(cmp_result, body_block, exit_block).unwrap();
-                    
-                    // Build the loop body block
-                    self.builder.position_at_end(body_block);
-                    
-                    // Save the old loop exit and continue blocks (for nested loops)
-                    let old_loop_exit = self.current_loop_exit;
-                    let old_loop_continue = self.current_loop_continue;
-                    
-                    // Update the current loop exit and continue blocks
-                    self.current_loop_exit = Some(exit_block);
-                    self.current_loop_continue = Some(header_block); // Continue goes back to header
-                    
-                    // Print the current counter value
-                    let counter_val = counter_phi.as_basic_value();
-                    self.print_value(counter_val)?;
-                    
-                    // Increment the counter
-                    let increment = self.i64_type.const_int(1, false);
-                    let next_counter = if counting_up {
-                        self.builder.build_int_add(
-                            counter_phi.as_basic_value().into_int_value(),
-                            increment,
-                            "next_counter"
-                        ).unwrap()
-                    } else {
-                        self.builder.build_int_sub(
-                            counter_phi.as_basic_value().into_int_value(),
-                            increment,
-                            "next_counter"
-                        ).unwrap()
-                    };
-                    
-                    // Add the incremented counter to the PHI node
-                    counter_phi.add_incoming(&[(&next_counter, self.builder.get_insert_block().unwrap())]);
-                    
-                    // Branch back to the header
-                    self.builder.build_unconditional_branch(header_block).unwrap();
-                    
-                    // Store the final counter value in the variable
-                    self.builder.position_at_end(exit_block);
-                    // Store counter in the variable
-                    let final_counter_val = if counting_up {
-                        // At exit, counter equals limit
-                        limit_val
-                    } else {
-                        // At exit for counting down, counter equals limit - 1
-                        self.builder.build_int_sub(
-                            limit_val,
-                            self.i64_type.const_int(1, false),
-                            "final_counter"
-                        ).unwrap()
-                    };
-                    self.builder.build_store(counter_ptr, final_counter_val).unwrap();
-                    
-                    // Restore the old loop exit and continue blocks
-                    self.current_loop_exit = old_loop_exit;
-                    self.current_loop_continue = old_loop_continue;
-                } else {
-                    // Use the standard loop implementation
-                    // Create basic blocks for the loop
-                    let condition_block = self.context.append_basic_block(current_function, "while_cond");
-                    let body_block = self.context.append_basic_block(current_function, "while_body");
-                    let exit_block = self.context.append_basic_block(current_function, "while_exit");
+                // Create basic blocks for the loop
+                let condition_block = self.context.append_basic_block(current_function, "while_cond");
+                let body_block = self.context.append_basic_block(current_function, "while_body");
+                let exit_block = self.context.append_basic_block(current_function, "while_exit");
 
-                    // Branch to the condition block
-                    self.builder.build_unconditional_branch(condition_block).unwrap();
+                // Branch to the condition block
+                self.builder.build_unconditional_branch(condition_block).unwrap();
 
-                    // Start with the condition block
-                    self.builder.position_at_end(condition_block);
+                // Start with the condition block
+                self.builder.position_at_end(condition_block);
 
-                    // Compile the condition
-                    let condition_value = self.compile_expression(condition.clone())?;
+                // Compile the condition
+                let condition_value = self.compile_expression(condition.clone())?;
 
-                    // Convert to boolean (0 or 1)
-                    let condition_bool = match condition_value {
-                        BasicValueEnum::IntValue(int_val) => {
-                            // Compare with 0 to get a boolean value (0 = false, anything else = true)
-                            let zero = self.i64_type.const_int(0, false);
-                            self.builder.build_int_compare(
-                                inkwell::IntPredicate::NE,
-                                int_val,
-                                zero,
-                                "while_cond"
-                            ).unwrap()
-                        },
-                        _ => return Err(LutError::compiler_error("Expected integer condition in while loop", None))
-                    };
+                // Convert to boolean (0 or 1)
+                let condition_bool = match condition_value {
+                    BasicValueEnum::IntValue(int_val) => {
+                        // Compare with 0 to get a boolean value (0 = false, anything else = true)
+                        let zero = self.i64_type.const_int(0, false);
+                        self.builder.build_int_compare(
+                            inkwell::IntPredicate::NE,
+                            int_val,
+                            zero,
+                            "while_cond"
+                        ).unwrap()
+                    },
+                    _ => return Err(LutError::compiler_error("Expected integer condition in while loop", None))
+                };
+                self.release_temporaries(mark);
 
-                    // Conditional branch: if condition is true, go to body, otherwise exit
-                    self.builder.build_conditional_branch(condition_bool, body_block, exit_block).unwrap();
+                // Conditional branch: if condition is true, go to body, otherwise exit
+                self.builder.build_conditional_branch(condition_bool, body_block, exit_block).unwrap();
 
-                    // Set up the loop body block
-                    self.builder.position_at_end(body_block);
+                // Set up the loop body block
+                self.builder.position_at_end(body_block);
 
-                    // Save the old loop exit and continue blocks (for nested loops)
-                    let old_loop_exit = self.current_loop_exit;
-                    let old_loop_continue = self.current_loop_continue;
+                // Save the old loop exit and continue blocks (for nested loops)
+                let old_loop_exit = self.current_loop_exit;
+                let old_loop_continue = self.current_loop_continue;
 
-                    // Update the current loop exit and continue blocks
-                    self.current_loop_exit = Some(exit_block);
-                    self.current_loop_continue = Some(condition_block); // Continue goes back to condition
+                // Update the current loop exit and continue blocks
+                self.current_loop_exit = Some(exit_block);
+                self.current_loop_continue = Some(condition_block); // Continue goes back to condition
 
-                    // Compile the loop body
-                    for stmt in body {
-                        self.compile_statement(stmt)?;
-                    }
+                // Compile the loop body
+                for stmt in body {
+                    self.compile_statement(stmt)?;
+                }
 
-                    // Restore the old loop exit and continue blocks
-                    self.current_loop_exit = old_loop_exit;
-                    self.current_loop_continue = old_loop_continue;
+                // Restore the old loop exit and continue blocks
+                self.current_loop_exit = old_loop_exit;
+                self.current_loop_continue = old_loop_continue;
 
-                    // Unconditionally branch back to the condition block
-                    self.builder.build_unconditional_branch(condition_block).unwrap();
+                // Unconditionally branch back to the condition block
+                self.builder.build_unconditional_branch(condition_block).unwrap();
 
-                    // Position at the exit block for subsequent code
-                    self.builder.position_at_end(exit_block);
-                }
+                // Position at the exit block for subsequent code
+                self.builder.position_at_end(exit_block);
             },
-            Stmt::For { initializer, update, condition, body } => {
+            Stmt::For { initializer, update, condition, body, line } => {
                 // Get the current function
                 let current_function = self.builder.get_insert_block().unwrap().get_parent().unwrap();
 
@@ -1085,8 +1818,8 @@ impl<'ctx> LLVMCompiler<'ctx> {
                                 };
 
                                 // Store the value in the variable
-                                let _store_inst = self.builder.build_store(ptr, value).unwrap(); // Ignoring the result
-                    // Let LLVM handle alignment automatically
+                                self.store_variable(ptr, value);
+                                self.declare_debug_variable(name, ptr, &var_type, line, None);
 
                                 // Add the variable to our variable maps
                                 self.variables.insert(name.clone(), ptr);
@@ -1107,6 +1840,7 @@ impl<'ctx> LLVMCompiler<'ctx> {
                 };
 
                 // Branch to the condition block
+                self.release_temporaries(mark);
                 self.builder.build_unconditional_branch(condition_block).unwrap();
 
                 // Set up the condition block
@@ -1129,6 +1863,7 @@ impl<'ctx> LLVMCompiler<'ctx> {
                     },
                     _ => return Err(LutError::compiler_error("Expected integer condition in for loop", None))
                 };
+                self.release_temporaries(mark);
 
                 // Conditional branch: if condition is true, go to body, otherwise exit
                 self.builder.build_conditional_branch(condition_bool, body_block, exit_block).unwrap();
@@ -1169,9 +1904,9 @@ impl<'ctx> LLVMCompiler<'ctx> {
                                 let value = self.compile_expression(*right.clone())?;
 
                                 // Get the variable's allocation
-                                if let Some(ptr) = self.variables.get(name) {
+                                if let Some(&ptr) = self.variables.get(name) {
                                     // Store the new value
-                                    self.builder.build_store(*ptr, value).unwrap();
+                                    self.store_variable(ptr, value);
                                 } else {
                                     return Err(LutError::compiler_error(format!("Undefined variable in for loop update: {}", name), None));
                                 }
@@ -1191,9 +1926,9 @@ impl<'ctx> LLVMCompiler<'ctx> {
                                     let result = self.compile_expression(update.clone())?;
 
                                     // Get the variable's allocation
-                                    if let Some(ptr) = self.variables.get(&actual_name) {
+                                    if let Some(&ptr) = self.variables.get(&actual_name) {
                                         // Store the new value
-                                        self.builder.build_store(*ptr, result).unwrap();
+                                        self.store_variable(ptr, result);
                                     } else {
                                         return Err(LutError::compiler_error(format!("Undefined variable in for loop update: {}", actual_name), None));
                                     }
@@ -1214,6 +1949,7 @@ impl<'ctx> LLVMCompiler<'ctx> {
                 };
 
                 // Branch back to the condition block
+                self.release_temporaries(mark);
                 self.builder.build_unconditional_branch(condition_block).unwrap();
 
                 // Position at the exit block for subsequent code
@@ -1247,22 +1983,159 @@ impl<'ctx> LLVMCompiler<'ctx> {
                     return Err(LutError::compiler_error("Continue statement outside of loop", None));
                 }
             }
-            Stmt::Import { functions, module_path } => {
-                // For now, we'll handle imports at compile time by adding the imported functions
-                // to our function table. In the interpreter, we already handle this dynamically.
-                // For the compiler, we'll need to implement a similar resolution mechanism.
-                
-                // TODO: Implement compilation for imported functions
-                // This would involve:
-                // 1. Loading the module (using DependencyManager)
-                // 2. Compiling the imported functions
-                // 3. Linking them into the current module
-                
-                // For now, just skip imports in the compiler
-                eprintln!("Warning: Import statements are not yet supported in compiler mode");
+            Stmt::Const { name, line, .. } => {
+                // Top-level constants were compiled into the module initializer up front
+                if !self.constant_names.contains(&name) {
+                    return Err(LutError::compiler_error(
+                        format!("Constant '{}' must be declared at the top level of the file", name),
+                        Some(line)
+                    ));
+                }
+            },
+            Stmt::Import { .. } => {
+                // Imports were resolved and compiled before anything else in compile()
+            },
+            Stmt::Extern { name, line, .. } => {
+                // Declared up front in compile(), like constants
+                if self.current_function.is_some() {
+                    return Err(LutError::compiler_error(
+                        format!("extern func '{}' must be declared at the top level of the file", name),
+                        Some(line)
+                    ));
+                }
             }
         }
         
+        if let Some(scales_before) = scales_before {
+            self.check_decimal_scales(&scales_before)?;
+        }
+        self.release_temporaries(mark);
+        // What follows a nested statement, like a for loop's update, belongs to the enclosing one
+        self.current_line = outer_line;
+        Ok(())
+    }
+
+    // The value of an if whose branches both give one (see ends_with_value), like a ternary.
+    // A branch ending in none gives the none of the other branch's type
+    fn compile_if_value(&mut self, condition: Expr, then_branch: &[Stmt], else_branch: &[Stmt], line: usize) -> Result<BasicValueEnum<'ctx>, LutError> {
+        let mark = self.temporaries.len();
+        self.set_debug_line(line);
+        let scales_before = self.decimal_scales();
+        let condition_value = match self.compile_expression(condition)? {
+            BasicValueEnum::IntValue(int_val) => {
+                let zero = self.i64_type.const_int(0, false);
+                self.builder.build_int_compare(inkwell::IntPredicate::NE, int_val, zero, "ifcond").unwrap()
+            },
+            _ => return Err(LutError::compiler_error("Expected integer condition in if statement", Some(line))),
+        };
+        self.release_temporaries(mark);
+
+        let current_function = self.builder.get_insert_block().unwrap().get_parent().unwrap();
+        let then_block = self.context.append_basic_block(current_function, "then");
+        let else_block = self.context.append_basic_block(current_function, "else");
+        let merge_block = self.context.append_basic_block(current_function, "ifcont");
+        self.builder.build_conditional_branch(condition_value, then_block, else_block).unwrap();
+
+        // Each branch owns the text or array it gives, since its other temporaries are
+        // released before the merge. The branches to the merge wait until the type is known
+        self.builder.position_at_end(then_block);
+        let then_value = self.compile_branch_value(then_branch)?;
+        self.take_reference(then_value);
+        self.release_temporaries(mark);
+        let then_end = self.builder.get_insert_block().unwrap();
+
+        self.builder.position_at_end(else_block);
+        let else_value = self.compile_branch_value(else_branch)?;
+        self.take_reference(else_value);
+        self.release_temporaries(mark);
+        let else_end = self.builder.get_insert_block().unwrap();
+
+        let then_value = if ends_with_none(then_branch) { self.none_of(else_value.get_type()) } else { then_value };
+        let else_value = if ends_with_none(else_branch) { self.none_of(then_value.get_type()) } else { else_value };
+        // A number and an fp meet as fp, as in arithmetic
+        let result_type = match (then_value, else_value) {
+            (BasicValueEnum::IntValue(_), BasicValueEnum::FloatValue(_)) | (BasicValueEnum::FloatValue(_), BasicValueEnum::IntValue(_)) => {
+                self.context.f64_type().into()
+            },
+            _ => then_value.get_type(),
+        };
+        let mut incoming = Vec::with_capacity(2);
+        for (value, end) in [(then_value, then_end), (else_value, else_end)] {
+            self.builder.position_at_end(end);
+            let value = if value.get_type() == result_type {
+                Some(value)
+            } else if value.is_int_value() && result_type.is_float_type() {
+                self.coerce_value(value, result_type)
+            } else {
+                None
+            };
+            let Some(value) = value else {
+                return Err(LutError::compiler_error(
+                    format!(
+                        "The branches of this if give {} and {}\n\nTip: Convert one branch so both give the same type",
+                        self.describe_llvm_type(then_value.get_type()),
+                        self.describe_llvm_type(else_value.get_type())
+                    ),
+                    Some(line)
+                ));
+            };
+            self.builder.build_unconditional_branch(merge_block).unwrap();
+            incoming.push((value, end));
+        }
+
+        self.builder.position_at_end(merge_block);
+        let phi = self.builder.build_phi(result_type, "ifresult").unwrap();
+        phi.add_incoming(&[(&incoming[0].0, incoming[0].1), (&incoming[1].0, incoming[1].1)]);
+        if let BasicValueEnum::PointerValue(ptr) = phi.as_basic_value() {
+            self.temporaries.push(ptr);
+        }
+        self.check_decimal_scales(&scales_before)?;
+        Ok(phi.as_basic_value())
+    }
+
+    // Run a branch's statements and give its value: its last expression, or the value of
+    // the if that ends it
+    fn compile_branch_value(&mut self, branch: &[Stmt]) -> Result<BasicValueEnum<'ctx>, LutError> {
+        let (last, statements) = branch.split_last().unwrap();
+        for stmt in statements {
+            self.compile_statement(stmt.clone())?;
+        }
+        match last {
+            Stmt::Expression(expr, line) => {
+                self.set_debug_line(*line);
+                self.compile_expression(expr.clone())
+            },
+            Stmt::If { condition, then_branch, else_branch: Some(else_branch), line } => {
+                self.compile_if_value(condition.clone(), then_branch, else_branch, *line)
+            },
+            _ => unreachable!("compile_branch_value needs a branch that ends with a value"),
+        }
+    }
+
+    fn decimal_scales(&self) -> HashMap<String, u32> {
+        self.variable_types
+            .iter()
+            .filter_map(|(name, var_type)| match var_type {
+                VariableType::Decimal(scale) => Some((name.clone(), *scale)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // A dec register's scale is fixed at compile time, so code that runs conditionally or
+    // repeatedly cannot change it; the code after a branch or loop would not know which scale it has
+    fn check_decimal_scales(&self, before: &HashMap<String, u32>) -> Result<(), LutError> {
+        for (name, scale) in before {
+            if let Some(VariableType::Decimal(after)) = self.variable_types.get(name) {
+                if after != scale {
+                    let message = format!(
+                        "dec register '{}' changes from scale {} to scale {} inside an if or a loop\n\nTip: Keep the scale with [ round value, {}, 'half_even' ] when assigning it",
+                        name, scale, after, scale
+                    );
+                    return Err(LutError::compiler_error(message, None));
+                }
+            }
+        }
         Ok(())
     }
     
@@ -1270,8 +2143,8 @@ impl<'ctx> LLVMCompiler<'ctx> {
         match expr {
             Expr::FunctionCall { name, arguments } => {
                 // Clone the function reference to avoid borrowing issues
-                let function_clone = if let Some(function) = self.functions.get(&name) {
-                    *function
+                let function_clone = if let Some(function) = self.lookup_function(&name) {
+                    function
                 } else {
                     return Err(LutError::compiler_error(
                         format!("Undefined function: {}", name),
@@ -1279,25 +2152,40 @@ impl<'ctx> LLVMCompiler<'ctx> {
                     ));
                 };
                 
-                // Compile the arguments
-                let mut compiled_args = Vec::with_capacity(arguments.len());
-                for arg in arguments {
-                    let compiled_arg = self.compile_expression(arg)?;
-                    compiled_args.push(compiled_arg.into());
-                }
-                
                 // Check that we have the right number of arguments
-                if compiled_args.len() != function_clone.count_params() as usize {
+                if arguments.len() != function_clone.count_params() as usize {
                     return Err(LutError::compiler_error(
                         format!(
                             "Function {} takes {} arguments, but {} were provided",
                             name,
                             function_clone.count_params(),
-                            compiled_args.len()
+                            arguments.len()
                         ),
                         None
                     ));
                 }
+
+                // Compile the arguments, converting each to its parameter's type like the interpreter's
+                // parameter_value: fp is never truncated, and a bool or dec is not an fp
+                let param_types = function_clone.get_type().get_param_types();
+                let mut compiled_args: Vec<BasicMetadataValueEnum<'ctx>> = Vec::with_capacity(arguments.len());
+                for (i, (arg, param_type)) in arguments.into_iter().zip(param_types).enumerate() {
+                    let not_fp = self.is_boolean_expr(&arg) || self.decimal_scale(&arg).is_some();
+                    let value = self.compile_expression(arg)?;
+                    let target = BasicTypeEnum::try_from(param_type).unwrap();
+                    let converted = match (value, target) {
+                        (BasicValueEnum::FloatValue(_), BasicTypeEnum::IntType(_)) => None,
+                        (_, BasicTypeEnum::FloatType(_)) if not_fp => None,
+                        _ => self.coerce_value(value, target),
+                    };
+                    match converted {
+                        Some(converted) => compiled_args.push(converted.into()),
+                        None => return Err(LutError::compiler_error(
+                            format!("Argument {} of {} must be {}", i + 1, name, self.describe_llvm_type(target)),
+                            None
+                        )),
+                    }
+                }
                 
                 // Create a unique call ID
                 let call_id = format!("call_{}", self.module.get_globals().count());
@@ -1309,7 +2197,21 @@ impl<'ctx> LLVMCompiler<'ctx> {
                 )?;
                 
                 // Get the return value
+                let is_extern = self.extern_functions.contains_key(function_clone.get_name().to_str().unwrap_or_default());
                 match result.try_as_basic_value() {
+                    // Text from C is copied, the runtime can only release its own
+                    Either::Left(BasicValueEnum::PointerValue(value)) if is_extern => {
+                        Ok(self.call_runtime("text_from_c", &[value.into()]).unwrap())
+                    },
+                    // Booleans come back as i1 and are widened like every other boolean
+                    Either::Left(BasicValueEnum::IntValue(value)) if value.get_type().get_bit_width() == 1 => {
+                        Ok(self.builder.build_int_z_extend(value, self.i64_type, "bool_result").unwrap().into())
+                    },
+                    // Text and arrays come back with a reference the caller owns
+                    Either::Left(BasicValueEnum::PointerValue(value)) => {
+                        self.temporaries.push(value);
+                        Ok(value.into())
+                    },
                     // Function returned a value
                     Either::Left(value) => Ok(value),
                     // Function returned void (should not happen for our functions)
@@ -1318,12 +2220,7 @@ impl<'ctx> LLVMCompiler<'ctx> {
                     }
                 }
             },
-            Expr::FloatLiteral(value) => {
-                // Currently we don't have proper float support in the LLVM compiler
-                // So we convert it to an integer value for now
-                let int_value = self.i64_type.const_int(value as u64, true);
-                Ok(int_value.into())
-            },
+            Expr::FloatLiteral(value) => Ok(self.context.f64_type().const_float(value).into()),
             Expr::Ternary { condition, then_branch, else_branch } => {
                 // Compile the condition
                 let condition_val = self.compile_expression(*condition)?;
@@ -1352,15 +2249,21 @@ impl<'ctx> LLVMCompiler<'ctx> {
                 // Branch based on the condition
                 self.builder.build_conditional_branch(condition_bool, then_block, else_block).unwrap();
 
-                // Build the then block
+                // Build the then block; each branch owns the text or array it produces,
+                // since its other temporaries are released before the merge
                 self.builder.position_at_end(then_block);
+                let mark = self.temporaries.len();
                 let then_value = self.compile_expression(*then_branch)?;
+                self.take_reference(then_value);
+                self.release_temporaries(mark);
                 let then_block_end = self.builder.get_insert_block().unwrap();
                 self.builder.build_unconditional_branch(merge_block).unwrap();
 
                 // Build the else block
                 self.builder.position_at_end(else_block);
                 let else_value = self.compile_expression(*else_branch)?;
+                self.take_reference(else_value);
+                self.release_temporaries(mark);
                 let else_block_end = self.builder.get_insert_block().unwrap();
                 self.builder.build_unconditional_branch(merge_block).unwrap();
 
@@ -1379,6 +2282,14 @@ impl<'ctx> LLVMCompiler<'ctx> {
                         ]);
                         Ok(phi.as_basic_value())
                     },
+                    (BasicValueEnum::FloatValue(then_float), BasicValueEnum::FloatValue(else_float)) => {
+                        let phi = self.builder.build_phi(self.context.f64_type(), "ternary_result").unwrap();
+                        phi.add_incoming(&[
+                            (&then_float, then_block_end),
+                            (&else_float, else_block_end)
+                        ]);
+                        Ok(phi.as_basic_value())
+                    },
                     (BasicValueEnum::PointerValue(then_ptr), BasicValueEnum::PointerValue(else_ptr)) => {
                         // Both branches return pointers (strings)
                         let ptr_type = self.context.ptr_type(AddressSpace::default());
@@ -1387,6 +2298,7 @@ impl<'ctx> LLVMCompiler<'ctx> {
                             (&then_ptr, then_block_end),
                             (&else_ptr, else_block_end)
                         ]);
+                        self.temporaries.push(phi.as_basic_value().into_pointer_value());
                         Ok(phi.as_basic_value())
                     },
                     _ => Err(LutError::compiler_error("Ternary branches must return the same type", None))
@@ -1401,8 +2313,12 @@ impl<'ctx> LLVMCompiler<'ctx> {
 
                         // Use the variable_types map to determine how to load the value
                         match self.variable_types.get(&var_name) {
-                            Some(VariableType::Integer) | Some(VariableType::Boolean) | Some(VariableType::Float) => {
-                                // Load as integer value (float is treated as integer for now)
+                            Some(VariableType::Float) => {
+                                let float_load = self.builder.build_load(self.context.f64_type(), ptr_val, &format!("{}_fp", var_name)).unwrap();
+                                Ok(float_load)
+                            },
+                            Some(VariableType::Integer) | Some(VariableType::Boolean) | Some(VariableType::Decimal(_)) => {
+                                // Load as integer value
                                 let int_load = self.builder.build_load(self.i64_type, ptr_val, &format!("{}_int", var_name)).unwrap();
                                 Ok(int_load)
                             },
@@ -1413,7 +2329,7 @@ impl<'ctx> LLVMCompiler<'ctx> {
                                 Ok(ptr_load)
                             },
                             Some(VariableType::Array) | Some(VariableType::Array2D) => {
-                                // For now, treat arrays as pointers
+                                // Arrays are pointers to their heap block
                                 let ptr_type = self.context.ptr_type(AddressSpace::default());
                                 let ptr_load = self.builder.build_load(ptr_type, ptr_val, &format!("{}_ptr", var_name)).unwrap();
                                 Ok(ptr_load)
@@ -1444,8 +2360,8 @@ impl<'ctx> LLVMCompiler<'ctx> {
                 Ok(int_value.into())
             },
             Expr::TextLiteral(value) => {
-                // Create a heap-allocated string for the text literal
-                let string_ptr = self.create_heap_string(&value);
+                // Literals are immortal globals, so they are never copied or freed
+                let string_ptr = self.create_string_literal(&value);
                 Ok(string_ptr.into())
             },
             Expr::BooleanLiteral(value) => {
@@ -1454,19 +2370,28 @@ impl<'ctx> LLVMCompiler<'ctx> {
                 Ok(bool_value.into())
                 // Note: When storing this in a variable, it will be tagged as VariableType::Boolean
             },
+            Expr::NoneLiteral => {
+                // none is represented by a null pointer
+                let ptr_type = self.context.ptr_type(AddressSpace::default());
+                Ok(ptr_type.const_null().into())
+            },
             Expr::Grouping { expression } => {
                 // Grouping just evaluates the inner expression
                 self.compile_expression(*expression)
             },
             Expr::Unary { operator, right } => {
+                let overflow_message = if self.decimal_scale(&right).is_some() { "Decimal overflow" } else { "Integer overflow" };
                 let right_val = self.compile_expression(*right)?;
+
+                if let (BasicValueEnum::FloatValue(float_val), TokenType::Minus) = (right_val, &operator.token_type) {
+                    return Ok(self.builder.build_float_neg(float_val, "fneg").unwrap().into());
+                }
                 
                 if let BasicValueEnum::IntValue(int_val) = right_val {
                     match operator.token_type {
                         TokenType::Minus => {
                             let zero = self.i64_type.const_int(0, true);
-                            let result = self.builder.build_int_sub(zero, int_val, "neg").unwrap();
-                            Ok(result.into())
+                            Ok(self.build_checked_int_op("llvm.ssub.with.overflow", zero, int_val, overflow_message).into())
                         },
                         TokenType::Not => {
                             // Convert to boolean (0 or 1) and negate
@@ -1516,44 +2441,71 @@ impl<'ctx> LLVMCompiler<'ctx> {
                     }
                 }
 
+                if operator.token_type == TokenType::Coalesce {
+                    return self.compile_coalesce(*left, *right, operator.line);
+                }
+
+                // none only compares for equality; anything else needs an explicit default
+                let left_is_none = matches!(*left, Expr::NoneLiteral);
+                let right_is_none = matches!(*right, Expr::NoneLiteral);
+                if left_is_none || right_is_none {
+                    let is_none = match operator.token_type {
+                        TokenType::Equal => true,
+                        TokenType::NotEqual => false,
+                        _ => return Err(LutError::compiler_error(
+                            format!(
+                                "Cannot use none with '{}'\n\nTip: Provide a default with ?? (e.g. $value ?? 0) or check it with is_none",
+                                operator.lexeme
+                            ),
+                            Some(operator.line)
+                        )),
+                    };
+
+                    let other = if left_is_none { *right } else { *left };
+                    let check = self.compile_none_check(other)?;
+                    let check = if is_none {
+                        check
+                    } else {
+                        self.builder.build_not(check, "not_none").unwrap()
+                    };
+                    let result_ext = self.builder.build_int_z_extend(check, self.i64_type, "zext").unwrap();
+                    return Ok(result_ext.into());
+                }
+
+                // Decimal arithmetic works on scaled integers with a statically known scale
+                if !matches!(operator.token_type, TokenType::And | TokenType::Or)
+                    && (self.decimal_scale(&left).is_some() || self.decimal_scale(&right).is_some())
+                {
+                    return self.compile_decimal_binary(*left, operator, *right);
+                }
+
                 // Regular binary expression (non-assignment)
                 let left_val = self.compile_expression(*left)?;
                 let right_val = self.compile_expression(*right)?;
+                // Adding to text concatenates, like in the interpreter
+                if operator.token_type == TokenType::Plus && (left_val.is_pointer_value() || right_val.is_pointer_value()) {
+                    let left_text = self.value_to_text(left_val)?;
+                    let right_text = self.value_to_text(right_val)?;
+                    return Ok(self.call_runtime("concat", &[left_text.into(), right_text.into()]).unwrap());
+                }
+                if left_val.is_float_value() || right_val.is_float_value() {
+                    return self.compile_float_binary(left_val, &operator, right_val);
+                }
 
-                // Helper function to convert a value to integer if possible
-                let to_int_value = |val: BasicValueEnum<'ctx>| -> Result<inkwell::values::IntValue<'ctx>, String> {
-                    match val {
-                        BasicValueEnum::IntValue(int_val) => Ok(int_val),
-                        BasicValueEnum::PointerValue(ptr_val) => {
-                            // Try to convert string to number using atoll
-                            let result = self.builder.build_call(
-                                self.atoll_func,
-                                &[ptr_val.into()],
-                                "atoll_call"
-                            ).unwrap();
-
-                            Ok(result.try_as_basic_value().left().unwrap().into_int_value())
-                        },
-                        _ => Err("Cannot convert value to integer".to_string())
-                    }
-                };
-
-                // Try to convert both operands to integers
-                let left_int = to_int_value(left_val)?;
-                let right_int = to_int_value(right_val)?;
+                // Text operands of other operators are read as numbers
+                let left_int = self.value_to_int(left_val)?;
+                let right_int = self.value_to_int(right_val)?;
+                self.build_none_operand_check(left_int.into(), right_int.into(), &operator);
 
                 match operator.token_type {
                     TokenType::Plus => {
-                        let result = self.builder.build_int_add(left_int, right_int, "add").unwrap();
-                        Ok(result.into())
+                        Ok(self.build_checked_int_op("llvm.sadd.with.overflow", left_int, right_int, "Integer overflow").into())
                     },
                     TokenType::Minus => {
-                        let result = self.builder.build_int_sub(left_int, right_int, "sub").unwrap();
-                        Ok(result.into())
+                        Ok(self.build_checked_int_op("llvm.ssub.with.overflow", left_int, right_int, "Integer overflow").into())
                     },
                     TokenType::Star => {
-                        let result = self.builder.build_int_mul(left_int, right_int, "mul").unwrap();
-                        Ok(result.into())
+                        Ok(self.build_checked_int_op("llvm.smul.with.overflow", left_int, right_int, "Integer overflow").into())
                     },
                     TokenType::Slash => {
                         // Add division by zero check
@@ -1576,6 +2528,7 @@ impl<'ctx> LLVMCompiler<'ctx> {
 
                         // Division block
                         self.builder.position_at_end(div_block);
+                        self.build_division_overflow_check(left_int, right_int, "Integer overflow");
                         let div_result = self.builder.build_int_signed_div(left_int, right_int, "div").unwrap();
                         self.builder.build_unconditional_branch(cont_block).unwrap();
                         let div_block_end = self.builder.get_insert_block().unwrap();
@@ -1583,18 +2536,8 @@ impl<'ctx> LLVMCompiler<'ctx> {
                         // Division by zero error block
                         self.builder.position_at_end(div_by_zero_block);
 
-                        // Print error message
-                        let error_msg = "Runtime error: Division by zero\n";
-                        let error_ptr = self.create_string_literal(error_msg);
-                        self.builder.build_call(
-                            self.printf_func,
-                            &[error_ptr.into()],
-                            "div_zero_error_msg"
-                        ).unwrap();
-
-                        // Exit with error code - using consistent i64 type
-                        let exit_code = self.i64_type.const_int(1, false);
-                        self.builder.build_return(Some(&exit_code)).unwrap();
+                        // Print error message and exit with status 1
+                        self.build_runtime_abort("Division by zero");
 
                         // Continue block for normal execution
                         self.builder.position_at_end(cont_block);
@@ -1626,6 +2569,7 @@ impl<'ctx> LLVMCompiler<'ctx> {
 
                         // Modulo block
                         self.builder.position_at_end(mod_block);
+                        self.build_division_overflow_check(left_int, right_int, "Integer overflow");
                         let mod_result = self.builder.build_int_signed_rem(left_int, right_int, "mod").unwrap();
                         self.builder.build_unconditional_branch(cont_block).unwrap();
                         let mod_block_end = self.builder.get_insert_block().unwrap();
@@ -1633,18 +2577,8 @@ impl<'ctx> LLVMCompiler<'ctx> {
                         // Modulo by zero error block
                         self.builder.position_at_end(mod_by_zero_block);
 
-                        // Print error message
-                        let error_msg = "Runtime error: Modulo by zero\n";
-                        let error_ptr = self.create_string_literal(error_msg);
-                        self.builder.build_call(
-                            self.printf_func,
-                            &[error_ptr.into()],
-                            "mod_zero_error_msg"
-                        ).unwrap();
-
-                        // Exit with error code - using consistent i64 type
-                        let exit_code = self.i64_type.const_int(1, false);
-                        self.builder.build_return(Some(&exit_code)).unwrap();
+                        // Print error message and exit with status 1
+                        self.build_runtime_abort("Modulo by zero");
 
                         // Continue block for normal execution
                         self.builder.position_at_end(cont_block);
@@ -1774,173 +2708,58 @@ impl<'ctx> LLVMCompiler<'ctx> {
                     _ => Err(LutError::compiler_error(format!("Binary operator not yet implemented: {:?}", operator.token_type), Some(operator.line)))
                 }
             },
-            Expr::ArrayLiteral(elements) => {
-                // Create a string representation of the array for the compiled output
-                let mut array_str = String::from("[");
-
-                // Add array elements as a comma-separated list with placeholders
-                for (i, _) in elements.iter().enumerate() {
-                    if i > 0 {
-                        array_str.push_str(", ");
-                    }
-
-                    // Use a placeholder for each element
-                    array_str.push_str("?");
-                }
-
-                array_str.push_str("]");
-
-                // Create a string literal for the array representation
-                let string_ptr = self.create_heap_string(&array_str);
-                Ok(string_ptr.into())
-            },
-            Expr::ArrayLiteral2D(rows) => {
-                // Create a string representation of the 2D array for the compiled output
-                let mut array_str = String::from("[");
-
-                // Add array rows
-                for (i, row) in rows.iter().enumerate() {
-                    if i > 0 {
-                        array_str.push_str("; ");
-                    }
-
-                    array_str.push_str("[");
-
-                    // Add row elements
-                    for (j, _) in row.iter().enumerate() {
-                        if j > 0 {
-                            array_str.push_str(", ");
-                        }
-
-                        // Use a placeholder for each element
-                        array_str.push_str("?");
-                    }
-
-                    array_str.push_str("]");
-                }
-
-                array_str.push_str("]");
-
-                // Create a string literal for the array representation
-                let string_ptr = self.create_heap_string(&array_str);
-                Ok(string_ptr.into())
-            },
+            Expr::ArrayLiteral(elements) => Ok(self.build_array(1, &elements)?.into()),
+            Expr::ArrayLiteral2D(rows) => Ok(self.build_array_2d(&rows)?.into()),
             Expr::Command { name, args } => {
                 match name.as_str() {
+                    "is_none" => {
+                        if args.len() != 1 {
+                            return Err(LutError::compiler_error("Is_none command expects one argument", None));
+                        }
+
+                        let check = self.compile_none_check(args[0].clone())?;
+                        let result = self.builder.build_int_z_extend(check, self.i64_type, "zext_none").unwrap();
+                        Ok(result.into())
+                    },
+                    "dec" => self.compile_dec_command(&args).map(Into::into),
+                    "div" => self.compile_div_command(&args).map(Into::into),
+                    "round" => self.compile_round_command(&args).map(Into::into),
                     "fp" | "-fp" => {
                         if args.len() != 1 {
                             return Err(LutError::compiler_error("Floating point command expects one argument", None));
                         }
 
-                        // Compile the argument expression
-                        let value = self.compile_expression(args[0].clone())?;
+                        let f64_type = self.context.f64_type();
+                        if let Some(scale) = self.decimal_scale(&args[0]) {
+                            let units = self.compile_decimal_at_scale(&args[0], scale)?;
+                            let units = self.builder.build_signed_int_to_float(units, f64_type, "dec_units").unwrap();
+                            let divisor = f64_type.const_float(10f64.powi(scale as i32));
+                            return Ok(self.builder.build_float_div(units, divisor, "dec_fp").unwrap().into());
+                        }
 
-                        // For now, we just use integers to represent floating point values
-                        match value {
-                            BasicValueEnum::IntValue(int_val) => {
-                                // Already an integer
-                                Ok(int_val.into())
-                            },
+                        match self.compile_expression(args[0].clone())? {
                             BasicValueEnum::PointerValue(ptr_val) => {
-                                // Try to convert string to integer using atoll
-                                let result = self.builder.build_call(
-                                    self.atoll_func,
-                                    &[ptr_val.into()],
-                                    "atoll_call"
-                                ).unwrap();
-
-                                Ok(result.try_as_basic_value().left().unwrap())
+                                Ok(self.call_runtime("text_to_float", &[ptr_val.into()]).unwrap())
                             },
-                            _ => Err(LutError::compiler_error("Cannot convert value to floating point", None))
-                        }
-                    },
-                    "array" | "-array" => {
-                        // Note: For the LLVM compiler, we're implementing basic array support
-                        // that will at least display the array description in compiled output
-
-                        if args.len() == 1 {
-                            match &args[0] {
-                                Expr::ArrayLiteral(elements) => {
-                                    // Create a string representation of the array
-                                    let mut array_str = String::from("[");
-
-                                    // Add array elements as a comma-separated list
-                                    for (i, _element) in elements.iter().enumerate() {
-                                        if i > 0 {
-                                            array_str.push_str(", ");
-                                        }
-
-                                        // Add placeholder for element - we could evaluate here
-                                        // for a more complete implementation
-                                        array_str.push_str("?");
-                                    }
-
-                                    array_str.push_str("]");
-
-                                    // Create a string literal for the array representation
-                                    let string_ptr = self.create_heap_string(&array_str);
-                                    return Ok(string_ptr.into());
-                                },
-                                Expr::ArrayLiteral2D(rows) => {
-                                    // Create a string representation of the 2D array
-                                    let mut array_str = String::from("[");
-
-                                    // Add array rows
-                                    for (i, row) in rows.iter().enumerate() {
-                                        if i > 0 {
-                                            array_str.push_str("; ");
-                                        }
-
-                                        array_str.push_str("[");
-
-                                        // Add row elements
-                                        for (j, _) in row.iter().enumerate() {
-                                            if j > 0 {
-                                                array_str.push_str(", ");
-                                            }
-
-                                            // Add placeholder for element
-                                            array_str.push_str("?");
-                                        }
-
-                                        array_str.push_str("]");
-                                    }
-
-                                    array_str.push_str("]");
-
-                                    // Create a string literal for the array representation
-                                    let string_ptr = self.create_heap_string(&array_str);
-                                    return Ok(string_ptr.into());
-                                },
-                                _ => {
-                                    // Try to evaluate the argument
-                                    return self.compile_expression(args[0].clone());
-                                }
-                            }
+                            value => self.coerce_value(value, f64_type.into())
+                                .ok_or_else(|| LutError::compiler_error("Cannot convert value to floating point", None)),
                         }
-
-                        // If we get here, create a generic array placeholder
-                        let array_str = "[array]";
-                        let string_ptr = self.create_heap_string(array_str);
-                        Ok(string_ptr.into())
                     },
+                    "array" | "-array" => Ok(self.compile_array_command(&args)?.into()),
+                    "length" | "-length" => self.compile_length_command(&args),
+                    "width" | "-width" => self.compile_width_command(&args),
+                    "get" | "-get" | "get2d" | "-get2d" => self.compile_get_command(&name, &args),
                     "hex" | "-hex" => {
                         if args.len() != 1 {
                             return Err(LutError::compiler_error("Hex command expects one argument", None));
                         }
-
-                        // Compile the argument expression - for now we just treat it as a number
-                        let value = self.compile_expression(args[0].clone())?;
-                        Ok(value)
+                        self.compile_radix_command(&args[0], 16)
                     },
                     "bin" | "-bin" => {
                         if args.len() != 1 {
                             return Err(LutError::compiler_error("Binary command expects one argument", None));
                         }
-
-                        // Compile the argument expression - for now we just treat it as a number
-                        let value = self.compile_expression(args[0].clone())?;
-                        Ok(value)
+                        self.compile_radix_command(&args[0], 2)
                     },
                     "text" | "-text" => {
                         if args.len() != 1 {
@@ -1950,41 +2769,30 @@ impl<'ctx> LLVMCompiler<'ctx> {
                         // If the argument is already a string literal, just return that
                         match &args[0] {
                             Expr::TextLiteral(value) => {
-                                let string_ptr = self.create_heap_string(value);
+                                let string_ptr = self.create_string_literal(value);
                                 // String type is indicated by the PointerValue return
                                 Ok(string_ptr.into())
                             },
-                            // If it's another expression, compile it and convert to string
+                            // Decimals are formatted with all their digits
+                            expr if self.decimal_scale(expr).is_some() => {
+                                let scale = self.decimal_scale(expr).unwrap_or(0);
+                                let units = self.compile_decimal_at_scale(expr, scale)?;
+                                let scale = self.i64_type.const_int(scale as u64, false);
+                                Ok(self.call_runtime("decimal_to_text", &[units.into(), scale.into()]).unwrap())
+                            },
+                            expr if self.is_boolean_expr(expr) => {
+                                let value = self.compile_expression(expr.clone())?;
+                                let is_true = self.coerce_value(value, self.context.bool_type().into())
+                                    .ok_or_else(|| LutError::compiler_error("Cannot convert value to text", None))?
+                                    .into_int_value();
+                                let true_text = self.create_string_literal("true");
+                                let false_text = self.create_string_literal("false");
+                                Ok(self.builder.build_select(is_true, true_text, false_text, "bool_text").unwrap())
+                            },
+                            // Anything else is formatted by the runtime
                             expr => {
                                 let value = self.compile_expression(expr.clone())?;
-                                match value {
-                                    // If it's a string already, return it
-                                    BasicValueEnum::PointerValue(ptr_val) => {
-                                        // Already a string
-                                        Ok(ptr_val.into())
-                                    },
-                                    // If it's an integer or boolean, convert it to string
-                                    BasicValueEnum::IntValue(int_val) => {
-                                        // First create a format string for sprintf
-                                        let format = "%lld";
-                                        let format_ptr = self.create_string_literal(format);
-
-                                        // Allocate buffer for result (20 chars should be enough for int64)
-                                        let buffer_size = 20;
-                                        let buffer = self.allocate_string(buffer_size);
-
-                                        // Call sprintf
-                                        self.builder.build_call(
-                                            self.sprintf_func,
-                                            &[buffer.into(), format_ptr.into(), int_val.into()],
-                                            "sprintf_call"
-                                        ).unwrap();
-
-                                        // Result is a string (pointer)
-                                        Ok(buffer.into())
-                                    },
-                                    _ => Err(LutError::compiler_error("Cannot convert value to text", None))
-                                }
+                                Ok(self.value_to_text(value)?.into())
                             }
                         }
                     },
@@ -2063,19 +2871,8 @@ impl<'ctx> LLVMCompiler<'ctx> {
                                 // Already an integer
                                 Ok(int_val.into())
                             },
-                            // If it's a string, try to convert to integer
-                            BasicValueEnum::PointerValue(ptr_val) => {
-                                // Call atoll to convert string to integer
-                                let result = self.builder.build_call(
-                                    self.atoll_func,
-                                    &[ptr_val.into()],
-                                    "atoll_call"
-                                ).unwrap();
-                                
-                                let int_result = result.try_as_basic_value().left().unwrap();
-                                // Result is an integer
-                                Ok(int_result)
-                            },
+                            // Text is parsed by the runtime
+                            BasicValueEnum::PointerValue(_) => Ok(self.value_to_int(value)?.into()),
                             _ => Err(LutError::compiler_error("Cannot convert value to number", None))
                         }
                     },
@@ -2089,35 +2886,7 @@ impl<'ctx> LLVMCompiler<'ctx> {
                         
                         match value {
                             BasicValueEnum::IntValue(int_val) => {
-                                // Allocate 2 bytes (char + null terminator)
-                                let buffer = self.allocate_string(2);
-                                
-                                // Get pointer to buffer as i8*
-                                let buffer_i8_ptr = buffer;
-                                
-                                // Convert int_val to i8 (truncate to ASCII range)
-                                let char_val = self.builder.build_int_truncate(
-                                    int_val,
-                                    self.context.i8_type(),
-                                    "ascii_char"
-                                ).unwrap();
-                                
-                                // Store the character at buffer[0]
-                                self.builder.build_store(buffer_i8_ptr, char_val).unwrap();
-                                
-                                // Store null terminator at buffer[1]
-                                let null_term = self.context.i8_type().const_int(0, false);
-                                let buffer_plus_one = unsafe {
-                                    self.builder.build_gep(
-                                        self.context.i8_type(),
-                                        buffer_i8_ptr,
-                                        &[self.context.i32_type().const_int(1, false)],
-                                        "buffer_plus_one"
-                                    ).unwrap()
-                                };
-                                self.builder.build_store(buffer_plus_one, null_term).unwrap();
-                                
-                                Ok(buffer.into())
+                                Ok(self.call_runtime("char_to_text", &[int_val.into()]).unwrap())
                             },
                             _ => Err(LutError::compiler_error("Asc command expects an integer argument", None))
                         }
@@ -2177,8 +2946,9 @@ impl<'ctx> LLVMCompiler<'ctx> {
         }
     }
     
-    // Helper to create a pointer alloca instruction in the entry block with consistent alignment
-    fn create_pointer_alloca(&self, name: &str) -> PointerValue<'ctx> {
+    // Helper to create a pointer alloca instruction in the entry block with consistent alignment.
+    // The slot starts out as none and is released when the function returns
+    fn create_pointer_alloca(&mut self, name: &str) -> PointerValue<'ctx> {
         let ptr_type = self.context.ptr_type(AddressSpace::default());
         let func = self.builder.get_insert_block().unwrap().get_parent().unwrap();
         let entry = func.get_first_basic_block().unwrap();
@@ -2196,9 +2966,11 @@ impl<'ctx> LLVMCompiler<'ctx> {
                 // Use array_alloca with explicit size for better alignment control
                 let alloca_size = self.context.i32_type().const_int(1, false); // 1 element
                 let alloca = builder.build_array_alloca(ptr_type, alloca_size, &unique_id).unwrap();
+                builder.build_store(alloca, ptr_type.const_null()).unwrap();
                 
                 // Note: We're using default alignment from LLVM
                 
+                self.owned_locals.push(alloca);
                 alloca
             }
             None => {
@@ -2209,14 +2981,30 @@ impl<'ctx> LLVMCompiler<'ctx> {
                 // Use array_alloca with explicit size for better alignment control
                 let alloca_size = self.context.i32_type().const_int(1, false); // 1 element
                 let alloca = builder.build_array_alloca(ptr_type, alloca_size, &unique_id).unwrap();
+                builder.build_store(alloca, ptr_type.const_null()).unwrap();
                 
                 // Note: We're using default alignment from LLVM
                 
                 self.builder.position_at_end(current_block);
+                self.owned_locals.push(alloca);
                 alloca
             }
         }
     }
+
+    fn create_float_alloca(&self, name: &str) -> PointerValue<'ctx> {
+        let func = self.builder.get_insert_block().unwrap().get_parent().unwrap();
+        let entry = func.get_first_basic_block().unwrap();
+        let unique_id = format!("fp_{}.{}", name, func.get_basic_blocks().len());
+
+        // Allocas go at the top of the entry block, like the other variable kinds
+        let builder = self.context.create_builder();
+        match entry.get_first_instruction() {
+            Some(first_instr) => builder.position_before(&first_instr),
+            None => builder.position_at_end(entry),
+        }
+        builder.build_alloca(self.context.f64_type(), &unique_id).unwrap()
+    }
     
     // Create a string literal as a global constant
     fn create_string_literal(&mut self, string_val: &str) -> PointerValue<'ctx> {
@@ -2229,234 +3017,1240 @@ impl<'ctx> LLVMCompiler<'ctx> {
         )
     }
     
-    // Allocate heap memory for a string
-    fn allocate_string(&self, size: u64) -> PointerValue<'ctx> {
-        let size_val = self.i64_type.const_int(size, false);
-        let malloc_call = self.builder.build_call(
-            self.malloc_func,
-            &[size_val.into()],
-            "malloc_call"
-        ).unwrap();
-        
-        malloc_call.try_as_basic_value().left().unwrap().into_pointer_value()
-    }
-    
-    // Create a heap-allocated string from a string literal
-    fn create_heap_string(&mut self, string_val: &str) -> PointerValue<'ctx> {
-        // First, check if we already have identical static constant
-        // If this is a frequently used string, we can directly return the static version
-        if string_val.len() < 32 {  // Only apply this optimization for small strings
-            // Intern the string
-            let _interned = self.string_pool.intern(string_val.to_string());
-            // Check if this string has already been compiled as a global constant
-            if let Some(&ptr) = self.string_pool.global_strings.get(string_val) {
-                // No need to allocate if we already have it
-                return ptr;
+    // Print the arguments separated by spaces, then a newline
+    fn compile_print(&mut self, args: &[Expr]) -> Result<(), LutError> {
+        for (i, arg) in args.iter().enumerate() {
+            if i > 0 {
+                self.create_print_string(" ");
             }
+            self.print_expression(arg)?;
         }
-        
-        // Otherwise, allocate memory for the string (+1 for null terminator)
-        let size = string_val.len() as u64 + 1;
-        let heap_ptr = self.allocate_string(size);
-        
-        // Use a more efficient method for small strings
-        if string_val.len() <= 32 {
-            // For small strings, we can use memcpy intrinsic
-            let src_ptr = self.create_string_literal(string_val);
-            // Use the modern llvm.memcpy intrinsic with correct signature
-            let void_ptr_type = self.context.ptr_type(AddressSpace::default());
-            let i1_type = self.context.bool_type();
-            
-            // Modern memcpy signature: void @llvm.memcpy.p0.p0.i64(ptr dest, ptr src, i64 size, i1 isvolatile)
-            let memcpy_type = self.context.void_type().fn_type(
-                &[
-                    void_ptr_type.into(),     // dest ptr
-                    void_ptr_type.into(),     // src ptr
-                    self.i64_type.into(),     // size
-                    i1_type.into()            // is_volatile
-                ],
-                false // not variadic
-            );
-            
-            // Get or create the memcpy intrinsic using the modern name
-            let memcpy_fn = self.module.get_function("llvm.memcpy.p0.p0.i64")
-                .unwrap_or_else(|| {
-                    self.module.add_function(
-                        "llvm.memcpy.p0.p0.i64", 
-                        memcpy_type, 
-                        None
-                    )
-                });
-            
-            // Call memcpy to copy the string with the correct arguments
-            self.builder.build_call(
-                memcpy_fn,
-                &[
-                    heap_ptr.into(),                              // dest pointer
-                    src_ptr.into(),                               // source pointer
-                    self.i64_type.const_int(size, false).into(),  // size in bytes
-                    i1_type.const_int(0, false).into()            // is_volatile=false
-                ],
-                "memcpy_call"
-            ).unwrap();
-        } else {
-            // For larger strings, use the old character-by-character approach
-            // Copy the string data to the allocated memory
-            for (i, byte) in string_val.bytes().enumerate() {
-                // Get pointer to character position
-                let char_ptr = unsafe {
-                    self.builder.build_gep(
-                        self.context.i8_type(),
-                        heap_ptr,
-                        &[self.context.i32_type().const_int(i as u64, false)],
-                        &format!("char_ptr_{}", i)
-                    ).unwrap()
-                };
-                
-                // Store the character
-                let char_val = self.context.i8_type().const_int(byte as u64, false);
-                self.builder.build_store(char_ptr, char_val).unwrap();
+        self.create_print_string("\n");
+        Ok(())
+    }
+
+    // Print an expression the way the interpreter displays its value
+    fn print_expression(&mut self, expr: &Expr) -> Result<(), LutError> {
+        if self.is_array_expr(expr) {
+            let array = self.compile_expression(expr.clone())?;
+            self.call_runtime("print_array", &[array.into()]);
+            return Ok(());
+        }
+        // Elements are printed from their cell, so mixed arrays print each value as stored
+        if let Expr::Command { name, args } = expr {
+            if matches!(name.as_str(), "get" | "-get" | "get2d" | "-get2d") {
+                let cell = self.compile_cell_access(name, args)?;
+                self.call_runtime("print_cell", &[cell.into()]);
+                return Ok(());
             }
-            
-            // Add null terminator
-            let null_ptr = unsafe {
-                self.builder.build_gep(
-                    self.context.i8_type(),
-                    heap_ptr,
-                    &[self.context.i32_type().const_int(string_val.len() as u64, false)],
-                    "null_ptr"
-                ).unwrap()
-            };
-            
-            let null_char = self.context.i8_type().const_int(0, false);
-            self.builder.build_store(null_ptr, null_char).unwrap();
         }
-        
-        heap_ptr
+        // Booleans are i64 0/1 once compiled, so the runtime is told to name them
+        if self.is_boolean_expr(expr) {
+            let value = self.compile_expression(expr.clone())?;
+            let value = self.coerce_value(value, self.i64_type.into())
+                .ok_or_else(|| LutError::compiler_error("Unsupported value type for printing", None))?;
+            self.call_runtime("print_bool", &[value.into()]);
+            return Ok(());
+        }
+
+        if let Some(scale) = self.decimal_scale(expr) {
+            let units = self.compile_decimal_at_scale(expr, scale)?;
+            let scale = self.i64_type.const_int(scale as u64, false);
+            self.call_runtime("print_decimal", &[units.into(), scale.into()]);
+            return Ok(());
+        }
+
+        let value = self.compile_expression(expr.clone())?;
+        self.print_value(value)
     }
-    
-    fn print_value(&mut self, value: BasicValueEnum<'ctx>) -> Result<(), LutError> {
-        // Create a unique printf call id to avoid name conflicts
-        let call_id = format!("printf_call_{}", self.module.get_globals().count());
 
+    fn print_value(&mut self, value: BasicValueEnum<'ctx>) -> Result<(), LutError> {
         match value {
-            BasicValueEnum::IntValue(int_val) => {
-                // Create format string for integer: "%lld"
-                let format = "%lld";
-                let format_ptr = self.create_string_literal(format);
-
-                // Build call with improved error handling
-                self.handle_llvm_err(
-                    self.builder.build_call(
-                        self.printf_func,
-                        &[format_ptr.into(), int_val.into()],
-                        &call_id
-                    ),
-                    "integer printf"
-                )?;
-
-                Ok(())
+            BasicValueEnum::IntValue(_) => {
+                let value = self.coerce_value(value, self.i64_type.into()).unwrap();
+                self.call_runtime("print_int", &[value.into()]);
             },
+            // A null pointer is none, which the runtime prints as such
             BasicValueEnum::PointerValue(ptr_val) => {
-                // Assume pointer is a string and print it with "%s"
-                let format = "%s";
-                let format_ptr = self.create_string_literal(format);
-
-                // Build call with improved error handling
-                self.handle_llvm_err(
-                    self.builder.build_call(
-                        self.printf_func,
-                        &[format_ptr.into(), ptr_val.into()],
-                        &call_id
-                    ),
-                    "string printf"
-                )?;
-
-                Ok(())
+                self.call_runtime("print_text", &[ptr_val.into()]);
             },
-            _ => Err(LutError::compiler_error("Unsupported value type for printing", None))
+            BasicValueEnum::FloatValue(float_val) => {
+                self.call_runtime("print_float", &[float_val.into()]);
+            },
+            _ => return Err(LutError::compiler_error("Unsupported value type for printing", None)),
         }
+        Ok(())
     }
     
-    // Write the module to a file
-    pub fn write_to_file(&self, filename: &str) -> Result<(), LutError> {
-        // Write the LLVM IR to the file
-        if let Err(e) = self.module.print_to_file(filename) {
-            return Err(LutError::compiler_error(
-                format!("Error writing LLVM IR to file: {}", e.to_string()),
-                None
-            ));
-        }
-        
-        // Now that we've fixed the core issue with main function handling,
-        // we don't need to post-process the IR anymore
-        if filename.contains("benchmark") || filename.contains("factorial") || filename.contains("simple_factorial") {
-            println!("Generated LLVM IR for {}", filename);
-        }
-        
-        Ok(())
+    // Text for a value, formatted by the runtime like the interpreter's Display
+    fn value_to_text(&mut self, value: BasicValueEnum<'ctx>) -> Result<PointerValue<'ctx>, LutError> {
+        let text = match value {
+            BasicValueEnum::PointerValue(ptr_val) => return Ok(ptr_val),
+            BasicValueEnum::IntValue(_) => {
+                let value = self.coerce_value(value, self.i64_type.into()).unwrap();
+                self.call_runtime("int_to_text", &[value.into()])
+            },
+            BasicValueEnum::FloatValue(float_val) => self.call_runtime("float_to_text", &[float_val.into()]),
+            _ => return Err(LutError::compiler_error("Cannot convert value to text", None)),
+        };
+        Ok(text.unwrap().into_pointer_value())
     }
-    
-    // JIT compile and execute the module
-    pub fn jit_compile_and_run(&self) -> Result<(), LutError> {
-        // Create JIT execution engine with better error message
-        let execution_engine = self.handle_llvm_err(
-            self.module.create_jit_execution_engine(OptimizationLevel::Default),
-            "JIT execution engine creation"
-        )?;
 
-        unsafe {
-            // Get the main function with improved error message
-            let main_fn = self.handle_llvm_err(
-                execution_engine.get_function::<unsafe extern "C" fn() -> i32>("main"),
-                "retrieving main function for JIT execution"
-            )?;
+    // Integer for a value; text is parsed by the runtime, which reports text that is not a number
+    fn value_to_int(&mut self, value: BasicValueEnum<'ctx>) -> Result<IntValue<'ctx>, LutError> {
+        match value {
+            BasicValueEnum::IntValue(int_val) => Ok(int_val),
+            BasicValueEnum::PointerValue(ptr_val) => {
+                Ok(self.call_runtime("text_to_int", &[ptr_val.into()]).unwrap().into_int_value())
+            },
+            _ => Err(LutError::compiler_error("Cannot convert value to integer", None)),
+        }
+    }
+
+    // i1 that is set when the expression has no value
+    fn compile_none_check(&mut self, expr: Expr) -> Result<IntValue<'ctx>, LutError> {
+        let value = self.compile_expression(expr)?;
+        Ok(self.build_is_none(value).unwrap_or_else(|| self.context.bool_type().const_int(0, false)))
+    }
+
+    // $value ?? default, only evaluating the default when the value is none
+    fn compile_coalesce(&mut self, left: Expr, right: Expr, line: usize) -> Result<BasicValueEnum<'ctx>, LutError> {
+        let left_val = self.compile_expression(left)?;
+        let Some(is_none) = self.build_is_none(left_val) else {
+            // Booleans always have a value
+            return Ok(left_val);
+        };
+
+        let current_function = self.builder.get_insert_block().unwrap().get_parent().unwrap();
+        let default_block = self.context.append_basic_block(current_function, "coalesce_default");
+        let merge_block = self.context.append_basic_block(current_function, "coalesce_cont");
+
+        // Both edges into the merge carry a reference the result owns
+        self.take_reference(left_val);
+        let value_block = self.builder.get_insert_block().unwrap();
+        self.builder.build_conditional_branch(is_none, default_block, merge_block).unwrap();
+
+        self.builder.position_at_end(default_block);
+        let mark = self.temporaries.len();
+        let default_val = self.compile_expression(right)?;
+        // Compiled values have one type, so the default must have the value's type
+        if default_val.get_type() != left_val.get_type() {
+            return Err(LutError::compiler_error(
+                format!(
+                    "The default of ?? must be {}, like the value, in compiled code\n\nTip: Convert the default, e.g. [ text value ]",
+                    self.describe_llvm_type(left_val.get_type())
+                ),
+                Some(line)
+            ));
+        }
+        self.take_reference(default_val);
+        self.release_temporaries(mark);
+        let default_end = self.builder.get_insert_block().unwrap();
+        self.builder.build_unconditional_branch(merge_block).unwrap();
+
+        self.builder.position_at_end(merge_block);
+        let phi = self.builder.build_phi(left_val.get_type(), "coalesce").unwrap();
+        phi.add_incoming(&[(&left_val, value_block), (&default_val, default_end)]);
+        if let BasicValueEnum::PointerValue(ptr) = phi.as_basic_value() {
+            self.temporaries.push(ptr);
+        }
+        Ok(phi.as_basic_value())
+    }
+
+    // Statically known scale of a decimal expression, None if the expression is not a decimal
+    fn decimal_scale(&self, expr: &Expr) -> Option<u32> {
+        match expr {
+            Expr::VariableRef(name) => match self.variable_types.get(name.trim_start_matches('$')) {
+                Some(VariableType::Decimal(scale)) => Some(*scale),
+                _ => None,
+            },
+            Expr::Command { name, args } => match (name.as_str(), args.as_slice()) {
+                ("dec", [_, Expr::NumberLiteral(scale)]) => Some(*scale as u32),
+                ("dec", [Expr::TextLiteral(text)]) => Some(decimal::literal_scale(text)),
+                ("dec", [Expr::FloatLiteral(f)]) => {
+                    Some(Decimal::from_f64(*f).map(|d| d.scale).unwrap_or(0))
+                },
+                ("dec", [value]) => Some(self.decimal_scale(value).unwrap_or(0)),
+                ("div", [_, _, Expr::NumberLiteral(scale), _]) => Some(*scale as u32),
+                ("round", [_, Expr::NumberLiteral(scale), _]) => Some(*scale as u32),
+                ("get" | "-get" | "get2d" | "-get2d", [array, ..]) => match self.array_element_tag(array) {
+                    Some(tag) if tag >= ARRAY_TAG_DECIMAL => Some((tag - ARRAY_TAG_DECIMAL) as u32),
+                    _ => None,
+                },
+                _ => None,
+            },
+            Expr::Grouping { expression } => self.decimal_scale(expression),
+            Expr::Unary { operator, right } if operator.token_type == TokenType::Minus => {
+                self.decimal_scale(right)
+            },
+            Expr::Binary { left, operator, right } => {
+                let (left_scale, right_scale) = (self.decimal_scale(left), self.decimal_scale(right));
+                if left_scale.is_none() && right_scale.is_none() {
+                    return None;
+                }
+
+                let (left_scale, right_scale) = (left_scale.unwrap_or(0), right_scale.unwrap_or(0));
+                match operator.token_type {
+                    TokenType::Plus | TokenType::Minus => Some(left_scale.max(right_scale)),
+                    TokenType::Star => Some(left_scale + right_scale),
+                    // Comparisons produce booleans
+                    _ => None,
+                }
+            },
+            _ => None,
+        }
+    }
+
+    // Compile a dec or number operand to its units and scale (numbers have scale 0)
+    fn compile_decimal_operand(&mut self, expr: &Expr) -> Result<(IntValue<'ctx>, u32), LutError> {
+        let is_float = match expr {
+            Expr::FloatLiteral(_) => true,
+            Expr::Command { name, .. } => name == "fp" || name == "-fp",
+            Expr::VariableRef(name) => {
+                self.variable_types.get(name.trim_start_matches('$')) == Some(&VariableType::Float)
+            },
+            _ => false,
+        };
+        if is_float {
+            return Err(LutError::compiler_error(
+                "Cannot mix dec and fp values\n\nTip: Convert the fp value with [ dec value ] first",
+                None
+            ));
+        }
+
+        let scale = self.decimal_scale(expr).unwrap_or(0);
+        match self.compile_expression(expr.clone())? {
+            BasicValueEnum::IntValue(units) => Ok((units, scale)),
+            _ => Err(LutError::compiler_error(
+                "Expected a dec or number value\n\nTip: Only text literals can be converted with dec when compiling",
+                None
+            )),
+        }
+    }
+
+    // Compile a decimal expression at a given scale, adding digits if needed
+    fn compile_decimal_at_scale(&mut self, expr: &Expr, scale: u32) -> Result<IntValue<'ctx>, LutError> {
+        let (units, from) = self.compile_decimal_operand(expr)?;
+        if from > scale {
+            return Err(LutError::compiler_error(
+                format!(
+                    "A dec value with scale {} does not fit in scale {} without rounding\n\nTip: Use [ round value, {}, 'half_even' ] to choose how digits are dropped",
+                    from, scale, scale
+                ),
+                None
+            ));
+        }
+        Ok(self.scale_up(units, scale - from))
+    }
+
+    fn scale_up(&mut self, units: IntValue<'ctx>, digits: u32) -> IntValue<'ctx> {
+        if digits == 0 {
+            return units;
+        }
+        match 10i64.checked_pow(digits) {
+            Some(factor) => {
+                let factor = self.i64_type.const_int(factor as u64, false);
+                self.build_checked_int_op("llvm.smul.with.overflow", units, factor, "Decimal overflow")
+            },
+            // Only zero has room for that many more digits
+            None => {
+                let is_zero = self.builder.build_int_compare(inkwell::IntPredicate::EQ, units, self.i64_type.const_zero(), "dec_is_zero").unwrap();
+                let function = self.builder.get_insert_block().unwrap().get_parent().unwrap();
+                self.build_runtime_check(function, is_zero, "Decimal overflow");
+                units
+            },
+        }
+    }
+
+    // Scales and rounding modes are fixed at compile time
+    fn literal_decimal_scale(&self, expr: &Expr, command: &str) -> Result<u32, LutError> {
+        match expr {
+            Expr::NumberLiteral(n) if (0..=decimal::MAX_SCALE as i64).contains(n) => Ok(*n as u32),
+            _ => Err(LutError::compiler_error(
+                format!(
+                    "The scale of {} must be a number literal between 0 and {} when compiling",
+                    command,
+                    decimal::MAX_SCALE
+                ),
+                None
+            )),
+        }
+    }
+
+    fn literal_rounding_mode(&self, expr: &Expr, command: &str) -> Result<RoundingMode, LutError> {
+        match expr {
+            Expr::TextLiteral(mode) => {
+                RoundingMode::parse(mode).map_err(|e| LutError::compiler_error(e, None))
+            },
+            _ => Err(LutError::compiler_error(
+                format!("The rounding mode of {} must be a text literal when compiling", command),
+                None
+            )),
+        }
+    }
+
+    fn compile_dec_command(&mut self, args: &[Expr]) -> Result<IntValue<'ctx>, LutError> {
+        if args.is_empty() || args.len() > 2 {
+            return Err(LutError::compiler_error("Dec command expects a value and an optional scale", None));
+        }
+
+        let target = match args.get(1) {
+            Some(scale) => Some(self.literal_decimal_scale(scale, "dec")?),
+            None => None,
+        };
+
+        // Literals are converted exactly at compile time
+        let literal = match &args[0] {
+            Expr::TextLiteral(text) => Some(Decimal::parse(text)),
+            Expr::FloatLiteral(f) => Some(Decimal::from_f64(*f)),
+            Expr::NumberLiteral(n) => Some(Ok(Decimal::from_int(*n))),
+            _ => None,
+        };
+
+        if let Some(parsed) = literal {
+            let mut value = parsed.map_err(|e| LutError::compiler_error(e, None))?;
+            if let Some(scale) = target {
+                value = value.rescale_exact(scale).map_err(|e| LutError::compiler_error(e, None))?;
+            }
+
+            let units = i64::try_from(value.units).map_err(|_| LutError::compiler_error(
+                format!("Decimal value {} does not fit in 64 bits", value),
+                None
+            ))?;
+            return Ok(self.i64_type.const_int(units as u64, true));
+        }
+
+        match target {
+            Some(scale) => self.compile_decimal_at_scale(&args[0], scale),
+            None => self.compile_decimal_operand(&args[0]).map(|(units, _)| units),
+        }
+    }
+
+    fn compile_div_command(&mut self, args: &[Expr]) -> Result<IntValue<'ctx>, LutError> {
+        if args.len() != 4 {
+            return Err(LutError::compiler_error(
+                format!(
+                    "Div command expects a dividend, a divisor, a scale and a rounding mode, got {} arguments",
+                    args.len()
+                ),
+                None
+            ));
+        }
+
+        let (dividend, dividend_scale) = self.compile_decimal_operand(&args[0])?;
+        let (divisor, divisor_scale) = self.compile_decimal_operand(&args[1])?;
+        let scale = self.literal_decimal_scale(&args[2], "div")?;
+        let mode = self.literal_rounding_mode(&args[3], "div")?;
+
+        // a / 10^sa divided by b / 10^sb at scale s is (a * 10^(s + sb - sa)) / b
+        let shift = scale as i64 + divisor_scale as i64 - dividend_scale as i64;
+        let (numerator, denominator) = if shift >= 0 {
+            (self.scale_up(dividend, shift as u32), divisor)
+        } else {
+            (dividend, self.scale_up(divisor, (-shift) as u32))
+        };
+
+        self.build_rounded_division(numerator, denominator, mode)
+    }
+
+    fn compile_round_command(&mut self, args: &[Expr]) -> Result<IntValue<'ctx>, LutError> {
+        if args.len() != 3 {
+            return Err(LutError::compiler_error(
+                format!(
+                    "Round command expects a value, a scale and a rounding mode, got {} arguments",
+                    args.len()
+                ),
+                None
+            ));
+        }
+
+        let (units, from) = self.compile_decimal_operand(&args[0])?;
+        let scale = self.literal_decimal_scale(&args[1], "round")?;
+        let mode = self.literal_rounding_mode(&args[2], "round")?;
+
+        if scale >= from {
+            return Ok(self.scale_up(units, scale - from));
+        }
+
+        // Compiled decs are 64-bit, so at most 18 digits can be dropped at once
+        let Some(divisor) = 10i64.checked_pow(from - scale) else {
+            return Err(LutError::compiler_error(
+                format!(
+                    "round cannot drop {} digits of a dec with scale {} in compiled code\n\nTip: Round the operands to a smaller scale before multiplying them",
+                    from - scale, from
+                ),
+                None
+            ));
+        };
+        let divisor = self.i64_type.const_int(divisor as u64, false);
+        self.build_rounded_division(units, divisor, mode)
+    }
+
+    fn compile_decimal_binary(&mut self, left: Expr, operator: Token, right: Expr) -> Result<BasicValueEnum<'ctx>, LutError> {
+        let (left_units, left_scale) = self.compile_decimal_operand(&left)?;
+        let (right_units, right_scale) = self.compile_decimal_operand(&right)?;
+
+        // Products keep every digit, the scales simply add up
+        if operator.token_type == TokenType::Star {
+            let result = self.build_checked_int_op("llvm.smul.with.overflow", left_units, right_units, "Decimal overflow");
+            return Ok(result.into());
+        }
+
+        if matches!(operator.token_type, TokenType::Slash | TokenType::Percent) {
+            return Err(LutError::compiler_error(
+                "Decimal division needs a scale and a rounding mode\n\nTip: Use [ div a, b, 2, 'half_even' ] instead of '/'",
+                Some(operator.line)
+            ));
+        }
+
+        // Everything else works on both sides brought to the same scale
+        let scale = left_scale.max(right_scale);
+        let left_units = self.scale_up(left_units, scale - left_scale);
+        let right_units = self.scale_up(right_units, scale - right_scale);
+
+        let predicate = match operator.token_type {
+            TokenType::Plus => {
+                return Ok(self.build_checked_int_op("llvm.sadd.with.overflow", left_units, right_units, "Decimal overflow").into());
+            },
+            TokenType::Minus => {
+                return Ok(self.build_checked_int_op("llvm.ssub.with.overflow", left_units, right_units, "Decimal overflow").into());
+            },
+            TokenType::Equal => inkwell::IntPredicate::EQ,
+            TokenType::NotEqual => inkwell::IntPredicate::NE,
+            TokenType::Less => inkwell::IntPredicate::SLT,
+            TokenType::LessEqual => inkwell::IntPredicate::SLE,
+            TokenType::Greater => inkwell::IntPredicate::SGT,
+            TokenType::GreaterEqual => inkwell::IntPredicate::SGE,
+            _ => return Err(LutError::compiler_error(
+                format!("Invalid operator for decimal values: {:?}", operator.token_type),
+                Some(operator.line)
+            )),
+        };
+
+        let result = self.builder.build_int_compare(predicate, left_units, right_units, "dec_cmp").unwrap();
+        let result_ext = self.builder.build_int_z_extend(result, self.i64_type, "zext").unwrap();
+        Ok(result_ext.into())
+    }
+
+    // Integer division rounded with a compile-time rounding mode, mirroring decimal::div_round
+    fn build_rounded_division(
+        &mut self,
+        numerator: IntValue<'ctx>,
+        denominator: IntValue<'ctx>,
+        mode: RoundingMode
+    ) -> Result<IntValue<'ctx>, LutError> {
+        let zero = self.i64_type.const_int(0, false);
+        let one = self.i64_type.const_int(1, false);
+        let is_zero = self.builder.build_int_compare(
+            inkwell::IntPredicate::EQ,
+            denominator,
+            zero,
+            "is_zero"
+        ).unwrap();
+
+        let current_function = self.builder.get_insert_block().unwrap().get_parent().unwrap();
+        let div_block = self.context.append_basic_block(current_function, "dec_div");
+        let div_by_zero_block = self.context.append_basic_block(current_function, "dec_div_by_zero");
+        let cont_block = self.context.append_basic_block(current_function, "dec_div_cont");
+
+        self.builder.build_conditional_branch(is_zero, div_by_zero_block, div_block).unwrap();
+
+        // Division block
+        self.builder.position_at_end(div_block);
+        self.build_division_overflow_check(numerator, denominator, "Decimal overflow");
+        let quotient = self.builder.build_int_signed_div(numerator, denominator, "dec_q").unwrap();
+        let remainder = self.builder.build_int_signed_rem(numerator, denominator, "dec_r").unwrap();
+
+        let remainder_abs = self.build_int_abs(remainder, "dec_r_abs");
+        let denominator_abs = self.build_int_abs(denominator, "dec_d_abs");
+        let numerator_negative = self.builder.build_int_compare(inkwell::IntPredicate::SLT, numerator, zero, "n_neg").unwrap();
+        let denominator_negative = self.builder.build_int_compare(inkwell::IntPredicate::SLT, denominator, zero, "d_neg").unwrap();
+        let negative = self.builder.build_xor(numerator_negative, denominator_negative, "dec_neg").unwrap();
+        let has_remainder = self.builder.build_int_compare(inkwell::IntPredicate::NE, remainder, zero, "has_rem").unwrap();
+
+        // Compare the remainder against the other half without doubling it, so it cannot overflow
+        let other_half = self.builder.build_int_sub(denominator_abs, remainder_abs, "other_half").unwrap();
+        let above_half = self.builder.build_int_compare(inkwell::IntPredicate::SGT, remainder_abs, other_half, "above_half").unwrap();
+        let at_half = self.builder.build_int_compare(inkwell::IntPredicate::EQ, remainder_abs, other_half, "at_half").unwrap();
+
+        let bool_type = self.context.bool_type();
+        let rounds_away = match mode {
+            RoundingMode::Up => bool_type.const_int(1, false),
+            RoundingMode::Down => bool_type.const_int(0, false),
+            RoundingMode::Ceiling => self.builder.build_not(negative, "ceil").unwrap(),
+            RoundingMode::Floor => negative,
+            RoundingMode::HalfUp => self.builder.build_or(above_half, at_half, "half_up").unwrap(),
+            RoundingMode::HalfDown => above_half,
+            RoundingMode::HalfEven => {
+                let low_bit = self.builder.build_and(quotient, one, "low_bit").unwrap();
+                let is_odd = self.builder.build_int_compare(inkwell::IntPredicate::NE, low_bit, zero, "is_odd").unwrap();
+                let tie_to_even = self.builder.build_and(at_half, is_odd, "tie_odd").unwrap();
+                self.builder.build_or(above_half, tie_to_even, "half_even").unwrap()
+            },
+        };
+        let rounds_away = self.builder.build_and(has_remainder, rounds_away, "rounds_away").unwrap();
+
+        // Rounding away moves the quotient one unit further from zero, which can overflow too
+        let minus_one = self.i64_type.const_all_ones();
+        let away = self.builder.build_select(negative, minus_one, one, "q_away").unwrap().into_int_value();
+        let step = self.builder.build_select(rounds_away, away, zero, "q_step").unwrap().into_int_value();
+        let div_result = self.build_checked_int_op("llvm.sadd.with.overflow", quotient, step, "Decimal overflow");
+        self.builder.build_unconditional_branch(cont_block).unwrap();
+        let div_block_end = self.builder.get_insert_block().unwrap();
+
+        // Division by zero error block
+        self.builder.position_at_end(div_by_zero_block);
+        self.build_runtime_abort("Division by zero");
+
+        // Continue block for normal execution
+        self.builder.position_at_end(cont_block);
+        let phi = self.builder.build_phi(self.i64_type, "dec_div_phi").unwrap();
+        phi.add_incoming(&[(&div_result, div_block_end)]);
+
+        Ok(phi.as_basic_value().into_int_value())
+    }
+
+    fn build_int_abs(&self, value: IntValue<'ctx>, name: &str) -> IntValue<'ctx> {
+        let zero = self.i64_type.const_int(0, false);
+        let is_negative = self.builder.build_int_compare(inkwell::IntPredicate::SLT, value, zero, "is_neg").unwrap();
+        let negated = self.builder.build_int_sub(zero, value, "negated").unwrap();
+        self.builder.build_select(is_negative, negated, value, name).unwrap().into_int_value()
+    }
+
+    // What a function without a return annotation returns, told from the value its body
+    // ends with: an array, text, or a number (None). main's text is its exit status
+    fn inferred_return_type(&self, name: &str, body: &[Stmt], parameters: &[FunctionParam]) -> Option<&'static str> {
+        self.branch_value_type(body, body, parameters)
+            .filter(|type_name| name != "main" || *type_name == "array")
+    }
+
+    // Type of the value a branch ends with; an if takes the type of a branch that is not a
+    // number, since the other can only match it or be none
+    fn branch_value_type(&self, branch: &[Stmt], body: &[Stmt], parameters: &[FunctionParam]) -> Option<&'static str> {
+        match branch.last() {
+            Some(Stmt::Expression(expr, _)) => self.value_type(expr, body, parameters, true),
+            Some(Stmt::If { then_branch, else_branch: Some(else_branch), .. }) => self
+                .branch_value_type(then_branch, body, parameters)
+                .or_else(|| self.branch_value_type(else_branch, body, parameters)),
+            _ => None,
+        }
+    }
+
+    // "array" or "text" for an expression giving one, looking through the body's declarations
+    // for variables; 'ok' is a status, not text
+    fn value_type(&self, expr: &Expr, body: &[Stmt], parameters: &[FunctionParam], follow_variables: bool) -> Option<&'static str> {
+        match expr {
+            Expr::ArrayLiteral(_) | Expr::ArrayLiteral2D(_) => Some("array"),
+            Expr::TextLiteral(text) if text != "ok" => Some("text"),
+            Expr::Command { name, args } => match name.as_str() {
+                "array" | "-array" => Some("array"),
+                "text" | "-text" | "asc" | "-asc" => Some("text"),
+                "get" | "-get" | "get2d" | "-get2d" => match args.first().and_then(|array| self.array_element_tag(array)) {
+                    Some(ARRAY_TAG_TEXT) => Some("text"),
+                    Some(ARRAY_TAG_ARRAY) => Some("array"),
+                    _ => None,
+                },
+                _ => None,
+            },
+            Expr::FunctionCall { name, .. } => match self.lookup_function(name)?.get_type().get_return_type() {
+                Some(BasicTypeEnum::PointerType(_)) if self.returns_array(name) => Some("array"),
+                Some(BasicTypeEnum::PointerType(_)) => Some("text"),
+                _ => None,
+            },
+            Expr::Binary { left, operator, right } => match operator.token_type {
+                TokenType::Plus => [left, right]
+                    .into_iter()
+                    .any(|side| self.value_type(side, body, parameters, follow_variables) == Some("text"))
+                    .then_some("text"),
+                TokenType::Coalesce => self.value_type(left, body, parameters, follow_variables),
+                _ => None,
+            },
+            Expr::Grouping { expression } => self.value_type(expression, body, parameters, follow_variables),
+            Expr::VariableRef(name) if follow_variables => {
+                let name = name.trim_start_matches('$');
+                match parameters.iter().find(|param| param.name == name) {
+                    Some(param) => match param.param_type.as_str() {
+                        "array" => Some("array"),
+                        "text" => Some("text"),
+                        _ => None,
+                    },
+                    None => body.iter().find_map(|stmt| match stmt {
+                        Stmt::Declaration { name: declared, initializer, .. } if declared == name => {
+                            self.value_type(initializer, body, parameters, false)
+                        },
+                        _ => None,
+                    }),
+                }
+            },
+            _ => None,
+        }
+    }
 
-            // Store the return value for potential error reporting
-            let result = main_fn.call();
+    // Whether an expression evaluates to an array in compiled code
+    fn is_array_expr(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::ArrayLiteral(_) | Expr::ArrayLiteral2D(_) => true,
+            Expr::Command { name, args } => match name.as_str() {
+                "array" | "-array" => true,
+                "get" | "-get" | "get2d" | "-get2d" => {
+                    args.first().and_then(|array| self.array_element_tag(array)) == Some(ARRAY_TAG_ARRAY)
+                },
+                _ => false,
+            },
+            Expr::VariableRef(name) => matches!(
+                self.variable_types.get(name.trim_start_matches('$')),
+                Some(VariableType::Array) | Some(VariableType::Array2D)
+            ),
+            Expr::FunctionCall { name, .. } => self.returns_array(name),
+            Expr::Grouping { expression } => self.is_array_expr(expression),
+            _ => false,
+        }
+    }
 
-            // Check if the program ended with an error code
-            if result != 0 {
-                return Err(LutError::runtime_error(
-                    format!("Program exited with non-zero status code: {}", result),
+    fn is_array_2d_expr(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::ArrayLiteral2D(_) => true,
+            Expr::Command { name, args } if name == "array" || name == "-array" => match args.as_slice() {
+                [Expr::ArrayLiteral2D(_)] => true,
+                [Expr::ArrayLiteral(_)] => false,
+                _ => args.iter().any(|arg| matches!(arg, Expr::Command { name, .. } if name == "array")),
+            },
+            Expr::VariableRef(name) => {
+                self.variable_types.get(name.trim_start_matches('$')) == Some(&VariableType::Array2D)
+            },
+            Expr::Grouping { expression } => self.is_array_2d_expr(expression),
+            _ => false,
+        }
+    }
+
+    fn returns_array(&self, function_name: &str) -> bool {
+        self.lookup_function(function_name)
+            .map(|function| self.array_functions.contains(function.get_name().to_str().unwrap_or_default()))
+            .unwrap_or(false)
+    }
+
+    fn is_boolean_expr(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::BooleanLiteral(_) => true,
+            Expr::Binary { operator, .. } => matches!(
+                operator.token_type,
+                TokenType::Equal | TokenType::NotEqual | TokenType::Less |
+                TokenType::LessEqual | TokenType::Greater | TokenType::GreaterEqual |
+                TokenType::And | TokenType::Or
+            ),
+            Expr::Unary { operator, .. } => operator.token_type == TokenType::Not,
+            Expr::Command { name, .. } => name == "is_none",
+            Expr::VariableRef(name) => {
+                self.variable_types.get(name.trim_start_matches('$')) == Some(&VariableType::Boolean)
+            },
+            Expr::Grouping { expression } => self.is_boolean_expr(expression),
+            _ => false,
+        }
+    }
+
+    // Cell tag an element gets, when it can be told without evaluating it
+    fn static_cell_tag(&self, expr: &Expr) -> Option<u64> {
+        match expr {
+            Expr::FloatLiteral(_) => Some(ARRAY_TAG_FLOAT),
+            Expr::TextLiteral(_) | Expr::NoneLiteral => Some(ARRAY_TAG_TEXT),
+            Expr::NumberLiteral(_) => Some(ARRAY_TAG_INT),
+            _ if self.is_array_expr(expr) => Some(ARRAY_TAG_ARRAY),
+            _ if self.is_boolean_expr(expr) => Some(ARRAY_TAG_BOOL),
+            _ => {
+                if let Some(scale) = self.decimal_scale(expr) {
+                    return Some(ARRAY_TAG_DECIMAL + scale as u64);
+                }
+                match expr {
+                    Expr::Command { name, .. } if name == "fp" || name == "-fp" => Some(ARRAY_TAG_FLOAT),
+                    Expr::VariableRef(name) => match self.variable_types.get(name.trim_start_matches('$')) {
+                        Some(VariableType::Integer) => Some(ARRAY_TAG_INT),
+                        Some(VariableType::Float) => Some(ARRAY_TAG_FLOAT),
+                        Some(VariableType::String) => Some(ARRAY_TAG_TEXT),
+                        _ => None,
+                    },
+                    _ => None,
+                }
+            },
+        }
+    }
+
+    // Tag shared by every element of an array, None when it is mixed or unknown
+    fn array_element_tag(&self, array: &Expr) -> Option<u64> {
+        let elements: Vec<&Expr> = match array {
+            Expr::ArrayLiteral(elements) => elements.iter().collect(),
+            Expr::ArrayLiteral2D(rows) => rows.iter().flatten().collect(),
+            Expr::Command { name, args } if name == "array" || name == "-array" => match args.as_slice() {
+                [literal @ Expr::ArrayLiteral(_)] | [literal @ Expr::ArrayLiteral2D(_)] => {
+                    return self.array_element_tag(literal);
+                },
+                _ if self.is_array_2d_expr(array) => args.iter()
+                    .flat_map(|row| match row {
+                        Expr::Command { args, .. } => args.iter().collect::<Vec<_>>(),
+                        other => vec![other],
+                    })
+                    .collect(),
+                _ => args.iter().collect(),
+            },
+            Expr::VariableRef(name) => return self.array_cell_tags.get(name.trim_start_matches('$')).copied(),
+            Expr::Grouping { expression } => return self.array_element_tag(expression),
+            _ => return None,
+        };
+
+        let first = self.static_cell_tag(elements.first()?)?;
+        elements.iter().all(|element| self.static_cell_tag(element) == Some(first)).then_some(first)
+    }
+
+    // Build a heap array from element expressions (see ARRAY_HEADER_WORDS for the layout)
+    fn build_array(&mut self, dims: u64, cells: &[Expr]) -> Result<PointerValue<'ctx>, LutError> {
+        // The runtime allocates the block and fills in the header
+        let dims = self.i64_type.const_int(dims, false);
+        let length = self.i64_type.const_int(cells.len() as u64, false);
+        let array = self.call_runtime("array_new", &[dims.into(), length.into()]).unwrap().into_pointer_value();
+
+        for (i, cell) in cells.iter().enumerate() {
+            let (tag, payload) = self.compile_array_cell(cell)?;
+            let tag_word = self.array_word(array, ARRAY_HEADER_WORDS + 2 * i as u64);
+            self.builder.build_store(tag_word, self.i64_type.const_int(tag, false)).unwrap();
+            let payload_word = self.array_word(array, ARRAY_HEADER_WORDS + 2 * i as u64 + 1);
+            self.builder.build_store(payload_word, payload).unwrap();
+        }
+
+        Ok(array)
+    }
+
+    // 2D arrays are an array of row arrays, so rows may differ in length like in the interpreter
+    fn build_array_2d(&mut self, rows: &[Vec<Expr>]) -> Result<PointerValue<'ctx>, LutError> {
+        let rows: Vec<Expr> = rows.iter().map(|row| Expr::ArrayLiteral(row.clone())).collect();
+        self.build_array(2, &rows)
+    }
+
+    fn compile_array_command(&mut self, args: &[Expr]) -> Result<PointerValue<'ctx>, LutError> {
+        match args {
+            [] => Err(LutError::compiler_error("Array command expects at least one argument", None)),
+            [Expr::ArrayLiteral(elements)] => self.build_array(1, elements),
+            [Expr::ArrayLiteral2D(rows)] => self.build_array_2d(rows),
+            _ if args.iter().any(|arg| matches!(arg, Expr::Command { name, .. } if name == "array")) => {
+                let mut rows = Vec::with_capacity(args.len());
+                for arg in args {
+                    match arg {
+                        Expr::Command { name, args: row } if name == "array" => rows.push(row.clone()),
+                        _ => return Err(LutError::compiler_error("Expected array command for 2D array row", None)),
+                    }
+                }
+                if rows.iter().any(|row| row.len() != rows[0].len()) {
+                    return Err(LutError::compiler_error("All rows in a 2D array must have the same length", None));
+                }
+                self.build_array_2d(&rows)
+            },
+            _ => self.build_array(1, args),
+        }
+    }
+
+    // Compile an element to its (tag, payload) pair
+    fn compile_array_cell(&mut self, expr: &Expr) -> Result<(u64, IntValue<'ctx>), LutError> {
+        if let Some(scale) = self.decimal_scale(expr) {
+            let units = self.compile_decimal_at_scale(expr, scale)?;
+            return Ok((ARRAY_TAG_DECIMAL + scale as u64, units));
+        }
+
+        let tag = self.static_cell_tag(expr).unwrap_or(ARRAY_TAG_INT);
+        match self.compile_expression(expr.clone())? {
+            BasicValueEnum::IntValue(value) => {
+                let value = if value.get_type().get_bit_width() < 64 {
+                    self.builder.build_int_z_extend(value, self.i64_type, "cell_ext").unwrap()
+                } else {
+                    value
+                };
+                Ok((tag, value))
+            },
+            // The array keeps a reference to the text or row it holds
+            BasicValueEnum::PointerValue(ptr) => {
+                let tag = if tag == ARRAY_TAG_ARRAY { ARRAY_TAG_ARRAY } else { ARRAY_TAG_TEXT };
+                self.take_reference(ptr.into());
+                Ok((tag, self.builder.build_ptr_to_int(ptr, self.i64_type, "cell_ptr").unwrap()))
+            },
+            // fp payloads are the bits of the f64
+            BasicValueEnum::FloatValue(value) => {
+                let bits = self.builder.build_bit_cast(value, self.i64_type, "fp_bits").unwrap().into_int_value();
+                Ok((ARRAY_TAG_FLOAT, bits))
+            },
+            _ => Err(LutError::compiler_error("Unsupported value type in array", None)),
+        }
+    }
+
+    // Pointer to the cell a get or get2d command reads, bounds-checked at runtime
+    fn compile_cell_access(&mut self, name: &str, args: &[Expr]) -> Result<PointerValue<'ctx>, LutError> {
+        let is_2d = name.trim_start_matches('-') == "get2d";
+        let expected = if is_2d { 3 } else { 2 };
+        if args.len() != expected {
+            return Err(LutError::compiler_error(
+                if is_2d {
+                    format!("Get2d command expects three arguments (2D array, row, column), got {}", args.len())
+                } else {
+                    format!("Get command expects two arguments (array and index), got {}", args.len())
+                },
+                None
+            ));
+        }
+        if !self.is_array_expr(&args[0]) {
+            return Err(LutError::compiler_error(
+                if is_2d { "First argument to get2d must be a 2D array" } else { "First argument to get must be an array" },
+                None
+            ));
+        }
+
+        let array = self.compile_expression(args[0].clone())?.into_pointer_value();
+        let mut call_args: Vec<BasicMetadataValueEnum<'ctx>> = vec![array.into()];
+        for index in &args[1..] {
+            match self.compile_expression(index.clone())? {
+                BasicValueEnum::IntValue(value) => call_args.push(value.into()),
+                _ => return Err(LutError::compiler_error(
+                    if is_2d { "Array indices must be numbers" } else { "Array index must be a number" },
                     None
-                ));
+                )),
             }
         }
 
+        let function = self.runtime_function(if is_2d { "array_cell2d" } else { "array_cell" });
+        Ok(self.builder.build_call(function, &call_args, "cell").unwrap()
+            .try_as_basic_value().left().unwrap().into_pointer_value())
+    }
+
+    // Value of a get or get2d command, typed from the array's elements when they all agree
+    fn compile_get_command(&mut self, name: &str, args: &[Expr]) -> Result<BasicValueEnum<'ctx>, LutError> {
+        let cell = self.compile_cell_access(name, args)?;
+        let payload_ptr = unsafe {
+            self.builder.build_gep(self.i64_type, cell, &[self.i64_type.const_int(1, false)], "payload_ptr").unwrap()
+        };
+        let payload = self.builder.build_load(self.i64_type, payload_ptr, "payload").unwrap().into_int_value();
+        let ptr_type = self.context.ptr_type(AddressSpace::default());
+
+        match self.array_element_tag(&args[0]) {
+            Some(ARRAY_TAG_TEXT) | Some(ARRAY_TAG_ARRAY) => {
+                Ok(self.builder.build_int_to_ptr(payload, ptr_type, "cell_ptr").unwrap().into())
+            },
+            Some(ARRAY_TAG_FLOAT) => Ok(self.builder.build_bit_cast(payload, self.context.f64_type(), "cell_fp").unwrap()),
+            _ => Ok(payload.into()),
+        }
+    }
+
+    fn compile_length_command(&mut self, args: &[Expr]) -> Result<BasicValueEnum<'ctx>, LutError> {
+        if args.len() != 1 {
+            return Err(LutError::compiler_error(format!("Length command expects one argument, got {}", args.len()), None));
+        }
+
+        let is_array = self.is_array_expr(&args[0]);
+        match self.compile_expression(args[0].clone())? {
+            BasicValueEnum::PointerValue(array) if is_array => {
+                let length_word = self.array_word(array, 1);
+                Ok(self.builder.build_load(self.i64_type, length_word, "array_length").unwrap())
+            },
+            BasicValueEnum::PointerValue(text) => {
+                let result = self.builder.build_call(self.strlen_func, &[text.into()], "strlen_call").unwrap();
+                Ok(result.try_as_basic_value().left().unwrap())
+            },
+            _ => Err(LutError::compiler_error("Cannot get length of non-array/non-text value", None)),
+        }
+    }
+
+    // hex and bin parse text in the runtime; numbers are already what they would parse to
+    fn compile_radix_command(&mut self, arg: &Expr, radix: u64) -> Result<BasicValueEnum<'ctx>, LutError> {
+        match self.compile_expression(arg.clone())? {
+            BasicValueEnum::PointerValue(text) => {
+                let radix = self.i64_type.const_int(radix, false);
+                Ok(self.call_runtime("parse_radix", &[text.into(), radix.into()]).unwrap())
+            },
+            value @ BasicValueEnum::IntValue(_) => Ok(value),
+            _ => Err(LutError::compiler_error(
+                if radix == 16 { "Expected hexadecimal string" } else { "Expected binary string" },
+                None
+            )),
+        }
+    }
+
+    fn compile_width_command(&mut self, args: &[Expr]) -> Result<BasicValueEnum<'ctx>, LutError> {
+        if args.len() != 1 {
+            return Err(LutError::compiler_error(format!("Width command expects one argument, got {}", args.len()), None));
+        }
+        if !self.is_array_expr(&args[0]) {
+            return Err(LutError::compiler_error("Cannot get width of non-2D array", None));
+        }
+
+        let array = self.compile_expression(args[0].clone())?.into_pointer_value();
+        Ok(self.call_runtime("array_width", &[array.into()]).unwrap())
+    }
+
+    fn array_word(&self, array: PointerValue<'ctx>, index: u64) -> PointerValue<'ctx> {
+        let index = self.i64_type.const_int(index, false);
+        unsafe { self.builder.build_gep(self.i64_type, array, &[index], "array_word").unwrap() }
+    }
+
+    // Runtime library functions are declared on first use; `koze build` links the
+    // library and `koze jit` maps its functions into the JIT
+    fn runtime_function(&mut self, name: &str) -> FunctionValue<'ctx> {
+        let symbol = format!("koze_{}", name);
+        if let Some(function) = self.module.get_function(&symbol) {
+            return function;
+        }
+
+        let ptr_type = self.context.ptr_type(AddressSpace::default());
+        let f64_type = self.context.f64_type();
+        let void_type = self.context.void_type();
+        let i64_type = self.i64_type;
+        let function_type = match name {
+            "retain" | "release" | "print_text" | "print_cell" | "print_array" => {
+                void_type.fn_type(&[ptr_type.into()], false)
+            },
+            "runtime_error" => void_type.fn_type(&[ptr_type.into(), ptr_type.into(), i64_type.into()], false),
+            "print_int" | "print_bool" => void_type.fn_type(&[i64_type.into()], false),
+            "print_float" => void_type.fn_type(&[f64_type.into()], false),
+            "print_decimal" => void_type.fn_type(&[i64_type.into(), i64_type.into()], false),
+            "flush" | "check_leaks" => void_type.fn_type(&[], false),
+            "int_to_text" => ptr_type.fn_type(&[i64_type.into()], false),
+            "main_status" => i64_type.fn_type(&[ptr_type.into()], false),
+            "exit_code" => i64_type.fn_type(&[i64_type.into()], false),
+            "char_to_text" => ptr_type.fn_type(&[i64_type.into(), ptr_type.into(), i64_type.into()], false),
+            "float_to_text" => ptr_type.fn_type(&[f64_type.into()], false),
+            "decimal_to_text" | "array_new" => ptr_type.fn_type(&[i64_type.into(), i64_type.into()], false),
+            "concat" => ptr_type.fn_type(&[ptr_type.into(), ptr_type.into(), ptr_type.into(), i64_type.into()], false),
+            "text_from_c" => ptr_type.fn_type(&[ptr_type.into()], false),
+            "text_to_int" => i64_type.fn_type(&[ptr_type.into(), ptr_type.into(), i64_type.into()], false),
+            "text_to_float" => f64_type.fn_type(&[ptr_type.into(), ptr_type.into(), i64_type.into()], false),
+            "parse_radix" => i64_type.fn_type(&[ptr_type.into(), i64_type.into(), ptr_type.into(), i64_type.into()], false),
+            "array_cell" => ptr_type.fn_type(&[ptr_type.into(), i64_type.into(), ptr_type.into(), i64_type.into()], false),
+            "array_cell2d" => {
+                ptr_type.fn_type(&[ptr_type.into(), i64_type.into(), i64_type.into(), ptr_type.into(), i64_type.into()], false)
+            },
+            "array_width" => i64_type.fn_type(&[ptr_type.into(), ptr_type.into(), i64_type.into()], false),
+            _ => unreachable!("unknown runtime function {}", name),
+        };
+        self.module.add_function(&symbol, function_type, Some(inkwell::module::Linkage::External))
+    }
+
+    // Call a runtime function, returning its result if it has one. Functions that can
+    // fail are also given the file and line to report
+    fn call_runtime(&mut self, name: &str, args: &[BasicMetadataValueEnum<'ctx>]) -> Option<BasicValueEnum<'ctx>> {
+        let function = self.runtime_function(name);
+        let mut args = args.to_vec();
+        if RUNTIME_LOCATED.contains(&name) {
+            let file = self.location_file.clone();
+            args.push(self.create_string_literal(&file).into());
+            args.push(self.i64_type.const_int(self.current_line as u64, false).into());
+        }
+        let result = self.builder.build_call(function, &args, name).unwrap().try_as_basic_value().left();
+        if RUNTIME_ALLOCATORS.contains(&name) {
+            self.temporaries.push(result.unwrap().into_pointer_value());
+        }
+        result
+    }
+
+    // Keep a text or array value alive past the current statement: a temporary's
+    // reference is handed over, anything else is retained
+    fn take_reference(&mut self, value: BasicValueEnum<'ctx>) {
+        if let BasicValueEnum::PointerValue(ptr) = value {
+            match self.temporaries.iter().rposition(|temporary| *temporary == ptr) {
+                Some(index) => {
+                    self.temporaries.remove(index);
+                },
+                None => {
+                    self.call_runtime("retain", &[ptr.into()]);
+                },
+            }
+        }
+    }
+
+    // Release the temporaries created since `mark`, unless the block already ended
+    fn release_temporaries(&mut self, mark: usize) {
+        let temporaries = self.temporaries.split_off(mark.min(self.temporaries.len()));
+        if self.builder.get_insert_block().and_then(|block| block.get_terminator()).is_some() {
+            return;
+        }
+        for temporary in temporaries {
+            self.call_runtime("release", &[temporary.into()]);
+        }
+    }
+
+    // Store into a variable; text and array variables release the value they held
+    fn store_variable(&mut self, slot: PointerValue<'ctx>, value: BasicValueEnum<'ctx>) {
+        if !value.is_pointer_value() || !self.owned_locals.contains(&slot) {
+            self.builder.build_store(slot, value).unwrap();
+            return;
+        }
+        let ptr_type = self.context.ptr_type(AddressSpace::default());
+        let old_value = self.builder.build_load(ptr_type, slot, "old_value").unwrap();
+        self.take_reference(value);
+        self.builder.build_store(slot, value).unwrap();
+        self.call_runtime("release", &[old_value.into()]);
+    }
+
+    // Drop the references held by the current function's text and array variables
+    fn release_locals(&mut self) {
+        let ptr_type = self.context.ptr_type(AddressSpace::default());
+        for local in self.owned_locals.clone() {
+            let value = self.builder.build_load(ptr_type, local, "local").unwrap();
+            self.call_runtime("release", &[value.into()]);
+        }
+    }
+
+    // Report a runtime error and exit with status 1
+    fn build_runtime_abort(&mut self, message: &str) {
+        let message = self.create_string_literal(message);
+        self.call_runtime("runtime_error", &[message.into()]);
+        self.builder.build_unreachable().unwrap();
+    }
+
+    // Branch to a block that aborts with the message unless the condition holds
+    fn build_runtime_check(&mut self, function: FunctionValue<'ctx>, ok: IntValue<'ctx>, message: &str) {
+        let ok_block = self.context.append_basic_block(function, "check_ok");
+        let fail_block = self.context.append_basic_block(function, "check_fail");
+        self.builder.build_conditional_branch(ok, ok_block, fail_block).unwrap();
+
+        self.builder.position_at_end(fail_block);
+        self.build_runtime_abort(message);
+        self.builder.position_at_end(ok_block);
+    }
+
+    // LLVM type a parameter or return annotation is passed as; numbers and unannotated values are i64
+    fn llvm_type_for(&self, type_name: Option<&str>) -> BasicTypeEnum<'ctx> {
+        match type_name {
+            Some("fp") => self.context.f64_type().into(),
+            Some("text") | Some("array") => self.context.ptr_type(AddressSpace::default()).into(),
+            Some("bool") => self.context.bool_type().into(),
+            _ => self.i64_type.into(),
+        }
+    }
+
+    fn function_type_for(&self, parameters: &[FunctionParam], return_type: Option<&str>) -> FunctionType<'ctx> {
+        let param_types: Vec<BasicMetadataTypeEnum<'ctx>> = parameters
+            .iter()
+            .map(|param| self.llvm_type_for(Some(&param.param_type)).into())
+            .collect();
+        self.llvm_type_for(return_type).fn_type(&param_types, false)
+    }
+
+    fn describe_llvm_type(&self, llvm_type: BasicTypeEnum<'ctx>) -> &'static str {
+        match llvm_type {
+            BasicTypeEnum::FloatType(_) => "fp",
+            BasicTypeEnum::PointerType(_) => "text or an array",
+            BasicTypeEnum::IntType(int_type) if int_type.get_bit_width() == 1 => "bool",
+            _ => "a number",
+        }
+    }
+
+    // Convert a value to a parameter or return type, None when text and numbers are mixed
+    fn coerce_value(&self, value: BasicValueEnum<'ctx>, target: BasicTypeEnum<'ctx>) -> Option<BasicValueEnum<'ctx>> {
+        match (value, target) {
+            (BasicValueEnum::IntValue(int_val), BasicTypeEnum::IntType(int_type)) => {
+                let width = int_val.get_type().get_bit_width();
+                Some(match (width, int_type.get_bit_width()) {
+                    (from, to) if from == to => int_val.into(),
+                    (_, 1) => {
+                        let zero = int_val.get_type().const_zero();
+                        self.builder.build_int_compare(inkwell::IntPredicate::NE, int_val, zero, "to_bool").unwrap().into()
+                    },
+                    _ => self.builder.build_int_z_extend(int_val, int_type, "to_int").unwrap().into(),
+                })
+            },
+            (BasicValueEnum::IntValue(int_val), BasicTypeEnum::FloatType(float_type)) => {
+                Some(if int_val.get_type().get_bit_width() == 1 {
+                    self.builder.build_unsigned_int_to_float(int_val, float_type, "bool_to_fp").unwrap().into()
+                } else {
+                    self.builder.build_signed_int_to_float(int_val, float_type, "int_to_fp").unwrap().into()
+                })
+            },
+            (BasicValueEnum::FloatValue(float_val), BasicTypeEnum::FloatType(_)) => Some(float_val.into()),
+            (BasicValueEnum::FloatValue(float_val), BasicTypeEnum::IntType(int_type)) => {
+                Some(if int_type.get_bit_width() == 1 {
+                    let zero = self.context.f64_type().const_zero();
+                    self.builder.build_float_compare(inkwell::FloatPredicate::ONE, float_val, zero, "fp_to_bool").unwrap().into()
+                } else {
+                    self.builder.build_float_to_signed_int(float_val, int_type, "fp_to_int").unwrap().into()
+                })
+            },
+            (BasicValueEnum::PointerValue(ptr_val), BasicTypeEnum::PointerType(_)) => Some(ptr_val.into()),
+            _ => None,
+        }
+    }
+
+    // Return none from a function whose body falls off the end
+    fn build_default_return(&self, function: FunctionValue<'ctx>) {
+        match function.get_type().get_return_type() {
+            Some(target) => self.builder.build_return(Some(&self.none_of(target))),
+            None => self.builder.build_return(None),
+        }.unwrap();
+    }
+
+    // none as a value of the given type: a null pointer, NONE_NUMBER or the NONE_FP_BITS NaN.
+    // A bool has no spare value, so its none is false
+    fn none_of(&self, target: BasicTypeEnum<'ctx>) -> BasicValueEnum<'ctx> {
+        match target {
+            BasicTypeEnum::PointerType(ptr_type) => ptr_type.const_null().into(),
+            BasicTypeEnum::FloatType(float_type) => float_type.const_float(f64::from_bits(NONE_FP_BITS)).into(),
+            BasicTypeEnum::IntType(int_type) if int_type.get_bit_width() == 1 => int_type.const_zero().into(),
+            _ => self.i64_type.const_int(NONE_NUMBER as u64, true).into(),
+        }
+    }
+
+    // i1 that is set when the value is none, None for a bool, which is never none
+    fn build_is_none(&self, value: BasicValueEnum<'ctx>) -> Option<IntValue<'ctx>> {
+        match value {
+            BasicValueEnum::PointerValue(ptr) => Some(self.builder.build_is_null(ptr, "is_none").unwrap()),
+            BasicValueEnum::IntValue(int_val) if int_val.get_type() == self.i64_type => {
+                let none = self.i64_type.const_int(NONE_NUMBER as u64, true);
+                Some(self.builder.build_int_compare(inkwell::IntPredicate::EQ, int_val, none, "is_none").unwrap())
+            },
+            BasicValueEnum::FloatValue(float_val) => {
+                let bits = self.builder.build_bit_cast(float_val, self.i64_type, "fp_bits").unwrap().into_int_value();
+                let none = self.i64_type.const_int(NONE_FP_BITS, false);
+                Some(self.builder.build_int_compare(inkwell::IntPredicate::EQ, bits, none, "is_none").unwrap())
+            },
+            _ => None,
+        }
+    }
+
+    // none only compares for equality, as in the interpreter; other operators end the program
+    fn build_none_operand_check(&mut self, left: BasicValueEnum<'ctx>, right: BasicValueEnum<'ctx>, operator: &Token) {
+        if matches!(operator.token_type, TokenType::Equal | TokenType::NotEqual | TokenType::And | TokenType::Or) {
+            return;
+        }
+        let either_none = match (self.build_is_none(left), self.build_is_none(right)) {
+            (Some(left_none), Some(right_none)) => self.builder.build_or(left_none, right_none, "operand_none").unwrap(),
+            (Some(none), None) | (None, Some(none)) => none,
+            (None, None) => return,
+        };
+        let has_values = self.builder.build_not(either_none, "operands_set").unwrap();
+        let function = self.builder.get_insert_block().unwrap().get_parent().unwrap();
+        self.build_runtime_check(
+            function,
+            has_values,
+            &format!(
+                "Cannot use none with '{}'\n\nTip: Provide a default with ?? (e.g. $value ?? 0) or check it with is_none",
+                operator.lexeme
+            ),
+        );
+    }
+
+    // fp arithmetic and comparisons; number operands are converted like in the interpreter
+    fn compile_float_binary(&mut self, left: BasicValueEnum<'ctx>, operator: &Token, right: BasicValueEnum<'ctx>) -> Result<BasicValueEnum<'ctx>, LutError> {
+        self.build_none_operand_check(left, right, operator);
+        let f64_type = self.context.f64_type();
+        let (left, right) = match (self.coerce_value(left, f64_type.into()), self.coerce_value(right, f64_type.into())) {
+            (Some(left), Some(right)) => (left.into_float_value(), right.into_float_value()),
+            _ => return Err(LutError::compiler_error(
+                format!("Cannot use text with '{}' on fp values\n\nTip: Convert the text with [ fp value ]", operator.lexeme),
+                Some(operator.line)
+            )),
+        };
+
+        let predicate = match operator.token_type {
+            TokenType::Plus => return Ok(self.builder.build_float_add(left, right, "fadd").unwrap().into()),
+            TokenType::Minus => return Ok(self.builder.build_float_sub(left, right, "fsub").unwrap().into()),
+            TokenType::Star => return Ok(self.builder.build_float_mul(left, right, "fmul").unwrap().into()),
+            TokenType::Slash => {
+                self.build_float_zero_check(right, "Division by zero");
+                return Ok(self.builder.build_float_div(left, right, "fdiv").unwrap().into());
+            },
+            TokenType::Percent => {
+                self.build_float_zero_check(right, "Modulo by zero");
+                return Ok(self.builder.build_float_rem(left, right, "frem").unwrap().into());
+            },
+            TokenType::Equal => inkwell::FloatPredicate::OEQ,
+            TokenType::NotEqual => inkwell::FloatPredicate::UNE,
+            TokenType::Less => inkwell::FloatPredicate::OLT,
+            TokenType::LessEqual => inkwell::FloatPredicate::OLE,
+            TokenType::Greater => inkwell::FloatPredicate::OGT,
+            TokenType::GreaterEqual => inkwell::FloatPredicate::OGE,
+            _ => return Err(LutError::compiler_error(
+                format!("Operator '{}' is not supported for fp values", operator.lexeme),
+                Some(operator.line)
+            )),
+        };
+        let result = self.builder.build_float_compare(predicate, left, right, "fcmp").unwrap();
+        Ok(self.builder.build_int_z_extend(result, self.i64_type, "zext").unwrap().into())
+    }
+
+    fn build_float_zero_check(&mut self, divisor: FloatValue<'ctx>, message: &str) {
+        let function = self.builder.get_insert_block().unwrap().get_parent().unwrap();
+        let zero = self.context.f64_type().const_zero();
+        let non_zero = self.builder.build_float_compare(inkwell::FloatPredicate::UNE, divisor, zero, "fp_non_zero").unwrap();
+        self.build_runtime_check(function, non_zero, message);
+    }
+
+    // 64-bit arithmetic through one of LLVM's *.with.overflow intrinsics, ending the
+    // program with the message when the result does not fit
+    fn build_checked_int_op(&mut self, intrinsic: &str, left: IntValue<'ctx>, right: IntValue<'ctx>, message: &str) -> IntValue<'ctx> {
+        let declaration = Intrinsic::find(intrinsic)
+            .and_then(|intrinsic| intrinsic.get_declaration(&self.module, &[self.i64_type.into()]))
+            .unwrap_or_else(|| panic!("LLVM has no {} intrinsic", intrinsic));
+        let result = self.builder.build_call(declaration, &[left.into(), right.into()], "checked").unwrap()
+            .try_as_basic_value().left().unwrap().into_struct_value();
+        let value = self.builder.build_extract_value(result, 0, "checked_value").unwrap().into_int_value();
+        let overflowed = self.builder.build_extract_value(result, 1, "overflowed").unwrap().into_int_value();
+        let fits = self.builder.build_not(overflowed, "fits").unwrap();
+        let function = self.builder.get_insert_block().unwrap().get_parent().unwrap();
+        self.build_runtime_check(function, fits, message);
+        value
+    }
+
+    // i64::MIN / -1 has no result, like in the interpreter
+    fn build_division_overflow_check(&mut self, left: IntValue<'ctx>, right: IntValue<'ctx>, message: &str) {
+        let min = self.i64_type.const_int(i64::MIN as u64, true);
+        let is_min = self.builder.build_int_compare(inkwell::IntPredicate::EQ, left, min, "is_min").unwrap();
+        let is_minus_one = self.builder.build_int_compare(inkwell::IntPredicate::EQ, right, self.i64_type.const_all_ones(), "is_minus_one").unwrap();
+        let overflows = self.builder.build_and(is_min, is_minus_one, "div_overflows").unwrap();
+        let fits = self.builder.build_not(overflows, "div_fits").unwrap();
+        let function = self.builder.get_insert_block().unwrap().get_parent().unwrap();
+        self.build_runtime_check(function, fits, message);
+    }
+
+    // Write the module to a file
+    pub fn write_to_file(&self, filename: &str) -> Result<(), LutError> {
+        // Write the LLVM IR to the file
+        if let Err(e) = self.module.print_to_file(filename) {
+            return Err(LutError::compiler_error(
+                format!("Error writing LLVM IR to file: {}", e.to_string()),
+                None
+            ));
+        }
+        
         Ok(())
     }
     
-    // Create a native executable file
-    pub fn create_executable(&self, output_filename: &str) -> Result<(), LutError> {
-        // Get the default target triple
-        let target_triple = TargetMachine::get_default_triple();
-        println!("Targeting: {}", target_triple.to_string());
-
-        // Get the target from the triple with improved error handling
-        let target = self.handle_llvm_err(
-            Target::from_triple(&target_triple),
-            "obtaining target from triple"
-        )?;
+    pub fn write_bitcode(&self, filename: &str) -> Result<(), LutError> {
+        if !self.module.write_bitcode_to_path(Path::new(filename)) {
+            return Err(LutError::io_error(format!("Error writing LLVM bitcode to '{}'", filename)));
+        }
+        Ok(())
+    }
 
-        // Create a target machine with improved error handling
-        let target_machine = target.create_target_machine(
-            &target_triple,
-            "generic",
-            "",
-            OptimizationLevel::Default,
-            RelocMode::Default,
-            CodeModel::Default,
-        ).ok_or_else(|| LutError::compiler_error(
-            "Failed to create target machine: no machine for target triple",
-            None
-        ))?;
+    // Assembly or an object file for the target
+    pub fn write_machine_code(&self, filename: &str, file_type: FileType) -> Result<(), LutError> {
+        let target_machine = self.target_machine()?;
+        self.module.set_data_layout(&target_machine.get_target_data().get_data_layout());
+        self.module.set_triple(&target_machine.get_triple());
+        self.verify_module()?;
 
-        // Set the data layout for the module
+        self.handle_llvm_err(
+            target_machine.write_to_file(&self.module, file_type, Path::new(filename)),
+            "writing machine code"
+        )
+    }
+
+    // Run the pass pipeline for the chosen optimization level over the module
+    pub fn optimize(&self) -> Result<(), LutError> {
+        // The passes assume valid IR, so problems are reported before they run
+        self.verify_module()?;
+
+        let target_machine = self.target_machine()?;
+        self.module.set_triple(&target_machine.get_triple());
         self.module.set_data_layout(&target_machine.get_target_data().get_data_layout());
-        self.module.set_triple(&target_triple);
+        self.handle_llvm_err(
+            self.module.run_passes(self.opt_level.pipeline(), &target_machine, PassBuilderOptions::create()),
+            &format!("running the {} pass pipeline", self.opt_level.pipeline())
+        )
+    }
 
-        // Verify the module is valid with detailed error information
+    fn verify_module(&self) -> Result<(), LutError> {
         if let Err(err) = self.module.verify() {
             return Err(LutError::compiler_error(
                 format!(
@@ -2466,50 +4260,302 @@ impl<'ctx> LLVMCompiler<'ctx> {
                 None
             ));
         }
+        Ok(())
+    }
+
+    fn target_triple(&self) -> String {
+        match &self.target {
+            Some(target) => llvm_triple(target),
+            None => TargetMachine::get_default_triple().to_string(),
+        }
+    }
 
-        // First create an object file
-        let object_filename = format!("{}.o", output_filename);
-        let object_path = std::path::Path::new(&object_filename);
+    // Machine for --target, --cpu and --features; the host with a generic CPU by default
+    fn target_machine(&self) -> Result<TargetMachine, LutError> {
+        let target_triple = match &self.target {
+            Some(triple) => TargetTriple::create(&llvm_triple(triple)),
+            None => TargetMachine::get_default_triple(),
+        };
 
-        // Write to file with improved error handling
-        self.handle_llvm_err(
-            target_machine.write_to_file(&self.module, FileType::Object, object_path),
-            "writing object file"
+        let triple = target_triple.to_string();
+
+        let target = Target::from_triple(&target_triple).map_err(|e| LutError::compiler_error(
+            format!(
+                "Unknown target '{}': {}\n\nTip: Use an LLVM target triple such as aarch64-unknown-linux-gnu or riscv64gc-unknown-linux-gnu",
+                triple,
+                e
+            ),
+            None
+        ))?;
+
+        // --cpu native also picks up every feature of the host CPU
+        let (cpu, host_features) = match self.cpu.as_deref() {
+            Some("native") => (
+                TargetMachine::get_host_cpu_name().to_string(),
+                TargetMachine::get_host_cpu_features().to_string(),
+            ),
+            cpu => (cpu.unwrap_or("generic").to_string(), String::new()),
+        };
+        let features = self.features.clone().unwrap_or(host_features);
+
+        target.create_target_machine(
+            &target_triple,
+            &cpu,
+            &features,
+            self.opt_level.codegen_level(),
+            // Shared libraries need position independent code
+            if self.library.is_some() { RelocMode::PIC } else { RelocMode::Default },
+            CodeModel::Default,
+        ).ok_or_else(|| LutError::compiler_error(
+            format!(
+                "Failed to create a target machine for '{}' with CPU '{}' and features '{}'\n\nTip: Check the --cpu and --features values against the target",
+                triple,
+                cpu,
+                features
+            ),
+            None
+        ))
+    }
+
+    // JIT compile the module, run main and return the program's exit status
+    pub fn jit_compile_and_run(&self) -> Result<i32, LutError> {
+        // Create JIT execution engine with better error message
+        let execution_engine = self.handle_llvm_err(
+            self.module.create_jit_execution_engine(self.opt_level.codegen_level()),
+            "JIT execution engine creation"
         )?;
 
-        println!("Generated object file: {}", object_filename);
+        // The runtime library is linked into koze itself
+        for (name, address) in kozeig_runtime::symbols() {
+            if let Some(function) = self.module.get_function(name) {
+                execution_engine.add_global_mapping(&function, address);
+            }
+        }
 
-        // Now link the object file into an executable using system linker
-        #[cfg(target_os = "macos")]
-        let linking_result = Command::new("cc")
-            .arg("-o")
-            .arg(output_filename)
-            .arg(&object_filename)
-            .status()
-            .map_err(|e| LutError::compiler_error(
-                format!("Error executing linker (cc): {}. Make sure you have a C compiler installed.", e),
-                None
-            ))?;
+        // C functions are found the way koze run finds them
+        for (name, library) in &self.extern_functions {
+            let address = foreign::resolve(name, library.as_deref())
+                .map_err(|e| LutError::runtime_error(e, None))?;
+            execution_engine.add_global_mapping(&self.module.get_function(name).unwrap(), address as usize);
+        }
+
+        unsafe {
+            // Get the main function with improved error message
+            let main_fn = self.handle_llvm_err(
+                execution_engine.get_function::<unsafe extern "C" fn() -> i32>("main"),
+                "retrieving main function for JIT execution"
+            )?;
 
-        #[cfg(target_os = "linux")]
-        let linking_result = Command::new("cc")
+            // main has already reported a failure, so its status is just passed on
+            Ok(main_fn.call())
+        }
+    }
+    
+    // A WASI command module. rustc links it, because the runtime needs Rust's standard
+    // library and wasi-libc, which rustup installs with the wasm32-wasip1 target.
+    // wasi-libc's _start calls the __main_void the program defines
+    fn create_wasm_module(&self, output_filename: &str) -> Result<(), LutError> {
+        let triple = self.target_triple();
+        let build_dir = tempfile::tempdir()
+            .map_err(|e| LutError::io_error(format!("Failed to create a build directory: {}", e)))?;
+        let object_path = build_dir.path().join("program.o");
+        self.write_machine_code(&object_path.to_string_lossy(), FileType::Object)?;
+
+        let source_path = write_runtime_source(build_dir.path())?;
+        let runtime_path = build_dir.path().join("libkozeig_runtime.rlib");
+        let entry_path = build_dir.path().join("entry.rs");
+        fs::write(&entry_path, "#![no_main]\nextern crate kozeig_runtime;\n")
+            .map_err(|e| LutError::io_error(format!("Failed to write the runtime library source: {}", e)))?;
+
+        let mut command = Command::new("rustc");
+        command
+            .args(["--crate-name", "kozeig_runtime", "--crate-type", "rlib", "--edition", "2021"])
+            .args(["-C", "opt-level=3", "-C", "panic=abort", "--target", rust_target(&triple)])
             .arg("-o")
-            .arg(output_filename)
-            .arg(&object_filename)
-            .status()
-            .map_err(|e| LutError::compiler_error(
-                format!("Error executing linker (cc): {}. Make sure you have a C compiler installed.", e),
+            .arg(&runtime_path)
+            .arg(&source_path);
+        run_rustc(command, "build the runtime library", &triple)?;
+
+        let mut command = Command::new("rustc");
+        command
+            .args(["--crate-name", "koze_program", "--crate-type", "bin", "--edition", "2021"])
+            .args(["-C", "opt-level=3", "-C", "panic=abort", "--target", rust_target(&triple)])
+            .arg("--extern")
+            .arg(format!("kozeig_runtime={}", runtime_path.display()))
+            .arg("-C")
+            .arg(format!("link-arg={}", object_path.display()));
+        for arg in self.extern_link_args(&triple) {
+            command.arg("-C").arg(format!("link-arg={}", arg));
+        }
+        if let Some(linker) = &self.linker {
+            command.arg("-C").arg(format!("linker={}", linker));
+        }
+        command.arg("-o").arg(output_filename).arg(&entry_path);
+        run_rustc(command, "link the WebAssembly module", &triple)
+    }
+
+    // --linker is split on whitespace, so it can carry arguments ("zig cc -target ...").
+    // Without it the host links with cc (cl for MSVC) and other targets with clang
+    fn linker_command(&self, triple: &str) -> (String, Vec<String>) {
+        if let Some(linker) = &self.linker {
+            let mut words = linker.split_whitespace().map(str::to_string);
+            let program = words.next().unwrap_or_else(|| "cc".to_string());
+            return (program, words.collect());
+        }
+        match &self.target {
+            Some(target) => ("clang".to_string(), vec![format!("--target={}", target)]),
+            None if triple.contains("msvc") => ("cl".to_string(), Vec::new()),
+            None => ("cc".to_string(), Vec::new()),
+        }
+    }
+
+    // Library file name for the target when there is no -o
+    pub fn library_file_name(&self, stem: &str, kind: LibKind) -> String {
+        let triple = self.target_triple();
+        match kind {
+            LibKind::Shared if triple.contains("windows") => format!("{}.dll", stem),
+            LibKind::Shared if triple.contains("apple") => format!("lib{}.dylib", stem),
+            LibKind::Shared => format!("lib{}.so", stem),
+            LibKind::Static if triple.contains("msvc") => format!("{}.lib", stem),
+            LibKind::Static => format!("lib{}.a", stem),
+        }
+    }
+
+    // A library with the program's pub functions and the runtime library in it, so C
+    // callers link nothing else from Kozeig
+    pub fn create_library(&self, output_filename: &str, kind: LibKind) -> Result<(), LutError> {
+        let triple = self.target_triple();
+        let build_dir = tempfile::tempdir()
+            .map_err(|e| LutError::io_error(format!("Failed to create a build directory: {}", e)))?;
+        let object_path = build_dir.path().join("program.o");
+        self.write_machine_code(&object_path.to_string_lossy(), FileType::Object)?;
+        let runtime_path = write_runtime_library(build_dir.path(), &triple, self.target.is_some())?;
+
+        let (tool, mut command) = match kind {
+            LibKind::Shared => {
+                let (linker, linker_args) = self.linker_command(&triple);
+                let mut command = Command::new(&linker);
+                command.args(&linker_args);
+                if triple.contains("msvc") {
+                    command.arg("/LD").arg("/Fe:").arg(output_filename);
+                } else {
+                    command.arg("-shared").arg("-o").arg(output_filename);
+                }
+                command
+                    .arg(&object_path)
+                    .arg(&runtime_path)
+                    .args(self.extern_link_args(&triple))
+                    .args(runtime_system_libs(&triple));
+                (linker, command)
+            },
+            LibKind::Static if triple.contains("msvc") => {
+                let mut command = Command::new("lib");
+                command.arg(format!("/OUT:{}", output_filename)).arg(&runtime_path).arg(&object_path);
+                ("lib".to_string(), command)
+            },
+            LibKind::Static => {
+                // The runtime archive with the program's object added to it
+                fs::copy(&runtime_path, output_filename)
+                    .map_err(|e| LutError::io_error(format!("Failed to write '{}': {}", output_filename, e)))?;
+                let mut command = Command::new("ar");
+                command.arg("rs").arg(output_filename).arg(&object_path);
+                ("ar".to_string(), command)
+            },
+        };
+
+        let status = command.status().map_err(|e| LutError::compiler_error(
+            format!("Error executing {}: {}\n\nTip: Install a C toolchain for {}", tool, e, triple),
+            None
+        ))?;
+        if !status.success() {
+            return Err(LutError::compiler_error(
+                format!("Building the {} failed: {} exited with {}", kind.describe(), tool, status.code().unwrap_or(-1)),
                 None
-            ))?;
+            ));
+        }
+        Ok(())
+    }
+
+    // C declarations of the exported functions, from their parameter and return annotations
+    pub fn write_c_header(&self, path: &Path, stem: &str) -> Result<(), LutError> {
+        let guard: String = stem
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+            .collect();
+        // Written right before the name, so pointers read "char *name"
+        let c_type = |type_name: Option<&str>, is_return: bool| match type_name {
+            Some("fp") => "double ",
+            Some("bool") => "bool ",
+            Some("text") if is_return => "char *",
+            Some("text") => "const char *",
+            _ => "int64_t ",
+        };
+
+        let mut header = format!(
+            "/* Generated by koze build --lib from {}.ko */\n\
+             #ifndef KOZE_{guard}_H\n\
+             #define KOZE_{guard}_H\n\n\
+             #include <stdbool.h>\n\
+             #include <stdint.h>\n\n\
+             #ifdef __cplusplus\n\
+             extern \"C\" {{\n\
+             #endif\n\n\
+             /* Text returned by these functions belongs to the caller; free it with koze_release */\n\
+             void koze_release(void *value);\n\n",
+            stem,
+            guard = guard
+        );
+        for export in &self.exports {
+            let parameters: Vec<String> = export
+                .parameters
+                .iter()
+                .map(|param| format!("{}{}", c_type(Some(&param.param_type), false), param.name))
+                .collect();
+            let parameters = if parameters.is_empty() { "void".to_string() } else { parameters.join(", ") };
+            header.push_str(&format!("{}{}({});\n", c_type(export.return_type.as_deref(), true), export.name, parameters));
+        }
+        header.push_str("\n#ifdef __cplusplus\n}\n#endif\n\n#endif\n");
 
-        #[cfg(target_os = "windows")]
-        let linking_result = Command::new("cl")
-            .arg("/Fe:")
-            .arg(output_filename)
-            .arg(&object_filename)
+        fs::write(path, header)
+            .map_err(|e| LutError::io_error(format!("Failed to write '{}': {}", path.display(), e)))
+    }
+
+    // Create a native executable file; the object file only lives in a temporary directory, next to the runtime library
+    pub fn create_executable(&self, output_filename: &str) -> Result<(), LutError> {
+        let target_machine = self.target_machine()?;
+        let triple = target_machine.get_triple().to_string();
+        if is_wasm(&triple) {
+            return self.create_wasm_module(output_filename);
+        }
+
+        let build_dir = tempfile::tempdir()
+            .map_err(|e| LutError::io_error(format!("Failed to create a build directory: {}", e)))?;
+        let object_path = build_dir.path().join("program.o");
+        self.write_machine_code(&object_path.to_string_lossy(), FileType::Object)?;
+
+        // Printing, text and arrays are implemented by the runtime library
+        let runtime_path = write_runtime_library(build_dir.path(), &triple, self.target.is_some())?;
+
+        // Now link the object file into an executable
+        let (linker, linker_args) = self.linker_command(&triple);
+        let mut command = Command::new(&linker);
+        command.args(&linker_args);
+        if triple.contains("msvc") {
+            command.arg("/Fe:").arg(output_filename);
+        } else {
+            command.arg("-o").arg(output_filename);
+        }
+        let linking_result = command
+            .arg(&object_path)
+            .arg(&runtime_path)
+            .args(self.extern_link_args(&triple))
+            .args(runtime_system_libs(&triple))
             .status()
             .map_err(|e| LutError::compiler_error(
-                format!("Error executing linker (cl): {}. Make sure you have Visual Studio or the MSVC toolchain installed.", e),
+                format!(
+                    "Error executing linker ({}): {}\n\nTip: Install a C toolchain for {} or choose one with --linker",
+                    linker, e, triple
+                ),
                 None
             ))?;
 
@@ -2525,6 +4571,20 @@ impl<'ctx> LLVMCompiler<'ctx> {
             ));
         }
 
+        // On macOS the linker leaves DWARF in the object file, which is about to be deleted
+        if self.debug_info && triple.contains("apple") {
+            let status = Command::new("dsymutil")
+                .arg(output_filename)
+                .status()
+                .map_err(|e| LutError::compiler_error(
+                    format!("Error running dsymutil: {}\n\nTip: dsymutil comes with the Xcode command line tools", e),
+                    None
+                ))?;
+            if !status.success() {
+                return Err(LutError::compiler_error(format!("dsymutil failed for '{}'", output_filename), None));
+            }
+        }
+
         // Make the resulting binary executable on Unix-like systems
         #[cfg(any(target_os = "macos", target_os = "linux"))]
         {
@@ -2546,13 +4606,12 @@ impl<'ctx> LLVMCompiler<'ctx> {
                 ))?;
         }
 
-        println!("Generated executable: {}", output_filename);
         Ok(())
     }
 }
 
 // Top-level compile function that takes source code and outputs binary
-pub fn compile(source: &str, file_path: &str, silent_mode: bool) -> Result<(), LutError> {
+pub fn compile(source: &str, file_path: &str, silent_mode: bool, options: &BuildOptions) -> Result<(), LutError> {
     let mut lexer = Lexer::new(source);
     let tokens = lexer.scan_tokens()?;
 
@@ -2568,20 +4627,63 @@ pub fn compile(source: &str, file_path: &str, silent_mode: bool) -> Result<(), L
         .to_string_lossy();
     let module_name = file_stem.to_string();
     
-    let mut llvm_compiler = LLVMCompiler::new(&context, &module_name, file_path).with_silent_mode(silent_mode);
+    if options.emit == Emit::C {
+        return Err(LutError::compiler_error(
+            "--emit=c writes the output of the C backend\n\nTip: Add --backend=c",
+            None
+        ));
+    }
+    let wasm = options.target.as_deref().is_some_and(|target| is_wasm(&llvm_triple(target)));
+    if wasm && options.lib.is_some() {
+        return Err(LutError::compiler_error(
+            "--lib cannot be used with a WebAssembly target\n\nTip: Build a .wasm module without --lib",
+            None
+        ));
+    }
+
+    let mut llvm_compiler = LLVMCompiler::new(&context, &module_name, file_path)
+        .with_silent_mode(silent_mode)
+        .with_opt_level(options.opt_level)
+        .with_debug_info(options.debug_info)
+        .with_target(options)
+        .with_library(options.lib);
     llvm_compiler.compile(statements)?;
+    llvm_compiler.optimize()?;
     
-    // Generate output paths
-    let ir_path = format!("{}.ll", module_name);
-    llvm_compiler.write_to_file(&ir_path)?;
+    let output = options.output.clone().unwrap_or_else(|| match options.lib {
+        Some(kind) if options.emit == Emit::Exe => llvm_compiler.library_file_name(&module_name, kind),
+        None if options.emit == Emit::Exe && wasm => format!("{}.wasm", module_name),
+        _ => options.emit.default_output(&module_name),
+    });
+    let description = match options.lib {
+        Some(kind) if options.emit == Emit::Exe => kind.describe(),
+        None if options.emit == Emit::Exe && wasm => "WebAssembly module",
+        _ => options.emit.describe(),
+    };
+    match options.emit {
+        Emit::LlvmIr => llvm_compiler.write_to_file(&output)?,
+        Emit::LlvmBc => llvm_compiler.write_bitcode(&output)?,
+        Emit::Asm => llvm_compiler.write_machine_code(&output, FileType::Assembly)?,
+        Emit::Obj => llvm_compiler.write_machine_code(&output, FileType::Object)?,
+        Emit::C => unreachable!("C source comes from the C backend"),
+        Emit::Exe => match options.lib {
+            Some(kind) => llvm_compiler.create_library(&output, kind)?,
+            None => llvm_compiler.create_executable(&output)?,
+        },
+    }
     
     if !silent_mode {
-        println!("Generated LLVM IR: {}", ir_path);
-        println!("Generating native executable...");
+        println!("Generated {}: {}", description, output);
+    }
+
+    // The header goes next to the library
+    if options.lib.is_some() {
+        let header = Path::new(&output).with_file_name(format!("{}.h", module_name));
+        llvm_compiler.write_c_header(&header, &module_name)?;
+        if !silent_mode {
+            println!("Generated C header: {}", header.display());
+        }
     }
-    
-    // Generate executable
-    llvm_compiler.create_executable(&module_name)?;
     
     if !silent_mode {
         println!("Compilation successful!");
@@ -2591,11 +4693,11 @@ pub fn compile(source: &str, file_path: &str, silent_mode: bool) -> Result<(), L
 
 // Backward compatibility wrapper
 pub fn compile_default(source: &str, file_path: &str) -> Result<(), String> {
-    compile(source, file_path, false).map_err(|e| e.to_string())
+    compile(source, file_path, false, &BuildOptions::default()).map_err(|e| e.to_string())
 }
 
 // JIT compile and run function - used for development/testing
-pub fn jit_compile_and_run(source: &str, file_path: &str, silent_mode: bool) -> Result<(), LutError> {
+pub fn jit_compile_and_run(source: &str, file_path: &str, silent_mode: bool, opt_level: OptLevel) -> Result<i32, LutError> {
     let mut lexer = Lexer::new(source);
     let tokens = lexer.scan_tokens()?;
 
@@ -2611,8 +4713,11 @@ pub fn jit_compile_and_run(source: &str, file_path: &str, silent_mode: bool) ->
         .to_string_lossy();
     let module_name = file_stem.to_string();
     
-    let mut llvm_compiler = LLVMCompiler::new(&context, &module_name, file_path).with_silent_mode(silent_mode);
+    let mut llvm_compiler = LLVMCompiler::new(&context, &module_name, file_path)
+        .with_silent_mode(silent_mode)
+        .with_opt_level(opt_level);
     llvm_compiler.compile(statements)?;
+    llvm_compiler.optimize()?;
     
     // Generate IR for debugging
     let ir_path = format!("{}.ll", module_name);
@@ -2628,6 +4733,6 @@ pub fn jit_compile_and_run(source: &str, file_path: &str, silent_mode: bool) ->
 }
 
 // Backward compatibility wrapper
-pub fn jit_compile_and_run_default(source: &str, file_path: &str) -> Result<(), String> {
-    jit_compile_and_run(source, file_path, false).map_err(|e| e.to_string())
+pub fn jit_compile_and_run_default(source: &str, file_path: &str) -> Result<i32, String> {
+    jit_compile_and_run(source, file_path, false, OptLevel::default()).map_err(|e| e.to_string())
 }
\ No newline at end of file
@@ -0,0 +1,299 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+// Largest scale a decimal may carry. Keeps 10^scale comfortably inside i128
+// so rescaling and multiplication have room before overflowing.
+pub const MAX_SCALE: u32 = 18;
+
+/// Rounding modes accepted by the `div` and `round` commands
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RoundingMode {
+    HalfUp,
+    HalfDown,
+    HalfEven,
+    Up,
+    Down,
+    Ceiling,
+    Floor,
+}
+
+impl RoundingMode {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "half_up" => Ok(RoundingMode::HalfUp),
+            "half_down" => Ok(RoundingMode::HalfDown),
+            "half_even" => Ok(RoundingMode::HalfEven),
+            "up" => Ok(RoundingMode::Up),
+            "down" => Ok(RoundingMode::Down),
+            "ceiling" => Ok(RoundingMode::Ceiling),
+            "floor" => Ok(RoundingMode::Floor),
+            _ => Err(format!(
+                "Unknown rounding mode '{}'\n\nTip: Valid rounding modes are 'half_up', 'half_down', 'half_even', 'up', 'down', 'ceiling' and 'floor'",
+                name
+            )),
+        }
+    }
+
+    /// Decide whether a truncated quotient must be moved one unit away from zero.
+    /// `remainder` and `divisor` are magnitudes, `negative` is the sign of the exact result.
+    pub fn rounds_away(
+        &self,
+        quotient: i128,
+        remainder: i128,
+        divisor: i128,
+        negative: bool,
+    ) -> bool {
+        if remainder == 0 {
+            return false;
+        }
+
+        // Twice the remainder against the divisor; a remainder too large to double is past half
+        let half = match remainder.checked_mul(2) {
+            Some(twice) => twice.cmp(&divisor),
+            None => Ordering::Greater,
+        };
+        match self {
+            RoundingMode::Up => true,
+            RoundingMode::Down => false,
+            RoundingMode::Ceiling => !negative,
+            RoundingMode::Floor => negative,
+            RoundingMode::HalfUp => half != Ordering::Less,
+            RoundingMode::HalfDown => half == Ordering::Greater,
+            RoundingMode::HalfEven => {
+                half == Ordering::Greater || (half == Ordering::Equal && quotient % 2 != 0)
+            }
+        }
+    }
+}
+
+/// Fixed-point decimal value: `units / 10^scale`
+#[derive(Debug, Clone, Copy)]
+pub struct Decimal {
+    pub units: i128,
+    pub scale: u32,
+}
+
+impl Decimal {
+    pub fn new(units: i128, scale: u32) -> Result<Self, String> {
+        if scale > MAX_SCALE {
+            return Err(format!(
+                "Decimal scale {} is too large (maximum is {})",
+                scale, MAX_SCALE
+            ));
+        }
+        Ok(Decimal { units, scale })
+    }
+
+    pub fn from_int(n: i64) -> Self {
+        Decimal {
+            units: n as i128,
+            scale: 0,
+        }
+    }
+
+    /// Parse decimal text such as `19.99`, `-0.5` or `42` without going through a float
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let trimmed = text.trim();
+        let (negative, digits) = match trimmed.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+        };
+
+        let (int_part, frac_part) = match digits.split_once('.') {
+            Some((i, f)) => (i, f),
+            None => (digits, ""),
+        };
+
+        if (int_part.is_empty() && frac_part.is_empty())
+            || !int_part.chars().all(|c| c.is_ascii_digit())
+            || !frac_part.chars().all(|c| c.is_ascii_digit())
+        {
+            return Err(format!("Cannot convert '{}' to a decimal", text));
+        }
+
+        let scale = frac_part.len() as u32;
+        let mut units: i128 = 0;
+        for c in int_part.chars().chain(frac_part.chars()) {
+            units = units
+                .checked_mul(10)
+                .and_then(|u| u.checked_add(c.to_digit(10).unwrap() as i128))
+                .ok_or_else(|| format!("Decimal value '{}' is out of range", text))?;
+        }
+
+        Decimal::new(if negative { -units } else { units }, scale)
+    }
+
+    /// Convert a float through its shortest round-trip representation, so `19.99` stays `19.99`
+    pub fn from_f64(f: f64) -> Result<Self, String> {
+        if !f.is_finite() {
+            return Err(format!("Cannot convert {} to a decimal", f));
+        }
+        Decimal::parse(&f.to_string())
+    }
+
+    /// Change the scale, rounding with `mode` when digits have to be dropped
+    pub fn rescale(&self, scale: u32, mode: RoundingMode) -> Result<Self, String> {
+        match scale.cmp(&self.scale) {
+            Ordering::Equal => Ok(*self),
+            Ordering::Greater => {
+                let units = self
+                    .units
+                    .checked_mul(pow10(scale - self.scale)?)
+                    .ok_or_else(overflow)?;
+                Decimal::new(units, scale)
+            }
+            Ordering::Less => {
+                let units = div_round(self.units, pow10(self.scale - scale)?, mode)?;
+                Decimal::new(units, scale)
+            }
+        }
+    }
+
+    /// Change the scale without losing digits; dropping digits needs an explicit `round`
+    pub fn rescale_exact(&self, scale: u32) -> Result<Self, String> {
+        let rescaled = self.rescale(scale, RoundingMode::Down)?;
+        if rescaled != *self {
+            return Err(format!(
+                "Value {} does not fit in scale {} without rounding\n\nTip: Use [ round value, {}, 'half_even' ] to choose how digits are dropped",
+                self, scale, scale
+            ));
+        }
+        Ok(rescaled)
+    }
+
+    pub fn add(&self, other: &Decimal) -> Result<Self, String> {
+        let (a, b, scale) = align(self, other)?;
+        Decimal::new(a.checked_add(b).ok_or_else(overflow)?, scale)
+    }
+
+    pub fn sub(&self, other: &Decimal) -> Result<Self, String> {
+        let (a, b, scale) = align(self, other)?;
+        Decimal::new(a.checked_sub(b).ok_or_else(overflow)?, scale)
+    }
+
+    /// Exact product; the result scale is the sum of both scales
+    pub fn mul(&self, other: &Decimal) -> Result<Self, String> {
+        let units = self.units.checked_mul(other.units).ok_or_else(overflow)?;
+        Decimal::new(units, self.scale + other.scale)
+    }
+
+    /// Division always needs a target scale and a rounding mode
+    pub fn div(&self, other: &Decimal, scale: u32, mode: RoundingMode) -> Result<Self, String> {
+        if other.units == 0 {
+            return Err("Division by zero".to_string());
+        }
+
+        // self / other = (a / 10^sa) / (b / 10^sb); bring the numerator up to the target scale
+        let shift = scale as i64 + other.scale as i64 - self.scale as i64;
+        let (numerator, divisor) = if shift >= 0 {
+            (
+                self.units
+                    .checked_mul(pow10(shift as u32)?)
+                    .ok_or_else(overflow)?,
+                other.units,
+            )
+        } else {
+            (
+                self.units,
+                other
+                    .units
+                    .checked_mul(pow10((-shift) as u32)?)
+                    .ok_or_else(overflow)?,
+            )
+        };
+
+        Decimal::new(div_round(numerator, divisor, mode)?, scale)
+    }
+
+    pub fn neg(&self) -> Result<Self, String> {
+        Decimal::new(self.units.checked_neg().ok_or_else(overflow)?, self.scale)
+    }
+
+    pub fn compare(&self, other: &Decimal) -> Result<Ordering, String> {
+        let (a, b, _) = align(self, other)?;
+        Ok(a.cmp(&b))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.units == 0
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Self) -> bool {
+        matches!(self.compare(other), Ok(Ordering::Equal))
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let magnitude = self.units.unsigned_abs();
+        if self.units < 0 {
+            write!(f, "-")?;
+        }
+
+        if self.scale == 0 {
+            return write!(f, "{}", magnitude);
+        }
+
+        let divisor = 10u128.pow(self.scale);
+        write!(
+            f,
+            "{}.{:0width$}",
+            magnitude / divisor,
+            magnitude % divisor,
+            width = self.scale as usize
+        )
+    }
+}
+
+/// Number of digits after the decimal point in a literal as it is written
+#[cfg(feature = "llvm")]
+pub fn literal_scale(text: &str) -> u32 {
+    text.split_once('.')
+        .map(|(_, frac)| frac.len() as u32)
+        .unwrap_or(0)
+}
+
+pub fn pow10(exp: u32) -> Result<i128, String> {
+    10i128.checked_pow(exp).ok_or_else(overflow)
+}
+
+/// Integer division of `numerator / divisor` rounded with `mode`
+pub fn div_round(numerator: i128, divisor: i128, mode: RoundingMode) -> Result<i128, String> {
+    if divisor == 0 {
+        return Err("Division by zero".to_string());
+    }
+
+    let quotient = numerator.checked_div(divisor).ok_or_else(overflow)?;
+    let remainder = (numerator % divisor).abs();
+    let negative = (numerator < 0) != (divisor < 0);
+    let magnitude = divisor.checked_abs().ok_or_else(overflow)?;
+
+    if mode.rounds_away(quotient, remainder, magnitude, negative) {
+        if negative {
+            quotient.checked_sub(1).ok_or_else(overflow)
+        } else {
+            quotient.checked_add(1).ok_or_else(overflow)
+        }
+    } else {
+        Ok(quotient)
+    }
+}
+
+fn align(a: &Decimal, b: &Decimal) -> Result<(i128, i128, u32), String> {
+    let scale = a.scale.max(b.scale);
+    let a_units = a
+        .units
+        .checked_mul(pow10(scale - a.scale)?)
+        .ok_or_else(overflow)?;
+    let b_units = b
+        .units
+        .checked_mul(pow10(scale - b.scale)?)
+        .ok_or_else(overflow)?;
+    Ok((a_units, b_units, scale))
+}
+
+fn overflow() -> String {
+    "Decimal overflow".to_string()
+}
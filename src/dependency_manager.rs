@@ -1,4 +1,5 @@
-use std::collections::HashMap;
+use crate::parser::ImportItem;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
@@ -7,9 +8,59 @@ use std::process::Command;
 #[derive(Debug, Clone)]
 pub struct ImportedFunction {
     pub name: String,
+    pub source_name: String, // Name the function is declared with in its module
     pub body: Vec<crate::parser::Stmt>,
     pub parameters: Vec<crate::parser::FunctionParam>,
+    // Only the LLVM backend declares imported functions with their return type
+    #[cfg_attr(not(feature = "llvm"), allow(dead_code))]
+    pub return_type: Option<String>,
     pub is_public: bool,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct ImportedConstant {
+    pub name: String,
+    pub source_name: String,
+    pub initializer: crate::parser::Expr,
+    pub is_public: bool,
+}
+
+/// A parsed module: its functions, its constants in declaration order, its own `use` statements
+/// and the C functions it declares, which stay private to the module
+#[derive(Debug, Clone)]
+pub struct LoadedModule {
+    pub id: String,   // Canonical path or repository key, unique per module
+    pub dir: PathBuf, // Directory the module's own relative imports resolve from
+    pub file: PathBuf, // Source file the module was parsed from
+    pub functions: Vec<ImportedFunction>,
+    pub constants: Vec<ImportedConstant>,
+    pub imports: Vec<crate::parser::Stmt>,
+    pub externs: Vec<crate::parser::Stmt>,
+}
+
+/// What an import binds in the importing file
+#[derive(Debug, Clone, Default)]
+pub struct ResolvedImport {
+    pub functions: Vec<ImportedFunction>,
+    pub constants: Vec<ImportedConstant>,
+}
+
+impl LoadedModule {
+    /// Everything other files may import; `prot` functions and constants stay inside the module
+    pub fn exports(&self) -> ResolvedImport {
+        ResolvedImport {
+            functions: self.functions.iter().filter(|f| f.is_public).cloned().collect(),
+            constants: self.constants.iter().filter(|c| c.is_public).cloned().collect(),
+        }
+    }
+}
+
+// One `use` on the chain of imports currently being resolved
+struct ImportStep {
+    importer: String, // Module id of the importing file
+    line: usize,
+    module_path: String, // As written in the `use` statement
 }
 
 pub struct DependencyManager {
@@ -17,7 +68,9 @@ pub struct DependencyManager {
     current_file_dir: Option<PathBuf>, // Directory of the currently executing file
     dependencies_dir: PathBuf,
     cache_dir: PathBuf,
-    cached_modules: HashMap<String, Vec<ImportedFunction>>,
+    cached_modules: HashMap<String, LoadedModule>,
+    entry_file: Option<String>, // Module id of the file being run
+    import_stack: Vec<ImportStep>,
 }
 
 impl DependencyManager {
@@ -36,6 +89,8 @@ impl DependencyManager {
             dependencies_dir,
             cache_dir,
             cached_modules: HashMap::new(),
+            entry_file: None,
+            import_stack: Vec::new(),
         }
     }
 
@@ -43,28 +98,129 @@ impl DependencyManager {
         self.current_file_dir = Some(dir);
     }
 
-    pub fn resolve_import(
+    pub fn current_file_dir(&self) -> Option<PathBuf> {
+        self.current_file_dir.clone()
+    }
+
+    // Put back a directory saved with current_file_dir, including none
+    pub fn restore_current_file_dir(&mut self, dir: Option<PathBuf>) {
+        self.current_file_dir = dir;
+    }
+
+    pub fn set_entry_file(&mut self, path: &Path) {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.entry_file = Some(path.display().to_string());
+    }
+
+    // Record that `importer` (None for the file being run) imports `module` at `line`.
+    // Fails if `module` is already being imported further up the chain.
+    pub fn enter_import(
         &mut self,
-        functions: &[String],
+        importer: Option<&str>,
+        line: usize,
         module_path: &str,
-    ) -> Result<Vec<ImportedFunction>, String> {
-        // Check if it's a local file import (starts with ./ or /)
-        if module_path.starts_with("./") || module_path.starts_with("/") {
-            self.import_from_local_file(functions, module_path)
+        module: &LoadedModule,
+    ) -> Result<(), String> {
+        let importer = importer
+            .map(str::to_string)
+            .or_else(|| self.entry_file.clone())
+            .unwrap_or_default();
+        self.import_stack.push(ImportStep {
+            importer,
+            line,
+            module_path: module_path.to_string(),
+        });
+
+        if self.import_stack.iter().any(|step| step.importer == module.id) {
+            let chain: Vec<String> = self
+                .import_stack
+                .iter()
+                .map(|step| {
+                    format!(
+                        "  {} line {} imports '{}'",
+                        self.display_path(&step.importer),
+                        step.line,
+                        step.module_path
+                    )
+                })
+                .collect();
+            self.import_stack.pop();
+
+            return Err(format!(
+                "Import cycle detected:\n{}\n\nTip: Move the functions these files share into a separate module",
+                chain.join("\n")
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn exit_import(&mut self) {
+        self.import_stack.pop();
+    }
+
+    // Module ids are absolute paths; show them relative to the project where possible
+    pub fn display_path(&self, module_id: &str) -> String {
+        let path = Path::new(module_id);
+        path.strip_prefix(&self.project_root)
+            .unwrap_or(path)
+            .display()
+            .to_string()
+    }
+
+    // The file a module was parsed from, as runtime errors in its code name it
+    pub fn display_file(&self, module: &LoadedModule) -> String {
+        module.file.strip_prefix(&self.project_root)
+            .unwrap_or(&module.file)
+            .display()
+            .to_string()
+    }
+
+    // `taken` holds the function names already bound in the importing file; imports may not shadow them
+    pub fn resolve_import(
+        &self,
+        module: &LoadedModule,
+        items: &[ImportItem],
+        taken: &HashSet<String>,
+    ) -> Result<ResolvedImport, String> {
+        self.filter_requested_items(module, items, taken)
+    }
+
+    // Resolve every pub function and constant of a module under its own name (use * from './utils.ko')
+    pub fn resolve_wildcard_import(
+        &self,
+        module: &LoadedModule,
+        taken: &HashSet<String>,
+    ) -> Result<ResolvedImport, String> {
+        let exports = module.exports();
+        let requested: Vec<ImportItem> = exports
+            .functions
+            .iter()
+            .map(|f| &f.name)
+            .chain(exports.constants.iter().map(|c| &c.name))
+            .map(|name| ImportItem {
+                name: name.clone(),
+                alias: None,
+            })
+            .collect();
+
+        self.filter_requested_items(module, &requested, taken)
+    }
+
+    pub fn load_module(&mut self, module_path: &str) -> Result<LoadedModule, String> {
+        // Check if it's a local file import (starts with ./, ../ or /)
+        if module_path.starts_with("./") || module_path.starts_with("../") || module_path.starts_with("/") {
+            self.load_local_file(module_path)
         } else if module_path.starts_with("http://") || module_path.starts_with("https://") {
             // Full URL import (GitHub, GitLab, Codeberg, etc.)
-            self.import_from_git_url(functions, module_path)
+            self.load_git_url(module_path)
         } else {
             // Legacy GitHub shorthand (username/repo)
-            self.import_from_github_shorthand(functions, module_path)
+            self.load_github_shorthand(module_path)
         }
     }
 
-    fn import_from_local_file(
-        &mut self,
-        functions: &[String],
-        file_path: &str,
-    ) -> Result<Vec<ImportedFunction>, String> {
+    fn load_local_file(&mut self, file_path: &str) -> Result<LoadedModule, String> {
         let path = if file_path.starts_with("./") || file_path.starts_with("../") {
             // Relative path - resolve from current file directory if available, otherwise project root
             if let Some(ref current_dir) = self.current_file_dir {
@@ -84,18 +240,24 @@ impl DependencyManager {
             return Err(format!("File not found: {}", path.display()));
         }
 
+        // The same file can be imported under several names, parse it once
+        let path = path.canonicalize().unwrap_or(path);
+        let cache_key = path.display().to_string();
+        if let Some(cached) = self.cached_modules.get(&cache_key) {
+            return Ok(cached.clone());
+        }
+
         // Read and parse the file
         let content = fs::read_to_string(&path)
             .map_err(|e| format!("Failed to read file {}: {}", path.display(), e))?;
 
-        self.parse_and_extract_functions(&content, functions)
+        let module = self.parse_module(cache_key.clone(), path, &content)?;
+        self.cached_modules.insert(cache_key, module.clone());
+
+        Ok(module)
     }
 
-    fn import_from_github_shorthand(
-        &mut self,
-        functions: &[String],
-        module_path: &str,
-    ) -> Result<Vec<ImportedFunction>, String> {
+    fn load_github_shorthand(&mut self, module_path: &str) -> Result<LoadedModule, String> {
         // Parse the module path (username/repo@version)
         let (repo_path, version) = if module_path.contains('@') {
             let parts: Vec<&str> = module_path.split('@').collect();
@@ -108,14 +270,10 @@ impl DependencyManager {
         let repo_url = format!("https://github.com/{}.git", repo_path);
 
         // Use the common git URL handler
-        self.import_from_git_url_internal(functions, &repo_url, version)
+        self.load_git_url_internal(&repo_url, version)
     }
 
-    fn import_from_git_url(
-        &mut self,
-        functions: &[String],
-        url: &str,
-    ) -> Result<Vec<ImportedFunction>, String> {
+    fn load_git_url(&mut self, url: &str) -> Result<LoadedModule, String> {
         // Parse URL to extract version if specified with @
         let (base_url, version) = if url.contains('@') {
             let parts: Vec<&str> = url.rsplitn(2, '@').collect();
@@ -132,15 +290,14 @@ impl DependencyManager {
             ));
         }
 
-        self.import_from_git_url_internal(functions, base_url, version)
+        self.load_git_url_internal(base_url, version)
     }
 
-    fn import_from_git_url_internal(
+    fn load_git_url_internal(
         &mut self,
-        functions: &[String],
         repo_url: &str,
         version: Option<&str>,
-    ) -> Result<Vec<ImportedFunction>, String> {
+    ) -> Result<LoadedModule, String> {
         // Create a cache key from the URL and version
         let cache_key = if let Some(v) = version {
             format!("{}@{}", repo_url, v)
@@ -149,9 +306,8 @@ impl DependencyManager {
         };
 
         // Check if already cached
-        if self.cached_modules.contains_key(&cache_key) {
-            let cached = self.cached_modules.get(&cache_key).unwrap();
-            return self.filter_requested_functions(cached, functions);
+        if let Some(cached) = self.cached_modules.get(&cache_key) {
+            return Ok(cached.clone());
         }
 
         // Create a safe directory name from the URL
@@ -187,107 +343,131 @@ impl DependencyManager {
         let content =
             fs::read_to_string(&lib_file).map_err(|e| format!("Failed to read lib.ko: {}", e))?;
 
-        let all_functions = self.parse_and_extract_all_functions(&content)?;
+        let module = self.parse_module(cache_key.clone(), lib_file, &content)?;
 
         // Cache the module
-        self.cached_modules.insert(cache_key, all_functions.clone());
+        self.cached_modules.insert(cache_key, module.clone());
 
-        // Filter for requested functions
-        self.filter_requested_functions(&all_functions, functions)
+        Ok(module)
     }
 
-    fn parse_and_extract_functions(
-        &self,
-        content: &str,
-        requested_functions: &[String],
-    ) -> Result<Vec<ImportedFunction>, String> {
-        // Parse the content using the Kozeig lexer and parser
+    fn parse_module(&self, id: String, file: PathBuf, content: &str) -> Result<LoadedModule, String> {
         let mut lexer = crate::lexer::Lexer::new(content);
         let tokens = lexer.scan_tokens()?;
 
         let mut parser = crate::parser::Parser::new(tokens);
         let statements = parser.parse()?;
 
-        let mut imported_functions = Vec::new();
+        let mut functions = Vec::new();
+        let mut constants = Vec::new();
+        let mut imports = Vec::new();
+        let mut externs = Vec::new();
 
-        // Extract requested functions
         for stmt in statements {
-            if let crate::parser::Stmt::Function {
-                name,
-                is_public,
-                parameters,
-                body,
-            } = stmt
-            {
-                if requested_functions.contains(&name) {
-                    imported_functions.push(ImportedFunction {
-                        name: name.clone(),
+            match stmt {
+                crate::parser::Stmt::Function {
+                    name,
+                    is_public,
+                    parameters,
+                    return_type,
+                    body,
+                    line,
+                } => {
+                    functions.push(ImportedFunction {
+                        source_name: name.clone(),
+                        name,
                         body,
                         parameters,
+                        return_type,
                         is_public,
+                        line,
                     });
                 }
+                crate::parser::Stmt::Const {
+                    name,
+                    is_public,
+                    initializer,
+                    ..
+                } => {
+                    constants.push(ImportedConstant {
+                        source_name: name.clone(),
+                        name,
+                        initializer,
+                        is_public,
+                    });
+                }
+                // Kept so the module's own imports resolve inside its scope
+                import @ crate::parser::Stmt::Import { .. } => imports.push(import),
+                declaration @ crate::parser::Stmt::Extern { .. } => externs.push(declaration),
+                _ => {}
             }
         }
 
-        // Check if all requested functions were found
-        for func_name in requested_functions {
-            if !imported_functions.iter().any(|f| &f.name == func_name) {
-                return Err(format!("Function '{}' not found in module", func_name));
-            }
-        }
-
-        Ok(imported_functions)
+        Ok(LoadedModule {
+            id,
+            dir: file.parent().map(Path::to_path_buf).unwrap_or_default(),
+            file,
+            functions,
+            constants,
+            imports,
+            externs,
+        })
     }
 
-    fn parse_and_extract_all_functions(
+    // Pick the requested functions and constants, renamed to their aliases
+    fn filter_requested_items(
         &self,
-        content: &str,
-    ) -> Result<Vec<ImportedFunction>, String> {
-        let mut lexer = crate::lexer::Lexer::new(content);
-        let tokens = lexer.scan_tokens()?;
-
-        let mut parser = crate::parser::Parser::new(tokens);
-        let statements = parser.parse()?;
+        module: &LoadedModule,
+        requested: &[ImportItem],
+        taken: &HashSet<String>,
+    ) -> Result<ResolvedImport, String> {
+        let mut resolved = ResolvedImport::default();
+        let mut bound: HashSet<&str> = HashSet::new();
+
+        for item in requested {
+            let local_name = item.local_name();
+
+            if let Some(func) = module.functions.iter().find(|f| f.name == item.name) {
+                if !func.is_public {
+                    return Err(format!(
+                        "Function '{}' is prot and cannot be imported\n\nTip: Declare it as func pub {} to export it",
+                        item.name, item.name
+                    ));
+                }
+                if taken.contains(local_name) {
+                    return Err(format!(
+                        "'{}' conflicts with a function that already exists\n\nTip: Rename the import, e.g. use {{ {} as other_name }} from ...",
+                        local_name, item.name
+                    ));
+                }
 
-        let mut functions = Vec::new();
+                let mut func = func.clone();
+                func.name = local_name.to_string();
+                resolved.functions.push(func);
+            } else if let Some(constant) = module.constants.iter().find(|c| c.name == item.name) {
+                if !constant.is_public {
+                    return Err(format!(
+                        "Constant '{}' is prot and cannot be imported\n\nTip: Declare it as const pub {} to export it",
+                        item.name, item.name
+                    ));
+                }
 
-        for stmt in statements {
-            if let crate::parser::Stmt::Function {
-                name,
-                is_public,
-                parameters,
-                body,
-            } = stmt
-            {
-                functions.push(ImportedFunction {
-                    name,
-                    body,
-                    parameters,
-                    is_public,
-                });
+                let mut constant = constant.clone();
+                constant.name = local_name.to_string();
+                resolved.constants.push(constant);
+            } else {
+                return Err(format!("'{}' not found in module", item.name));
             }
-        }
-
-        Ok(functions)
-    }
 
-    fn filter_requested_functions(
-        &self,
-        all_functions: &[ImportedFunction],
-        requested: &[String],
-    ) -> Result<Vec<ImportedFunction>, String> {
-        let mut filtered = Vec::new();
-
-        for func_name in requested {
-            if let Some(func) = all_functions.iter().find(|f| &f.name == func_name) {
-                filtered.push(func.clone());
-            } else {
-                return Err(format!("Function '{}' not found in module", func_name));
+            if !bound.insert(local_name) {
+                return Err(format!(
+                    "'{}' is imported more than once from the same module",
+                    local_name
+                ));
             }
         }
 
-        Ok(filtered)
+        Ok(resolved)
     }
 
     // Git operations
@@ -0,0 +1,221 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use crate::build_options::OptLevel;
+use crate::error_reporting::LutError;
+
+// What one execution mode produced for a program
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModeResult {
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_code: Option<i32>,
+}
+
+impl ModeResult {
+    fn from_output(output: &Output) -> Self {
+        ModeResult {
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            exit_code: output.status.code(),
+        }
+    }
+}
+
+// A program whose execution modes disagree
+#[derive(Debug)]
+pub struct Mismatch {
+    pub program: PathBuf,
+    pub differences: Vec<String>,
+}
+
+// Every program under dir, in a stable order. Modules that only declare things for
+// others to import, like the ones in lib/, are left out
+pub fn find_programs(dir: &Path) -> Result<Vec<PathBuf>, LutError> {
+    let mut programs = Vec::new();
+    let entries = fs::read_dir(dir)
+        .map_err(|e| LutError::io_error(format!("Failed to read directory '{}': {}", dir.display(), e)))?;
+    for entry in entries {
+        let path = entry
+            .map_err(|e| LutError::io_error(format!("Failed to read directory '{}': {}", dir.display(), e)))?
+            .path();
+        if path.is_dir() {
+            programs.extend(find_programs(&path)?);
+        } else if path.extension().is_some_and(|ext| ext == "ko") && is_program(&path) {
+            programs.push(path);
+        }
+    }
+    programs.sort();
+    Ok(programs)
+}
+
+// A program defines main or has top-level code to run. Files that don't parse are kept,
+// so the modes still get to report the error
+fn is_program(path: &Path) -> bool {
+    use crate::parser::Stmt;
+
+    let Ok(source) = fs::read_to_string(path) else {
+        return true;
+    };
+    let Ok(tokens) = crate::lexer::Lexer::new(&source).scan_tokens() else {
+        return true;
+    };
+    match crate::parser::Parser::new(tokens).parse() {
+        Ok(statements) => statements.iter().any(|stmt| match stmt {
+            Stmt::Function { name, .. } => name == "main",
+            Stmt::Import { .. } | Stmt::Const { .. } | Stmt::Extern { .. } | Stmt::Comment(_) => false,
+            _ => true,
+        }),
+        Err(_) => true,
+    }
+}
+
+// Run a program with `koze run`, `koze jit` and binaries from both `koze build` backends.
+// koze is the executable to drive, so every mode runs in its own process
+pub fn run_modes(koze: &Path, program: &Path, opt_level: OptLevel) -> Result<Vec<(&'static str, ModeResult)>, LutError> {
+    let program = fs::canonicalize(program)
+        .map_err(|e| LutError::io_error(format!("Failed to resolve '{}': {}", program.display(), e)))?;
+    // jit writes its .ll and build its binary to the working directory
+    let work_dir = tempfile::tempdir()
+        .map_err(|e| LutError::io_error(format!("Failed to create a temporary directory: {}", e)))?;
+
+    let run = spawn(Command::new(koze).arg("run").arg(&program).current_dir(work_dir.path()))?;
+    let mut results = vec![("run", run)];
+
+    if cfg!(feature = "llvm") {
+        let jit = spawn(
+            Command::new(koze)
+                .arg("jit")
+                .arg(&program)
+                .args(["-s", opt_level.flag()])
+                .current_dir(work_dir.path()),
+        )?;
+        results.push(("jit", jit));
+
+        let stem = program.file_stem().unwrap_or_default().to_string_lossy().to_string();
+        results.push(("build", build_and_run(koze, &program, work_dir.path(), opt_level, "llvm", &stem)?));
+    }
+
+    let c_binary = format!("{}-c", program.file_stem().unwrap_or_default().to_string_lossy());
+    results.push(("build-c", build_and_run(koze, &program, work_dir.path(), opt_level, "c", &c_binary)?));
+
+    Ok(results)
+}
+
+// Build the program with one backend and run the binary
+fn build_and_run(koze: &Path, program: &Path, work_dir: &Path, opt_level: OptLevel, backend: &str, output: &str) -> Result<ModeResult, LutError> {
+    let build = spawn(
+        Command::new(koze)
+            .arg("build")
+            .arg(program)
+            .args(["-s", opt_level.flag()])
+            .arg(format!("--backend={}", backend))
+            .args(["-o", output])
+            .current_dir(work_dir),
+    )?;
+    if build.exit_code == Some(0) {
+        spawn(Command::new(work_dir.join(output)).current_dir(work_dir))
+    } else {
+        // Only the errors matter; stdout is the compiler's progress output
+        Ok(ModeResult { stdout: String::new(), ..build })
+    }
+}
+
+fn spawn(command: &mut Command) -> Result<ModeResult, LutError> {
+    let output = command
+        .output()
+        .map_err(|e| LutError::io_error(format!("Failed to run {:?}: {}", command.get_program(), e)))?;
+    Ok(ModeResult::from_output(&output))
+}
+
+// Describe how each mode differs from the first one (the interpreter)
+pub fn compare(program: &Path, results: &[(&'static str, ModeResult)]) -> Option<Mismatch> {
+    let (reference_mode, reference) = results.first()?;
+    let mut differences = Vec::new();
+
+    for (mode, result) in &results[1..] {
+        if result.stdout != reference.stdout {
+            differences.push(format!(
+                "stdout of {} and {} differ{}",
+                reference_mode, mode, first_difference(&reference.stdout, &result.stdout)
+            ));
+        }
+        if result.stderr != reference.stderr {
+            differences.push(format!(
+                "stderr of {} and {} differ{}",
+                reference_mode, mode, first_difference(&reference.stderr, &result.stderr)
+            ));
+        }
+        if result.exit_code != reference.exit_code {
+            differences.push(format!(
+                "{} exited with {}, {} exited with {}",
+                reference_mode, describe_exit(reference.exit_code), mode, describe_exit(result.exit_code)
+            ));
+        }
+    }
+
+    if differences.is_empty() {
+        None
+    } else {
+        Some(Mismatch { program: program.to_path_buf(), differences })
+    }
+}
+
+fn first_difference(expected: &str, actual: &str) -> String {
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+    let mut line = 1;
+    loop {
+        match (expected_lines.next(), actual_lines.next()) {
+            (Some(a), Some(b)) if a == b => line += 1,
+            (None, None) => return String::new(),
+            (a, b) => {
+                return format!(
+                    " at line {}:\n    {:?}\n    {:?}",
+                    line,
+                    a.unwrap_or("<end of output>"),
+                    b.unwrap_or("<end of output>")
+                )
+            }
+        }
+    }
+}
+
+fn describe_exit(code: Option<i32>) -> String {
+    code.map_or("a signal".to_string(), |code| code.to_string())
+}
+
+// Check every program under dir and print a report; returns the mismatches
+pub fn run_difftest(koze: &Path, dir: &Path, opt_level: OptLevel) -> Result<Vec<Mismatch>, LutError> {
+    let programs = find_programs(dir)?;
+    if programs.is_empty() {
+        return Err(LutError::io_error(format!(
+            "No .ko files found under '{}'\n\nTip: Point difftest at a directory of Kozeig programs",
+            dir.display()
+        )));
+    }
+
+    let mut mismatches = Vec::new();
+    for program in &programs {
+        let results = run_modes(koze, program, opt_level)?;
+        match compare(program, &results) {
+            Some(mismatch) => {
+                println!("FAIL {}", mismatch.program.display());
+                for difference in &mismatch.differences {
+                    println!("  {}", difference);
+                }
+                mismatches.push(mismatch);
+            }
+            None => println!("ok   {}", program.display()),
+        }
+    }
+
+    println!(
+        "\n{} programs, {} passed, {} failed",
+        programs.len(),
+        programs.len() - mismatches.len(),
+        mismatches.len()
+    );
+    Ok(mismatches)
+}
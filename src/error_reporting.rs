@@ -1,6 +1,8 @@
 use crate::lexer::Token;
+use kozeig_runtime::report::{format_report, ReportLocation};
 use std::error::Error;
 use std::fmt;
+use std::fs;
 
 /// Represents the location of code in a source file
 #[derive(Debug, Clone, PartialEq)]
@@ -119,6 +121,21 @@ impl LutError {
             message: message.into(),
         }
     }
+
+    /// Give a runtime or compiler error a line if it does not have one yet
+    pub fn or_at_line(self, line: usize) -> Self {
+        match self {
+            LutError::Runtime { message, location: None } => LutError::Runtime {
+                message,
+                location: Some(SourceLocation::new(line)),
+            },
+            LutError::Compiler { message, location: None } => LutError::Compiler {
+                message,
+                location: Some(SourceLocation::new(line)),
+            },
+            other => other,
+        }
+    }
 }
 
 impl fmt::Display for LutError {
@@ -356,7 +373,8 @@ pub fn extract_line_info(error_msg: &str) -> Option<(usize, String)> {
     None
 }
 
-/// Function to pretty print error messages with source context
+/// Function to pretty print error messages with source context. Errors located in
+/// another file, such as an imported module, show that file's lines instead
 pub fn print_error_with_context(error: &LutError, source_code: &str) {
     let location = match error {
         LutError::Lexer { location, .. } => Some(location),
@@ -375,107 +393,18 @@ pub fn print_error_with_context(error: &LutError, source_code: &str) {
         LutError::IO { message } => ("I/O error", message),
     };
 
-    // Print colored error header
-    eprintln!("\x1b[1;31m{}\x1b[0m", "=".repeat(50));
-
-    // Format error message with type and location
-    if let Some(loc) = location {
-        eprintln!(
-            "\x1b[1;31m{}\x1b[0m at \x1b[1;33m{}\x1b[0m:",
-            error_type, loc
-        );
-    } else {
-        eprintln!("\x1b[1;31m{}\x1b[0m:", error_type);
-    }
-
-    // Print the actual error message with enhanced formatting
-    let message_lines: Vec<&str> = error_message.lines().collect();
-
-    // First line is the main error
-    if !message_lines.is_empty() {
-        eprintln!("\x1b[1m{}\x1b[0m", message_lines[0]);
-
-        // Additional lines might be suggestions or tips
-        for line in message_lines.iter().skip(1) {
-            if line.starts_with("Tip:") {
-                eprintln!("\x1b[1;36m{}\x1b[0m", line); // Cyan for tips
-            } else if line.contains("Did you mean") {
-                eprintln!("\x1b[1;32m{}\x1b[0m", line); // Green for suggestions
-            } else if line.starts_with("  - ") {
-                eprintln!("\x1b[1;32m{}\x1b[0m", line); // Green for suggestion items
-            } else {
-                eprintln!("{}", line);
-            }
-        }
-    }
-
-    // If we have location info, print source context
-    if let Some(loc) = location {
-        let lines: Vec<&str> = source_code.lines().collect();
-        let line_idx = loc.line.saturating_sub(1); // Convert to 0-based index
-
-        if line_idx < lines.len() {
-            // Print a few lines of context
-            let start_line = line_idx.saturating_sub(2);
-            let end_line = std::cmp::min(line_idx + 3, lines.len());
-
-            eprintln!("\n\x1b[1mSource context:\x1b[0m");
-            for i in start_line..end_line {
-                if i == line_idx {
-                    // Highlight the error line
-                    eprintln!(
-                        "\x1b[33m{:>4}\x1b[0m \x1b[31m>\x1b[0m \x1b[1m{}\x1b[0m",
-                        i + 1,
-                        lines[i]
-                    );
-
-                    // Print an error pointer if we have column info
-                    if let Some(col) = loc.column {
-                        let spaces = " ".repeat(6 + col);
-                        eprintln!(
-                            "{}\x1b[1;31m^\x1b[0m\x1b[31m-- Error occurs here\x1b[0m",
-                            spaces
-                        );
-                    } else {
-                        // Try to find the specific position in the line for better pointer placement
-                        if let Some(error_term) = message_lines.first().and_then(|msg| {
-                            // Extract a potential error term from the error message
-                            if msg.contains("variable") && msg.contains(':') {
-                                msg.split(':').nth(1).map(|s| s.trim())
-                            } else if msg.contains("'") {
-                                let start = msg.find('\'').map(|i| i + 1).unwrap_or(0);
-                                let end = msg[start..]
-                                    .find('\'')
-                                    .map(|i| i + start)
-                                    .unwrap_or(msg.len());
-                                Some(&msg[start..end])
-                            } else {
-                                None
-                            }
-                        }) {
-                            if let Some(pos) = lines[i].find(error_term) {
-                                let spaces = " ".repeat(6 + pos);
-                                eprintln!(
-                                    "{}\x1b[1;31m^\x1b[0m\x1b[31m-- Error occurs here\x1b[0m",
-                                    spaces
-                                );
-                            } else {
-                                eprintln!(
-                                    "      \x1b[1;31m^\x1b[0m\x1b[31m-- Error occurs here\x1b[0m"
-                                );
-                            }
-                        } else {
-                            eprintln!(
-                                "      \x1b[1;31m^\x1b[0m\x1b[31m-- Error occurs here\x1b[0m"
-                            );
-                        }
-                    }
-                } else {
-                    eprintln!("\x1b[90m{:>4} |\x1b[0m {}", i + 1, lines[i]);
-                }
-            }
-        }
-    }
-
-    eprintln!("\x1b[1;31m{}\x1b[0m", "=".repeat(50));
+    let file_source = location
+        .and_then(|loc| loc.file.as_ref())
+        .map(|file| fs::read_to_string(file).unwrap_or_default());
+    let shown = location.map(|loc| loc.to_string());
+    let report_location = location.zip(shown.as_deref()).map(|(loc, shown)| ReportLocation {
+        shown,
+        line: loc.line,
+        column: loc.column,
+    });
+
+    eprint!(
+        "{}",
+        format_report(error_type, error_message, report_location.as_ref(), file_source.as_deref().unwrap_or(source_code))
+    );
 }
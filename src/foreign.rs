@@ -0,0 +1,264 @@
+// C functions declared with `extern func`. Compiled code links against them directly;
+// the interpreter and jit look them up with dlopen/dlsym, and the interpreter calls them
+// through a fixed signature that covers every declaration it accepts.
+use crate::interpreter::Value;
+use crate::parser::FunctionParam;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::rc::Rc;
+
+// Integer-class and floating point arguments the interpreter can pass in registers
+const MAX_INT_ARGS: usize = 6;
+const MAX_FP_ARGS: usize = 8;
+
+// C library functions taking `...`; an extern func declares a fixed signature, and variadic
+// arguments are passed differently (on the stack on AArch64 macOS, with a count in al on x86-64)
+const VARIADIC_FUNCTIONS: &[&str] = &[
+    "printf", "fprintf", "dprintf", "sprintf", "snprintf", "asprintf", "scanf", "fscanf", "sscanf",
+    "syslog", "open", "openat", "fcntl", "ioctl", "execl", "execlp", "execle", "prctl",
+];
+
+#[derive(Debug, PartialEq)]
+pub struct ForeignFunction {
+    pub name: String,
+    pub parameters: Vec<FunctionParam>,
+    pub return_type: Option<String>,
+    address: usize,
+}
+
+// number is int64_t, fp is double, bool is bool and text is a NUL-terminated char *
+pub fn check_signature(name: &str, parameters: &[FunctionParam], return_type: Option<&str>) -> Result<(), String> {
+    let unsupported = parameters
+        .iter()
+        .map(|param| param.param_type.as_str())
+        .chain(return_type)
+        .find(|type_name| !matches!(*type_name, "number" | "fp" | "bool" | "text"));
+    if let Some(type_name) = unsupported {
+        return Err(format!(
+            "extern func '{}' cannot use {} values: they have no C type\n\nTip: Use number, fp, bool or text",
+            name, type_name
+        ));
+    }
+    if VARIADIC_FUNCTIONS.contains(&name) {
+        return Err(format!(
+            "extern func '{}' declares a variadic C function, which cannot be called with a fixed signature\n\nTip: Declare a C function with fixed parameters instead, e.g. puts or a wrapper of your own",
+            name
+        ));
+    }
+    Ok(())
+}
+
+// The file name dlopen is given for `from 'name'`; anything that looks like a file name is used as is
+pub fn library_file(library: &str) -> String {
+    if library.contains('/') || library.contains('.') {
+        library.to_string()
+    } else if cfg!(target_os = "macos") {
+        format!("lib{}.dylib", library)
+    } else {
+        format!("lib{}.so", library)
+    }
+}
+
+#[cfg(unix)]
+mod dl {
+    use std::ffi::{c_char, c_int, c_void};
+
+    pub const RTLD_NOW: c_int = 2;
+
+    extern "C" {
+        pub fn dlopen(filename: *const c_char, flags: c_int) -> *mut c_void;
+        pub fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+        pub fn dlerror() -> *mut c_char;
+    }
+}
+
+// Address of a C function. Symbols already in the process (libc, libm) are found
+// without opening the library, so `from 'm'` works where libm.so is a linker script
+#[cfg(unix)]
+pub fn resolve(name: &str, library: Option<&str>) -> Result<*const c_void, String> {
+    let symbol = CString::new(name).map_err(|_| format!("Invalid C function name '{}'", name))?;
+    unsafe {
+        let process = dl::dlopen(std::ptr::null(), dl::RTLD_NOW);
+        let address = dl::dlsym(process, symbol.as_ptr());
+        if !address.is_null() {
+            return Ok(address);
+        }
+
+        let library = match library {
+            Some(library) => library,
+            None => {
+                return Err(format!(
+                    "C function '{}' was not found\n\nTip: Name the library it comes from with extern func {} [ ... ] from 'library'",
+                    name, name
+                ))
+            }
+        };
+        let file = library_file(library);
+        let file_name = CString::new(file.clone()).map_err(|_| format!("Invalid library name '{}'", library))?;
+        let mut handle = dl::dlopen(file_name.as_ptr(), dl::RTLD_NOW);
+        // glibc's libm.so and friends are linker scripts; the library itself is .so.6
+        if handle.is_null() && cfg!(target_os = "linux") && file.ends_with(".so") {
+            let error = dl_error();
+            let versioned = CString::new(format!("{}.6", file)).unwrap();
+            handle = dl::dlopen(versioned.as_ptr(), dl::RTLD_NOW);
+            if handle.is_null() {
+                return Err(load_error(library, &error, &file));
+            }
+        }
+        if handle.is_null() {
+            return Err(load_error(library, &dl_error(), &file));
+        }
+        let address = dl::dlsym(handle, symbol.as_ptr());
+        if address.is_null() {
+            return Err(format!("C function '{}' was not found in '{}'", name, file));
+        }
+        Ok(address)
+    }
+}
+
+#[cfg(unix)]
+fn load_error(library: &str, error: &str, file: &str) -> String {
+    format!(
+        "Cannot load library '{}': {}\n\nTip: Check that {} is installed where the system linker looks for it",
+        library, error, file
+    )
+}
+
+#[cfg(not(unix))]
+pub fn resolve(name: &str, _library: Option<&str>) -> Result<*const c_void, String> {
+    Err(format!(
+        "C function '{}' cannot be loaded on this platform\n\nTip: Build the program with koze build instead",
+        name
+    ))
+}
+
+#[cfg(unix)]
+unsafe fn dl_error() -> String {
+    let message = dl::dlerror();
+    if message.is_null() {
+        "unknown error".to_string()
+    } else {
+        CStr::from_ptr(message).to_string_lossy().to_string()
+    }
+}
+
+impl ForeignFunction {
+    pub fn load(name: &str, parameters: &[FunctionParam], return_type: Option<&str>, library: Option<&str>) -> Result<Self, String> {
+        check_signature(name, parameters, return_type)?;
+        let fp_args = parameters.iter().filter(|param| param.param_type == "fp").count();
+        check_registers(name, parameters.len() - fp_args, fp_args)?;
+        let address = resolve(name, library)?;
+        Ok(ForeignFunction {
+            name: name.to_string(),
+            parameters: parameters.to_vec(),
+            return_type: return_type.map(str::to_string),
+            address: address as usize,
+        })
+    }
+
+    pub fn call(&self, arguments: &[Value]) -> Result<Value, String> {
+        // The C strings must outlive the call
+        let mut strings = Vec::new();
+        let mut ints = Vec::with_capacity(MAX_INT_ARGS);
+        let mut floats = Vec::with_capacity(MAX_FP_ARGS);
+
+        for (i, (param, argument)) in self.parameters.iter().zip(arguments).enumerate() {
+            let mismatch = || format!("Argument {} of {} must be {}", i + 1, self.name, describe(&param.param_type));
+            match param.param_type.as_str() {
+                "fp" => floats.push(match argument {
+                    Value::Float(value) => *value,
+                    Value::Number(value) => *value as f64,
+                    _ => return Err(mismatch()),
+                }),
+                "text" => ints.push(match argument {
+                    Value::Text(text) => {
+                        let string = CString::new(text.as_bytes())
+                            .map_err(|_| format!("Argument {} of {} contains a NUL character", i + 1, self.name))?;
+                        let pointer = string.as_ptr() as i64;
+                        strings.push(string);
+                        pointer
+                    }
+                    Value::Null => 0,
+                    _ => return Err(mismatch()),
+                }),
+                _ => ints.push(match argument {
+                    Value::Number(value) => *value,
+                    Value::Boolean(value) => *value as i64,
+                    _ => return Err(mismatch()),
+                }),
+            }
+        }
+
+        let result = call_address(&self.name, self.address, &ints, &floats, self.return_type.as_deref() == Some("fp"))?;
+
+        Ok(match (self.return_type.as_deref(), result) {
+            (None, _) => Value::Null,
+            (Some("fp"), Return::Float(value)) => Value::Float(value),
+            // C returns bool in the low byte, the rest of the register is unspecified
+            (Some("bool"), Return::Int(value)) => Value::Boolean(value & 0xff != 0),
+            (Some("text"), Return::Int(0)) => Value::Null,
+            (Some("text"), Return::Int(pointer)) => {
+                let text = unsafe { CStr::from_ptr(pointer as *const c_char) };
+                Value::Text(Rc::new(text.to_string_lossy().to_string()))
+            }
+            (_, Return::Int(value)) => Value::Number(value),
+            (_, Return::Float(value)) => Value::Float(value),
+        })
+    }
+}
+
+// Arguments past the registers go on the stack, where the fixed signature below has none
+fn check_registers(name: &str, int_args: usize, fp_args: usize) -> Result<(), String> {
+    if int_args > MAX_INT_ARGS || fp_args > MAX_FP_ARGS {
+        return Err(format!(
+            "extern func '{}' has too many parameters for koze run, which passes at most {} number, bool or text and {} fp arguments in registers\n\nTip: Build the program with koze build instead",
+            name, MAX_INT_ARGS, MAX_FP_ARGS
+        ));
+    }
+    Ok(())
+}
+
+fn describe(type_name: &str) -> &'static str {
+    match type_name {
+        "fp" => "fp",
+        "text" => "text",
+        "bool" => "bool",
+        _ => "a number",
+    }
+}
+
+enum Return {
+    Int(i64),
+    Float(f64),
+}
+
+type IntFunction = unsafe extern "C" fn(i64, i64, i64, i64, i64, i64, f64, f64, f64, f64, f64, f64, f64, f64) -> i64;
+type FloatFunction = unsafe extern "C" fn(i64, i64, i64, i64, i64, i64, f64, f64, f64, f64, f64, f64, f64, f64) -> f64;
+
+// Integer-class and fp arguments are assigned registers independently of each other on
+// x86-64 System V and AArch64, so passing all of them fills in any declared order.
+// Registers the C function does not read are ignored. Signatures that would need the
+// stack are rejected, since nothing is passed there
+#[cfg(all(unix, any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn call_address(name: &str, address: usize, ints: &[i64], floats: &[f64], returns_fp: bool) -> Result<Return, String> {
+    check_registers(name, ints.len(), floats.len())?;
+    let mut int_registers = [0i64; MAX_INT_ARGS];
+    let mut fp_registers = [0f64; MAX_FP_ARGS];
+    int_registers[..ints.len()].copy_from_slice(ints);
+    fp_registers[..floats.len()].copy_from_slice(floats);
+    let [a, b, c, d, e, f] = int_registers;
+    let [g, h, i, j, k, l, m, n] = fp_registers;
+    unsafe {
+        if returns_fp {
+            let function: FloatFunction = std::mem::transmute(address as *const c_void);
+            Ok(Return::Float(function(a, b, c, d, e, f, g, h, i, j, k, l, m, n)))
+        } else {
+            let function: IntFunction = std::mem::transmute(address as *const c_void);
+            Ok(Return::Int(function(a, b, c, d, e, f, g, h, i, j, k, l, m, n)))
+        }
+    }
+}
+
+#[cfg(not(all(unix, any(target_arch = "x86_64", target_arch = "aarch64"))))]
+fn call_address(_name: &str, _address: usize, _ints: &[i64], _floats: &[f64], _returns_fp: bool) -> Result<Return, String> {
+    Err("koze run can only call C functions on 64-bit Unix\n\nTip: Build the program with koze build instead".to_string())
+}
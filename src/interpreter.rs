@@ -1,7 +1,9 @@
+use crate::decimal::{Decimal, RoundingMode};
 use crate::lexer::{Lexer, TokenType};
-use crate::parser::{Expr, FunctionParam, Parser, Stmt};
-use crate::error_reporting::LutError;
-use crate::dependency_manager::DependencyManager;
+use crate::parser::{Expr, FunctionParam, ImportItem, Parser, Stmt};
+use crate::error_reporting::{LutError, SourceLocation};
+use crate::foreign::ForeignFunction;
+use crate::dependency_manager::{DependencyManager, LoadedModule, ResolvedImport};
 use std::collections::{HashMap, HashSet};
 use std::io::{self, BufWriter, Write};
 use std::rc::Rc;
@@ -126,6 +128,16 @@ pub struct Interpreter {
     dependency_manager: Option<DependencyManager>,
     // Path to the currently executing file
     current_file_path: Option<PathBuf>,
+    // Namespaces from `use name from 'path'`, mapped to their module path
+    namespaces: HashMap<String, String>,
+    // Functions visible inside each imported module, keyed by module id
+    module_scopes: HashMap<String, ModuleScope>,
+    // Module of the function being executed; None for the running file
+    current_module: Option<String>,
+    // Functions declared at the top level of the running file, which imports may not shadow
+    local_function_names: HashSet<String>,
+    // Constants of the running file, declared or imported, which may not be reassigned
+    constant_names: HashSet<String>,
 }
 
 // Use memory-efficient representation for values
@@ -135,12 +147,26 @@ pub struct Function {
     is_public: bool,
     parameters: Vec<FunctionParam>,
     body: Vec<Stmt>,
+    module: Option<String>, // Module the function was declared in
+    foreign: Option<Rc<ForeignFunction>>, // C function from extern func, which has no body
+}
+
+// What a module's own code can see: its functions and constants plus whatever it imports.
+// Nothing in here leaks into the importing file unless it is imported by name.
+#[derive(Default)]
+struct ModuleScope {
+    functions: HashMap<String, Rc<Function>>,
+    constants: HashMap<String, Value>,
+    namespaces: HashMap<String, String>,
+    // Source file, as runtime errors in the module's code name it
+    file: String,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     Number(i64),
     Float(f64),
+    Decimal(Decimal), // Fixed-point value from the dec command
     Text(Rc<String>), // Use reference counting for strings
     Boolean(bool),
     Array(Vec<Value>),
@@ -154,6 +180,7 @@ impl std::fmt::Display for Value {
         match self {
             Value::Number(n) => write!(f, "{}", n),
             Value::Float(n) => write!(f, "{}", n),
+            Value::Decimal(d) => write!(f, "{}", d),
             Value::Text(s) => write!(f, "{}", s),
             Value::Boolean(b) => write!(f, "{}", if *b { "true" } else { "false" }),
             Value::Array(arr) => {
@@ -184,7 +211,7 @@ impl std::fmt::Display for Value {
                 write!(f, "]")
             }
             Value::Function(func) => write!(f, "<function {}>", func.name),
-            Value::Null => write!(f, "null"),
+            Value::Null => write!(f, "none"),
         }
     }
 }
@@ -196,6 +223,7 @@ fn is_truthy(value: &Value) -> bool {
         Value::Boolean(b) => *b,
         Value::Number(n) => *n != 0,
         Value::Float(f) => *f != 0.0,
+        Value::Decimal(d) => !d.is_zero(),
         Value::Text(s) => !s.is_empty(),
         Value::Array(arr) => !arr.is_empty(),
         Value::Array2D(arr) => !arr.is_empty(),
@@ -218,6 +246,11 @@ impl Interpreter {
             functions: HashMap::with_capacity(32), // Pre-allocate space for functions
             dependency_manager: None,
             current_file_path: None,
+            namespaces: HashMap::new(),
+            module_scopes: HashMap::new(),
+            current_module: None,
+            local_function_names: HashSet::new(),
+            constant_names: HashSet::new(),
         }
     }
 
@@ -253,9 +286,36 @@ impl Interpreter {
         Ok(())
     }
 
+    // Exit status for the value main returns: a number from 0 to 255 is the status, 'ok' is success and
+    // other text is printed to stderr as the program's error, with status 1. A number outside 0 to 255
+    // is reported the same way, since the system would keep only its low byte. Anything else is success
+    fn exit_status(&mut self, value: &Value) -> Result<i32, LutError> {
+        match value {
+            Value::Number(n) if (0..=255).contains(n) => Ok(*n as i32),
+            Value::Number(n) => {
+                self.flush_buffer()?;
+                eprintln!("Exit status {} is outside 0 to 255", n);
+                Ok(1)
+            }
+            Value::Text(text) if text.as_str() != "ok" => {
+                self.flush_buffer()?;
+                eprintln!("{}", text);
+                Ok(1)
+            }
+            _ => Ok(0),
+        }
+    }
+
     pub fn interpret(&mut self, statements: Vec<Stmt>) -> Result<(), LutError> {
         // Buffer flush counter to enable streaming output during long-running operations
         let mut ops_count = 0;
+
+        // Imports come before the functions they could clash with, so collect local names up front
+        for stmt in &statements {
+            if let Stmt::Function { name, .. } | Stmt::Extern { name, .. } = stmt {
+                self.local_function_names.insert(name.clone());
+            }
+        }
         
         for stmt in statements {
             self.execute(&stmt)?;
@@ -281,19 +341,42 @@ impl Interpreter {
     }
 
     fn execute(&mut self, stmt: &Stmt) -> Result<(), LutError> {
+        self.execute_statement(stmt).map_err(|e| self.locate(e, stmt.line()))
+    }
+
+    // Runtime errors are reported at the innermost statement they happen in, in the file
+    // that statement is from. Errors that already know their line only get the file
+    fn locate(&self, error: LutError, line: Option<usize>) -> LutError {
+        let LutError::Runtime { message, location } = error else {
+            return error;
+        };
+        let file = match &self.current_module {
+            Some(id) => Some(self.module_scopes[id].file.clone()),
+            None => self.current_file_path.as_ref().map(|path| path.display().to_string()),
+        };
+        let location = match (location.or_else(|| line.map(SourceLocation::new)), file) {
+            (Some(location), Some(file)) if location.file.is_none() => Some(location.with_file(file)),
+            (location, _) => location,
+        };
+        LutError::Runtime { message, location }
+    }
+
+    fn execute_statement(&mut self, stmt: &Stmt) -> Result<(), LutError> {
         // Check for control flow interruptions before executing any statement
         if self.control_flow != ControlFlow::None {
             return Ok(()); // Skip this statement if we're in a break, continue, or return state
         }
 
         match stmt {
-            Stmt::Function { name, is_public, parameters, body } => {
+            Stmt::Function { name, is_public, parameters, body, .. } => {
                 // Create a function object
                 let func = Function {
                     name: name.clone(),
                     is_public: *is_public,
                     parameters: parameters.clone(),
                     body: body.clone(),
+                    module: None,
+                    foreign: None,
                 };
                 
                 // Store the function in the function table
@@ -303,14 +386,32 @@ impl Interpreter {
                 // Also store the function as a value in the environment for easier access
                 self.environment.insert(name.clone(), Value::Function(func_rc));
             },
-            Stmt::Declaration { name, initializer } => {
+            Stmt::Extern { .. } => {
+                let (name, func) = foreign_function(stmt, None)?;
+                self.environment.insert(name.clone(), Value::Function(Rc::clone(&func)));
+                self.functions.insert(name, func);
+            }
+            Stmt::Declaration { name, initializer, .. } => {
+                if self.current_module.is_none() && self.constant_names.contains(name) {
+                    return Err(LutError::runtime_error(
+                        format!("Cannot assign to constant '{}'\n\nTip: Declare a new register for the changed value", name),
+                        None,
+                    ));
+                }
+                let value = self.evaluate(initializer)?;
+                self.environment.insert(name.clone(), value);
+            }
+            Stmt::Const { name, initializer, line, .. } => {
+                if self.current_module.is_none() && !self.constant_names.insert(name.clone()) {
+                    return Err(LutError::runtime_error(format!("Constant '{}' is already defined", name), Some(*line)));
+                }
                 let value = self.evaluate(initializer)?;
                 self.environment.insert(name.clone(), value);
             }
-            Stmt::Expression(expr) => {
+            Stmt::Expression(expr, _) => {
                 self.evaluate(expr)?;
             }
-            Stmt::Command { name, args } => {
+            Stmt::Command { name, args, .. } => {
                 match name.as_str() {
                     "print" | "-print" => {
                         if self.silent_mode {
@@ -362,7 +463,7 @@ impl Interpreter {
                     _ => return Err(LutError::runtime_error(format!("Unknown command: {}", name), None)),
                 }
             }
-            Stmt::Print(exprs) => {
+            Stmt::Print(exprs, _) => {
                 if self.silent_mode {
                     return Ok(());
                 }
@@ -421,6 +522,7 @@ impl Interpreter {
                 condition,
                 then_branch,
                 else_branch,
+                ..
             } => {
                 let condition_value = self.evaluate(condition)?;
 
@@ -446,329 +548,7 @@ impl Interpreter {
                     }
                 }
             }
-            Stmt::While { condition, body } => {
-                // Hyper-optimized fast path for the counting benchmark
-                if let Expr::Binary {
-                    left,
-                    operator,
-                    right,
-                } = condition
-                {
-                    // Check if this is a pattern like "$count < 1000000"
-                    let op_type = &operator.token_type;
-                    if let (Expr::VariableRef(var_name), Expr::NumberLiteral(limit)) =
-                        (&**left, &**right)
-                    {
-                        if var_name.starts_with('$')
-                            && (*op_type == TokenType::Less || *op_type == TokenType::LessEqual)
-                        {
-                            let var_name_without_prefix = var_name[1..].to_string();
-
-                            // Check for a counting loop pattern
-                            if body.len() == 2 &&
-                               // First statement is increment: count : $count + 1
-                               matches!(&body[0], Stmt::Declaration { name, initializer } if
-                                        name == &var_name_without_prefix &&
-                                        matches!(initializer, Expr::Binary {
-                                            left: l,
-                                            operator: op,
-                                            right: r
-                                        } if
-                                            matches!(&**l, Expr::VariableRef(vname) if vname == var_name) &&
-                                            op.token_type == TokenType::Plus &&
-                                            matches!(&**r, Expr::NumberLiteral(1)))) &&
-                               // Second statement is print with the variable
-                               (matches!(&body[1], Stmt::Print(exprs) if exprs.len() == 1 &&
-                                        matches!(&exprs[0], Expr::VariableRef(vname) if vname == var_name)) ||
-                                matches!(&body[1], Stmt::Command { name, args } if
-                                        name == "print" && args.len() == 1 &&
-                                        matches!(&args[0], Expr::VariableRef(vname) if vname == var_name)))
-                            {
-                                // Get the current counter value
-                                let Some(Value::Number(mut counter)) =
-                                    self.environment.get(&var_name_without_prefix).cloned()
-                                else {
-                                    return Err(LutError::runtime_error(format!(
-                                        "Counter variable not found: {}",
-                                        var_name_without_prefix
-                                    ), None));
-                                };
-
-                                // Directly access the limit value without cloning
-                                let limit_val = *limit;
-
-                                // Use the optimal loop implementation based on the condition
-                                match op_type {
-                                    TokenType::Less => {
-                                        // Ultra-optimized counting loop with direct memory operations
-                                        if !self.silent_mode {
-                                            if let Some(buffer) = &mut self.output_buffer {
-                                                // Ultra-optimized batch printing using numeric-to-string optimization
-                                                const BATCH_SIZE: i64 = 8192; // Larger batch size for better throughput
-                                                const MAX_DIGITS: usize = 10; // Max digits for i64 values (up to 9,223,372,036,854,775,807)
-                                                const NEWLINE: u8 = b'\n'; // Newline character
-
-                                                // Pre-allocate a single buffer for the entire operation
-                                                // Each number (up to limit_val) needs at most MAX_DIGITS chars + 1 for newline
-                                                let total_capacity = std::cmp::min(
-                                                    (limit_val - counter) as usize,
-                                                    BATCH_SIZE as usize,
-                                                ) * (MAX_DIGITS + 1);
-                                                let mut byte_buffer: Vec<u8> =
-                                                    Vec::with_capacity(total_capacity);
-                                                let mut byte_buffer_len: usize;
-
-                                                // Process in large batches for maximum throughput
-                                                while counter < limit_val {
-                                                    // Clear the buffer for reuse without reallocation
-                                                    byte_buffer.clear();
-                                                    byte_buffer_len = 0;
-
-                                                    let batch_end = std::cmp::min(
-                                                        counter + BATCH_SIZE,
-                                                        limit_val,
-                                                    );
-
-                                                    // Fill the buffer with all numbers in this batch
-                                                    for i in counter..batch_end {
-                                                        // Ultra-optimized integer to string conversion
-                                                        // This is much faster than using to_string() as it avoids allocations
-                                                        let mut num_buffer = [0u8; MAX_DIGITS];
-                                                        let mut num = i;
-                                                        let mut idx = MAX_DIGITS;
-
-                                                        // Handle special case for zero
-                                                        if num == 0 {
-                                                            num_buffer[idx - 1] = b'0';
-                                                            idx -= 1;
-                                                        } else {
-                                                            // Convert digits from right to left
-                                                            while num > 0 && idx > 0 {
-                                                                idx -= 1;
-                                                                num_buffer[idx] =
-                                                                    b'0' + (num % 10) as u8;
-                                                                num /= 10;
-                                                            }
-                                                        }
-
-                                                        // Append the number's digits to the output buffer
-                                                        let digits = &num_buffer[idx..MAX_DIGITS];
-                                                        let digits_len = digits.len();
-
-                                                        // Ensure we have enough capacity
-                                                        while byte_buffer_len + digits_len + 1
-                                                            > byte_buffer.capacity()
-                                                        {
-                                                            byte_buffer
-                                                                .reserve(byte_buffer.capacity());
-                                                        }
-
-                                                        // Unsafe block for direct memory manipulation (maximum performance)
-                                                        unsafe {
-                                                            byte_buffer.set_len(
-                                                                byte_buffer_len + digits_len + 1,
-                                                            );
-                                                            std::ptr::copy_nonoverlapping(
-                                                                digits.as_ptr(),
-                                                                byte_buffer
-                                                                    .as_mut_ptr()
-                                                                    .add(byte_buffer_len),
-                                                                digits_len,
-                                                            );
-                                                            *byte_buffer.as_mut_ptr().add(
-                                                                byte_buffer_len + digits_len,
-                                                            ) = NEWLINE;
-                                                        }
-
-                                                        byte_buffer_len += digits_len + 1;
-                                                    }
-
-                                                    // Convert the byte buffer to a string slice and write it in one go
-                                                    let output = unsafe {
-                                                        std::str::from_utf8_unchecked(
-                                                            &byte_buffer[..byte_buffer_len],
-                                                        )
-                                                    };
-                                                    write!(buffer, "{}", output).map_err(|e| {
-                                                        LutError::io_error(format!("Failed to write to buffer: {}", e))
-                                                    })?;
-
-                                                    counter = batch_end;
-                                                }
-
-                                                // Flush the buffer
-                                                buffer.flush().map_err(|e| {
-                                                    LutError::io_error(format!("Failed to flush buffer: {}", e))
-                                                })?;
-                                            } else {
-                                                // Fallback for non-buffered output
-                                                while counter < limit_val {
-                                                    println!("{}", counter);
-                                                    counter += 1;
-                                                }
-                                            }
-                                        } else {
-                                            // Silent mode - just update the counter
-                                            counter = limit_val;
-                                        }
-                                    }
-                                    TokenType::LessEqual => {
-                                        // Similar optimization for <= condition
-                                        if !self.silent_mode {
-                                            if let Some(buffer) = &mut self.output_buffer {
-                                                // Use the same ultra-optimized approach for LessEqual
-                                                const BATCH_SIZE: i64 = 8192; // Larger batch size for better throughput
-                                                const MAX_DIGITS: usize = 10; // Max digits for i64 values
-                                                const NEWLINE: u8 = b'\n'; // Newline character
-
-                                                // Pre-allocate a single buffer for the entire operation
-                                                let total_capacity = std::cmp::min(
-                                                    (limit_val - counter + 1) as usize,
-                                                    BATCH_SIZE as usize,
-                                                ) * (MAX_DIGITS + 1);
-                                                let mut byte_buffer: Vec<u8> =
-                                                    Vec::with_capacity(total_capacity);
-                                                let mut byte_buffer_len: usize;
-
-                                                // Process in large batches for maximum throughput
-                                                while counter <= limit_val {
-                                                    // Clear the buffer for reuse without reallocation
-                                                    byte_buffer.clear();
-                                                    byte_buffer_len = 0;
-
-                                                    let batch_end = std::cmp::min(
-                                                        counter + BATCH_SIZE,
-                                                        limit_val + 1,
-                                                    );
-
-                                                    // Fill the buffer with all numbers in this batch
-                                                    for i in counter..batch_end {
-                                                        // Ultra-optimized integer to string conversion
-                                                        let mut num_buffer = [0u8; MAX_DIGITS];
-                                                        let mut num = i;
-                                                        let mut idx = MAX_DIGITS;
-
-                                                        // Handle special case for zero
-                                                        if num == 0 {
-                                                            num_buffer[idx - 1] = b'0';
-                                                            idx -= 1;
-                                                        } else {
-                                                            // Convert digits from right to left
-                                                            while num > 0 && idx > 0 {
-                                                                idx -= 1;
-                                                                num_buffer[idx] =
-                                                                    b'0' + (num % 10) as u8;
-                                                                num /= 10;
-                                                            }
-                                                        }
-
-                                                        // Append the number's digits to the output buffer
-                                                        let digits = &num_buffer[idx..MAX_DIGITS];
-                                                        let digits_len = digits.len();
-
-                                                        // Ensure we have enough capacity
-                                                        while byte_buffer_len + digits_len + 1
-                                                            > byte_buffer.capacity()
-                                                        {
-                                                            byte_buffer
-                                                                .reserve(byte_buffer.capacity());
-                                                        }
-
-                                                        // Unsafe block for direct memory manipulation (maximum performance)
-                                                        unsafe {
-                                                            byte_buffer.set_len(
-                                                                byte_buffer_len + digits_len + 1,
-                                                            );
-                                                            std::ptr::copy_nonoverlapping(
-                                                                digits.as_ptr(),
-                                                                byte_buffer
-                                                                    .as_mut_ptr()
-                                                                    .add(byte_buffer_len),
-                                                                digits_len,
-                                                            );
-                                                            *byte_buffer.as_mut_ptr().add(
-                                                                byte_buffer_len + digits_len,
-                                                            ) = NEWLINE;
-                                                        }
-
-                                                        byte_buffer_len += digits_len + 1;
-                                                    }
-
-                                                    // Convert the byte buffer to a string slice and write it in one go
-                                                    let output = unsafe {
-                                                        std::str::from_utf8_unchecked(
-                                                            &byte_buffer[..byte_buffer_len],
-                                                        )
-                                                    };
-                                                    write!(buffer, "{}", output).map_err(|e| {
-                                                        LutError::io_error(format!("Failed to write to buffer: {}", e))
-                                                    })?;
-
-                                                    counter = batch_end;
-                                                }
-
-                                                // Flush the buffer
-                                                buffer.flush().map_err(|e| {
-                                                    LutError::io_error(format!("Failed to flush buffer: {}", e))
-                                                })?;
-                                            } else {
-                                                while counter <= limit_val {
-                                                    println!("{}", counter);
-                                                    counter += 1;
-                                                }
-                                            }
-                                        } else {
-                                            // Silent mode - just update the counter
-                                            counter = limit_val + 1;
-                                        }
-                                    }
-                                    _ => {
-                                        // Fallback for other comparison operators
-                                        loop {
-                                            // Check the condition
-                                            let continue_loop = match op_type {
-                                                TokenType::Less => counter < limit_val,
-                                                TokenType::LessEqual => counter <= limit_val,
-                                                TokenType::Greater => counter > limit_val,
-                                                TokenType::GreaterEqual => counter >= limit_val,
-                                                _ => false,
-                                            };
-
-                                            if !continue_loop {
-                                                break;
-                                            }
-
-                                            // Print the counter if not in silent mode
-                                            if !self.silent_mode {
-                                                if let Some(buffer) = &mut self.output_buffer {
-                                                    writeln!(buffer, "{}", counter).map_err(
-                                                        |e| {
-                                                            LutError::io_error(format!(
-                                                                "Failed to write to buffer: {}",
-                                                                e
-                                                            ))
-                                                        },
-                                                    )?;
-                                                } else {
-                                                    println!("{}", counter);
-                                                }
-                                            }
-
-                                            // Increment the counter
-                                            counter += 1;
-                                        }
-                                    }
-                                }
-
-                                // Update the environment with the final counter value
-                                self.environment
-                                    .insert(var_name_without_prefix, Value::Number(counter));
-                                return Ok(());
-                            }
-                        }
-                    }
-                }
-
+            Stmt::While { condition, body, .. } => {
                 // General case for while loops
                 self.loop_counter = 0; // Reset the loop counter
                 self.expr_cache.clear(); // Clear expression cache for safety
@@ -803,6 +583,7 @@ impl Interpreter {
                 update,
                 condition,
                 body,
+                ..
             } => {
                 // Handle initializer specially to support variable declarations
                 match initializer {
@@ -906,17 +687,25 @@ impl Interpreter {
                     }
                 }
             }
-            Stmt::Import { functions, module_path } => {
+            Stmt::Import { functions, module_path, namespace, wildcard, line } => {
                 // Import the requested functions from the module
-                self.handle_import(functions, module_path)?;
+                self.handle_import(None, functions, module_path, namespace.as_deref(), *wildcard, *line)?;
             }
         }
 
         Ok(())
     }
     
-    // Handle import statements
-    fn handle_import(&mut self, functions: &[String], module_path: &str) -> Result<(), LutError> {
+    // Handle import statements. `scope` is the importing module, None for the running file
+    fn handle_import(
+        &mut self,
+        scope: Option<&str>,
+        functions: &[ImportItem],
+        module_path: &str,
+        namespace: Option<&str>,
+        wildcard: bool,
+        line: usize,
+    ) -> Result<(), LutError> {
         // Initialize dependency manager if needed
         if self.dependency_manager.is_none() {
             let cwd = std::env::current_dir()
@@ -928,30 +717,208 @@ impl Interpreter {
                 if let Some(parent) = file_path.parent() {
                     dep_manager.set_current_file_dir(parent.to_path_buf());
                 }
+                dep_manager.set_entry_file(file_path);
             }
             
             self.dependency_manager = Some(dep_manager);
         }
         
+        // A namespace can only stand for one module
+        if let Some(ns) = namespace {
+            let namespaces = match scope {
+                Some(id) => &self.module_scopes[id].namespaces,
+                None => &self.namespaces,
+            };
+            if let Some(existing) = namespaces.get(ns) {
+                if existing != module_path {
+                    let message = format!(
+                        "Namespace '{}' is already used for '{}'\n\nTip: Import '{}' under a different name",
+                        ns, existing, module_path
+                    );
+                    return Err(self.import_error(scope, line, message));
+                }
+            }
+        }
+
+        // Names already bound where the import happens
+        let taken: HashSet<String> = match scope {
+            Some(id) => self.module_scopes[id].functions.keys().cloned().collect(),
+            None => self
+                .functions
+                .keys()
+                .chain(self.local_function_names.iter())
+                .cloned()
+                .collect(),
+        };
+
         // Resolve the import
         let dependency_manager = self.dependency_manager.as_mut().unwrap();
-        let imported_functions = dependency_manager.resolve_import(functions, module_path)
-            .map_err(|e| LutError::runtime_error(format!("Import error: {}", e), None))?;
-            
-        // Register the imported functions
-        for imported_func in imported_functions {
+        let resolved = dependency_manager.load_module(module_path).and_then(|module| {
+            let imported = match namespace {
+                Some(_) => Ok(module.exports()),
+                None if wildcard => dependency_manager.resolve_wildcard_import(&module, &taken),
+                None => dependency_manager.resolve_import(&module, functions, &taken),
+            }?;
+            dependency_manager.enter_import(scope, line, module_path, &module)?;
+            Ok((module, imported))
+        });
+        let (module, imported) = resolved.map_err(|e| self.import_error(scope, line, e))?;
+
+        let loaded = self.load_module_scope(&module);
+        self.dependency_manager.as_mut().unwrap().exit_import();
+        match scope {
+            None => loaded.map_err(|e| e.or_at_line(line))?,
+            Some(_) => loaded?,
+        }
+
+        if let Some(ns) = namespace {
+            let namespaces = match scope {
+                Some(id) => &mut self.module_scopes.get_mut(id).unwrap().namespaces,
+                None => &mut self.namespaces,
+            };
+            namespaces.insert(ns.to_string(), module_path.to_string());
+        }
+
+        self.bind_imports(scope, &module, namespace, imported);
+        Ok(())
+    }
+
+    // Errors in a module's own imports name that module; the `use` line of the
+    // running file is attached once the error reaches it
+    fn import_error(&self, scope: Option<&str>, line: usize, message: String) -> LutError {
+        match scope {
+            None => LutError::runtime_error(format!("Import error: {}", message), Some(line)),
+            Some(id) => {
+                let file = self
+                    .dependency_manager
+                    .as_ref()
+                    .map(|dm| dm.display_path(id))
+                    .unwrap_or_else(|| id.to_string());
+                LutError::runtime_error(format!("Import error in {} line {}: {}", file, line, message), None)
+            }
+        }
+    }
+
+    // Bind imported functions and constants, qualified with the namespace if there is one.
+    // Functions still run inside their module, so their helpers and imports come along.
+    fn bind_imports(&mut self, scope: Option<&str>, module: &LoadedModule, namespace: Option<&str>, imported: ResolvedImport) {
+        let qualify = |name: &str| match namespace {
+            Some(ns) => format!("{}::{}", ns, name),
+            None => name.to_string(),
+        };
+
+        for imported_const in imported.constants {
+            let qualified_name = qualify(&imported_const.name);
+            let value = self.module_scopes[&module.id].constants[&imported_const.source_name].clone();
+
+            match scope {
+                Some(id) => {
+                    self.module_scopes
+                        .get_mut(id)
+                        .unwrap()
+                        .constants
+                        .insert(qualified_name, value);
+                }
+                None => {
+                    self.constant_names.insert(qualified_name.clone());
+                    self.environment.insert(qualified_name, value);
+                }
+            }
+        }
+
+        for imported_func in imported.functions {
+            let qualified_name = qualify(&imported_func.name);
+            let func_rc = Rc::clone(&self.module_scopes[&module.id].functions[&imported_func.source_name]);
+
+            match scope {
+                Some(id) => {
+                    self.module_scopes
+                        .get_mut(id)
+                        .unwrap()
+                        .functions
+                        .insert(qualified_name, func_rc);
+                }
+                None => {
+                    self.functions.insert(qualified_name.clone(), Rc::clone(&func_rc));
+                    self.environment.insert(qualified_name, Value::Function(func_rc));
+                }
+            }
+        }
+    }
+
+    // Set up a module's private scope the first time it is imported, then
+    // resolve the module's own imports into that scope
+    fn load_module_scope(&mut self, module: &LoadedModule) -> Result<(), LutError> {
+        if self.module_scopes.contains_key(&module.id) {
+            return Ok(());
+        }
+
+        let mut scope = ModuleScope {
+            file: self.dependency_manager.as_ref().unwrap().display_file(module),
+            ..ModuleScope::default()
+        };
+        for imported_func in &module.functions {
             let func = Function {
-                name: imported_func.name.clone(),
+                name: imported_func.source_name.clone(),
                 is_public: imported_func.is_public,
-                parameters: imported_func.parameters,
-                body: imported_func.body,
+                parameters: imported_func.parameters.clone(),
+                body: imported_func.body.clone(),
+                module: Some(module.id.clone()),
+                foreign: None,
             };
-            
-            let func_rc = Rc::new(func);
-            self.functions.insert(imported_func.name.clone(), Rc::clone(&func_rc));
-            self.environment.insert(imported_func.name.clone(), Value::Function(func_rc));
+            scope.functions.insert(imported_func.source_name.clone(), Rc::new(func));
         }
-        
+        for declaration in &module.externs {
+            let (name, func) = foreign_function(declaration, Some(&module.id))?;
+            scope.functions.insert(name, func);
+        }
+        self.module_scopes.insert(module.id.clone(), scope);
+
+        // Relative paths in the module are relative to the module itself
+        let dependency_manager = self.dependency_manager.as_mut().unwrap();
+        let saved_dir = dependency_manager.current_file_dir();
+        dependency_manager.set_current_file_dir(module.dir.clone());
+
+        let mut result = Ok(());
+        for stmt in &module.imports {
+            if let Stmt::Import { functions, module_path, namespace, wildcard, line } = stmt {
+                result = self.handle_import(Some(&module.id), functions, module_path, namespace.as_deref(), *wildcard, *line);
+                if result.is_err() {
+                    break;
+                }
+            }
+        }
+
+        self.dependency_manager.as_mut().unwrap().restore_current_file_dir(saved_dir);
+        if let Err(e) = result {
+            // A module that failed to load must not look loaded to a later import
+            self.module_scopes.remove(&module.id);
+            return Err(e);
+        }
+
+        // Module initializer: constants are evaluated once, in order, inside the module
+        for constant in &module.constants {
+            let environment = self.module_scopes[&module.id].constants.clone();
+            let saved_environment = std::mem::replace(&mut self.environment, environment);
+            let saved_module = self.current_module.replace(module.id.clone());
+            let value = self.evaluate(&constant.initializer);
+            self.environment = saved_environment;
+            self.current_module = saved_module;
+
+            let value = match value {
+                Ok(value) => value,
+                Err(e) => {
+                    self.module_scopes.remove(&module.id);
+                    return Err(e);
+                }
+            };
+            self.module_scopes
+                .get_mut(&module.id)
+                .unwrap()
+                .constants
+                .insert(constant.source_name.clone(), value);
+        }
+
         Ok(())
     }
 
@@ -963,8 +930,8 @@ impl Interpreter {
         
         // In a minimal function with a single expression, the result is the return value
         if body.len() == 1 {
-            if let Stmt::Expression(expr) = &body[0] {
-                return self.evaluate(expr);
+            if let Stmt::Expression(expr, line) = &body[0] {
+                return self.evaluate(expr).map_err(|e| self.locate(e, Some(*line)));
             }
         }
         
@@ -974,13 +941,13 @@ impl Interpreter {
         // Process all statements in the function body
         for stmt in body {
             match stmt {
-                Stmt::Expression(expr) => {
+                Stmt::Expression(expr, line) => {
                     // For expressions, evaluate and store the result
-                    last_expr_value = self.evaluate(expr)?;
+                    last_expr_value = self.evaluate(expr).map_err(|e| self.locate(e, Some(*line)))?;
                 },
-                Stmt::If { condition, then_branch, else_branch } => {
+                Stmt::If { condition, then_branch, else_branch, line } => {
                     // Special handling for if statements to capture their return values
-                    let condition_value = self.evaluate(condition)?;
+                    let condition_value = self.evaluate(condition).map_err(|e| self.locate(e, Some(*line)))?;
                     
                     if is_truthy(&condition_value) {
                         // Execute the then branch and capture its last expression
@@ -1017,11 +984,37 @@ impl Interpreter {
         Ok(last_expr_value)
     }
     
+    // Find a function by name. Code inside an imported module only sees that module's scope
+    fn lookup_function(&self, name: &str) -> Option<Rc<Function>> {
+        match &self.current_module {
+            Some(id) => self.module_scopes.get(id)?.functions.get(name).map(Rc::clone),
+            None => self.functions.get(name).map(Rc::clone),
+        }
+    }
+
     // Helper function to call a function
     fn call_function(&mut self, func_name: &str, arguments: &[Expr]) -> Result<Value, LutError> {
         // Clone the function definition to avoid borrowing issues
-        let func = if let Some(f) = self.functions.get(func_name) {
-            Rc::clone(f)
+        let func = if let Some(f) = self.lookup_function(func_name) {
+            f
+        } else if let Some((ns, _)) = func_name.split_once("::") {
+            let namespaces = match &self.current_module {
+                Some(id) => &self.module_scopes[id].namespaces,
+                None => &self.namespaces,
+            };
+            if let Some(path) = namespaces.get(ns) {
+                return Err(LutError::runtime_error(
+                    format!("Undefined function: {} (module '{}' has no pub function by that name)", func_name, path),
+                    None,
+                ));
+            }
+            return Err(LutError::runtime_error(
+                format!(
+                    "Undefined function: {}\n\nTip: Import the module first with use {} from './{}.ko'",
+                    func_name, ns, ns
+                ),
+                None,
+            ));
         } else {
             return Err(LutError::runtime_error(format!("Undefined function: {}", func_name), None));
         };
@@ -1045,6 +1038,19 @@ impl Interpreter {
             let arg_value = self.evaluate(arg)?;
             arg_values.push(arg_value);
         }
+
+        if let Some(foreign) = &func.foreign {
+            return foreign.call(&arg_values).map_err(|e| LutError::runtime_error(e, None));
+        }
+
+        // Crossing into another module: its code sees only that module's constants
+        let outer_environment = match &func.module {
+            Some(id) if func.module != self.current_module => {
+                let environment = self.module_scopes[id].constants.clone();
+                Some(std::mem::replace(&mut self.environment, environment))
+            }
+            _ => None,
+        };
         
         // Set up the function parameters in the environment
         for (i, param) in func.parameters.iter().enumerate() {
@@ -1053,17 +1059,26 @@ impl Interpreter {
                 saved_environment.insert(param.name.clone(), old_value.clone());
             }
             
-            // Set the parameter value in the environment
-            self.environment.insert(param.name.clone(), arg_values[i].clone());
+            // Set the parameter value in the environment, in the parameter's type
+            let value = parameter_value(arg_values[i].clone(), &param.param_type, i + 1, func_name)?;
+            self.environment.insert(param.name.clone(), value);
         }
         
-        // Execute the function body and get the return value
-        let return_value = self.evaluate_function_body(&func.body)?;
-        
+        // Execute the function body inside its module and get the return value
+        let saved_module = self.current_module.take();
+        self.current_module.clone_from(&func.module);
+        let return_value = self.evaluate_function_body(&func.body);
+        self.current_module = saved_module;
+
         // Restore the original environment
-        for (name, value) in saved_environment {
-            self.environment.insert(name, value);
+        if let Some(environment) = outer_environment {
+            self.environment = environment;
+        } else {
+            for (name, value) in saved_environment {
+                self.environment.insert(name, value);
+            }
         }
+        let return_value = return_value?;
         
         Ok(return_value)
     }
@@ -1237,6 +1252,7 @@ impl Interpreter {
                 }
                 Ok(result)
             }
+            Expr::NoneLiteral => Ok(Value::Null),
             Expr::ArrayLiteral(elements) => {
                 let mut values = Vec::with_capacity(elements.len());
                 for element in elements {
@@ -1264,12 +1280,16 @@ impl Interpreter {
                 match operator.token_type {
                     TokenType::Minus => {
                         match &right {
-                            Value::Number(n) => Ok(Value::Number(-n)),
+                            Value::Number(n) => checked_number(n.checked_neg()),
                             Value::Float(f) => Ok(Value::Float(-f)),
+                            Value::Decimal(d) => d
+                                .neg()
+                                .map(Value::Decimal)
+                                .map_err(|e| LutError::runtime_error(e, Some(operator.line))),
                             Value::Text(s) => {
                                 // Try to parse the string as a number first
                                 if let Ok(n) = s.parse::<i64>() {
-                                    Ok(Value::Number(-n))
+                                    checked_number(n.checked_neg())
                                 } else if let Ok(f) = s.parse::<f64>() {
                                     Ok(Value::Float(-f))
                                 } else {
@@ -1291,11 +1311,11 @@ impl Interpreter {
                 // Optimize common arithmetic operations on literals
                 if let (Expr::NumberLiteral(n1), Expr::NumberLiteral(n2)) = (&**left, &**right) {
                     match operator.token_type {
-                        TokenType::Plus => return Ok(Value::Number(n1 + n2)),
-                        TokenType::Minus => return Ok(Value::Number(n1 - n2)),
-                        TokenType::Star => return Ok(Value::Number(n1 * n2)),
-                        TokenType::Slash if *n2 != 0 => return Ok(Value::Number(n1 / n2)),
-                        TokenType::Percent if *n2 != 0 => return Ok(Value::Number(n1 % n2)),
+                        TokenType::Plus => return checked_number(n1.checked_add(*n2)),
+                        TokenType::Minus => return checked_number(n1.checked_sub(*n2)),
+                        TokenType::Star => return checked_number(n1.checked_mul(*n2)),
+                        TokenType::Slash if *n2 != 0 => return checked_number(n1.checked_div(*n2)),
+                        TokenType::Percent if *n2 != 0 => return checked_number(n1.checked_rem(*n2)),
                         TokenType::Equal => return Ok(Value::Boolean(n1 == n2)),
                         TokenType::NotEqual => return Ok(Value::Boolean(n1 != n2)),
                         TokenType::Less => return Ok(Value::Boolean(n1 < n2)),
@@ -1324,6 +1344,14 @@ impl Interpreter {
                         let right_val = self.evaluate(right)?;
                         return Ok(Value::Boolean(is_truthy(&right_val)));
                     }
+                    TokenType::Coalesce => {
+                        // Only evaluate the default when the left side has no value
+                        let left_val = self.evaluate(left)?;
+                        if left_val != Value::Null {
+                            return Ok(left_val);
+                        }
+                        return self.evaluate(right);
+                    }
                     _ => {}
                 }
 
@@ -1331,11 +1359,31 @@ impl Interpreter {
                 let left_val = self.evaluate(left)?;
                 let right_val = self.evaluate(right)?;
 
+                // none only compares for equality; anything else needs an explicit default
+                if left_val == Value::Null || right_val == Value::Null {
+                    return match operator.token_type {
+                        TokenType::Equal => Ok(Value::Boolean(left_val == right_val)),
+                        TokenType::NotEqual => Ok(Value::Boolean(left_val != right_val)),
+                        _ => Err(LutError::runtime_error(
+                            format!(
+                                "Cannot use none with '{}'\n\nTip: Provide a default with ?? (e.g. $value ?? 0) or check it with is_none",
+                                operator.lexeme
+                            ),
+                            Some(operator.line),
+                        )),
+                    };
+                }
+
+                // Decimals follow their own exact rules
+                if matches!(left_val, Value::Decimal(_)) || matches!(right_val, Value::Decimal(_)) {
+                    return self.decimal_binary(&operator.token_type, &left_val, &right_val, operator.line);
+                }
+
                 match operator.token_type {
                     // Arithmetic operators
                     TokenType::Plus => {
                         match (&left_val, &right_val) {
-                            (Value::Number(n1), Value::Number(n2)) => Ok(Value::Number(n1 + n2)),
+                            (Value::Number(n1), Value::Number(n2)) => checked_number(n1.checked_add(*n2)),
                             (Value::Float(f1), Value::Float(f2)) => Ok(Value::Float(f1 + f2)),
                             (Value::Number(n1), Value::Float(f2)) => {
                                 Ok(Value::Float(*n1 as f64 + f2))
@@ -1379,7 +1427,7 @@ impl Interpreter {
                     }
                     TokenType::Minus => {
                         match (&left_val, &right_val) {
-                            (Value::Number(n1), Value::Number(n2)) => Ok(Value::Number(n1 - n2)),
+                            (Value::Number(n1), Value::Number(n2)) => checked_number(n1.checked_sub(*n2)),
                             (Value::Float(f1), Value::Float(f2)) => Ok(Value::Float(f1 - f2)),
                             (Value::Number(n1), Value::Float(f2)) => {
                                 Ok(Value::Float(*n1 as f64 - f2))
@@ -1390,7 +1438,7 @@ impl Interpreter {
                             (Value::Text(s1), Value::Number(n2)) => {
                                 // Try to parse the string as a number first
                                 if let Ok(n1) = s1.parse::<i64>() {
-                                    Ok(Value::Number(n1 - n2))
+                                    checked_number(n1.checked_sub(*n2))
                                 } else if let Ok(f1) = s1.parse::<f64>() {
                                     Ok(Value::Float(f1 - *n2 as f64))
                                 } else {
@@ -1400,7 +1448,7 @@ impl Interpreter {
                             (Value::Number(n1), Value::Text(s2)) => {
                                 // Try to parse the string as a number first
                                 if let Ok(n2) = s2.parse::<i64>() {
-                                    Ok(Value::Number(n1 - n2))
+                                    checked_number(n1.checked_sub(n2))
                                 } else if let Ok(f2) = s2.parse::<f64>() {
                                     Ok(Value::Float(*n1 as f64 - f2))
                                 } else {
@@ -1428,7 +1476,7 @@ impl Interpreter {
                     }
                     TokenType::Star => {
                         match (&left_val, &right_val) {
-                            (Value::Number(n1), Value::Number(n2)) => Ok(Value::Number(n1 * n2)),
+                            (Value::Number(n1), Value::Number(n2)) => checked_number(n1.checked_mul(*n2)),
                             (Value::Float(f1), Value::Float(f2)) => Ok(Value::Float(f1 * f2)),
                             (Value::Number(n1), Value::Float(f2)) => {
                                 Ok(Value::Float(*n1 as f64 * f2))
@@ -1448,7 +1496,7 @@ impl Interpreter {
                             if *n2 == 0 {
                                 return Err(LutError::runtime_error("Division by zero", None));
                             }
-                            Ok(Value::Number(n1 / n2))
+                            checked_number(n1.checked_div(*n2))
                         }
                         (Value::Float(f1), Value::Float(f2)) => {
                             if *f2 == 0.0 {
@@ -1475,7 +1523,7 @@ impl Interpreter {
                             if *n2 == 0 {
                                 return Err(LutError::runtime_error("Modulo by zero", None));
                             }
-                            Ok(Value::Number(n1 % n2))
+                            checked_number(n1.checked_rem(*n2))
                         }
                         (Value::Float(f1), Value::Float(f2)) => {
                             if *f2 == 0.0 {
@@ -1631,6 +1679,7 @@ impl Interpreter {
                                 Err(_) => Err(LutError::runtime_error(format!("Cannot convert '{}' to a number", s), None)),
                             },
                             Value::Boolean(b) => Ok(Value::Number(if b { 1 } else { 0 })),
+                            Value::Null => Err(none_conversion_error("number")),
                             _ => Err(LutError::runtime_error("Expected number, text or boolean", None)),
                         }
                     }
@@ -1643,10 +1692,12 @@ impl Interpreter {
                         match arg {
                             Value::Text(s) => Ok(Value::Text(s)),
                             Value::Number(n) => Ok(self.make_text(n.to_string())),
+                            Value::Decimal(d) => Ok(self.make_text(d.to_string())),
                             Value::Boolean(b) => {
                                 let text = if b { "true" } else { "false" }.to_string();
                                 Ok(self.make_text(text))
                             }
+                            Value::Null => Err(none_conversion_error("text")),
                             _ => Err(LutError::runtime_error("Expected text, number or boolean", None)),
                         }
                     }
@@ -1659,6 +1710,7 @@ impl Interpreter {
                         match arg {
                             Value::Float(f) => Ok(Value::Float(f)),
                             Value::Number(n) => Ok(Value::Float(n as f64)),
+                            Value::Decimal(d) => Ok(Value::Float(d.to_string().parse::<f64>().unwrap_or(0.0))),
                             Value::Text(s) => match s.parse::<f64>() {
                                 Ok(f) => Ok(Value::Float(f)),
                                 Err(_) => Err(LutError::runtime_error(format!(
@@ -1667,9 +1719,92 @@ impl Interpreter {
                                 ), None)),
                             },
                             Value::Boolean(b) => Ok(Value::Float(if b { 1.0 } else { 0.0 })),
+                            Value::Null => Err(none_conversion_error("fp")),
                             _ => Err(LutError::runtime_error("Expected number, text or boolean", None)),
                         }
                     }
+                    "is_none" => {
+                        if args.len() != 1 {
+                            return Err(LutError::runtime_error("Is_none command expects one argument", None));
+                        }
+
+                        let value = self.evaluate(&args[0])?;
+                        Ok(Value::Boolean(value == Value::Null))
+                    }
+                    "dec" => {
+                        if args.is_empty() || args.len() > 2 {
+                            return Err(LutError::runtime_error(
+                                "Dec command expects a value and an optional scale",
+                                None,
+                            ));
+                        }
+
+                        let value = self.evaluate(&args[0])?;
+                        let decimal = match value {
+                            Value::Decimal(d) => d,
+                            Value::Number(n) => Decimal::from_int(n),
+                            Value::Float(f) => Decimal::from_f64(f)
+                                .map_err(|e| LutError::runtime_error(e, None))?,
+                            Value::Text(s) => Decimal::parse(&s)
+                                .map_err(|e| LutError::runtime_error(e, None))?,
+                            Value::Null => return Err(none_conversion_error("dec")),
+                            _ => return Err(LutError::runtime_error("Expected number, fp or text for dec", None)),
+                        };
+
+                        if args.len() == 1 {
+                            return Ok(Value::Decimal(decimal));
+                        }
+
+                        let scale = self.evaluate_scale(&args[1])?;
+                        decimal
+                            .rescale_exact(scale)
+                            .map(Value::Decimal)
+                            .map_err(|e| LutError::runtime_error(e, None))
+                    }
+                    "div" => {
+                        if args.len() != 4 {
+                            return Err(LutError::runtime_error(
+                                format!(
+                                    "Div command expects a dividend, a divisor, a scale and a rounding mode, got {} arguments",
+                                    args.len()
+                                ),
+                                None,
+                            ));
+                        }
+
+                        let dividend = self.evaluate(&args[0])?;
+                        let divisor = self.evaluate(&args[1])?;
+                        let dividend = decimal_operand(&dividend, None)?;
+                        let divisor = decimal_operand(&divisor, None)?;
+                        let scale = self.evaluate_scale(&args[2])?;
+                        let mode = self.evaluate_rounding_mode(&args[3])?;
+
+                        dividend
+                            .div(&divisor, scale, mode)
+                            .map(Value::Decimal)
+                            .map_err(|e| LutError::runtime_error(e, None))
+                    }
+                    "round" => {
+                        if args.len() != 3 {
+                            return Err(LutError::runtime_error(
+                                format!(
+                                    "Round command expects a value, a scale and a rounding mode, got {} arguments",
+                                    args.len()
+                                ),
+                                None,
+                            ));
+                        }
+
+                        let value = self.evaluate(&args[0])?;
+                        let value = decimal_operand(&value, None)?;
+                        let scale = self.evaluate_scale(&args[1])?;
+                        let mode = self.evaluate_rounding_mode(&args[2])?;
+
+                        value
+                            .rescale(scale, mode)
+                            .map(Value::Decimal)
+                            .map_err(|e| LutError::runtime_error(e, None))
+                    }
                     "bool" | "-bool" => {
                         if args.len() != 1 {
                             return Err(LutError::runtime_error("Boolean command expects one argument", None));
@@ -1680,6 +1815,7 @@ impl Interpreter {
                             Value::Boolean(b) => Ok(Value::Boolean(b)),
                             Value::Number(n) => Ok(Value::Boolean(n != 0)),
                             Value::Float(f) => Ok(Value::Boolean(f != 0.0)),
+                            Value::Decimal(d) => Ok(Value::Boolean(!d.is_zero())),
                             Value::Text(s) => {
                                 if s.as_str() == "true" {
                                     Ok(Value::Boolean(true))
@@ -1881,13 +2017,13 @@ impl Interpreter {
                             ), None));
                         }
 
-                        let mut result = 0;
+                        let mut result: i64 = 0;
                         for arg in args {
                             let value = self.evaluate(arg)?;
                             match value {
-                                Value::Number(n) => result += n,
+                                Value::Number(n) => result = result.checked_add(n).ok_or_else(integer_overflow)?,
                                 Value::Text(s) => match s.parse::<i64>() {
-                                    Ok(n) => result += n,
+                                    Ok(n) => result = result.checked_add(n).ok_or_else(integer_overflow)?,
                                     Err(_) => {
                                         return Err(LutError::runtime_error(format!(
                                             "Cannot convert '{}' to a number for addition",
@@ -1928,9 +2064,9 @@ impl Interpreter {
                         for arg in args.iter().skip(1) {
                             let value = self.evaluate(arg)?;
                             match value {
-                                Value::Number(n) => result -= n,
+                                Value::Number(n) => result = result.checked_sub(n).ok_or_else(integer_overflow)?,
                                 Value::Text(s) => match s.parse::<i64>() {
-                                    Ok(n) => result -= n,
+                                    Ok(n) => result = result.checked_sub(n).ok_or_else(integer_overflow)?,
                                     Err(_) => {
                                         return Err(LutError::runtime_error(
                                             format!("Cannot convert '{}' to a number for subtraction", s),
@@ -1955,14 +2091,14 @@ impl Interpreter {
                         }
 
                         // Start with 1 as the identity element for multiplication
-                        let mut result = 1;
+                        let mut result: i64 = 1;
                         for arg in args {
                             let value = self.evaluate(arg)?;
                             match value {
-                                Value::Number(n) => result *= n,
+                                Value::Number(n) => result = result.checked_mul(n).ok_or_else(integer_overflow)?,
                                 Value::Text(s) => {
                                     match s.parse::<i64>() {
-                                        Ok(n) => result *= n,
+                                        Ok(n) => result = result.checked_mul(n).ok_or_else(integer_overflow)?,
                                         Err(_) => return Err(LutError::runtime_error(
                                             format!(
                                                 "Cannot convert '{}' to a number for multiplication",
@@ -2015,14 +2151,14 @@ impl Interpreter {
                                     if n == 0 {
                                         return Err(LutError::runtime_error("Division by zero", None));
                                     }
-                                    result /= n;
+                                    result = result.checked_div(n).ok_or_else(integer_overflow)?;
                                 }
                                 Value::Text(s) => match s.parse::<i64>() {
                                     Ok(n) => {
                                         if n == 0 {
                                             return Err(LutError::runtime_error("Division by zero", None));
                                         }
-                                        result /= n;
+                                        result = result.checked_div(n).ok_or_else(integer_overflow)?;
                                     }
                                     Err(_) => {
                                         return Err(LutError::runtime_error(
@@ -2092,7 +2228,7 @@ impl Interpreter {
                             return Err(LutError::runtime_error("Modulo by zero", None));
                         }
 
-                        Ok(Value::Number(left % right))
+                        checked_number(left.checked_rem(right))
                     }
                     "get" | "-get" => {
                         if args.len() != 2 {
@@ -2237,7 +2373,165 @@ impl Interpreter {
     }
 }
 
-pub fn run(source: &str, file_path: Option<&Path>) -> Result<(), LutError> {
+// Conversions never turn a missing value into 0 or ''
+// An argument in its parameter's type: a number passed as fp is converted, fp is never
+// truncated to a number, and none passes as it is
+fn parameter_value(value: Value, param_type: &str, argument: usize, function: &str) -> Result<Value, LutError> {
+    let expected = match param_type {
+        "fp" => "fp",
+        "number" => "a number",
+        "bool" => "bool",
+        "text" => "text",
+        "array" => "an array",
+        "dec" => "a dec",
+        _ => return Ok(value),
+    };
+    match (param_type, value) {
+        (_, Value::Null) => Ok(Value::Null),
+        ("fp", Value::Number(n)) => Ok(Value::Float(n as f64)),
+        ("bool", Value::Number(n)) => Ok(Value::Boolean(n != 0)),
+        ("fp", value @ Value::Float(_))
+        | ("number", value @ (Value::Number(_) | Value::Boolean(_)))
+        | ("bool", value @ Value::Boolean(_))
+        | ("text", value @ Value::Text(_))
+        | ("array", value @ (Value::Array(_) | Value::Array2D(_)))
+        | ("dec", value @ Value::Decimal(_)) => Ok(value),
+        _ => Err(LutError::runtime_error(format!("Argument {} of {} must be {}", argument, function, expected), None)),
+    }
+}
+
+fn none_conversion_error(command: &str) -> LutError {
+    LutError::runtime_error(
+        format!(
+            "Cannot convert none with {}\n\nTip: Provide a default with ?? (e.g. $value ?? 0) before converting",
+            command
+        ),
+        None,
+    )
+}
+
+// Decimal helpers
+impl Interpreter {
+    fn evaluate_scale(&mut self, expr: &Expr) -> Result<u32, LutError> {
+        match self.evaluate(expr)? {
+            Value::Number(n) if (0..=crate::decimal::MAX_SCALE as i64).contains(&n) => Ok(n as u32),
+            Value::Number(n) => Err(LutError::runtime_error(
+                format!(
+                    "Decimal scale must be between 0 and {}, got {}",
+                    crate::decimal::MAX_SCALE,
+                    n
+                ),
+                None,
+            )),
+            _ => Err(LutError::runtime_error("Decimal scale must be a number", None)),
+        }
+    }
+
+    fn evaluate_rounding_mode(&mut self, expr: &Expr) -> Result<RoundingMode, LutError> {
+        match self.evaluate(expr)? {
+            Value::Text(s) => RoundingMode::parse(&s).map_err(|e| LutError::runtime_error(e, None)),
+            _ => Err(LutError::runtime_error(
+                "Rounding mode must be text such as 'half_up' or 'half_even'",
+                None,
+            )),
+        }
+    }
+
+    fn decimal_binary(
+        &mut self,
+        operator: &TokenType,
+        left: &Value,
+        right: &Value,
+        line: usize,
+    ) -> Result<Value, LutError> {
+        // Text concatenation keeps working with the formatted decimal
+        if *operator == TokenType::Plus {
+            match (left, right) {
+                (Value::Text(s), other) => return Ok(self.make_text(format!("{}{}", s, other))),
+                (other, Value::Text(s)) => return Ok(self.make_text(format!("{}{}", other, s))),
+                _ => {}
+            }
+        }
+
+        // Equality between a decimal and a non-numeric value is simply false
+        if matches!(operator, TokenType::Equal | TokenType::NotEqual)
+            && (matches!(left, Value::Text(_) | Value::Boolean(_) | Value::Array(_) | Value::Array2D(_) | Value::Function(_) | Value::Null)
+                || matches!(right, Value::Text(_) | Value::Boolean(_) | Value::Array(_) | Value::Array2D(_) | Value::Function(_) | Value::Null))
+        {
+            return Ok(Value::Boolean(*operator == TokenType::NotEqual));
+        }
+
+        let a = decimal_operand(left, Some(line))?;
+        let b = decimal_operand(right, Some(line))?;
+        let err = |e: String| LutError::runtime_error(e, Some(line));
+
+        match operator {
+            TokenType::Plus => a.add(&b).map(Value::Decimal).map_err(err),
+            TokenType::Minus => a.sub(&b).map(Value::Decimal).map_err(err),
+            TokenType::Star => a.mul(&b).map(Value::Decimal).map_err(err),
+            TokenType::Slash | TokenType::Percent => Err(LutError::runtime_error(
+                "Decimal division needs a scale and a rounding mode\n\nTip: Use [ div a, b, 2, 'half_even' ] instead of '/'",
+                Some(line),
+            )),
+            TokenType::Equal => Ok(Value::Boolean(a.compare(&b).map_err(err)?.is_eq())),
+            TokenType::NotEqual => Ok(Value::Boolean(a.compare(&b).map_err(err)?.is_ne())),
+            TokenType::Greater => Ok(Value::Boolean(a.compare(&b).map_err(err)?.is_gt())),
+            TokenType::GreaterEqual => Ok(Value::Boolean(a.compare(&b).map_err(err)?.is_ge())),
+            TokenType::Less => Ok(Value::Boolean(a.compare(&b).map_err(err)?.is_lt())),
+            TokenType::LessEqual => Ok(Value::Boolean(a.compare(&b).map_err(err)?.is_le())),
+            _ => Err(LutError::runtime_error(
+                format!("Invalid operator for decimal values: {:?}", operator),
+                Some(line),
+            )),
+        }
+    }
+}
+
+// Numbers are 64-bit in every mode; arithmetic that leaves that range is an error, not a wrap
+fn checked_number(result: Option<i64>) -> Result<Value, LutError> {
+    result.map(Value::Number).ok_or_else(integer_overflow)
+}
+
+fn integer_overflow() -> LutError {
+    LutError::runtime_error("Integer overflow", None)
+}
+
+// Numbers widen to decimals losslessly; floats have to be converted explicitly
+fn decimal_operand(value: &Value, line: Option<usize>) -> Result<Decimal, LutError> {
+    match value {
+        Value::Decimal(d) => Ok(*d),
+        Value::Number(n) => Ok(Decimal::from_int(*n)),
+        Value::Float(_) => Err(LutError::runtime_error(
+            "Cannot mix dec and fp values\n\nTip: Convert the fp value with [ dec value ] first",
+            line,
+        )),
+        _ => Err(LutError::runtime_error(
+            format!("Expected a dec or number value, got '{}'", value),
+            line,
+        )),
+    }
+}
+
+// Look up the C function an extern func declares, as a function without a body
+fn foreign_function(declaration: &Stmt, module: Option<&str>) -> Result<(String, Rc<Function>), LutError> {
+    let Stmt::Extern { name, parameters, return_type, library, line } = declaration else {
+        unreachable!("not an extern func declaration");
+    };
+    let foreign = ForeignFunction::load(name, parameters, return_type.as_deref(), library.as_deref())
+        .map_err(|e| LutError::runtime_error(e, Some(*line)))?;
+    let func = Function {
+        name: name.clone(),
+        is_public: false,
+        parameters: parameters.clone(),
+        body: Vec::new(),
+        module: module.map(str::to_string),
+        foreign: Some(Rc::new(foreign)),
+    };
+    Ok((name.clone(), Rc::new(func)))
+}
+
+// Runs the program and returns its exit status
+pub fn run(source: &str, file_path: Option<&Path>) -> Result<i32, LutError> {
     let mut lexer = Lexer::new(source);
     let tokens = lexer.scan_tokens()?;
 
@@ -2251,18 +2545,17 @@ pub fn run(source: &str, file_path: Option<&Path>) -> Result<(), LutError> {
     interpreter.interpret(statements)?;
     
     // Try to find and call the main function after interpreting all statements
-    if let Some(func) = interpreter.functions.get("main") {
-        if let Value::Function(_) = Value::Function(Rc::clone(func)) {
-            // Call the main function with no arguments
-            interpreter.call_function("main", &[])?;
-        }
+    if interpreter.functions.contains_key("main") {
+        // Call the main function with no arguments; its value is the exit status
+        let value = interpreter.call_function("main", &[])?;
+        return interpreter.exit_status(&value);
     }
 
-    Ok(())
+    Ok(0)
 }
 
 // Run with silent mode (no output), useful for benchmarking
-pub fn run_silent(source: &str, file_path: Option<&Path>) -> Result<(), LutError> {
+pub fn run_silent(source: &str, file_path: Option<&Path>) -> Result<i32, LutError> {
     let mut lexer = Lexer::new(source);
     let tokens = lexer.scan_tokens()?;
 
@@ -2276,12 +2569,11 @@ pub fn run_silent(source: &str, file_path: Option<&Path>) -> Result<(), LutError
     interpreter.interpret(statements)?;
     
     // Try to find and call the main function after interpreting all statements
-    if let Some(func) = interpreter.functions.get("main") {
-        if let Value::Function(_) = Value::Function(Rc::clone(func)) {
-            // Call the main function with no arguments
-            interpreter.call_function("main", &[])?;
-        }
+    if interpreter.functions.contains_key("main") {
+        // Call the main function with no arguments; its value is the exit status
+        let value = interpreter.call_function("main", &[])?;
+        return interpreter.exit_status(&value);
     }
 
-    Ok(())
+    Ok(0)
 }
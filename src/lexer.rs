@@ -11,6 +11,7 @@ pub enum TokenType {
     Text,     // string literal
     Variable, // variable reference with '$'
     Boolean,  // boolean literal (true/false)
+    None,     // 'none' literal for a missing value
     If,       // 'if' keyword
     Else,     // 'else' keyword
     While,    // 'while' keyword
@@ -23,10 +24,13 @@ pub enum TokenType {
     Pub,  // 'pub' keyword for public functions
     Prot, // 'prot' keyword for protected functions
     Call, // 'call' keyword for function calls
+    Const, // 'const' keyword for module-level constants
+    Extern, // 'extern' keyword for C function declarations
 
     // Import related keywords
     Use,  // 'use' keyword for imports
     From, // 'from' keyword for imports
+    As,   // 'as' keyword for import aliases
 
     // Symbols
     Colon,              // ':'
@@ -35,6 +39,7 @@ pub enum TokenType {
     Semicolon,          // ';'
     StatementSeparator, // ';;'
     QuestionMark,       // '?'
+    Coalesce,           // '??'
     LeftBracket,        // '['
     RightBracket,       // ']'
 
@@ -146,11 +151,22 @@ impl Lexer {
                         .push(Token::new(TokenType::Colon, ":".to_string(), self.line));
                 }
             }
-            '?' => self.tokens.push(Token::new(
-                TokenType::QuestionMark,
-                "?".to_string(),
-                self.line,
-            )),
+            '?' => {
+                // Check for the none-coalescing operator (??)
+                if self.match_char('?') {
+                    self.tokens.push(Token::new(
+                        TokenType::Coalesce,
+                        "??".to_string(),
+                        self.line,
+                    ));
+                } else {
+                    self.tokens.push(Token::new(
+                        TokenType::QuestionMark,
+                        "?".to_string(),
+                        self.line,
+                    ));
+                }
+            }
             ',' => self
                 .tokens
                 .push(Token::new(TokenType::Comma, ",".to_string(), self.line)),
@@ -325,6 +341,9 @@ impl Lexer {
             "true" | "false" => self
                 .tokens
                 .push(Token::new(TokenType::Boolean, text, self.line)),
+            "none" => self
+                .tokens
+                .push(Token::new(TokenType::None, text, self.line)),
             "if" => self.tokens.push(Token::new(TokenType::If, text, self.line)),
             "else" => self
                 .tokens
@@ -354,13 +373,23 @@ impl Lexer {
             "call" => self
                 .tokens
                 .push(Token::new(TokenType::Call, text, self.line)),
+            "const" => self
+                .tokens
+                .push(Token::new(TokenType::Const, text, self.line)),
+            "extern" => self
+                .tokens
+                .push(Token::new(TokenType::Extern, text, self.line)),
             "use" => self
                 .tokens
                 .push(Token::new(TokenType::Use, text, self.line)),
             "from" => self
                 .tokens
                 .push(Token::new(TokenType::From, text, self.line)),
-            "print" | "text" | "number" | "bool" | "asc" | "fp" | "hex" | "bin" | "array" => self
+            "as" => self
+                .tokens
+                .push(Token::new(TokenType::As, text, self.line)),
+            "print" | "text" | "number" | "bool" | "asc" | "fp" | "dec" | "div" | "round" | "hex"
+            | "bin" | "array" | "is_none" | "length" | "width" | "get" | "get2d" => self
                 .tokens
                 .push(Token::new(TokenType::Command, text, self.line)),
             _ => self
@@ -474,6 +503,15 @@ impl Lexer {
             self.advance();
         }
 
+        // Namespaced constant: $math::PI
+        if self.peek() == ':' && self.peek_next() == ':' {
+            self.advance();
+            self.advance();
+            while (self.peek().is_alphanumeric() || self.peek() == '_') && !self.is_at_end() {
+                self.advance();
+            }
+        }
+
         let value: String = self.source[self.start..self.current].iter().collect();
         self.tokens
             .push(Token::new(TokenType::Variable, value, self.line));
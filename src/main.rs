@@ -2,19 +2,37 @@ use std::env;
 use std::fs;
 use std::process;
 
+mod build_options;
+mod c_backend;
+#[cfg(feature = "llvm")]
 mod compiler;
+mod decimal;
 mod dependency_manager;
+mod difftest;
 mod error_reporting;
+mod foreign;
 mod interpreter;
 mod lexer;
 mod parser;
+mod runtime_library;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
     if args.len() < 2 {
-        eprintln!("Usage: koze [build|run|jit|debug] <file> [-s|--silent]");
+        eprintln!("Usage: koze [build|run|jit|debug] <file> [-s|--silent] [-O0|-O1|-O2|-O3|-Os] [-g]");
+        eprintln!("       koze difftest <dir> [-O0|-O1|-O2|-O3|-Os]");
         eprintln!("  -s, --silent   Run in silent mode with minimal output");
+        eprintln!("  -O<level>      Optimization level for build and jit (default -O2)");
+        eprintln!("  -g             Include DWARF debug info in the built binary");
+        eprintln!("  --target <triple>, --cpu <name>, --features <list>");
+        eprintln!("                 Build for another target, e.g. --target aarch64-unknown-linux-gnu");
+        eprintln!("                 or a WASI module with --target wasm32-wasi");
+        eprintln!("  --linker <cmd>  Command that links the executable (default cc, clang for --target)");
+        eprintln!("  --backend=<b>   Generate code with llvm (default) or c, which needs only a C compiler");
+        eprintln!("  --emit=<kind>   Write llvm-ir, llvm-bc, asm, obj, c or exe (default exe)");
+        eprintln!("  -o <path>       Where build writes its output (default the file name without .ko)");
+        eprintln!("  --lib[=<kind>]  Build a shared (default) or static library of the pub functions, with a C header");
         process::exit(1);
     }
 
@@ -74,17 +92,33 @@ fn main() {
         }
         "build" => {
             if args.len() < 3 {
-                eprintln!("Usage: koze build <file> [-s|--silent]");
+                eprintln!("Usage: koze build <file> [-s|--silent] [-O0|-O1|-O2|-O3|-Os] [-g] [--target <triple>] [--cpu <name>] [--features <list>] [--linker <command>] [--backend=llvm|c] [--emit=<kind>] [-o <path>] [--lib[=shared|static]]");
                 process::exit(1);
             }
             let file_path = &args[2];
 
             // Check for silent mode flag
             let silent_mode = args.iter().any(|arg| arg == "-s" || arg == "--silent");
+            let options = build_options::BuildOptions {
+                opt_level: parse_opt_level(&args),
+                debug_info: args.iter().any(|arg| arg == "-g"),
+                backend: parse_backend(&args),
+                emit: parse_emit(&args),
+                lib: parse_lib(&args),
+                output: option_value(&args, "-o"),
+                target: option_value(&args, "--target"),
+                cpu: option_value(&args, "--cpu"),
+                features: option_value(&args, "--features"),
+                linker: option_value(&args, "--linker"),
+            };
 
             match fs::read_to_string(file_path) {
                 Ok(source) => {
-                    match compiler::compile(&source, file_path, silent_mode) {
+                    let compiled = match options.backend {
+                        build_options::Backend::C => c_backend::compile(&source, file_path, silent_mode, &options),
+                        build_options::Backend::Llvm => compile_llvm(&source, file_path, silent_mode, &options),
+                    };
+                    match compiled {
                         Ok(_) => {
                             if !silent_mode {
                                 println!("Successfully compiled {}", file_path);
@@ -118,7 +152,7 @@ fn main() {
                     let path = std::path::Path::new(file_path);
                     if silent_mode {
                         match interpreter::run_silent(&source, Some(path)) {
-                            Ok(_) => (),
+                            Ok(status) => process::exit(status),
                             Err(e) => {
                                 // Print enhanced error with source context
                                 error_reporting::print_error_with_context(&e, &source);
@@ -127,7 +161,7 @@ fn main() {
                         }
                     } else {
                         match interpreter::run(&source, Some(path)) {
-                            Ok(_) => (),
+                            Ok(status) => process::exit(status),
                             Err(e) => {
                                 // Print enhanced error with source context
                                 error_reporting::print_error_with_context(&e, &source);
@@ -144,19 +178,20 @@ fn main() {
         }
         "jit" => {
             if args.len() < 3 {
-                eprintln!("Usage: koze jit <file> [-s|--silent]");
+                eprintln!("Usage: koze jit <file> [-s|--silent] [-O0|-O1|-O2|-O3|-Os]");
                 process::exit(1);
             }
             let file_path = &args[2];
 
             // Check for silent mode flag
             let silent_mode = args.iter().any(|arg| arg == "-s" || arg == "--silent");
+            let opt_level = parse_opt_level(&args);
 
             match fs::read_to_string(file_path) {
                 Ok(source) => {
                     // Use the LLVM JIT compiler to compile and execute
-                    match compiler::jit_compile_and_run(&source, file_path, silent_mode) {
-                        Ok(_) => (), // The JIT execution already happened
+                    match jit_compile_and_run(&source, file_path, silent_mode, opt_level) {
+                        Ok(status) => process::exit(status),
                         Err(e) => {
                             // Print enhanced error with source context
                             error_reporting::print_error_with_context(&e, &source);
@@ -170,11 +205,147 @@ fn main() {
                 }
             }
         }
+        "difftest" => {
+            if args.len() < 3 {
+                eprintln!("Usage: koze difftest <dir> [-O0|-O1|-O2|-O3|-Os]");
+                process::exit(1);
+            }
+            let dir = std::path::Path::new(&args[2]);
+            let opt_level = parse_opt_level(&args);
+
+            // Each mode is run by this same binary in a child process
+            let koze = match env::current_exe() {
+                Ok(path) => path,
+                Err(e) => {
+                    eprintln!("Could not locate the koze executable: {}", e);
+                    process::exit(1);
+                }
+            };
+
+            match difftest::run_difftest(&koze, dir, opt_level) {
+                Ok(mismatches) => {
+                    if !mismatches.is_empty() {
+                        process::exit(1);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+            }
+        }
         _ => {
             eprintln!("Unknown command: {}", command);
-            eprintln!("Usage: koze [build|run|jit|debug] <file> [-s|--silent]");
+            eprintln!("Usage: koze [build|run|jit|debug] <file> [-s|--silent] [-O0|-O1|-O2|-O3|-Os] [-g]");
+            eprintln!("       koze difftest <dir> [-O0|-O1|-O2|-O3|-Os]");
             eprintln!("  -s, --silent   Run in silent mode with minimal output");
+            eprintln!("  -O<level>      Optimization level for build and jit (default -O2)");
+            eprintln!("  -g             Include DWARF debug info in the built binary");
+            eprintln!("  --target <triple>, --cpu <name>, --features <list>");
+            eprintln!("                 Build for another target, e.g. --target aarch64-unknown-linux-gnu");
+            eprintln!("                 or a WASI module with --target wasm32-wasi");
+            eprintln!("  --linker <cmd>  Command that links the executable (default cc, clang for --target)");
+            eprintln!("  --backend=<b>   Generate code with llvm (default) or c, which needs only a C compiler");
+            eprintln!("  --emit=<kind>   Write llvm-ir, llvm-bc, asm, obj, c or exe (default exe)");
+            eprintln!("  -o <path>       Where build writes its output (default the file name without .ko)");
+            eprintln!("  --lib[=<kind>]  Build a shared (default) or static library of the pub functions, with a C header");
             process::exit(1);
         }
     }
 }
+
+// Value of --name <value> or --name=<value>
+fn option_value(args: &[String], name: &str) -> Option<String> {
+    let prefix = format!("{}=", name);
+    for (i, arg) in args.iter().enumerate().skip(3) {
+        if let Some(value) = arg.strip_prefix(&prefix) {
+            return Some(value.to_string());
+        }
+        if arg == name {
+            match args.get(i + 1) {
+                Some(value) => return Some(value.clone()),
+                None => {
+                    eprintln!("Missing value for {}", name);
+                    process::exit(1);
+                }
+            }
+        }
+    }
+    None
+}
+
+// --lib builds a shared library, --lib=static a static one
+fn parse_lib(args: &[String]) -> Option<build_options::LibKind> {
+    args.iter().skip(3).find_map(|arg| match arg.as_str() {
+        "--lib" | "--lib=shared" => Some(build_options::LibKind::Shared),
+        "--lib=static" => Some(build_options::LibKind::Static),
+        other => other.strip_prefix("--lib=").map(|kind| {
+            eprintln!("Unknown library kind '{}'. Use --lib=shared or --lib=static", kind);
+            process::exit(1)
+        }),
+    })
+}
+
+fn parse_emit(args: &[String]) -> build_options::Emit {
+    match option_value(args, "--emit") {
+        Some(value) => build_options::Emit::from_flag(&value).unwrap_or_else(|| {
+            eprintln!("Unknown --emit value '{}'. Use llvm-ir, llvm-bc, asm, obj, c or exe", value);
+            process::exit(1);
+        }),
+        None => build_options::Emit::default(),
+    }
+}
+
+fn parse_backend(args: &[String]) -> build_options::Backend {
+    match option_value(args, "--backend") {
+        Some(value) => build_options::Backend::from_flag(&value).unwrap_or_else(|| {
+            eprintln!("Unknown --backend value '{}'. Use llvm or c", value);
+            process::exit(1);
+        }),
+        None => build_options::Backend::default(),
+    }
+}
+
+// The last -O flag wins, like in cc
+fn parse_opt_level(args: &[String]) -> build_options::OptLevel {
+    let mut opt_level = build_options::OptLevel::default();
+    for arg in args.iter().skip(3).filter(|arg| arg.starts_with("-O")) {
+        match build_options::OptLevel::from_flag(arg) {
+            Some(level) => opt_level = level,
+            None => {
+                eprintln!("Unknown optimization level '{}'. Use -O0, -O1, -O2, -O3 or -Os", arg);
+                process::exit(1);
+            }
+        }
+    }
+    opt_level
+}
+
+#[cfg(feature = "llvm")]
+fn compile_llvm(source: &str, file_path: &str, silent_mode: bool, options: &build_options::BuildOptions) -> Result<(), error_reporting::LutError> {
+    compiler::compile(source, file_path, silent_mode, options)
+}
+
+#[cfg(not(feature = "llvm"))]
+fn compile_llvm(_source: &str, _file_path: &str, _silent_mode: bool, _options: &build_options::BuildOptions) -> Result<(), error_reporting::LutError> {
+    Err(without_llvm())
+}
+
+#[cfg(feature = "llvm")]
+fn jit_compile_and_run(source: &str, file_path: &str, silent_mode: bool, opt_level: build_options::OptLevel) -> Result<i32, error_reporting::LutError> {
+    compiler::jit_compile_and_run(source, file_path, silent_mode, opt_level)
+}
+
+#[cfg(not(feature = "llvm"))]
+fn jit_compile_and_run(_source: &str, _file_path: &str, _silent_mode: bool, _opt_level: build_options::OptLevel) -> Result<i32, error_reporting::LutError> {
+    Err(without_llvm())
+}
+
+// koze built with --no-default-features has no LLVM backend and no jit
+#[cfg(not(feature = "llvm"))]
+fn without_llvm() -> error_reporting::LutError {
+    error_reporting::LutError::compiler_error(
+        "koze was built without LLVM, so it can only build through C\n\nTip: Use koze build --backend=c, or koze run to interpret the program",
+        None,
+    )
+}
@@ -8,6 +8,7 @@ pub enum Expr {
     FloatLiteral(f64),
     TextLiteral(String),
     BooleanLiteral(bool),
+    NoneLiteral,                    // 'none', the absence of a value
     ArrayLiteral(Vec<Expr>),        // 1D array literal [1, 2, 3, 4]
     ArrayLiteral2D(Vec<Vec<Expr>>), // 2D array literal [1, 2][3, 4]
     Command {
@@ -46,48 +47,106 @@ pub struct FunctionParam {
     pub initialized: bool,
 }
 
+// One entry of an import list: name or name as alias
 #[derive(Debug, Clone, PartialEq)]
+pub struct ImportItem {
+    pub name: String,
+    pub alias: Option<String>,
+}
+
+impl ImportItem {
+    // Name the function is bound to in the importing file
+    pub fn local_name(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+// Statements carry the line they start on, for error messages and debug info
 pub enum Stmt {
     Declaration {
         name: String,
         initializer: Expr,
+        line: usize,
     },
-    Expression(Expr),
+    Expression(Expr, usize),
     Command {
         name: String,
         args: Vec<Expr>,
+        line: usize,
     },
-    Print(Vec<Expr>),
+    Print(Vec<Expr>, usize),
     Comment(String),
     If {
         condition: Expr,
         then_branch: Vec<Stmt>,
         else_branch: Option<Vec<Stmt>>,
+        line: usize,
     },
     While {
         condition: Expr,
         body: Vec<Stmt>,
+        line: usize,
     },
     For {
         initializer: Expr,
         update: Expr,
         condition: Expr,
         body: Vec<Stmt>,
+        line: usize,
     },
     Function {
         name: String,
         is_public: bool,
         parameters: Vec<FunctionParam>,
+        return_type: Option<String>, // func pub f { x : fp } : fp [ ... ]
         body: Vec<Stmt>,
+        line: usize,
     },
     Break,
     Continue,
     Import {
-        functions: Vec<String>,
+        functions: Vec<ImportItem>,
         module_path: String,
+        namespace: Option<String>, // use math from './math.ko' imports as math::name
+        wildcard: bool,            // use * from './utils.ko'
+        line: usize,
+    },
+    // Module-level constant: const pub TAX : 20
+    Const {
+        name: String,
+        is_public: bool,
+        initializer: Expr,
+        line: usize,
+    },
+    // C function: extern func sqrt { x : fp } : fp from 'm'
+    Extern {
+        name: String,
+        parameters: Vec<FunctionParam>,
+        return_type: Option<String>, // None for a void function
+        library: Option<String>,
+        line: usize,
     },
 }
 
+impl Stmt {
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Stmt::Declaration { line, .. }
+            | Stmt::Command { line, .. }
+            | Stmt::If { line, .. }
+            | Stmt::While { line, .. }
+            | Stmt::For { line, .. }
+            | Stmt::Function { line, .. }
+            | Stmt::Import { line, .. }
+            | Stmt::Const { line, .. }
+            | Stmt::Extern { line, .. } => Some(*line),
+            Stmt::Expression(_, line) | Stmt::Print(_, line) => Some(*line),
+            Stmt::Comment(_) | Stmt::Break | Stmt::Continue => None,
+        }
+    }
+}
+
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
@@ -146,6 +205,14 @@ impl Parser {
             return self.function_declaration();
         }
 
+        if self.match_token(TokenType::Const) {
+            return self.const_declaration();
+        }
+
+        if self.match_token(TokenType::Extern) {
+            return self.extern_declaration();
+        }
+
         // Check for main function using the format: func pub main {} [ ... ]
 
         if self.match_token(TokenType::Break) {
@@ -166,65 +233,31 @@ impl Parser {
 
             self.consume(TokenType::Colon, "Expect ':' after register name.")?;
 
-            // Skip any whitespace
-            while self.match_token(TokenType::Newline) {}
-
-            // Check for the new bracket syntax
-            if self.match_token(TokenType::LeftBrace) {
-                // We've got a type command with arguments in curly braces
-                if self.match_token(TokenType::Command) {
-                    let type_cmd = self.previous().lexeme.clone();
-
-                    // Parse the value
-                    let value = self.expression()?;
-
-                    // Expect closing brace
-                    self.consume(TokenType::RightBrace, "Expect '}' after type expression")?;
-
-                    // Create a command expression for the type operation
-                    let initializer = Expr::Command {
-                        name: type_cmd,
-                        args: vec![value],
-                    };
-
-                    return Ok(Stmt::Declaration { name, initializer });
-                } else {
-                    return Err("Expected type command after '{'".to_string());
-                }
-            } else {
-                // Handle the direct value case (boolean literals, arithmetic expressions, etc.)
-                let initializer = self.expression()?;
-
-                return Ok(Stmt::Declaration { name, initializer });
-            }
+            let line = self.previous().line;
+            let initializer = self.declaration_initializer()?;
+            return Ok(Stmt::Declaration { name, initializer, line });
         }
 
         if self.match_token(TokenType::Command) {
             let command = self.previous().lexeme.clone();
+            let line = self.previous().line;
 
             // Check for the new command syntax with curly braces
             if self.match_token(TokenType::LeftBrace) {
-                let mut args = Vec::new();
-
                 // Parse command arguments
-                if !self.check(TokenType::RightBrace) {
-                    args.push(self.expression()?);
-
-                    while self.match_token(TokenType::Comma) {
-                        args.push(self.expression()?);
-                    }
-                }
+                let args = self.command_arguments(&command)?;
 
                 // Expect the closing brace
                 self.consume(TokenType::RightBrace, "Expect '}' after command arguments")?;
 
                 // Handle print command
                 if command == "print" {
-                    return Ok(Stmt::Print(args));
+                    return Ok(Stmt::Print(args, line));
                 } else {
                     return Ok(Stmt::Command {
                         name: command,
                         args,
+                        line,
                     });
                 }
             } else {
@@ -238,6 +271,37 @@ impl Parser {
 
     // Parse import statement: bring { func1, func2 } from username/library
     fn import_statement(&mut self) -> Result<Stmt, String> {
+        let line = self.previous().line;
+
+        // Namespaced import: use math from './math.ko'
+        if self.match_token(TokenType::Register) {
+            let namespace = self.previous().lexeme.clone();
+            self.consume(TokenType::From, "Expect 'from' after module name")?;
+            let module_path = self.module_path()?;
+
+            return Ok(Stmt::Import {
+                functions: Vec::new(),
+                module_path,
+                namespace: Some(namespace),
+                wildcard: false,
+                line,
+            });
+        }
+
+        // Wildcard import: use * from './utils.ko'
+        if self.match_token(TokenType::Star) {
+            self.consume(TokenType::From, "Expect 'from' after '*'")?;
+            let module_path = self.module_path()?;
+
+            return Ok(Stmt::Import {
+                functions: Vec::new(),
+                module_path,
+                namespace: None,
+                wildcard: true,
+                line,
+            });
+        }
+
         // Expect left brace for function list
         self.consume(TokenType::LeftBrace, "Expect '{' after 'bring'")?;
 
@@ -246,9 +310,18 @@ impl Parser {
         // Parse function names
         if !self.check(TokenType::RightBrace) {
             loop {
-                // Function names are registers
+                // Function names are registers, optionally renamed with 'as'
                 self.consume(TokenType::Register, "Expect function name in import")?;
-                functions.push(self.previous().lexeme.clone());
+                let name = self.previous().lexeme.clone();
+
+                let alias = if self.match_token(TokenType::As) {
+                    self.consume(TokenType::Register, "Expect alias name after 'as'")?;
+                    Some(self.previous().lexeme.clone())
+                } else {
+                    None
+                };
+
+                functions.push(ImportItem { name, alias });
 
                 if !self.match_token(TokenType::Comma) {
                     break;
@@ -262,6 +335,18 @@ impl Parser {
         // Expect 'from' keyword
         self.consume(TokenType::From, "Expect 'from' after function list")?;
 
+        let module_path = self.module_path()?;
+
+        Ok(Stmt::Import {
+            functions,
+            module_path,
+            namespace: None,
+            wildcard: false,
+            line,
+        })
+    }
+
+    fn module_path(&mut self) -> Result<String, String> {
         // Parse module path - must be a text literal (quoted string)
         let module_path = if self.match_token(TokenType::Text) {
             // Module path is in quotes - this handles all cases: local files, URLs, GitHub shorthand
@@ -277,14 +362,13 @@ impl Parser {
             return Err("Expect module path after 'from' in import statement".to_string());
         };
 
-        Ok(Stmt::Import {
-            functions,
-            module_path,
-        })
+        Ok(module_path)
     }
 
     // Parse an if statement with the newer syntax: if { condition } [ ... ] else [ ... ]
     fn if_statement(&mut self) -> Result<Stmt, String> {
+        let line = self.previous().line;
+
         // Expect left brace for condition
         self.consume(TokenType::LeftBrace, "Expect '{' after 'if'")?;
 
@@ -376,11 +460,14 @@ impl Parser {
             condition,
             then_branch,
             else_branch,
+            line,
         })
     }
 
     // Parse a while statement: while { condition } [ ... ]
     fn while_statement(&mut self) -> Result<Stmt, String> {
+        let line = self.previous().line;
+
         // Expect left brace for condition
         self.consume(TokenType::LeftBrace, "Expect '{' after 'while'")?;
 
@@ -429,11 +516,13 @@ impl Parser {
         // Consume the closing bracket
         self.consume(TokenType::RightBracket, "Expect ']' after while loop body")?;
 
-        Ok(Stmt::While { condition, body })
+        Ok(Stmt::While { condition, body, line })
     }
 
     // Parse a for statement: for { init, update, condition } [ ... ]
     fn for_statement(&mut self) -> Result<Stmt, String> {
+        let line = self.previous().line;
+
         // Expect left brace for the components
         self.consume(TokenType::LeftBrace, "Expect '{' after 'for'")?;
 
@@ -524,6 +613,7 @@ impl Parser {
             update,
             condition,
             body,
+            line,
         })
     }
 
@@ -562,8 +652,9 @@ impl Parser {
     }
 
     fn expression_statement(&mut self) -> Result<Stmt, String> {
+        let line = self.peek().line;
         let expr = self.expression()?;
-        Ok(Stmt::Expression(expr))
+        Ok(Stmt::Expression(expr, line))
     }
 
     fn expression(&mut self) -> Result<Expr, String> {
@@ -572,7 +663,7 @@ impl Parser {
 
     fn conditional(&mut self) -> Result<Expr, String> {
         // Parse the condition part
-        let expr = self.logical_or()?;
+        let expr = self.coalesce()?;
 
         // Check if this is a ternary expression
         if self.match_token(TokenType::QuestionMark) {
@@ -597,6 +688,23 @@ impl Parser {
         Ok(expr)
     }
 
+    // Parse none-coalescing: $value ?? default
+    fn coalesce(&mut self) -> Result<Expr, String> {
+        let mut expr = self.logical_or()?;
+
+        while self.match_token(TokenType::Coalesce) {
+            let operator = self.previous().clone();
+            let right = self.logical_or()?;
+            expr = Expr::Binary {
+                left: Box::new(expr),
+                operator,
+                right: Box::new(right),
+            };
+        }
+
+        Ok(expr)
+    }
+
     fn logical_or(&mut self) -> Result<Expr, String> {
         let mut expr = self.logical_and()?;
 
@@ -713,8 +821,149 @@ impl Parser {
         self.primary()
     }
 
+    // The value after 'name :', either { command args } or a plain expression
+    fn declaration_initializer(&mut self) -> Result<Expr, String> {
+        // Skip any whitespace
+        while self.match_token(TokenType::Newline) {}
+
+        // Check for the new bracket syntax
+        if self.match_token(TokenType::LeftBrace) {
+            // We've got a type command with arguments in curly braces
+            if self.match_token(TokenType::Command) {
+                let type_cmd = self.previous().lexeme.clone();
+
+                // Parse the value and any extra arguments (scale, rounding mode, ...)
+                let args = self.command_arguments(&type_cmd)?;
+
+                // Expect closing brace
+                self.consume(TokenType::RightBrace, "Expect '}' after type expression")?;
+
+                // Create a command expression for the type operation
+                Ok(Expr::Command {
+                    name: type_cmd,
+                    args,
+                })
+            } else {
+                Err("Expected type command after '{'".to_string())
+            }
+        } else {
+            // Handle the direct value case (boolean literals, arithmetic expressions, etc.)
+            self.expression()
+        }
+    }
+
+    fn const_declaration(&mut self) -> Result<Stmt, String> {
+        let line = self.previous().line;
+
+        let is_public = if self.match_token(TokenType::Pub) {
+            true
+        } else if self.match_token(TokenType::Prot) {
+            false
+        } else {
+            return Err("Expected 'pub' or 'prot' after 'const'".to_string());
+        };
+
+        self.consume(TokenType::Register, "Expected constant name after visibility modifier")?;
+        let name = self.previous().lexeme.clone();
+        self.consume(TokenType::Colon, "Expect ':' after constant name.")?;
+
+        let initializer = self.declaration_initializer()?;
+        Ok(Stmt::Const {
+            name,
+            is_public,
+            initializer,
+            line,
+        })
+    }
+
+    // Parameters up to and including the closing '}': { a : number, b : text }
+    fn parameter_list(&mut self) -> Result<Vec<FunctionParam>, String> {
+        let mut parameters = Vec::new();
+        if !self.check(TokenType::RightBrace) {
+            loop {
+                // Parameter name
+                let param_name = if self.match_token(TokenType::Register) {
+                    self.previous().lexeme.clone()
+                } else {
+                    return Err("Expected parameter name in function declaration".to_string());
+                };
+
+                // Expect colon
+                self.consume(TokenType::Colon, "Expected ':' after parameter name")?;
+
+                // Parameter type
+                let param_type = if self.match_token(TokenType::Command) {
+                    self.previous().lexeme.clone()
+                } else {
+                    return Err("Expected parameter type after ':'".to_string());
+                };
+
+                // Check for ! (uninitialized parameter)
+                let initialized = !self.match_token(TokenType::Not);
+
+                // Add the parameter
+                parameters.push(FunctionParam {
+                    name: param_name,
+                    param_type,
+                    initialized,
+                });
+
+                // If we see a comma, continue parsing parameters
+                if !self.match_token(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+
+        // Expect right brace after parameter list
+        self.consume(TokenType::RightBrace, "Expected '}' after parameter list")?;
+
+        Ok(parameters)
+    }
+
+    // Optional return type, written like a parameter type
+    fn return_type(&mut self) -> Result<Option<String>, String> {
+        if !self.match_token(TokenType::Colon) {
+            return Ok(None);
+        }
+        if self.match_token(TokenType::Command) {
+            Ok(Some(self.previous().lexeme.clone()))
+        } else {
+            Err("Expected return type after ':'".to_string())
+        }
+    }
+
+    // extern func strlen { s : text } : number, optionally followed by from 'library'
+    fn extern_declaration(&mut self) -> Result<Stmt, String> {
+        let line = self.previous().line;
+        self.consume(TokenType::Func, "Expected 'func' after 'extern'")?;
+        self.consume(TokenType::Register, "Expected the C function's name after 'extern func'")?;
+        let name = self.previous().lexeme.clone();
+
+        self.consume(TokenType::LeftBrace, "Expected '{' after function name")?;
+        let parameters = self.parameter_list()?;
+        let return_type = self.return_type()?;
+
+        let library = if self.match_token(TokenType::From) {
+            self.consume(TokenType::Text, "Expected a library name after 'from'")?;
+            Some(self.previous().lexeme.clone())
+        } else {
+            None
+        };
+
+        Ok(Stmt::Extern {
+            name,
+            parameters,
+            return_type,
+            library,
+            line,
+        })
+    }
+
     // Parse function declaration: func pub { a : number !, b : fp ! } [<function>]
     fn function_declaration(&mut self) -> Result<Stmt, String> {
+        let line = self.previous().line;
+
         // Check for visibility modifier (pub or prot)
         let is_public = if self.match_token(TokenType::Pub) {
             true
@@ -737,57 +986,19 @@ impl Parser {
         // Expect left brace for parameter list
         self.consume(TokenType::LeftBrace, "Expected '{' after function name")?;
 
-        // Parse parameters
-        let mut parameters = Vec::new();
-
         // For main function, we expect an empty parameter list
-        if is_main {
+        let parameters = if is_main {
             // Expect right brace after empty parameter list
             self.consume(
                 TokenType::RightBrace,
                 "Expected '}' after empty parameter list for main function",
             )?;
+            Vec::new()
         } else {
-            // Regular function - parse parameters until we see right brace
-            if !self.check(TokenType::RightBrace) {
-                loop {
-                    // Parameter name
-                    let param_name = if self.match_token(TokenType::Register) {
-                        self.previous().lexeme.clone()
-                    } else {
-                        return Err("Expected parameter name in function declaration".to_string());
-                    };
-
-                    // Expect colon
-                    self.consume(TokenType::Colon, "Expected ':' after parameter name")?;
-
-                    // Parameter type
-                    let param_type = if self.match_token(TokenType::Command) {
-                        self.previous().lexeme.clone()
-                    } else {
-                        return Err("Expected parameter type after ':'".to_string());
-                    };
-
-                    // Check for ! (uninitialized parameter)
-                    let initialized = !self.match_token(TokenType::Not);
-
-                    // Add the parameter
-                    parameters.push(FunctionParam {
-                        name: param_name,
-                        param_type,
-                        initialized,
-                    });
-
-                    // If we see a comma, continue parsing parameters
-                    if !self.match_token(TokenType::Comma) {
-                        break;
-                    }
-                }
-            }
+            self.parameter_list()?
+        };
 
-            // Expect right brace after parameter list
-            self.consume(TokenType::RightBrace, "Expected '}' after parameter list")?;
-        }
+        let return_type = self.return_type()?;
 
         // Expect left bracket for function body
         self.consume(
@@ -832,7 +1043,9 @@ impl Parser {
             name,
             is_public,
             parameters,
+            return_type,
             body,
+            line,
         })
     }
 
@@ -842,12 +1055,18 @@ impl Parser {
         self.consume(TokenType::LeftBrace, "Expected '{' after 'call'")?;
 
         // Get the function name
-        let function_name = if self.match_token(TokenType::Register) {
+        let mut function_name = if self.match_token(TokenType::Register) {
             self.previous().lexeme.clone()
         } else {
             return Err("Expected function name in call statement".to_string());
         };
 
+        // Namespaced call: call { math::sqrt, 2 }
+        if self.match_token(TokenType::DoubleColon) {
+            self.consume(TokenType::Register, "Expected function name after '::'")?;
+            function_name = format!("{}::{}", function_name, self.previous().lexeme);
+        }
+
         // Parse arguments if there are any
         let mut arguments = Vec::new();
 
@@ -924,6 +1143,10 @@ impl Parser {
             return Ok(Expr::BooleanLiteral(value));
         }
 
+        if self.match_token(TokenType::None) {
+            return Ok(Expr::NoneLiteral);
+        }
+
         if self.match_token(TokenType::LeftBracket) {
             // Parse array literal [1, 2, 3, 4]
             let mut elements = Vec::new();
@@ -987,13 +1210,7 @@ impl Parser {
             // Check for the new command syntax with curly braces
             if self.match_token(TokenType::LeftBrace) {
                 // Parse command arguments
-                if !self.check(TokenType::RightBrace) {
-                    args.push(self.expression()?);
-
-                    while self.match_token(TokenType::Comma) {
-                        args.push(self.expression()?);
-                    }
-                }
+                args = self.command_arguments(&name)?;
 
                 // Expect the closing brace
                 self.consume(TokenType::RightBrace, "Expect '}' after command arguments")?;
@@ -1027,6 +1244,39 @@ impl Parser {
         }
     }
 
+    // Parse comma separated command arguments up to (not including) the closing brace
+    fn command_arguments(&mut self, command: &str) -> Result<Vec<Expr>, String> {
+        let mut args = Vec::new();
+
+        if self.check(TokenType::RightBrace) {
+            return Ok(args);
+        }
+
+        loop {
+            // A float literal handed straight to dec keeps the digits as written,
+            // so 0.10 stays at scale 2 instead of going through f64
+            if command == "dec" && args.is_empty() && self.check(TokenType::Float) {
+                let next = self.tokens.get(self.current + 1).map(|t| t.token_type.clone());
+                if matches!(next, Some(TokenType::Comma) | Some(TokenType::RightBrace)) {
+                    let literal = self.advance().lexeme.clone();
+                    args.push(Expr::TextLiteral(literal));
+                    if self.match_token(TokenType::Comma) {
+                        continue;
+                    }
+                    break;
+                }
+            }
+
+            args.push(self.expression()?);
+
+            if !self.match_token(TokenType::Comma) {
+                break;
+            }
+        }
+
+        Ok(args)
+    }
+
     fn consume(&mut self, token_type: TokenType, message: &str) -> Result<&Token, String> {
         if self.check(token_type) {
             return Ok(self.advance());
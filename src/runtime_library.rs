@@ -0,0 +1,103 @@
+// The runtime library every executable `koze build` produces links, whichever backend
+// compiled the program. build.rs builds it for the host and it is embedded in koze;
+// other targets get it compiled from its source with rustc
+use crate::error_reporting::LutError;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const RUNTIME_LIBRARY: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/kozeig_runtime.a"));
+// The target triple RUNTIME_LIBRARY is built for
+pub const HOST_TARGET: &str = env!("KOZE_HOST_TARGET");
+// lib.rs is written as kozeig_runtime.rs, next to the modules it declares
+const RUNTIME_SOURCES: &[(&str, &str)] = &[
+    ("kozeig_runtime.rs", include_str!("../runtime/src/lib.rs")),
+    ("report.rs", include_str!("../runtime/src/report.rs")),
+    ("values.rs", include_str!("../runtime/src/values.rs")),
+];
+
+// Write the runtime's source into dir for rustc, returning the crate root
+pub fn write_runtime_source(dir: &Path) -> Result<PathBuf, LutError> {
+    for (name, source) in RUNTIME_SOURCES {
+        fs::write(dir.join(name), source)
+            .map_err(|e| LutError::io_error(format!("Failed to write the runtime library source: {}", e)))?;
+    }
+    Ok(dir.join(RUNTIME_SOURCES[0].0))
+}
+
+// Write the runtime library for triple into dir. The embedded copy is built for the
+// host, so a cross build compiles it from source, which needs that target's Rust
+// standard library
+pub fn write_runtime_library(dir: &Path, triple: &str, cross: bool) -> Result<PathBuf, LutError> {
+    let runtime_path = dir.join(runtime_library_file(triple));
+    if !cross {
+        fs::write(&runtime_path, RUNTIME_LIBRARY)
+            .map_err(|e| LutError::io_error(format!("Failed to write the runtime library: {}", e)))?;
+        return Ok(runtime_path);
+    }
+
+    let source_path = write_runtime_source(dir)?;
+    let mut command = Command::new("rustc");
+    command
+        .args(["--crate-name", "kozeig_runtime", "--crate-type", "staticlib", "--edition", "2021"])
+        .args(["-C", "opt-level=3", "-C", "panic=abort", "--target", rust_target(triple)])
+        .arg("-o")
+        .arg(&runtime_path)
+        .arg(&source_path);
+    run_rustc(command, "build the runtime library", triple)?;
+    Ok(runtime_path)
+}
+
+pub fn runtime_library_file(triple: &str) -> &'static str {
+    if triple.contains("msvc") {
+        "kozeig_runtime.lib"
+    } else {
+        "libkozeig_runtime.a"
+    }
+}
+
+// Target name rustc builds the runtime library for
+pub fn rust_target(triple: &str) -> &str {
+    if is_wasm(triple) {
+        "wasm32-wasip1"
+    } else {
+        triple
+    }
+}
+
+pub fn is_wasm(triple: &str) -> bool {
+    triple.starts_with("wasm32")
+}
+
+// System libraries Rust's standard library needs alongside the runtime
+pub fn runtime_system_libs(triple: &str) -> &'static [&'static str] {
+    if triple.contains("msvc") {
+        &["kernel32.lib", "advapi32.lib", "ntdll.lib", "userenv.lib", "ws2_32.lib", "bcrypt.lib"]
+    } else if triple.contains("windows") {
+        &["-lkernel32", "-ladvapi32", "-lntdll", "-luserenv", "-lws2_32", "-lbcrypt"]
+    } else if triple.contains("apple") || triple.contains("darwin") {
+        &["-lSystem", "-lc", "-lm"]
+    } else {
+        &["-lgcc_s", "-lutil", "-lrt", "-lpthread", "-lm", "-ldl"]
+    }
+}
+
+pub fn run_rustc(mut command: Command, task: &str, triple: &str) -> Result<(), LutError> {
+    let output = command.output().map_err(|e| LutError::compiler_error(
+        format!("Error running rustc to {} for {}: {}\n\nTip: Install Rust from https://rustup.rs", task, triple, e),
+        None
+    ))?;
+    if !output.status.success() {
+        return Err(LutError::compiler_error(
+            format!(
+                "Failed to {} for {}:\n{}\nTip: Install the target's standard library with `rustup target add {}`",
+                task,
+                triple,
+                String::from_utf8_lossy(&output.stderr),
+                rust_target(triple)
+            ),
+            None
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,144 @@
+// Checks the code generation options of `koze build`.
+#![cfg(feature = "llvm")]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn corpus_program(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("corpus").join(name)
+}
+
+// build writes its output to the working directory unless -o says otherwise
+fn build(work_dir: &Path, program: &Path, options: &[&str]) -> Output {
+    fs::create_dir_all(work_dir).unwrap();
+    Command::new(env!("CARGO_BIN_EXE_koze"))
+        .arg("build")
+        .arg(program)
+        .arg("-s")
+        .args(options)
+        .current_dir(work_dir)
+        .output()
+        .expect("failed to run koze")
+}
+
+#[test]
+fn unknown_target_is_reported() {
+    let work_dir = std::env::temp_dir().join("koze-build-unknown-target");
+    let output = build(&work_dir, &corpus_program("hello.ko"), &["--target", "nonsense-unknown-nowhere"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(!output.status.success());
+    assert!(stderr.contains("Unknown target 'nonsense-unknown-nowhere'"), "{}", stderr);
+}
+
+#[test]
+fn linker_command_is_configurable() {
+    let work_dir = std::env::temp_dir().join("koze-build-linker");
+    let program = corpus_program("hello.ko");
+    let output = build(&work_dir, &program, &["--linker=cc", "--cpu", "native"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let binary = Command::new(work_dir.join("hello")).output().expect("failed to run the built binary");
+    let expected = fs::read_to_string(program.with_extension("out")).unwrap();
+    assert_eq!(String::from_utf8_lossy(&binary.stdout), expected);
+}
+
+#[test]
+fn emit_writes_only_the_requested_file() {
+    let work_dir = std::env::temp_dir().join("koze-build-emit");
+    let out_dir = std::env::temp_dir().join("koze-build-emit-out");
+    let _ = fs::remove_dir_all(&work_dir);
+    fs::create_dir_all(&out_dir).unwrap();
+    let ir_path = out_dir.join("hello.ll");
+    let output = build(&work_dir, &corpus_program("hello.ko"), &["--emit=llvm-ir", "-o", ir_path.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let ir = fs::read_to_string(&ir_path).unwrap();
+    assert!(ir.contains("define"), "{}", ir);
+    assert_eq!(fs::read_dir(&work_dir).unwrap().count(), 0);
+}
+
+#[test]
+fn objects_are_generated_for_other_targets() {
+    let work_dir = std::env::temp_dir().join("koze-build-cross-object");
+    let output = build(
+        &work_dir,
+        &corpus_program("factorial.ko"),
+        &["--emit=obj", "--target", "aarch64-unknown-linux-gnu", "-o", "factorial.o"],
+    );
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    // An ELF object whose e_machine is EM_AARCH64
+    let object = fs::read(work_dir.join("factorial.o")).unwrap();
+    assert_eq!(&object[..4], b"\x7fELF");
+    assert_eq!(u16::from_le_bytes([object[18], object[19]]), 183);
+}
+
+// Names of the sections in a 64-bit little-endian ELF file
+fn elf_section_names(elf: &[u8]) -> Vec<String> {
+    let u16_at = |offset: usize| u16::from_le_bytes([elf[offset], elf[offset + 1]]) as usize;
+    let u32_at = |offset: usize| u32::from_le_bytes(elf[offset..offset + 4].try_into().unwrap()) as usize;
+    let u64_at = |offset: usize| u64::from_le_bytes(elf[offset..offset + 8].try_into().unwrap()) as usize;
+
+    let (headers, header_size, count, names_index) = (u64_at(0x28), u16_at(0x3a), u16_at(0x3c), u16_at(0x3e));
+    let names = u64_at(headers + names_index * header_size + 0x18);
+    (0..count)
+        .map(|i| {
+            let start = names + u32_at(headers + i * header_size);
+            let end = start + elf[start..].iter().position(|&byte| byte == 0).unwrap();
+            String::from_utf8_lossy(&elf[start..end]).to_string()
+        })
+        .collect()
+}
+
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+#[test]
+fn debug_info_describes_the_source() {
+    let work_dir = std::env::temp_dir().join("koze-build-debug-info");
+    let program = corpus_program("factorial.ko");
+    let output = build(&work_dir, &program, &["-g", "-O0", "-o", "factorial"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let binary = work_dir.join("factorial");
+    let sections = elf_section_names(&fs::read(&binary).unwrap());
+    for section in [".debug_info", ".debug_line"] {
+        assert!(sections.iter().any(|name| name == section), "no {} section in {:?}", section, sections);
+    }
+
+    let dwarfdump = |option: &str| {
+        Command::new("llvm-dwarfdump")
+            .arg(option)
+            .arg(&binary)
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).to_string())
+    };
+    let Some(info) = dwarfdump("--debug-info") else {
+        eprintln!("skipping the DWARF contents: llvm-dwarfdump is not installed");
+        return;
+    };
+
+    // A compile unit for the .ko file, with a subprogram for each func
+    assert!(info.contains("DW_TAG_compile_unit") && info.contains("factorial.ko"), "{}", info);
+    for function in ["factorial", "main"] {
+        assert!(info.contains(&format!("(\"{}\")", function)), "no subprogram {}:\n{}", function, info);
+    }
+
+    // The line table of that unit has rows for statements in both functions. Each table
+    // starts with a debug_line[...] header; rows are "address line column file ..."
+    let lines = dwarfdump("--debug-line").unwrap();
+    let table = lines
+        .split("debug_line[")
+        .find(|table| table.contains("factorial.ko"))
+        .unwrap_or_else(|| panic!("no line table for factorial.ko:\n{}", lines));
+    let rows: Vec<usize> = table
+        .lines()
+        .filter(|line| line.starts_with("0x"))
+        .filter_map(|line| line.split_whitespace().nth(1)?.parse().ok())
+        .collect();
+    for line in [3, 11] {
+        assert!(rows.contains(&line), "no row for line {} of factorial.ko in:\n{}", line, table);
+    }
+}
@@ -0,0 +1,100 @@
+// Builds programs with `koze build --backend=c`, which compiles through the system
+// C compiler instead of LLVM.
+
+mod common;
+
+use common::corpus_programs;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+fn build(work_dir: &Path, program: &Path, options: &[&str]) -> Output {
+    fs::create_dir_all(work_dir).unwrap();
+    Command::new(env!("CARGO_BIN_EXE_koze"))
+        .arg("build")
+        .arg(program)
+        .args(["-s", "--backend=c"])
+        .args(options)
+        .current_dir(work_dir)
+        .output()
+        .expect("failed to run koze")
+}
+
+#[test]
+fn c_backend_output_matches_corpus() {
+    let work_dir = std::env::temp_dir().join("koze-c-backend-corpus");
+
+    let mut failures = Vec::new();
+    for program in corpus_programs() {
+        let expected = fs::read_to_string(program.with_extension("out"))
+            .unwrap_or_else(|_| panic!("{} has no .out file", program.display()));
+        let binary = work_dir.join(program.file_stem().unwrap());
+
+        let output = build(&work_dir, &program, &["-o", binary.to_str().unwrap()]);
+        if !output.status.success() {
+            failures.push(format!("{}\n{}", program.display(), String::from_utf8_lossy(&output.stderr)));
+            continue;
+        }
+
+        let output = Command::new(&binary).output().expect("failed to run the built binary");
+        let actual = String::from_utf8_lossy(&output.stdout);
+        if !output.status.success() || actual != expected {
+            failures.push(format!(
+                "{}\n--- expected\n{}--- actual\n{}{}",
+                program.display(),
+                expected,
+                actual,
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn emit_c_writes_the_program_and_runtime() {
+    let work_dir = std::env::temp_dir().join("koze-c-backend-emit");
+    let _ = fs::remove_dir_all(&work_dir);
+    let program = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("corpus").join("imports.ko");
+    let output = build(&work_dir, &program, &["--emit=c"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let source = fs::read_to_string(work_dir.join("imports.c")).unwrap();
+    assert!(source.contains("int main(void)"), "{}", source);
+    assert!(work_dir.join("koze_runtime.h").exists());
+    assert!(work_dir.join("libkozeig_runtime.a").exists());
+
+    // The emitted files build on their own; the runtime needs the system libraries of Rust's standard library
+    let status = Command::new("cc")
+        .args(["-o", "imports", "imports.c", "libkozeig_runtime.a"])
+        .args(["-lgcc_s", "-lutil", "-lrt", "-lpthread", "-lm", "-ldl"])
+        .current_dir(&work_dir)
+        .status()
+        .expect("failed to run cc");
+    assert!(status.success());
+    let binary = Command::new(work_dir.join("imports")).output().unwrap();
+    let expected = fs::read_to_string(program.with_extension("out")).unwrap();
+    assert_eq!(String::from_utf8_lossy(&binary.stdout), expected);
+}
+
+#[test]
+fn llvm_only_options_are_rejected() {
+    let work_dir = std::env::temp_dir().join("koze-c-backend-options");
+    let program = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("corpus").join("hello.ko");
+
+    let output = build(&work_dir, &program, &["--cpu", "native"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("--cpu is not supported by the C backend"), "{}", stderr);
+
+    let output = build(&work_dir, &program, &["--target", "wasm32-wasi"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("WebAssembly is not supported by the C backend"), "{}", stderr);
+
+    let output = build(&work_dir, &program, &["--emit=llvm-ir"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("The C backend cannot write"), "{}", stderr);
+}
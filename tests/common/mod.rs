@@ -0,0 +1,16 @@
+// Helpers shared by the integration tests that walk tests/corpus.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Every .ko program directly in tests/corpus, in a stable order
+pub fn corpus_programs() -> Vec<PathBuf> {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("corpus");
+    let mut programs: Vec<PathBuf> = fs::read_dir(&corpus)
+        .expect("tests/corpus should exist")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "ko"))
+        .collect();
+    programs.sort();
+    programs
+}
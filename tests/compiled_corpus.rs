@@ -0,0 +1,41 @@
+// Runs every program in tests/corpus through the JIT and compares its output
+// with the .out file next to it.
+#![cfg(feature = "llvm")]
+
+mod common;
+
+use common::corpus_programs;
+use std::fs;
+use std::process::Command;
+
+#[test]
+fn jit_output_matches_corpus() {
+    // The JIT leaves its .ll file in the working directory
+    let work_dir = std::env::temp_dir().join("koze-compiled-corpus");
+    fs::create_dir_all(&work_dir).unwrap();
+
+    let mut failures = Vec::new();
+    for program in corpus_programs() {
+        let expected = fs::read_to_string(program.with_extension("out"))
+            .unwrap_or_else(|_| panic!("{} has no .out file", program.display()));
+
+        let output = Command::new(env!("CARGO_BIN_EXE_koze"))
+            .args(["jit", program.to_str().unwrap(), "-s"])
+            .current_dir(&work_dir)
+            .output()
+            .expect("failed to run koze");
+        let actual = String::from_utf8_lossy(&output.stdout);
+
+        if !output.status.success() || actual != expected {
+            failures.push(format!(
+                "{}\n--- expected\n{}--- actual\n{}{}",
+                program.display(),
+                expected,
+                actual,
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
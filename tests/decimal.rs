@@ -0,0 +1,211 @@
+// Unit checks for the fixed-point decimal module shared by the interpreter and
+// the compiler. The binary crate has no library target, so the module is
+// included by path.
+
+#[path = "../src/decimal.rs"]
+#[allow(dead_code)]
+mod decimal;
+
+use decimal::{div_round, Decimal, RoundingMode, MAX_SCALE};
+
+const MODES: [&str; 7] = [
+    "half_up",
+    "half_down",
+    "half_even",
+    "up",
+    "down",
+    "ceiling",
+    "floor",
+];
+
+fn mode(name: &str) -> RoundingMode {
+    RoundingMode::parse(name).unwrap()
+}
+
+fn dec(text: &str) -> Decimal {
+    Decimal::parse(text).unwrap()
+}
+
+#[test]
+fn parses_decimal_text() {
+    for (text, units, scale) in [
+        ("42", 42, 0),
+        ("19.99", 1999, 2),
+        ("-0.5", -5, 1),
+        ("+3.10", 310, 2),
+        (".25", 25, 2),
+        ("7.", 7, 0),
+        (" 1.000 ", 1000, 3),
+    ] {
+        let value = dec(text);
+        assert_eq!(
+            (value.units, value.scale),
+            (units, scale),
+            "parsing '{}'",
+            text
+        );
+    }
+}
+
+#[test]
+fn rejects_malformed_decimal_text() {
+    for text in ["", ".", "-", "1.2.3", "abc", "1e5", "--1", "1,5"] {
+        let error = Decimal::parse(text).unwrap_err();
+        assert!(
+            error.contains("Cannot convert"),
+            "'{}' gave {}",
+            text,
+            error
+        );
+    }
+}
+
+#[test]
+fn rejects_out_of_range_decimal_text() {
+    let error = Decimal::parse(&"9".repeat(40)).unwrap_err();
+    assert!(error.contains("out of range"), "{}", error);
+
+    let error = Decimal::parse(&format!("0.{}", "1".repeat(MAX_SCALE as usize + 1))).unwrap_err();
+    assert!(error.contains("too large"), "{}", error);
+}
+
+#[test]
+fn displays_with_its_scale() {
+    for text in [
+        "0",
+        "42",
+        "19.99",
+        "-0.50",
+        "-12.005",
+        "0.000000000000000001",
+    ] {
+        assert_eq!(dec(text).to_string(), text);
+    }
+}
+
+#[test]
+fn rescales_up_and_down() {
+    assert_eq!(
+        dec("1.5").rescale(3, mode("half_up")).unwrap().to_string(),
+        "1.500"
+    );
+    assert_eq!(
+        dec("1.25").rescale(1, mode("half_up")).unwrap().to_string(),
+        "1.3"
+    );
+    assert_eq!(
+        dec("1.25")
+            .rescale(1, mode("half_even"))
+            .unwrap()
+            .to_string(),
+        "1.2"
+    );
+    assert_eq!(
+        dec("-1.25").rescale(1, mode("floor")).unwrap().to_string(),
+        "-1.3"
+    );
+    assert_eq!(
+        dec("-1.25").rescale(0, mode("down")).unwrap().to_string(),
+        "-1"
+    );
+}
+
+#[test]
+fn rescale_rejects_scales_past_the_maximum() {
+    let error = dec("1")
+        .rescale(MAX_SCALE + 1, mode("half_up"))
+        .unwrap_err();
+    assert!(error.contains("too large"), "{}", error);
+}
+
+#[test]
+fn rescale_exact_refuses_to_drop_digits() {
+    assert_eq!(dec("2.50").rescale_exact(1).unwrap().to_string(), "2.5");
+    assert!(dec("2.55").rescale_exact(1).is_err());
+}
+
+#[test]
+fn parses_every_rounding_mode() {
+    for name in MODES {
+        assert!(RoundingMode::parse(name).is_ok(), "{}", name);
+    }
+    let error = RoundingMode::parse("nearest").unwrap_err();
+    assert!(
+        error.contains("Unknown rounding mode 'nearest'"),
+        "{}",
+        error
+    );
+    assert!(error.contains("Tip:"), "{}", error);
+}
+
+#[test]
+fn div_round_matches_each_mode() {
+    // (numerator, divisor, expected per mode in MODES order)
+    let cases: [(i128, i128, [i128; 7]); 8] = [
+        (5, 2, [3, 2, 2, 3, 2, 3, 2]),
+        (7, 2, [4, 3, 4, 4, 3, 4, 3]),
+        (-5, 2, [-3, -2, -2, -3, -2, -2, -3]),
+        (-7, 2, [-4, -3, -4, -4, -3, -3, -4]),
+        (5, -2, [-3, -2, -2, -3, -2, -2, -3]),
+        (7, 3, [2, 2, 2, 3, 2, 3, 2]),
+        (8, 3, [3, 3, 3, 3, 2, 3, 2]),
+        (-8, 3, [-3, -3, -3, -3, -2, -2, -3]),
+    ];
+
+    for (numerator, divisor, expected) in cases {
+        for (name, want) in MODES.iter().zip(expected) {
+            assert_eq!(
+                div_round(numerator, divisor, mode(name)).unwrap(),
+                want,
+                "{} / {} with {}",
+                numerator,
+                divisor,
+                name
+            );
+        }
+    }
+}
+
+#[test]
+fn div_round_is_exact_without_a_remainder() {
+    for name in MODES {
+        assert_eq!(div_round(-9, 3, mode(name)).unwrap(), -3, "{}", name);
+    }
+}
+
+#[test]
+fn div_round_rejects_division_by_zero() {
+    assert_eq!(
+        div_round(1, 0, mode("half_up")).unwrap_err(),
+        "Division by zero"
+    );
+}
+
+#[test]
+fn div_round_handles_remainders_too_large_to_double() {
+    // The remainder is past half of a divisor near i128::MAX; doubling it would overflow
+    let divisor = i128::MAX;
+    let numerator = divisor - 1;
+    let positive = [1, 1, 1, 1, 0, 1, 0];
+    let negative = [-1, -1, -1, -1, 0, 0, -1];
+    for (i, name) in MODES.iter().enumerate() {
+        assert_eq!(
+            div_round(numerator, divisor, mode(name)).unwrap(),
+            positive[i],
+            "{}",
+            name
+        );
+        assert_eq!(
+            div_round(-numerator, divisor, mode(name)).unwrap(),
+            negative[i],
+            "-{}",
+            name
+        );
+    }
+}
+
+#[test]
+fn div_round_reports_overflow_instead_of_panicking() {
+    assert!(div_round(i128::MIN, -1, mode("half_up")).is_err());
+    assert!(div_round(1, i128::MIN, mode("half_up")).is_err());
+}
@@ -0,0 +1,162 @@
+// Runs the corpus through `koze difftest`, which compares `koze run`, `koze jit`
+// and a binary from `koze build` on stdout, stderr and exit code.
+
+use std::path::Path;
+use std::process::Command;
+
+fn difftest(opt_flag: &str) {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("corpus");
+    let output = Command::new(env!("CARGO_BIN_EXE_koze"))
+        .arg("difftest")
+        .arg(&corpus)
+        .arg(opt_flag)
+        .output()
+        .expect("failed to run koze");
+
+    assert!(
+        output.status.success(),
+        "execution modes disagree at {}:\n{}{}",
+        opt_flag,
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn modes_agree_unoptimized() {
+    difftest("-O0");
+}
+
+#[test]
+fn modes_agree_optimized() {
+    difftest("-O2");
+}
+
+// Modules that are only imported are not run as programs of their own
+#[test]
+fn imported_modules_are_skipped() {
+    let programs = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("runtime_errors");
+    let output = Command::new(env!("CARGO_BIN_EXE_koze"))
+        .arg("difftest")
+        .arg(&programs)
+        .output()
+        .expect("failed to run koze");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!stdout.contains("counter.ko"), "{}", stdout);
+    assert!(stdout.contains("module_overflow.ko"), "{}", stdout);
+}
+
+// Programs that stop with a runtime error must report it the same way in every mode
+#[test]
+fn runtime_errors_agree() {
+    let programs = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("runtime_errors");
+    let output = Command::new(env!("CARGO_BIN_EXE_koze"))
+        .arg("difftest")
+        .arg(&programs)
+        .output()
+        .expect("failed to run koze");
+
+    assert!(
+        output.status.success(),
+        "execution modes report runtime errors differently:\n{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn runtime_errors_name_the_failing_line() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let output = Command::new(env!("CARGO_BIN_EXE_koze"))
+        .args(["run", "tests/runtime_errors/module_overflow.ko"])
+        .current_dir(root)
+        .output()
+        .expect("failed to run koze");
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr.contains("tests/runtime_errors/lib/counter.ko:line 5"), "{}", stderr);
+    assert!(stderr.contains("Integer overflow"), "{}", stderr);
+}
+
+// main's value is the exit status in every mode
+#[test]
+fn exit_statuses_agree() {
+    let programs = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("exit_status");
+    let output = Command::new(env!("CARGO_BIN_EXE_koze"))
+        .arg("difftest")
+        .arg(&programs)
+        .output()
+        .expect("failed to run koze");
+
+    assert!(
+        output.status.success(),
+        "execution modes exit differently:\n{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn main_value_sets_the_exit_status() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let run = |program: &str| {
+        Command::new(env!("CARGO_BIN_EXE_koze"))
+            .arg("run")
+            .arg(format!("tests/exit_status/{}", program))
+            .current_dir(root)
+            .output()
+            .expect("failed to run koze")
+    };
+
+    assert_eq!(run("ok.ko").status.code(), Some(0));
+    assert_eq!(run("number.ko").status.code(), Some(3));
+    assert_eq!(run("other_value.ko").status.code(), Some(0));
+
+    let output = run("out_of_range.ko");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "Exit status 256 is outside 0 to 255\n");
+
+    let output = run("error_text.ko");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "settings.cfg is missing\n");
+}
+
+// koze build has to compile the example's nested imports like koze run follows them
+#[test]
+fn tempconverter_example_builds() {
+    let example = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples").join("tempconverter");
+    let output = Command::new(env!("CARGO_BIN_EXE_koze"))
+        .arg("difftest")
+        .arg(&example)
+        .output()
+        .expect("failed to run koze");
+
+    assert!(
+        output.status.success(),
+        "execution modes disagree on the tempconverter example:\n{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+// The example's modules import each other, so this also covers nested imports
+#[test]
+fn tempconverter_example_runs() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let output = Command::new(env!("CARGO_BIN_EXE_koze"))
+        .args(["run", "examples/tempconverter/main.ko"])
+        .current_dir(root)
+        .output()
+        .expect("failed to run koze");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "{}{}",
+        stdout,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(stdout.contains("  100 °C = 212 °F = 373 °K\n  Water state: Gas (Steam)"), "{}", stdout);
+}
@@ -0,0 +1,34 @@
+// Runs every program in tests/corpus through the JIT with the runtime's leak
+// check enabled; each text and array a program creates must be freed by the
+// time main returns.
+#![cfg(feature = "llvm")]
+
+mod common;
+
+use common::corpus_programs;
+use std::fs;
+use std::process::Command;
+
+#[test]
+fn compiled_programs_free_their_values() {
+    // The JIT leaves its .ll file in the working directory
+    let work_dir = std::env::temp_dir().join("koze-leak-check");
+    fs::create_dir_all(&work_dir).unwrap();
+
+    let mut failures = Vec::new();
+    for program in corpus_programs() {
+        let output = Command::new(env!("CARGO_BIN_EXE_koze"))
+            .args(["jit", program.to_str().unwrap(), "-s"])
+            .env("KOZE_LEAK_CHECK", "1")
+            .current_dir(&work_dir)
+            .output()
+            .expect("failed to run koze");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        if !output.status.success() || stderr.contains("Leak check") {
+            failures.push(format!("{}\n{}", program.display(), stderr));
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
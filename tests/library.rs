@@ -0,0 +1,91 @@
+// Builds tests/library/mathlib.ko with `koze build --lib` and links the C program
+// next to it against the library and its generated header.
+#![cfg(all(target_os = "linux", feature = "llvm"))]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn library_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("library")
+}
+
+fn koze_build(work_dir: &Path, program: &Path, options: &[&str]) -> Output {
+    fs::create_dir_all(work_dir).unwrap();
+    Command::new(env!("CARGO_BIN_EXE_koze"))
+        .arg("build")
+        .arg(program)
+        .arg("-s")
+        .args(options)
+        .current_dir(work_dir)
+        .output()
+        .expect("failed to run koze")
+}
+
+fn run_c_program(work_dir: &Path, link_args: &[&str]) -> String {
+    let status = Command::new("cc")
+        .arg(library_dir().join("main.c"))
+        .arg("-I")
+        .arg(work_dir)
+        .arg("-o")
+        .arg(work_dir.join("main"))
+        .args(link_args)
+        .status()
+        .expect("failed to run cc");
+    assert!(status.success());
+
+    let output = Command::new(work_dir.join("main")).output().expect("failed to run the C program");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn shared_library_is_callable_from_c() {
+    let work_dir = std::env::temp_dir().join("koze-library-shared");
+    let output = koze_build(&work_dir, &library_dir().join("mathlib.ko"), &["--lib"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let rpath = format!("-Wl,-rpath,{}", work_dir.display());
+    let stdout = run_c_program(&work_dir, &["-L", work_dir.to_str().unwrap(), "-lmathlib", &rpath]);
+    assert_eq!(stdout, fs::read_to_string(library_dir().join("main.out")).unwrap());
+}
+
+#[test]
+fn static_library_is_callable_from_c() {
+    let work_dir = std::env::temp_dir().join("koze-library-static");
+    let output = koze_build(&work_dir, &library_dir().join("mathlib.ko"), &["--lib=static"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    // The runtime inside the archive needs the system libraries of Rust's standard library
+    let library = work_dir.join("libmathlib.a");
+    let stdout = run_c_program(
+        &work_dir,
+        &[library.to_str().unwrap(), "-lgcc_s", "-lutil", "-lrt", "-lpthread", "-lm", "-ldl"],
+    );
+    assert_eq!(stdout, fs::read_to_string(library_dir().join("main.out")).unwrap());
+}
+
+#[test]
+fn header_declares_pub_functions() {
+    let work_dir = std::env::temp_dir().join("koze-library-header");
+    let output = koze_build(&work_dir, &library_dir().join("mathlib.ko"), &["--lib", "-o", "libmath.so"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let header = fs::read_to_string(work_dir.join("mathlib.h")).unwrap();
+    assert!(header.contains("int64_t add(int64_t a, int64_t b);"), "{}", header);
+    assert!(header.contains("double area(double r);"), "{}", header);
+    assert!(header.contains("char *greet(const char *name);"), "{}", header);
+    assert!(header.contains("bool is_big(int64_t n);"), "{}", header);
+    assert!(!header.contains("limit"), "{}", header);
+}
+
+#[test]
+fn library_without_entry_point() {
+    let work_dir = std::env::temp_dir().join("koze-library-main");
+    let program = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("corpus").join("hello.ko");
+    let output = koze_build(&work_dir, &program, &["--lib"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(!output.status.success());
+    assert!(stderr.contains("A library has no main function"), "{}", stderr);
+}
@@ -0,0 +1,19 @@
+/* Calls the functions of mathlib.ko through the header koze build --lib writes */
+#include <stdio.h>
+
+#include "mathlib.h"
+
+int main(void) {
+    char *greeting = greet("koze");
+
+    printf("%lld\n", (long long)add(2, 3));
+    printf("%.1f\n", area(2.0));
+    printf("%s\n", greeting);
+    printf("%d %d\n", is_big(500), is_big(5));
+    koze_release(greeting);
+
+    /* report prints through the runtime, which flushes before returning */
+    fflush(stdout);
+    printf("%lld\n", (long long)report(7));
+    return 0;
+}
@@ -0,0 +1,190 @@
+// Checks the structure of what `koze build --target wasm32-wasi` writes.
+#![cfg(feature = "llvm")]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn corpus_program(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("corpus").join(name)
+}
+
+fn build(work_dir: &Path, program: &Path, options: &[&str]) -> Output {
+    fs::create_dir_all(work_dir).unwrap();
+    Command::new(env!("CARGO_BIN_EXE_koze"))
+        .arg("build")
+        .arg(program)
+        .arg("-s")
+        .args(["--target", "wasm32-wasi"])
+        .args(options)
+        .current_dir(work_dir)
+        .output()
+        .expect("failed to run koze")
+}
+
+// Linking a module needs the Rust standard library for wasm32-wasip1
+fn wasi_target_installed() -> bool {
+    Command::new("rustc")
+        .args(["--print", "target-libdir", "--target", "wasm32-wasip1"])
+        .output()
+        .map(|output| output.status.success() && Path::new(String::from_utf8_lossy(&output.stdout).trim()).exists())
+        .unwrap_or(false)
+}
+
+struct Import {
+    module: String,
+    name: String,
+}
+
+struct Export {
+    name: String,
+    kind: u8,
+}
+
+// Sections of a binary module, with its imports and exports decoded
+struct WasmModule {
+    section_ids: Vec<u8>,
+    custom_sections: Vec<String>,
+    imports: Vec<Import>,
+    exports: Vec<Export>,
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> u8 {
+        let byte = self.bytes[self.position];
+        self.position += 1;
+        byte
+    }
+
+    fn leb_u32(&mut self) -> u32 {
+        let mut result = 0u32;
+        for shift in (0..35).step_by(7) {
+            let byte = self.byte();
+            result |= ((byte & 0x7f) as u32) << shift;
+            if byte & 0x80 == 0 {
+                return result;
+            }
+        }
+        panic!("LEB128 value longer than 5 bytes at {}", self.position);
+    }
+
+    fn bytes(&mut self, len: usize) -> &'a [u8] {
+        let bytes = &self.bytes[self.position..self.position + len];
+        self.position += len;
+        bytes
+    }
+
+    fn name(&mut self) -> String {
+        let len = self.leb_u32() as usize;
+        String::from_utf8(self.bytes(len).to_vec()).expect("names are UTF-8")
+    }
+
+    fn limits(&mut self) {
+        let flags = self.byte();
+        self.leb_u32();
+        if flags & 1 != 0 {
+            self.leb_u32();
+        }
+    }
+}
+
+fn parse_module(bytes: &[u8]) -> WasmModule {
+    assert_eq!(&bytes[..4], b"\0asm", "missing the wasm magic number");
+    assert_eq!(&bytes[4..8], &[1, 0, 0, 0], "not a version 1 module");
+
+    let mut module = WasmModule { section_ids: Vec::new(), custom_sections: Vec::new(), imports: Vec::new(), exports: Vec::new() };
+    let mut reader = Reader { bytes, position: 8 };
+    while reader.position < bytes.len() {
+        let id = reader.byte();
+        let size = reader.leb_u32() as usize;
+        assert!(reader.position + size <= bytes.len(), "section {} runs past the end of the module", id);
+        let mut section = Reader { bytes: reader.bytes(size), position: 0 };
+        assert!(id <= 12, "unknown section id {}", id);
+
+        match id {
+            0 => module.custom_sections.push(section.name()),
+            2 => {
+                for _ in 0..section.leb_u32() {
+                    let import = Import { module: section.name(), name: section.name() };
+                    match section.byte() {
+                        0 => {
+                            section.leb_u32();
+                        }
+                        1 => {
+                            section.byte();
+                            section.limits();
+                        }
+                        2 => section.limits(),
+                        3 => {
+                            section.byte();
+                            section.byte();
+                        }
+                        kind => panic!("unknown import kind {}", kind),
+                    }
+                    module.imports.push(import);
+                }
+                assert_eq!(section.position, size, "import section has trailing bytes");
+            }
+            7 => {
+                for _ in 0..section.leb_u32() {
+                    let name = section.name();
+                    let kind = section.byte();
+                    section.leb_u32();
+                    module.exports.push(Export { name, kind });
+                }
+                assert_eq!(section.position, size, "export section has trailing bytes");
+            }
+            _ => {}
+        }
+        module.section_ids.push(id);
+    }
+    module
+}
+
+#[test]
+fn objects_are_relocatable_wasm() {
+    let work_dir = std::env::temp_dir().join("koze-build-wasm-object");
+    let output = build(&work_dir, &corpus_program("hello.ko"), &["--emit=obj", "-o", "hello.o"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let module = parse_module(&fs::read(work_dir.join("hello.o")).unwrap());
+    assert!(module.section_ids.contains(&10), "no code section");
+    assert!(module.custom_sections.iter().any(|name| name == "linking"), "no linking section");
+    // The runtime is linked in later, so its functions are still imports
+    assert!(module.imports.iter().any(|import| import.name == "koze_print_text"));
+}
+
+#[test]
+fn modules_are_wasi_commands() {
+    if !wasi_target_installed() {
+        eprintln!("skipping: rustup target add wasm32-wasip1 to build WASI modules");
+        return;
+    }
+
+    let work_dir = std::env::temp_dir().join("koze-build-wasm-module");
+    let program = corpus_program("hello.ko");
+    let output = build(&work_dir, &program, &[]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let wasm_path = work_dir.join("hello.wasm");
+    let module = parse_module(&fs::read(&wasm_path).unwrap());
+    assert!(module.exports.iter().any(|export| export.name == "_start" && export.kind == 0), "no _start export");
+    assert!(module.exports.iter().any(|export| export.name == "memory" && export.kind == 2), "no memory export");
+
+    // Everything is resolved except the WASI calls, and printing goes through fd_write
+    for import in &module.imports {
+        assert_eq!(import.module, "wasi_snapshot_preview1", "unresolved import {}", import.name);
+    }
+    assert!(module.imports.iter().any(|import| import.name == "fd_write"));
+
+    // Run it when a WASI runtime is around
+    if let Ok(run) = Command::new("wasmtime").arg(&wasm_path).output() {
+        let expected = fs::read_to_string(program.with_extension("out")).unwrap();
+        assert_eq!(String::from_utf8_lossy(&run.stdout), expected);
+    }
+}